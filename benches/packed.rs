@@ -0,0 +1,71 @@
+//! Compares the visit of an `OptimizedTree` with boxed leaves to the same tree packed with
+//! `TreeFactory::optimize_packed` and unboxed leaves
+
+#[macro_use]
+extern crate criterion;
+extern crate behaviour_tree;
+
+use criterion::Criterion;
+
+use behaviour_tree::tree::{BehaviourTreeNode,LeafNodeFactory,VisitResult};
+use behaviour_tree::tree::factory::{NodeFactory,TreeFactory};
+
+/// Leaf counting its visits, and succeeding every `period` visits
+#[derive(Debug,Clone)]
+struct Counter {
+    period: u32,
+    visits: u32,
+}
+
+impl BehaviourTreeNode<u64> for Counter {
+    fn visit(&mut self, total: &mut u64) -> VisitResult {
+        self.visits += 1;
+        *total += 1;
+        if self.visits % self.period == 0 {
+            VisitResult::Success
+        } else {
+            VisitResult::Failure
+        }
+    }
+}
+
+impl LeafNodeFactory for Counter {
+    type Output = Counter;
+    fn instanciate(&self) -> Counter {
+        self.clone()
+    }
+}
+
+struct BoxedCounter(Counter);
+
+impl LeafNodeFactory for BoxedCounter {
+    type Output = Box<BehaviourTreeNode<u64>>;
+    fn instanciate(&self) -> Box<BehaviourTreeNode<u64>> {
+        Box::new(self.0.clone())
+    }
+}
+
+// Selectors of sequences of leaves, `width` children per composite and `depth` levels of them
+fn node<F, L: Fn(Counter) -> F + Copy>(depth: usize, width: u32, leaf: L) -> NodeFactory<F> {
+    if depth == 0 {
+        return NodeFactory::new_leaf(leaf(Counter { period: width, visits: 0 }));
+    }
+    let children = (0..width).map(|_| node(depth - 1, width, leaf)).collect();
+    if depth % 2 == 0 {
+        NodeFactory::new_selector(children)
+    } else {
+        NodeFactory::new_sequence(children)
+    }
+}
+
+fn visit(c: &mut Criterion) {
+    let boxed = TreeFactory::new(node(4, 6, BoxedCounter), String::from("boxed"));
+    let packed = TreeFactory::new(node(4, 6, |counter| counter), String::from("packed"));
+    let mut boxed_tree = boxed.optimize();
+    let mut packed_tree = packed.optimize_packed();
+    c.bench_function("boxed leaves", move |b| b.iter(|| boxed_tree.visit(&mut 0)));
+    c.bench_function("packed leaves", move |b| b.iter(|| packed_tree.visit(&mut 0)));
+}
+
+criterion_group!(benches, visit);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! `#[derive(FromOptions)]`, enabled by the `derive` feature of behaviour-tree
+//!
+//! Each named field of the struct is read from the entry of the options with the same name, see
+//! `behaviour_tree::parser::options`.
+
+extern crate proc_macro;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use syn::{Data,DeriveInput,Fields};
+
+#[proc_macro_derive(FromOptions)]
+pub fn derive_from_options(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).expect("Could not parse the input of derive(FromOptions)");
+    let name = &input.ident;
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => panic!("FromOptions can only be derived for structs with named fields"),
+        },
+        _ => panic!("FromOptions can only be derived for structs"),
+    };
+    let idents: Vec<_> = fields.iter().map(|field| field.ident.clone().unwrap()).collect();
+    let keys: Vec<String> = idents.iter().map(|ident| ident.to_string()).collect();
+    let all_keys = keys.clone();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::behaviour_tree::parser::FromOptions for #name #ty_generics #where_clause {
+            fn from_options(options: &::std::option::Option<::behaviour_tree::parser::Value>)
+            -> ::std::result::Result<Self, ::behaviour_tree::parser::OptionsError> {
+                let map = ::behaviour_tree::parser::options::options_map(options)?;
+                ::behaviour_tree::parser::options::check_fields(map, &[#(#all_keys),*])?;
+                ::std::result::Result::Ok(#name {
+                    #(#idents: ::behaviour_tree::parser::options::field(map, #keys)?,)*
+                })
+            }
+        }
+    };
+    expanded.into()
+}
@@ -0,0 +1,31 @@
+extern crate behaviour_tree;
+
+use std::collections::HashMap;
+
+use behaviour_tree::tree::{BehaviourTreeNode,VisitResult};
+use behaviour_tree::tree::factory::TreeBuilder;
+use behaviour_tree::parser::Value;
+use behaviour_tree::standard::{self,StandardFactory,StoreKind};
+
+type Context = HashMap<String,StoreKind>;
+
+fn print(text: &str) -> StandardFactory<Context> {
+    standard::print_text(&Some(Value::String(String::from(text)))).unwrap()
+}
+
+fn main() {
+    let tree = TreeBuilder::new()
+        .sequence(|b| b
+            .leaf(print("Locate door"))
+            .selector(|b| b
+                .inverter(|b| b
+                    .leaf(print("Door is not locked")))
+                .leaf(print("Unlock door")))
+            .leaf(print("Walk through door")))
+        .build("enter_room");
+
+    println!("Testing tree {}", tree.get_name());
+    let mut instance = tree.optimize();
+    let mut context = HashMap::new();
+    while instance.visit(&mut context) == VisitResult::Running {}
+}
@@ -1,9 +1,34 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+// Gives the same paths to Vec, Box or String with and without std
+#[cfg(feature = "std")]
+extern crate std as alloc;
+#[cfg(feature = "std")]
+extern crate core;
 extern crate flat_tree;
+#[cfg(feature = "std")]
+extern crate lalrpop_util;
 extern crate ref_slice;
+#[cfg(feature = "rand")]
+extern crate rand;
+#[cfg(feature = "derive")]
+extern crate behaviour_tree_derive;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
-pub use parser::parse;
+#[cfg(feature = "std")]
+pub use parser::{parse,parse_iter,parse_lenient,parse_one,parse_with_resolver,validate};
 pub use self::tree::OptimizedTree as BehaviourTree;
-pub use self::parser::FactoryProducer;
+#[cfg(feature = "std")]
+pub use self::parser::collection::TreeCollection;
+#[cfg(feature = "std")]
+pub use self::parser::{FactoryProducer,ParseError};
+#[cfg(feature = "std")]
 pub mod parser;
 pub mod tree;
+#[cfg(feature = "std")]
 pub mod standard;
@@ -1,14 +1,117 @@
-use parser::Value;
+//! Syntax tree produced by the parser, before leaves and subtrees are resolved
+//!
+//! Leaves are only described by their name and options, so unlike `NodeFactory` these types can
+//! be compared, and serialized when the `serde` feature is enabled.
+//!
+//! `Node::Leaf`, `Node::Switch` and `Node::StoreResult` keep their position in the input, so two
+//! trees only compare equal if these nodes are also at the same lines and columns: reformatting
+//! the input changes the AST.
 
+use parser::{Span,Value};
+
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Node {
     Sequence(Vec<Node>),
+    SequenceAll(Vec<Node>),
     Selector(Vec<Node>),
+    RandomSelector(Vec<Node>),
+    WeightedSelector(Vec<(i64,Node)>),
     Priority(Vec<Node>),
-    Leaf(String,Option<Value>),
+    ReactiveSelector(Vec<Node>),
+    Parallel(i64,Vec<Node>),
+    /// Name and options of the leaf, and its position in the input
+    Leaf(String,Option<Value>,Span),
     Inverter(Box<Node>),
+    Repeater(i64,Box<Node>),
+    UntilFail(Box<Node>),
+    Succeeder(Box<Node>),
+    Cooldown(i64,Box<Node>),
+    Limiter(i64,Box<Node>),
+    Retry(i64,Box<Node>),
+    Budget(i64,Box<Node>),
+    /// Name of the result returned while the child is running, and the child
+    NonBlocking(String,Box<Node>),
+    /// Variable receiving the result of the child, the child, and the position of the node in the
+    /// input
+    StoreResult(String,Box<Node>,Span),
+    /// Options of the `switch` leaf selecting the child, the children, and the position of the
+    /// node in the input
+    Switch(Option<Value>,Vec<Node>,Span),
+    Subtree(String),
+    /// Invocation of a tree with parameters, with the value of each argument
+    SubtreeCall(String,Vec<(String,Value)>),
+    /// Node preceded by `//` comments, with the text following each `//`
+    Commented(Vec<String>,Box<Node>),
+    /// Node given an identifier with `#name`, with the name and the node
+    Identified(String,Box<Node>),
+}
+
+impl Node {
+    /// Wraps the node in `Node::Identified` if `id` is set
+    pub fn with_id(id: Option<String>, node: Node) -> Node {
+        match id {
+            Some(id) => Node::Identified(id, Box::new(node)),
+            None => node,
+        }
+    }
+
+    /// Children of the node, in order
+    ///
+    /// Decorators, comments and identifiers have a single child, while leaves and subtrees have
+    /// none.
+    pub fn children(&self) -> Vec<&Node> {
+        match *self {
+            Node::Sequence(ref children) | Node::SequenceAll(ref children)
+                | Node::Selector(ref children) | Node::RandomSelector(ref children)
+                | Node::Priority(ref children) | Node::ReactiveSelector(ref children)
+                | Node::Parallel(_, ref children) | Node::Switch(_, ref children, _) => {
+                children.iter().collect()
+            }
+            Node::WeightedSelector(ref children) => {
+                children.iter().map(|&(_, ref child)| child).collect()
+            }
+            Node::Inverter(ref child) | Node::Repeater(_, ref child)
+                | Node::UntilFail(ref child) | Node::Succeeder(ref child)
+                | Node::Cooldown(_, ref child) | Node::Limiter(_, ref child)
+                | Node::Retry(_, ref child) | Node::Budget(_, ref child)
+                | Node::NonBlocking(_, ref child) | Node::StoreResult(_, ref child, _)
+                | Node::Commented(_, ref child) | Node::Identified(_, ref child) => vec![&**child],
+            Node::Leaf(..) | Node::Subtree(_) | Node::SubtreeCall(..) => Vec::new(),
+        }
+    }
+
+    /// Same as `children`
+    pub fn children_mut(&mut self) -> Vec<&mut Node> {
+        match *self {
+            Node::Sequence(ref mut children) | Node::SequenceAll(ref mut children)
+                | Node::Selector(ref mut children) | Node::RandomSelector(ref mut children)
+                | Node::Priority(ref mut children) | Node::ReactiveSelector(ref mut children)
+                | Node::Parallel(_, ref mut children) | Node::Switch(_, ref mut children, _) => {
+                children.iter_mut().collect()
+            }
+            Node::WeightedSelector(ref mut children) => {
+                children.iter_mut().map(|&mut (_, ref mut child)| child).collect()
+            }
+            Node::Inverter(ref mut child) | Node::Repeater(_, ref mut child)
+                | Node::UntilFail(ref mut child) | Node::Succeeder(ref mut child)
+                | Node::Cooldown(_, ref mut child) | Node::Limiter(_, ref mut child)
+                | Node::Retry(_, ref mut child) | Node::Budget(_, ref mut child)
+                | Node::NonBlocking(_, ref mut child) | Node::StoreResult(_, ref mut child, _)
+                | Node::Commented(_, ref mut child) | Node::Identified(_, ref mut child) => {
+                vec![&mut **child]
+            }
+            Node::Leaf(..) | Node::Subtree(_) | Node::SubtreeCall(..) => Vec::new(),
+        }
+    }
 }
 
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Tree {
     pub name: String,
+    /// Names of the parameters, empty for trees that are not templates
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub params: Vec<String>,
     pub root: Node,
 }
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::slice;
+
+use parser::ParseError;
+use tree::factory::TreeFactory;
+
+/// Trees indexed by their name, usually built from the result of `parse`
+///
+/// Trees using the standard leaves can be parsed with `str::parse`.
+#[derive(Clone)]
+pub struct TreeCollection<F> {
+    trees: Vec<TreeFactory<F>>,
+    indices: HashMap<String,usize>,
+}
+
+impl <F> fmt::Debug for TreeCollection<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(),fmt::Error> {
+        f.debug_list().entries(self.trees.iter()).finish()
+    }
+}
+
+impl <F> TreeCollection<F> {
+    /// Returns an error listing the names used by several trees, if any
+    pub fn new(trees: Vec<TreeFactory<F>>) -> Result<TreeCollection<F>,ParseError> {
+        try!(super::check_duplicate_names(trees.iter().map(|tree| tree.get_name())));
+        let indices = trees.iter().enumerate()
+            .map(|(index, tree)| (String::from(tree.get_name()), index))
+            .collect();
+        Ok(TreeCollection {
+            trees: trees,
+            indices: indices,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TreeFactory<F>> {
+        self.indices.get(name).map(|&index| &self.trees[index])
+    }
+
+    /// Names of the trees, in the order they were given
+    pub fn names(&self) -> Vec<&str> {
+        self.trees.iter().map(|tree| tree.get_name()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Iterates over the trees in the order they were given
+    pub fn iter(&self) -> slice::Iter<TreeFactory<F>> {
+        self.trees.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<TreeFactory<F>> {
+        self.trees
+    }
+}
+
+impl <'a, F> IntoIterator for &'a TreeCollection<F> {
+    type Item = &'a TreeFactory<F>;
+    type IntoIter = slice::Iter<'a, TreeFactory<F>>;
+    fn into_iter(self) -> slice::Iter<'a, TreeFactory<F>> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::ParseError;
+    use tree::factory::{NodeFactory,TreeFactory};
+    use super::TreeCollection;
+
+    fn tree(name: &str) -> TreeFactory<()> {
+        TreeFactory::new(NodeFactory::new_sequence(Vec::new()), String::from(name))
+    }
+
+    #[test]
+    fn get() {
+        let trees = TreeCollection::new(vec![tree("b"), tree("a"), tree("c")]).unwrap();
+        assert_eq!(trees.len(), 3);
+        assert_eq!(trees.get("a").map(|tree| tree.get_name()), Some("a"));
+        assert_eq!(trees.get("c").map(|tree| tree.get_name()), Some("c"));
+        assert!(trees.get("d").is_none());
+    }
+
+    #[test]
+    fn order() {
+        let trees = TreeCollection::new(vec![tree("b"), tree("a"), tree("c")]).unwrap();
+        assert_eq!(trees.names(), ["b", "a", "c"]);
+        let names: Vec<_> = trees.iter().map(|tree| tree.get_name()).collect();
+        assert_eq!(names, trees.names());
+        assert_eq!(trees.into_vec()[0].get_name(), "b");
+    }
+
+    #[test]
+    fn duplicates() {
+        match TreeCollection::new(vec![tree("a"), tree("b"), tree("a"), tree("a")]) {
+            Err(ParseError::DuplicateTrees { ref names }) => assert_eq!(*names, ["a"]),
+            other => panic!("Expected duplicate trees error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::fmt::{self,Display,Formatter};
+
+use parser::lexer::{Span,Token};
+
+/// Errors returned while parsing a tree description and resolving its leaves and subtrees
+#[derive(Debug)]
+pub enum ParseError {
+    /// The input contains something that is not a valid token
+    Lex {
+        message: String,
+        span: Span,
+    },
+    /// A token appeared where the grammar does not allow it
+    UnexpectedToken {
+        found: Token,
+        span: Span,
+        expected: Vec<String>,
+    },
+    /// The input ended in the middle of a tree
+    UnexpectedEof {
+        expected: Vec<String>,
+    },
+    /// No leaf with this name could be provided by the `FactoryProducer`
+    UnknownLeaf {
+        name: String,
+    },
+    /// The leaf exists, but rejected the options it was given
+    InvalidLeafOptions {
+        name: String,
+        message: String,
+    },
+    /// A subtree references a tree that does not exist
+    UnresolvedSubtree {
+        name: String,
+        tree: String,
+    },
+    /// Trees reference each other as subtrees in a cycle
+    SubtreeCycle {
+        path: Vec<String>,
+    },
+    /// A node argument, like the count of a repeater, is out of range
+    InvalidArgument {
+        message: String,
+    },
+    /// A composite node, like a sequence, has no children
+    EmptyComposite {
+        node: String,
+    },
+    /// Several trees have the same name
+    DuplicateTrees {
+        names: Vec<String>,
+    },
+    /// Two nodes of the same tree have the same identifier
+    DuplicateId {
+        tree: String,
+        id: String,
+    },
+    /// The input was expected to contain exactly one tree
+    ExpectedOneTree {
+        found: usize,
+    },
+    /// An imported file could not be loaded or parsed
+    Import {
+        path: String,
+        message: String,
+    },
+    /// Files import each other in a cycle
+    ImportCycle {
+        path: Vec<String>,
+    },
+    /// A leaf could not be resolved, with the tree and the position where it appears
+    InTree {
+        tree: String,
+        span: Span,
+        error: Box<ParseError>,
+    },
+}
+
+impl ParseError {
+    /// The error itself, without the location added by `InTree`
+    ///
+    /// The errors of the leaves are wrapped in `InTree` by both `parse` and `validate`, matching
+    /// on the result of this method finds them whatever the function that reported them.
+    pub fn root_cause(&self) -> &ParseError {
+        match *self {
+            ParseError::InTree { ref error, .. } => error.root_cause(),
+            ref other => other,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            ParseError::Lex { ref message, span } => {
+                write!(f, "Lexer error: {} at {}", message, span)
+            }
+            ParseError::UnexpectedToken { ref found, span, ref expected } => {
+                try!(write!(f, "Parsing error: unexpected token {:?} at {}", found, span));
+                if !expected.is_empty() {
+                    try!(write!(f, ", expected one of {}", expected.join(", ")));
+                }
+                Ok(())
+            }
+            ParseError::UnexpectedEof { ref expected } => {
+                try!(write!(f, "Parsing error: unexpected end of file"));
+                if !expected.is_empty() {
+                    try!(write!(f, ", expected one of {}", expected.join(", ")));
+                }
+                Ok(())
+            }
+            ParseError::UnknownLeaf { ref name } => {
+                write!(f, "Could not find leaf with name {}", name)
+            }
+            ParseError::InvalidLeafOptions { ref name, ref message } => {
+                write!(f, "Invalid options for leaf node {}: {}", name, message)
+            }
+            ParseError::UnresolvedSubtree { ref name, ref tree } => {
+                write!(f, "Reference to undefined subtree {} in tree {}", name, tree)
+            }
+            ParseError::SubtreeCycle { ref path } => {
+                write!(f, "Cycle detected in subtree references: {}", path.join(" -> "))
+            }
+            ParseError::InvalidArgument { ref message } => {
+                write!(f, "Invalid argument: {}", message)
+            }
+            ParseError::EmptyComposite { ref node } => {
+                write!(f, "Empty {}, composite nodes need at least one child", node)
+            }
+            ParseError::DuplicateTrees { ref names } => {
+                write!(f, "Several trees are named {}", names.join(", "))
+            }
+            ParseError::DuplicateId { ref tree, ref id } => {
+                write!(f, "Several nodes of tree {} are identified as #{}", tree, id)
+            }
+            ParseError::ExpectedOneTree { found } => {
+                write!(f, "Expected exactly one tree, found {}", found)
+            }
+            ParseError::Import { ref path, ref message } => {
+                write!(f, "Could not import {}: {}", path, message)
+            }
+            ParseError::ImportCycle { ref path } => {
+                write!(f, "Cycle detected in imports: {}", path.join(" -> "))
+            }
+            ParseError::InTree { ref tree, span, ref error } => {
+                write!(f, "{} in tree {} at {}", error, tree, span)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        match *self {
+            ParseError::Lex { .. } => "invalid token",
+            ParseError::UnexpectedToken { .. } => "unexpected token",
+            ParseError::UnexpectedEof { .. } => "unexpected end of file",
+            ParseError::UnknownLeaf { .. } => "unknown leaf",
+            ParseError::InvalidLeafOptions { .. } => "invalid leaf options",
+            ParseError::UnresolvedSubtree { .. } => "undefined subtree",
+            ParseError::SubtreeCycle { .. } => "cycle in subtree references",
+            ParseError::InvalidArgument { .. } => "invalid argument",
+            ParseError::EmptyComposite { .. } => "empty composite node",
+            ParseError::DuplicateTrees { .. } => "duplicate tree names",
+            ParseError::DuplicateId { .. } => "duplicate node identifier",
+            ParseError::ExpectedOneTree { .. } => "expected exactly one tree",
+            ParseError::Import { .. } => "import failed",
+            ParseError::ImportCycle { .. } => "cycle in imports",
+            ParseError::InTree { ref error, .. } => error.description(),
+        }
+    }
+}
@@ -1,16 +1,38 @@
+use std::collections::{HashMap,HashSet};
+use std::fmt::{self,Display,Formatter};
 use std::str::Chars;
 
-#[derive(Debug)]
+use parser::{Comparison,ParseError};
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Token {
     Ident(String),
     QuotedString(String),
     Integer(i64),
+    Bool(bool),
     Root,
     Subtree,
     Selector,
+    RandomSelector,
+    WeightedSelector,
     Sequence,
+    SequenceAll,
     Priority,
+    ReactiveSelector,
+    Parallel,
     Inverter,
+    Repeat,
+    UntilFail,
+    ForceSuccess,
+    Cooldown,
+    Limit,
+    Retry,
+    Budget,
+    NonBlocking,
+    StoreResult,
+    Default,
+    Import,
+    Switch,
     LeftBracket,
     RightBracket,
     Comma,
@@ -23,21 +45,77 @@ pub enum Token {
     Minus,
     Multiply,
     Divide,
+    Power,
+    Comparison(Comparison),
+    /// Reference to a parameter of the tree, written `$name`
+    Parameter(String),
+    /// Identifier of a node, written `#name`
+    NodeId(String),
+    /// Text following `//` up to the end of the line, only produced by `Tokenizer::keep_comments`
+    Comment(String),
+}
+
+/// Invalid token found by the `Tokenizer`
+#[derive(Debug,Clone,PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> ParseError {
+        ParseError::Lex { message: error.message, span: error.span }
+    }
+}
+
+/// Position of a character in the input, both starting at 1
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    fn start() -> Span {
+        Span {
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
 }
 
 struct Memory<T: Iterator> {
     inner: T,
     last_item: Option<<T as Iterator>::Item>,
     rewind: bool,
+    // Position of the next character of inner
+    position: Span,
+    // Position of last_item
+    last_position: Span,
 }
 
-impl <I,T> Iterator for Memory<T>
-where I: Copy,
-      T: Iterator<Item=I> {
+impl <T> Iterator for Memory<T>
+where T: Iterator<Item=char> {
     type Item = <T as Iterator>::Item;
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
         if !self.rewind {
             self.last_item = self.inner.next();
+            self.last_position = self.position;
+            match self.last_item {
+                Some('\n') => {
+                    self.position.line += 1;
+                    self.position.column = 1;
+                }
+                Some(_) => self.position.column += 1,
+                None => {}
+            }
         } else {
             self.rewind = false;
         }
@@ -45,14 +123,24 @@ where I: Copy,
     }
 }
 
-impl <I,T> Memory<T>
-where I: Copy,
-      T: Iterator<Item=I> {
+impl <T> Memory<T>
+where T: Iterator<Item=char> {
     fn new(iter: T) -> Memory<T> {
         Memory {
             inner: iter,
             last_item: None,
             rewind: false,
+            position: Span::start(),
+            last_position: Span::start(),
+        }
+    }
+
+    /// Position of the character that will be returned by the next call to `next`
+    fn position(&self) -> Span {
+        if self.rewind {
+            self.last_position
+        } else {
+            self.position
         }
     }
 
@@ -64,20 +152,130 @@ where I: Copy,
             panic!("The lexer was already rewinding, must be a bug");
         }
     }
+}
 
-    fn previous(&self) -> Option<<Self as Iterator>::Item> {
-        self.last_item
+/// Spelling of the keywords of the language
+///
+/// The default configuration uses the usual keywords, like `sequence` and `selector`. Other
+/// spellings can be given to them, for instance to use `all` and `any` instead:
+///
+/// ```
+/// use behaviour_tree::parser::ParserConfig;
+///
+/// let config = ParserConfig::new().rename("sequence", "all").rename("selector", "any");
+/// ```
+///
+/// Note that `TreeFactory::to_source` always writes the default keywords.
+#[derive(Debug,Clone,Default)]
+pub struct ParserConfig {
+    // Spellings of the keywords, mapped to the default keyword
+    spellings: HashMap<String,String>,
+    // Default keywords that were renamed, and are identifiers in this configuration
+    renamed: HashSet<String>,
+}
+
+impl ParserConfig {
+    pub fn new() -> ParserConfig {
+        ParserConfig::default()
+    }
+
+    /// Uses `spelling` instead of the default `keyword`, which becomes a plain identifier
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keyword` is not one of the default keywords.
+    pub fn rename(self, keyword: &str, spelling: &str) -> ParserConfig {
+        let mut config = self.alias(keyword, spelling);
+        config.renamed.insert(String::from(keyword));
+        config
+    }
+
+    /// Accepts `spelling` as well as the default `keyword`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keyword` is not one of the default keywords.
+    pub fn alias(mut self, keyword: &str, spelling: &str) -> ParserConfig {
+        if !is_keyword(keyword) {
+            panic!("Cannot give another spelling to {}, which is not a keyword", keyword);
+        }
+        self.spellings.insert(String::from(spelling), String::from(keyword));
+        self
+    }
+
+    /// Token of the keyword `word` in this configuration, if it is one
+    fn keyword(&self, word: &str) -> Option<Token> {
+        match self.spellings.get(word) {
+            Some(keyword_name) => keyword(keyword_name),
+            None if self.renamed.contains(word) => None,
+            None => keyword(word),
+        }
     }
 }
 
 pub struct Tokenizer<'a> {
     inner: Memory<Chars<'a>>,
+    // The default keywords are used without configuration
+    config: Option<&'a ParserConfig>,
+    // Produce line comments as tokens instead of skipping them
+    comments: bool,
 }
 
 impl <'a> Iterator for Tokenizer<'a> {
-    type Item = Result<Token,String>;
+    type Item = Result<(Span,Token,Span),ParseError>;
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        self.next_token().map(|result| result.map_err(ParseError::from))
+    }
+}
+
+impl <'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer {
+            inner: Memory::new(input.chars()),
+            config: None,
+            comments: false,
+        }
+    }
+
+    /// Same as `new`, the keywords being spelled as given by `config`
+    pub fn with_config(input: &'a str, config: &'a ParserConfig) -> Tokenizer<'a> {
+        Tokenizer {
+            inner: Memory::new(input.chars()),
+            config: Some(config),
+            comments: false,
+        }
+    }
+
+    /// Produces a `Token::Comment` for each `//` comment, instead of skipping them
+    ///
+    /// Block comments are still skipped.
+    pub fn keep_comments(mut self) -> Tokenizer<'a> {
+        self.comments = true;
+        self
+    }
+
+    /// Tokenizes the whole input, reporting every invalid token instead of stopping at the first
+    ///
+    /// Invalid tokens are skipped, and the tokenizer starts again right after them. This is meant
+    /// for tools like syntax highlighters, which need to make sense of inputs containing errors.
+    pub fn tokenize_all(input: &str) -> (Vec<(Span,Token,Span)>, Vec<LexError>) {
+        let mut tokenizer = Tokenizer::new(input);
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(result) = tokenizer.next_token() {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
+            }
+        }
+        (tokens, errors)
+    }
+
+    // After an error, the tokenizer is always positioned after the invalid token, so that it can
+    // be called again
+    fn next_token(&mut self) -> Option<Result<(Span,Token,Span),LexError>> {
         self.consume_whitespace();
+        let start = self.inner.position();
         let next = match self.inner.next() {
             None => return None,
             Some(c) => c,
@@ -92,8 +290,69 @@ impl <'a> Iterator for Tokenizer<'a> {
             '[' => Token::LeftArray,
             ']' => Token::RightArray,
             '+' => Token::Plus,
+            '<' => self.comparison(Comparison::Less, Comparison::LessOrEqual),
+            '>' => self.comparison(Comparison::Greater, Comparison::GreaterOrEqual),
+            '=' => Token::Comparison(Comparison::Equal),
             '*' => Token::Multiply,
-            '/' => Token::Divide,
+            '^' => Token::Power,
+            '!' => {
+                match self.inner.next() {
+                    Some('=') => Token::Comparison(Comparison::NotEqual),
+                    _ => {
+                        self.inner.rewind();
+                        let message = String::from("expected = after !");
+                        return Some(Err(LexError { message: message, span: start }));
+                    }
+                }
+            }
+            '`' => {
+                match self.parse_escaped_word() {
+                    Ok(token) => token,
+                    Err(message) => {
+                        return Some(Err(LexError { message: message, span: start }));
+                    }
+                }
+            }
+            '$' => {
+                let name: String = self.inner.by_ref().take_while(is_valid_id).collect();
+                self.inner.rewind();
+                if name.is_empty() {
+                    let message = String::from("expected a parameter name after $");
+                    return Some(Err(LexError { message: message, span: start }));
+                }
+                Token::Parameter(name)
+            }
+            '#' => {
+                let name: String = self.inner.by_ref().take_while(is_valid_id).collect();
+                self.inner.rewind();
+                if name.is_empty() {
+                    let message = String::from("expected a node identifier after #");
+                    return Some(Err(LexError { message: message, span: start }));
+                }
+                Token::NodeId(name)
+            }
+            '/' => {
+                // Either the start of a comment or a division
+                match self.inner.next() {
+                    Some('/') => {
+                        let comment = self.consume_line_comment();
+                        if !self.comments {
+                            return self.next_token();
+                        }
+                        Token::Comment(comment)
+                    }
+                    Some('*') => {
+                        if let Err(e) = self.consume_block_comment(start) {
+                            return Some(Err(e));
+                        }
+                        return self.next_token();
+                    }
+                    _ => {
+                        self.inner.rewind();
+                        Token::Divide
+                    }
+                }
+            }
             c if c.is_alphabetic() => {
                 self.inner.rewind();
                 self.parse_word()
@@ -101,21 +360,35 @@ impl <'a> Iterator for Tokenizer<'a> {
             c if c == '"' => {
                 match self.parse_quoted_string() {
                     Ok(token) => token,
-                    Err(e) => return Some(Err(e)),
+                    Err(message) => {
+                        return Some(Err(LexError { message: message, span: start }));
+                    }
                 }
             }
             c if c.is_numeric() => {
                 self.inner.rewind();
-                Token::Integer(self.parse_number())
+                match self.parse_number(false) {
+                    Ok(number) => Token::Integer(number),
+                    Err(message) => {
+                        return Some(Err(LexError { message: message, span: start }));
+                    }
+                }
             }
             '-' => {
                 // Special case for - : it can be an operator in an expression or a negative number
-                // They can be differenciated by the following character
+                // They can be differenciated by the following character: `-2` is a number, while
+                // `- 2` or `-]` start with an operator. Only ASCII digits count, other numeric
+                // characters like '½' are not part of a number.
                 match self.inner.next() {
-                    Some(c) if c.is_numeric() => {
+                    Some(c) if c.is_digit(10) => {
                         // Negative number
                         self.inner.rewind();
-                        Token::Integer(-self.parse_number())
+                        match self.parse_number(true) {
+                            Ok(number) => Token::Integer(number),
+                            Err(message) => {
+                                return Some(Err(LexError { message: message, span: start }));
+                            }
+                        }
                     }
                     _ => {
                         self.inner.rewind();
@@ -123,16 +396,22 @@ impl <'a> Iterator for Tokenizer<'a> {
                     }
                 }
             }
-            other => return Some(Err(format!("Unrecognized character {}", other))),
+            other => {
+                let message = format!("Unrecognized character {}", other);
+                return Some(Err(LexError { message: message, span: start }));
+            }
         };
-        Some(Ok(token))
+        Some(Ok((start, token, self.inner.position())))
     }
-}
 
-impl <'a> Tokenizer<'a> {
-    pub fn new(input: &'a str) -> Tokenizer<'a> {
-        Tokenizer {
-            inner: Memory::new(input.chars())
+    // Comparison starting with the character just read, `or_equal` if it is followed by =
+    fn comparison(&mut self, alone: Comparison, or_equal: Comparison) -> Token {
+        match self.inner.next() {
+            Some('=') => Token::Comparison(or_equal),
+            _ => {
+                self.inner.rewind();
+                Token::Comparison(alone)
+            }
         }
     }
 
@@ -141,64 +420,433 @@ impl <'a> Tokenizer<'a> {
         self.inner.rewind();
     }
 
+    // Consumes everything until the end of the line
+    // Returns the text of the comment, the opening // being already consumed
+    fn consume_line_comment(&mut self) -> String {
+        let mut comment: String = self.inner.by_ref().take_while(|&c| c != '\n').collect();
+        if comment.ends_with('\r') {
+            comment.pop();
+        }
+        comment
+    }
+
+    // Consumes everything until the first */, the opening /* being already consumed
+    fn consume_block_comment(&mut self, start: Span) -> Result<(),LexError> {
+        let mut previous = None;
+        for c in self.inner.by_ref() {
+            if previous == Some('*') && c == '/' {
+                return Ok(());
+            }
+            previous = Some(c);
+        }
+        Err(LexError { message: String::from("unterminated block comment"), span: start })
+    }
+
     fn parse_word(&mut self) -> Token {
         let word: String = self.inner.by_ref().take_while(is_valid_id).collect();
         self.inner.rewind();
-        match word.as_ref() {
-            "tree" => return Token::Root,
-            "subtree" => return Token::Subtree,
-            "selector" => return Token::Selector,
-            "sequence" => return Token::Sequence,
-            "inverter" => return Token::Inverter,
-            "priority" => return Token::Priority,
-            _ => {}
-        }
         assert!(word.len() != 0);
-        Token::Ident(word)
+        let token = match self.config {
+            Some(config) => config.keyword(&word),
+            None => keyword(&word),
+        };
+        token.unwrap_or(Token::Ident(word))
+    }
+
+    // Parses an identifier escaped with backticks, the opening one being already consumed
+    fn parse_escaped_word(&mut self) -> Result<Token,String> {
+        let word: String = self.inner.by_ref().take_while(is_valid_id).collect();
+        self.inner.rewind();
+        match self.inner.next() {
+            Some('`') if !word.is_empty() => Ok(Token::Ident(word)),
+            Some('`') => Err(String::from("empty escaped identifier")),
+            Some(c) => {
+                self.inner.rewind();
+                Err(format!("invalid character {:?} in escaped identifier", c))
+            }
+            None => Err(String::from("unterminated escaped identifier")),
+        }
     }
 
-    fn parse_number(&mut self) -> i64 {
-        let number_str: String = self.inner.by_ref().take_while(|&c| c.is_numeric()).collect();
+    // Parses a decimal, hexadecimal (0x) or binary (0b) literal. The sign is parsed along with the
+    // digits, so that i64::MIN can be written
+    fn parse_number(&mut self, negative: bool) -> Result<i64,String> {
+        let sign = if negative { "-" } else { "" };
+        let mut digits = String::from(sign);
+        let mut radix = 10;
+        let mut prefix = "";
+        match self.inner.next() {
+            Some('0') => {
+                match self.inner.next() {
+                    Some('x') => { radix = 16; prefix = "0x"; }
+                    Some('b') => { radix = 2; prefix = "0b"; }
+                    _ => {
+                        self.inner.rewind();
+                        digits.push('0');
+                    }
+                }
+            }
+            Some(c) => digits.push(c),
+            None => unreachable!("parse_number called at the end of the input"),
+        }
+        digits.extend(self.inner.by_ref().take_while(|&c| c.is_digit(radix)));
         self.inner.rewind();
-        let number = i64::from_str_radix(&number_str, 10).unwrap();
-        number
+        if digits.len() == sign.len() {
+            return Err(format!("missing digits after {}{}", sign, prefix));
+        }
+        i64::from_str_radix(&digits, radix).map_err(|_| {
+            format!("integer literal {}{}{} does not fit in 64 bits", sign, prefix, &digits[sign.len()..])
+        })
     }
 
+    // Reads the string up to its closing quote, the opening one being already consumed. Every
+    // character is read once, so this always ends, with an error at the end of the input.
     fn parse_quoted_string(&mut self) -> Result<Token,String> {
         let mut res = String::new();
         loop {
-            res.extend(self.inner.by_ref().take_while(|&c| c != '"' && c != '\\'));
-            match self.inner.previous() {
-                Some('\\') => {
-                    match self.inner.next() {
-                        Some('\\') => res.push('\\'),
-                        Some('n') => res.push('\n'),
-                        Some('"') => res.push('"'),
-                        Some('t') => res.push('\t'),
-                        Some(other) => {
-                            println!("Lexer: unnecessary escape for character {}", other);
-                            res.push(other);
-                        }
-                        None => {
-                            return Err(String::from("Lexer error: unfinished quoted string during escape sequence"));
-                        }
+            match self.inner.next() {
+                Some('"') => return Ok(Token::QuotedString(res)),
+                Some('\\') => res.push(try!(self.parse_escape())),
+                Some(c) => res.push(c),
+                None => return Err(String::from("unfinished quoted string")),
+            }
+        }
+    }
+
+    // Parses an escape sequence of a quoted string, the backslash being already consumed. Escaping
+    // any other character is unnecessary but allowed, `\'` being read as `'`
+    fn parse_escape(&mut self) -> Result<char,String> {
+        let c = match self.inner.next() {
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('"') => '"',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('u') => {
+                match self.parse_unicode_escape() {
+                    Ok(c) => c,
+                    Err(message) => {
+                        // Skip the rest of the string, so that it is not read as code
+                        self.consume_quoted_string();
+                        return Err(message);
                     }
-                },
-                Some('"') => break,
-                Some(..) => {
-                    println!("Oops, a weird thing happened");
-                    break;
                 }
-                None => {
-                    return Err(String::from("Lexer error: unfinished quoted string"));
+            }
+            Some(other) => other,
+            None => return Err(String::from("unfinished quoted string during escape sequence")),
+        };
+        Ok(c)
+    }
+
+    // Consumes everything until the end of the current quoted string, the escape sequences included
+    fn consume_quoted_string(&mut self) {
+        while let Some(c) = self.inner.next() {
+            match c {
+                '\\' => { self.inner.next(); }
+                '"' => break,
+                _ => {}
+            }
+        }
+    }
+
+    // Parses the {XXXX} part of a \u{XXXX} escape, XXXX being 1 to 6 hexadecimal digits
+    fn parse_unicode_escape(&mut self) -> Result<char,String> {
+        if self.inner.next() != Some('{') {
+            self.inner.rewind();
+            return Err(String::from("expected { after \\u in escape sequence"));
+        }
+        let mut digits = String::new();
+        loop {
+            match self.inner.next() {
+                Some('}') => break,
+                Some(c) if c.is_digit(16) && digits.len() < 6 => digits.push(c),
+                Some(c) => {
+                    // The character may be the end of the string
+                    self.inner.rewind();
+                    return Err(format!("invalid character {:?} in unicode escape", c));
                 }
+                None => return Err(String::from("unfinished unicode escape")),
             }
         }
-        Ok(Token::QuotedString(res))
+        if digits.is_empty() {
+            return Err(String::from("empty unicode escape"));
+        }
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        ::std::char::from_u32(code).ok_or_else(|| {
+            format!("invalid unicode code point {:X} in escape", code)
+        })
     }
 }
 
+// Token of the keyword `word`, if it is one
+fn keyword(word: &str) -> Option<Token> {
+    let token = match word {
+        "tree" => Token::Root,
+        "subtree" => Token::Subtree,
+        "selector" => Token::Selector,
+        "random_selector" => Token::RandomSelector,
+        "weighted_selector" => Token::WeightedSelector,
+        "sequence" => Token::Sequence,
+        "sequence_all" => Token::SequenceAll,
+        "inverter" => Token::Inverter,
+        "priority" => Token::Priority,
+        "reactive_selector" => Token::ReactiveSelector,
+        "parallel" => Token::Parallel,
+        "repeat" => Token::Repeat,
+        "until_fail" => Token::UntilFail,
+        "force_success" => Token::ForceSuccess,
+        "cooldown" => Token::Cooldown,
+        "limit" => Token::Limit,
+        "retry" => Token::Retry,
+        "budget" => Token::Budget,
+        "non_blocking" => Token::NonBlocking,
+        "store_result" => Token::StoreResult,
+        "default" => Token::Default,
+        "import" => Token::Import,
+        "switch" => Token::Switch,
+        "true" => Token::Bool(true),
+        "false" => Token::Bool(false),
+        _ => return None,
+    };
+    Some(token)
+}
+
+/// Whether `word` is a keyword of the language
+///
+/// Keywords can still be used as identifiers, for instance as leaf names, by escaping them with
+/// backticks: `` `sequence` ``.
+pub fn is_keyword(word: &str) -> bool {
+    keyword(word).is_some()
+}
+
 fn is_valid_id(&c: &char) -> bool {
     c.is_alphanumeric() || c == '_'
 }
 
+
+#[cfg(test)]
+mod test {
+    use parser::{Comparison,ParseError};
+    use super::{LexError,Token,Tokenizer};
+
+    fn tokenize(input: &str) -> Result<Vec<Token>,ParseError> {
+        Tokenizer::new(input).map(|r| r.map(|(_, token, _)| token)).collect()
+    }
+
+    fn ident(name: &str) -> Token {
+        Token::Ident(String::from(name))
+    }
+
+    #[test]
+    fn comment_at_end_of_file() {
+        assert_eq!(tokenize("leaf // trailing comment").unwrap(), [ident("leaf")]);
+        assert_eq!(tokenize("leaf /* trailing comment */").unwrap(), [ident("leaf")]);
+    }
+
+    #[test]
+    fn comment_between_leaves() {
+        let expected = [ident("a"), Token::Comma, ident("b"), Token::Comma, ident("c")];
+        assert_eq!(tokenize("a, // first\nb, /* second\n */ c").unwrap(), expected);
+    }
+
+    #[test]
+    fn kept_comments() {
+        let tokens: Vec<_> = Tokenizer::new("a, // first\r\nb, /* second */ c //").keep_comments()
+            .map(|r| r.unwrap()).collect();
+        let comment = |text: &str| Token::Comment(String::from(text));
+        let expected = [ident("a"), Token::Comma, comment(" first"), ident("b"), Token::Comma,
+                        ident("c"), comment("")];
+        assert_eq!(tokens.iter().map(|&(_, ref token, _)| token.clone()).collect::<Vec<_>>(), expected);
+        assert_eq!((tokens[2].0.line, tokens[2].0.column), (1, 4));
+    }
+
+    #[test]
+    fn divide_is_not_a_comment() {
+        assert_eq!(tokenize("a / b").unwrap(), [ident("a"), Token::Divide, ident("b")]);
+        assert_eq!(tokenize("a /").unwrap(), [ident("a"), Token::Divide]);
+    }
+
+    #[test]
+    fn not_equal() {
+        let not_equal = Token::Comparison(Comparison::NotEqual);
+        assert_eq!(tokenize("a != b").unwrap(), [ident("a"), not_equal, ident("b")]);
+        // A lone ! is not an operator
+        assert!(tokenize("!a").is_err());
+        let (tokens, errors) = tokenize_all("!a ! =");
+        assert_eq!(tokens, [ident("a"), Token::Comparison(Comparison::Equal)]);
+        let positions: Vec<(usize,usize)> = errors.iter().map(|e| (e.span.line, e.span.column)).collect();
+        assert_eq!(positions, [(1, 1), (1, 4)]);
+        assert_eq!(errors[0].message, "expected = after !");
+    }
+
+    #[test]
+    fn comparisons() {
+        let tokens = tokenize("> >= < <= = > =").unwrap();
+        let expected = [Comparison::Greater, Comparison::GreaterOrEqual, Comparison::Less,
+                        Comparison::LessOrEqual, Comparison::Equal, Comparison::Greater,
+                        Comparison::Equal];
+        assert_eq!(tokens, expected.iter().map(|&c| Token::Comparison(c)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn block_comment_edge_cases() {
+        // The slash of /*/ does not close the comment
+        assert_eq!(tokenize("a /*/ b */ c").unwrap(), [ident("a"), ident("c")]);
+        assert_eq!(tokenize("a /**/ c").unwrap(), [ident("a"), ident("c")]);
+        assert_eq!(tokenize("a /*** b ***/ c").unwrap(), [ident("a"), ident("c")]);
+        match tokenize("a /*/") {
+            Err(ParseError::Lex { span, .. }) => assert_eq!((span.line, span.column), (1, 3)),
+            other => panic!("Expected lexer error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn integer_limits() {
+        assert_eq!(tokenize("9223372036854775807").unwrap(), [Token::Integer(i64::max_value())]);
+        assert_eq!(tokenize("-9223372036854775808").unwrap(), [Token::Integer(i64::min_value())]);
+        match tokenize("9223372036854775808") {
+            Err(ParseError::Lex { span, .. }) => assert_eq!((span.line, span.column), (1, 1)),
+            other => panic!("Expected a lexer error, got {:?}", other),
+        }
+        assert!(tokenize("-9223372036854775809").is_err());
+    }
+
+    #[test]
+    fn hex_and_binary_literals() {
+        assert_eq!(tokenize("0xFF").unwrap(), [Token::Integer(255)]);
+        assert_eq!(tokenize("0xff").unwrap(), [Token::Integer(255)]);
+        assert_eq!(tokenize("0b1010").unwrap(), [Token::Integer(10)]);
+        assert_eq!(tokenize("-0x10").unwrap(), [Token::Integer(-16)]);
+        assert_eq!(tokenize("0 07").unwrap(), [Token::Integer(0), Token::Integer(7)]);
+        assert_eq!(tokenize("0x7FFFFFFFFFFFFFFF").unwrap(), [Token::Integer(i64::max_value())]);
+        assert!(tokenize("0x8000000000000000").is_err());
+    }
+
+    #[test]
+    fn malformed_prefixed_literals() {
+        match tokenize("0x") {
+            Err(ParseError::Lex { message, .. }) => assert!(message.contains("0x"), "{}", message),
+            other => panic!("Expected a lexer error, got {:?}", other),
+        }
+        assert!(tokenize("0b }").is_err());
+        assert!(tokenize("-0x").is_err());
+    }
+
+    fn quoted(string: &str) -> Token {
+        Token::QuotedString(String::from(string))
+    }
+
+    #[test]
+    fn unicode_escape() {
+        assert_eq!(tokenize(r#""smile \u{1F600}!""#).unwrap(), [quoted("smile \u{1F600}!")]);
+        assert_eq!(tokenize(r#""\u{e9}t\u{E9}""#).unwrap(), [quoted("été")]);
+    }
+
+    #[test]
+    fn invalid_unicode_escape() {
+        // Surrogates and values above 10FFFF are not code points
+        assert!(tokenize(r#""\u{D800}""#).is_err());
+        assert!(tokenize(r#""\u{110000}""#).is_err());
+        assert!(tokenize(r#""\u{1234567}""#).is_err());
+        assert!(tokenize(r#""\u{}""#).is_err());
+        assert!(tokenize(r#""\u1F600""#).is_err());
+        assert!(tokenize(r#""\u{1F600"#).is_err());
+    }
+
+    #[test]
+    fn carriage_return_escape() {
+        assert_eq!(tokenize(r#""a\r\nb""#).unwrap(), [quoted("a\r\nb")]);
+    }
+
+    #[test]
+    fn unnecessary_escape() {
+        assert_eq!(tokenize(r#""it\'s \a \{""#).unwrap(), [quoted("it's a {")]);
+        assert_eq!(tokenize(r#""\é""#).unwrap(), [quoted("é")]);
+    }
+
+    #[test]
+    fn parameter() {
+        let expected = [Token::LeftParenthesis, Token::Parameter(String::from("speed_2")), Token::RightParenthesis];
+        assert_eq!(tokenize("($speed_2)").unwrap(), expected);
+        assert!(tokenize("$ speed").is_err());
+    }
+
+    #[test]
+    fn node_id() {
+        let expected = [Token::Sequence, Token::NodeId(String::from("patrol_2")), Token::LeftBracket];
+        assert_eq!(tokenize("sequence #patrol_2 {").unwrap(), expected);
+        assert!(tokenize("sequence # patrol").is_err());
+    }
+
+    fn tokenize_all(input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let (tokens, errors) = Tokenizer::tokenize_all(input);
+        (tokens.into_iter().map(|(_, token, _)| token).collect(), errors)
+    }
+
+    #[test]
+    fn all_errors() {
+        let (tokens, errors) = tokenize_all("a & b\n# c(99999999999999999999, $)");
+        assert_eq!(tokens, [ident("a"), ident("b"), ident("c"), Token::LeftParenthesis, Token::Comma,
+                            Token::RightParenthesis]);
+        let positions: Vec<(usize,usize)> = errors.iter().map(|e| (e.span.line, e.span.column)).collect();
+        assert_eq!(positions, [(1, 3), (2, 1), (2, 5), (2, 27)]);
+        assert!(errors[2].message.contains("99999999999999999999"), "{}", errors[2].message);
+    }
+
+    #[test]
+    fn errors_in_strings() {
+        // The rest of a string with an invalid escape is not read as code
+        let (tokens, errors) = tokenize_all(r#"a("\u{D800} b", "\u{x} c", "\u" d) "e"#);
+        assert_eq!(tokens, [ident("a"), Token::LeftParenthesis, Token::Comma, Token::Comma, ident("d"),
+                            Token::RightParenthesis]);
+        assert_eq!(errors.len(), 4);
+        assert_eq!(errors[3].message, "unfinished quoted string");
+    }
+
+    #[test]
+    fn escaped_keyword() {
+        assert_eq!(tokenize("`sequence` sequence").unwrap(), [ident("sequence"), Token::Sequence]);
+        assert_eq!(tokenize("`my_leaf2`").unwrap(), [ident("my_leaf2")]);
+        for input in ["``", "`sequence", "`two words`"].iter() {
+            assert!(tokenize(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn unfinished_strings() {
+        let cases = [
+            (r#""\"#, "unfinished quoted string during escape sequence"),
+            (r#""abc"#, "unfinished quoted string"),
+            (r#""a\"#, "unfinished quoted string during escape sequence"),
+            (r#""a\""#, "unfinished quoted string"),
+        ];
+        for &(input, message) in cases.iter() {
+            let (tokens, errors) = tokenize_all(input);
+            assert!(tokens.is_empty(), "{}", input);
+            let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+            assert_eq!(messages, [message], "{}", input);
+        }
+        // An escaped quote right before the closing one
+        assert_eq!(tokenize(r#""a\"""#).unwrap(), [Token::QuotedString(String::from("a\""))]);
+        assert_eq!(tokenize(r#""\\""#).unwrap(), [Token::QuotedString(String::from("\\"))]);
+    }
+
+    #[test]
+    fn minus_in_arrays() {
+        let array = |tokens: Vec<Token>| {
+            let mut all = vec![Token::LeftArray];
+            all.extend(tokens);
+            all.push(Token::RightArray);
+            all
+        };
+        assert_eq!(tokenize("[5 -2 -]").unwrap(), array(vec![Token::Integer(5), Token::Integer(-2), Token::Minus]));
+        assert_eq!(tokenize("[5 2 -]").unwrap(), array(vec![Token::Integer(5), Token::Integer(2), Token::Minus]));
+        assert_eq!(tokenize("[5 - 2]").unwrap(), array(vec![Token::Integer(5), Token::Minus, Token::Integer(2)]));
+        assert_eq!(tokenize("[5, -0x10, -]").unwrap(),
+                   array(vec![Token::Integer(5), Token::Comma, Token::Integer(-16), Token::Comma, Token::Minus]));
+        // A numeric character that is not a digit does not make a negative number
+        let (tokens, errors) = tokenize_all("-½");
+        assert_eq!(tokens, [Token::Minus]);
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -1,98 +1,1550 @@
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
+use std::fmt::{self,Display,Formatter};
+use std::mem;
 
-use tree::factory::{TreeFactory,NodeFactory};
-use self::ast::Node;
+use lalrpop_util::ParseError as LalrpopError;
+
+use tree::factory::{self,LeafFactory,TreeFactory,NodeFactory};
+use tree::NonBlockingResult;
+use self::ast::{Tree,Node};
 
 mod parser;
-mod ast;
+pub mod ast;
+pub mod collection;
 mod lexer;
+mod error;
+mod template;
+pub mod options;
 
-pub use self::lexer::{Token,Tokenizer};
+pub use self::lexer::{LexError,ParserConfig,Span,Token,Tokenizer,is_keyword};
+pub use self::error::ParseError;
+pub use self::options::{FromOptions,FromValue,OptionsError};
+#[cfg(feature = "derive")]
+pub use behaviour_tree_derive::FromOptions;
 
-#[derive(Debug,Clone,Copy)]
+#[derive(Debug,Clone,Copy,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Operator {
     Plus,
     Minus,
     Multiply,
     Divide,
+    Power,
+}
+
+/// Comparison operator, used by conditions
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Comparison {
+    Greater,
+    Less,
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    LessOrEqual,
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Value {
     String(String),
     Map(HashMap<String,Value>),
     Array(Vec<Value>),
     Integer(i64),
+    Bool(bool),
     Operator(Operator),
+    Comparison(Comparison),
+    /// Parameter of a tree, replaced by the value of the argument when the tree is invoked
+    Parameter(String),
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        let symbol = match *self {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Power => "^",
+        };
+        f.write_str(symbol)
+    }
 }
 
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        let symbol = match *self {
+            Comparison::Greater => ">",
+            Comparison::Less => "<",
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::LessOrEqual => "<=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+/// Formats the value using the syntax of the tree description language
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            Value::String(ref string) => {
+                try!(f.write_str("\""));
+                for c in string.chars() {
+                    match c {
+                        '\\' => try!(f.write_str("\\\\")),
+                        '"' => try!(f.write_str("\\\"")),
+                        '\n' => try!(f.write_str("\\n")),
+                        '\t' => try!(f.write_str("\\t")),
+                        c => try!(write!(f, "{}", c)),
+                    }
+                }
+                f.write_str("\"")
+            }
+            Value::Map(ref map) => {
+                if map.is_empty() {
+                    return f.write_str("{}");
+                }
+                // Sorted to get a stable output
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                try!(f.write_str("{ "));
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i != 0 {
+                        try!(f.write_str(", "));
+                    }
+                    try!(write!(f, "{}: {}", key, map[key]));
+                }
+                f.write_str(" }")
+            }
+            Value::Array(ref array) => {
+                try!(f.write_str("["));
+                for value in array.iter() {
+                    try!(write!(f, " {}", value));
+                }
+                f.write_str(" ]")
+            }
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Operator(op) => write!(f, "{}", op),
+            Value::Comparison(comparison) => write!(f, "{}", comparison),
+            Value::Parameter(ref name) => write!(f, "${}", name),
+        }
+    }
+}
+
+/// Provides the leaves of the parsed trees, by name
+///
+/// Besides the leaves written in the trees, two names are requested for the nodes built on a
+/// leaf: `switch` nodes get their selector from the leaf named `switch`, given the options of the
+/// node, and `store_result` nodes get their writer from the leaf named `store_result`, without
+/// options. Trees using these nodes fail to parse if the producer does not provide these leaves.
 pub trait FactoryProducer {
     type Factory;
-    fn generate_leaf(&self, name: &str, option: &Option<Value>) -> Result<Self::Factory,String>;
+    fn generate_leaf(&self, name: &str, option: &Option<Value>) -> Result<Self::Factory,ParseError>;
 }
 
 pub fn parse<T: ?Sized>(
     input: &str,
     leaves: &T,
-    ) -> Result<Vec<TreeFactory<T::Factory>>,String>
+    ) -> Result<Vec<TreeFactory<T::Factory>>,ParseError>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    let trees = try!(parse_ast(input));
+    resolve(trees, leaves)
+}
+
+/// Same as `parse`, the keywords being spelled as given by `config`
+pub fn parse_with_config<T: ?Sized>(
+    input: &str,
+    leaves: &T,
+    config: &ParserConfig,
+    ) -> Result<Vec<TreeFactory<T::Factory>>,ParseError>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    let trees = try!(parse_ast_with_config(input, config));
+    resolve(trees, leaves)
+}
+
+/// Same as `parse`, for inputs containing a single tree
+///
+/// Returns an error if the input contains no tree or more than one.
+pub fn parse_one<T: ?Sized>(
+    input: &str,
+    leaves: &T,
+    ) -> Result<TreeFactory<T::Factory>,ParseError>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    let mut trees = try!(parse(input, leaves));
+    if trees.len() != 1 {
+        return Err(ParseError::ExpectedOneTree { found: trees.len() });
+    }
+    Ok(trees.remove(0))
+}
+
+/// Checks the input like `parse`, but reports every error instead of stopping at the first one
+///
+/// All the leaves are created, which validates their options, and the resolved trees are then
+/// checked with `TreeFactory::validate`. Nothing is run. Errors that prevent reading the rest of
+/// the input, like syntax errors, are reported alone.
+///
+/// As with `parse`, the errors of the leaves are wrapped in `ParseError::InTree`, use
+/// `ParseError::root_cause` to match on them.
+pub fn validate<T: ?Sized>(input: &str, leaves: &T) -> Result<(),Vec<ParseError>>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    let trees = try!(parse_ast(input).map_err(|error| vec![error]));
+    try!(check_duplicates(&trees).map_err(|error| vec![error]));
+    let expanded = try!(template::expand(trees.clone()).map_err(|error| vec![error]));
+    let mut errors = Vec::new();
+    for tree in expanded.iter() {
+        validate_node(&tree.root, &tree.name, leaves, &mut errors);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let factories = try!(resolve(trees, leaves).map_err(|error| vec![error]));
+    for factory in factories.iter() {
+        if let Err(messages) = factory.validate() {
+            errors.extend(messages.into_iter().map(|message| {
+                let message = format!("{} in tree {}", message, factory.get_name());
+                ParseError::InvalidArgument { message: message }
+            }));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// Locates the error of the leaf `name` in `tree`
+//
+// Errors converted from `OptionsError` do not know the name of their leaf, it is filled in here.
+fn leaf_error(mut error: ParseError, name: &str, tree: &str, span: Span) -> ParseError {
+    if let ParseError::InvalidLeafOptions { name: ref mut leaf, .. } = error {
+        if leaf.is_empty() {
+            *leaf = String::from(name);
+        }
+    }
+    ParseError::InTree { tree: String::from(tree), span: span, error: Box::new(error) }
+}
+
+// Creates the leaves of the node and its descendants, adding the errors to `errors`
+//
+// The errors of the leaves are located in `tree`, as when resolving the trees.
+fn validate_node<T: ?Sized>(node: &Node, tree: &str, leaves: &T, errors: &mut Vec<ParseError>)
+where T: FactoryProducer {
+    if let Some(keyword) = empty_composite(node) {
+        errors.push(ParseError::EmptyComposite { node: String::from(keyword) });
+    }
+    let leaf = match *node {
+        Node::Leaf(ref name, ref options, span) => Some((name.as_str(), options, span)),
+        Node::Switch(ref options, _, span) => Some(("switch", options, span)),
+        Node::StoreResult(_, _, span) => Some(("store_result", &None, span)),
+        _ => None,
+    };
+    if let Some((name, options, span)) = leaf {
+        if let Err(error) = leaves.generate_leaf(name, options) {
+            errors.push(leaf_error(error, name, tree, span));
+        }
+    }
+    for child in node.children() {
+        validate_node(child, tree, leaves, errors);
+    }
+}
+
+/// Same as `parse`, but parses and resolves the trees one at a time, as they are iterated
+///
+/// A tree can be used before the rest of the input is read, and only one tree is held in memory
+/// by the parser. The iteration stops after the first error. As each tree is resolved on its own,
+/// trees using a subtree or a template fail with `ParseError::UnresolvedSubtree`: use `parse` for
+/// them. Templates themselves are skipped, and imports are rejected as with `parse`.
+pub fn parse_iter<'a, T: ?Sized>(input: &'a str, leaves: &'a T) -> TreeIter<'a, T>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    TreeIter {
+        tokenizer: Tokenizer::new(input).keep_comments(),
+        leaves: leaves,
+        names: HashSet::new(),
+        finished: false,
+    }
+}
+
+/// Iterator over the trees of an input, returned by `parse_iter`
+pub struct TreeIter<'a, T: ?Sized + 'a> {
+    tokenizer: Tokenizer<'a>,
+    leaves: &'a T,
+    // Names of the trees already parsed, to detect duplicates
+    names: HashSet<String>,
+    finished: bool,
+}
+
+impl <'a, T: ?Sized> TreeIter<'a, T> {
+    fn fail<F>(&mut self, error: ParseError) -> Option<Result<F,ParseError>> {
+        self.finished = true;
+        Some(Err(error))
+    }
+}
+
+impl <'a, T: ?Sized> Iterator for TreeIter<'a, T>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    type Item = Result<TreeFactory<T::Factory>,ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            let trees = match parse_tokens(&mut self.tokenizer, true).and_then(reject_imports) {
+                Ok(trees) => trees,
+                Err(error) => return self.fail(error),
+            };
+            // Only the end of the input contains no tree
+            if trees.is_empty() {
+                self.finished = true;
+                break;
+            }
+            for tree in trees.iter() {
+                if !self.names.insert(tree.name.clone()) {
+                    return self.fail(ParseError::DuplicateTrees { names: vec![tree.name.clone()] });
+                }
+            }
+            match resolve(trees, self.leaves) {
+                Ok(mut factories) => {
+                    // Nothing is produced for a template
+                    if let Some(factory) = factories.pop() {
+                        return Some(Ok(factory));
+                    }
+                }
+                Err(error) => return self.fail(error),
+            }
+        }
+        None
+    }
+}
+
+/// Same as `parse`, replacing the leaves with an unknown name by an `always_failure` leaf
+///
+/// Meant for editing trees: the rest of the input is still loaded, and a warning is returned for
+/// each replaced leaf. `leaves` must know `always_failure`, as the standard leaves do. Any other
+/// error stops the parse, and is returned as the only warning, with no tree.
+pub fn parse_lenient<T: ?Sized>(
+    input: &str,
+    leaves: &T,
+    ) -> (Vec<TreeFactory<T::Factory>>,Vec<String>)
+where T: FactoryProducer,
+      T::Factory: Clone {
+    let mut warnings = Vec::new();
+    let result = parse_ast(input)
+        .and_then(|trees| { try!(check_duplicates(&trees)); template::expand(trees) })
+        .and_then(|mut trees| {
+            for tree in trees.iter_mut() {
+                replace_unknown_leaves(&mut tree.root, leaves, &tree.name, &mut warnings);
+            }
+            resolve(trees, leaves)
+        });
+    match result {
+        Ok(trees) => (trees, warnings),
+        Err(error) => (Vec::new(), vec![error.to_string()]),
+    }
+}
+
+// Replaces the leaves of the node and its descendants that `leaves` does not know
+fn replace_unknown_leaves<T: ?Sized>(node: &mut Node, leaves: &T, tree: &str, warnings: &mut Vec<String>)
 where T: FactoryProducer {
-    let tokenizer = Tokenizer::new(input);
-    let tokenizer_mapped = tokenizer.map(|e| {
-        e.map(|token| ((),token,()))
-    });
-    let trees = match parser::parse_TreeCollection(tokenizer_mapped) {
-        Ok(t) => t,
-        Err(e) => {
-            println!("Error: {:#?}", e);
-            return Err(format!("Parsing error {:#?}", e));
+    let unknown = match *node {
+        Node::Leaf(ref name, ref options, span) => {
+            match leaves.generate_leaf(name, options) {
+                Err(ParseError::UnknownLeaf { .. }) => {
+                    warnings.push(format!("Unknown leaf {} in tree {}, replaced by always_failure",
+                                          name, tree));
+                    Some(span)
+                }
+                _ => None,
+            }
         }
+        _ => None,
     };
+    if let Some(span) = unknown {
+        *node = Node::Leaf(String::from("always_failure"), None, span);
+    }
+    for child in node.children_mut() {
+        replace_unknown_leaves(child, leaves, tree, warnings);
+    }
+}
+
+/// Same as `parse`, with support for `import "path"` directives
+///
+/// The `resolver` is given the path of each imported file, and returns its content. The trees of
+/// the imported files are added before the trees of the input, so they can be used as subtrees.
+/// A file imported several times is only loaded once.
+pub fn parse_with_resolver<T: ?Sized, R>(
+    input: &str,
+    leaves: &T,
+    resolver: R,
+    ) -> Result<Vec<TreeFactory<T::Factory>>,ParseError>
+where T: FactoryProducer,
+      T::Factory: Clone,
+      R: Fn(&str) -> Result<String,String> {
+    let mut trees = Vec::new();
+    try!(load_imports(input, &resolver, &mut Vec::new(), &mut HashSet::new(), &mut trees));
+    resolve(trees, leaves)
+}
+
+// Parses `input` and appends its trees to `trees`, after those of the files it imports. `stack`
+// contains the files being imported, to detect cycles.
+fn load_imports<R>(input: &str, resolver: &R, stack: &mut Vec<String>, loaded: &mut HashSet<String>,
+                   trees: &mut Vec<Tree>) -> Result<(),ParseError>
+where R: Fn(&str) -> Result<String,String> {
+    let (imports, new_trees) = try!(parse_source(Tokenizer::new(input)));
+    for path in imports {
+        if stack.contains(&path) {
+            let mut cycle = stack.clone();
+            cycle.push(path);
+            return Err(ParseError::ImportCycle { path: cycle });
+        }
+        if loaded.contains(&path) {
+            continue;
+        }
+        let content = try!(resolver(&path).map_err(|message| {
+            ParseError::Import { path: path.clone(), message: message }
+        }));
+        stack.push(path.clone());
+        match load_imports(&content, resolver, stack, loaded, trees) {
+            Ok(()) => {}
+            // Errors of the imported file itself are reported with its path
+            Err(error @ ParseError::Import { .. }) | Err(error @ ParseError::ImportCycle { .. }) => {
+                return Err(error);
+            }
+            Err(error) => return Err(ParseError::Import { path: path, message: error.to_string() }),
+        }
+        stack.pop();
+        loaded.insert(path);
+    }
+    trees.extend(new_trees);
+    Ok(())
+}
+
+/// Parses the input without resolving the leaves and subtrees
+///
+/// The resulting trees can be stored (for example serialized with the `serde` feature) and turned
+/// into factories later with `resolve`. Imports are rejected, use `parse_with_resolver` instead.
+pub fn parse_ast(input: &str) -> Result<Vec<Tree>,ParseError> {
+    reject_imports(try!(parse_source(Tokenizer::new(input))))
+}
+
+/// Same as `parse_ast`, the keywords being spelled as given by `config`
+pub fn parse_ast_with_config(input: &str, config: &ParserConfig) -> Result<Vec<Tree>,ParseError> {
+    reject_imports(try!(parse_source(Tokenizer::with_config(input, config))))
+}
+
+fn reject_imports((imports, trees): (Vec<String>,Vec<Tree>)) -> Result<Vec<Tree>,ParseError> {
+    match imports.into_iter().next() {
+        Some(path) => {
+            let message = String::from("imports can only be used with parse_with_resolver");
+            Err(ParseError::Import { path: path, message: message })
+        }
+        None => Ok(trees),
+    }
+}
+
+fn parse_source(tokenizer: Tokenizer) -> Result<(Vec<String>,Vec<Tree>),ParseError> {
+    parse_tokens(&mut tokenizer.keep_comments(), false)
+}
+
+// Line comments are attached to the node following them, and ignored anywhere else. With
+// `one_block`, stops after the bracket closing the first top-level block, usually a tree.
+fn parse_tokens(tokenizer: &mut Tokenizer, one_block: bool)
+-> Result<(Vec<String>,Vec<Tree>),ParseError> {
+    let mut tokens = Vec::new();
+    let mut comments = HashMap::new();
+    let mut pending = Vec::new();
+    let mut depth = 0;
+    // A lexer error is given to the parser after the valid tokens, so that a syntax error coming
+    // before it is still reported first
+    let mut error = None;
+    for result in tokenizer {
+        match result {
+            Ok((_, Token::Comment(text), _)) => pending.push(text),
+            Ok(token) => {
+                if !pending.is_empty() {
+                    comments.insert(token.0, mem::replace(&mut pending, Vec::new()));
+                }
+                let closed = match token.1 {
+                    Token::LeftBracket => { depth += 1; false }
+                    Token::RightBracket => { depth -= 1; depth == 0 }
+                    _ => false,
+                };
+                tokens.push(token);
+                if one_block && closed {
+                    break;
+                }
+            }
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+    let tokens = tokens.into_iter().map(Ok).chain(error.into_iter().map(Err));
+    parser::parse_TreeCollection(&comments, tokens).map_err(convert_parse_error)
+}
+
+/// Generates the leaves of the trees using `leaves`, and links the subtrees
+///
+/// Trees with parameters are only templates: their invocations are expanded, but they are not
+/// part of the returned trees.
+pub fn resolve<T: ?Sized>(
+    trees: Vec<Tree>,
+    leaves: &T,
+    ) -> Result<Vec<TreeFactory<T::Factory>>,ParseError>
+where T: FactoryProducer,
+      T::Factory: Clone {
+    try!(check_duplicates(&trees));
+    let trees = try!(template::expand(trees));
     let mut new_trees = Vec::new();
     for tree in trees {
-        let new_root = try!(resolve_dependencies(tree.root, leaves));
-        let new_tree = TreeFactory::new(new_root, tree.name);
+        let (mut comments, mut ids) = (Vec::new(), Vec::new());
+        collect_annotations(&tree.root, &mut 0, &mut comments, &mut ids);
+        let new_root = try!(resolve_dependencies(tree.root, &tree.name, leaves));
+        let mut new_tree = TreeFactory::new(new_root, tree.name);
+        for (index, comment) in comments {
+            new_tree.add_comment(index, comment);
+        }
+        for (index, id) in ids {
+            new_tree.set_id(index, id);
+        }
         new_trees.push(new_tree);
     }
-    Ok(new_trees)
+    factory::link_subtrees(new_trees)
+}
+
+// Lists every name used by several trees, in the order of their first duplicate, and then checks
+// that no tree gives the same identifier to two of its nodes
+//
+// The subtrees are not inlined yet, so an identifier repeated by inlining the same subtree twice
+// is still allowed: the first node keeps it.
+fn check_duplicates(trees: &[Tree]) -> Result<(),ParseError> {
+    try!(check_duplicate_names(trees.iter().map(|tree| tree.name.as_str())));
+    for tree in trees {
+        if let Some(id) = duplicate_id(&tree.root, &mut HashSet::new()) {
+            return Err(ParseError::DuplicateId { tree: tree.name.clone(), id: id });
+        }
+    }
+    Ok(())
+}
+
+// Lists the names given several times, each one once and in the order of their second use
+fn check_duplicate_names<'a, I>(names: I) -> Result<(),ParseError>
+where I: Iterator<Item=&'a str> {
+    let mut seen = HashMap::new();
+    let mut duplicates = Vec::new();
+    for name in names {
+        let count = seen.entry(name).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            duplicates.push(String::from(name));
+        }
+    }
+    if duplicates.is_empty() {
+        Ok(())
+    } else {
+        Err(ParseError::DuplicateTrees { names: duplicates })
+    }
+}
+
+// First identifier found twice in `node` and its descendants
+fn duplicate_id<'a>(node: &'a Node, seen: &mut HashSet<&'a str>) -> Option<String> {
+    if let Node::Identified(ref id, _) = *node {
+        if !seen.insert(id.as_str()) {
+            return Some(id.clone());
+        }
+    }
+    node.children().into_iter().filter_map(|child| duplicate_id(child, seen)).next()
 }
 
-fn resolve_dependencies<T: ?Sized>(node: Node, leaves: &T) -> Result<NodeFactory<T::Factory>,String>
+fn convert_parse_error(error: LalrpopError<Span,Token,ParseError>) -> ParseError {
+    match error {
+        LalrpopError::InvalidToken { location } => {
+            ParseError::Lex { message: String::from("invalid token"), span: location }
+        }
+        LalrpopError::UnrecognizedToken { token: Some((span, token, _)), expected } => {
+            ParseError::UnexpectedToken { found: token, span: span, expected: expected }
+        }
+        LalrpopError::UnrecognizedToken { token: None, expected } => {
+            ParseError::UnexpectedEof { expected: expected }
+        }
+        LalrpopError::ExtraToken { token: (span, token, _) } => {
+            ParseError::UnexpectedToken { found: token, span: span, expected: Vec::new() }
+        }
+        LalrpopError::User { error } => error,
+    }
+}
+
+// Keyword of the composite node if it has no children. They are rejected as they are almost
+// always a mistake: they would succeed or fail without doing anything.
+fn empty_composite(node: &Node) -> Option<&'static str> {
+    let keyword = match *node {
+        Node::Sequence(_) => "sequence",
+        Node::SequenceAll(_) => "sequence_all",
+        Node::Selector(_) => "selector",
+        Node::RandomSelector(_) => "random_selector",
+        Node::WeightedSelector(_) => "weighted_selector",
+        Node::Priority(_) => "priority",
+        Node::ReactiveSelector(_) => "reactive_selector",
+        Node::Parallel(..) => "parallel",
+        Node::Switch(..) => "switch",
+        Node::Commented(_, ref node) | Node::Identified(_, ref node) => return empty_composite(node),
+        Node::Leaf(..) | Node::Inverter(_) | Node::Repeater(..) | Node::UntilFail(_)
+            | Node::Succeeder(_) | Node::Cooldown(..) | Node::Limiter(..) | Node::Retry(..) | Node::Budget(..)
+            | Node::NonBlocking(..) | Node::StoreResult(..) | Node::Subtree(_)
+            | Node::SubtreeCall(..) => return None,
+    };
+    if node.children().is_empty() { Some(keyword) } else { None }
+}
+
+// Creates the factory of the node, `tree` being the name of the tree containing it
+fn resolve_dependencies<T: ?Sized>(node: Node, tree: &str, leaves: &T)
+-> Result<NodeFactory<T::Factory>,ParseError>
 where T: FactoryProducer {
+    if let Some(keyword) = empty_composite(&node) {
+        return Err(ParseError::EmptyComposite { node: String::from(keyword) });
+    }
     match node {
         Node::Sequence(children) => {
-            let new_children = try!(resolve_dependencies_vec(children, leaves));
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
             Ok(NodeFactory::new_sequence(new_children))
         }
+        Node::SequenceAll(children) => {
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
+            Ok(NodeFactory::new_sequence_all(new_children))
+        }
         Node::Selector(children) => {
-            let new_children = try!(resolve_dependencies_vec(children, leaves));
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
             Ok(NodeFactory::new_selector(new_children))
         }
+        Node::RandomSelector(children) => {
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
+            Ok(NodeFactory::new_random_selector(new_children))
+        }
+        Node::WeightedSelector(children) => {
+            let mut new_children = Vec::with_capacity(children.len());
+            for (weight, child) in children {
+                if weight < 0 || weight > u32::max_value() as i64 {
+                    let message = format!("Expected a weight between 0 and {}, found {}", u32::max_value(), weight);
+                    return Err(ParseError::InvalidArgument { message: message });
+                }
+                new_children.push((weight as u32, try!(resolve_dependencies(child, tree, leaves))));
+            }
+            Ok(NodeFactory::new_weighted_selector(new_children))
+        }
         Node::Priority(children) => {
-            let new_children = try!(resolve_dependencies_vec(children, leaves));
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
             Ok(NodeFactory::new_priority(new_children))
         }
+        Node::ReactiveSelector(children) => {
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
+            Ok(NodeFactory::new_reactive_selector(new_children))
+        }
+        Node::Parallel(threshold, children) => {
+            if threshold < 0 {
+                let message = format!("Expected a positive parallel threshold, found {}", threshold);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
+            Ok(NodeFactory::new_parallel(threshold as usize, new_children))
+        }
         Node::Inverter(child) => {
-            let new_child = try!(resolve_dependencies(*child,leaves));
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
             Ok(NodeFactory::new_inverter(Box::new(new_child)))
         }
-        Node::Leaf(name, options) => {
-            match leaves.generate_leaf(&name, &options) {
-                Err(e) => Err(format!("Could not find leaf node {}: {}", name, e)),
-                Ok(f) => {
-                    Ok(NodeFactory::new_leaf(f))
-                }
+        Node::Repeater(count, child) => {
+            if count < 0 {
+                let message = format!("Expected a positive repeat count, found {}", count);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_repeater(count as usize, Box::new(new_child)))
+        }
+        Node::UntilFail(child) => {
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_until_fail(Box::new(new_child)))
+        }
+        Node::Succeeder(child) => {
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_succeeder(Box::new(new_child)))
+        }
+        Node::Cooldown(ticks, child) => {
+            if ticks < 0 {
+                let message = format!("Expected a positive cooldown, found {}", ticks);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_cooldown(ticks as usize, Box::new(new_child)))
+        }
+        Node::Limiter(max, child) => {
+            if max < 0 {
+                let message = format!("Expected a positive limit, found {}", max);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_limiter(max as usize, Box::new(new_child)))
+        }
+        Node::Retry(attempts, child) => {
+            if attempts <= 0 {
+                let message = format!("Expected a strictly positive number of attempts, found {}", attempts);
+                return Err(ParseError::InvalidArgument { message: message });
             }
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_retry(attempts as usize, Box::new(new_child)))
+        }
+        Node::Budget(max, child) => {
+            if max < 0 {
+                let message = format!("Expected a positive budget, found {}", max);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_budget(max as usize, Box::new(new_child)))
+        }
+        Node::NonBlocking(result, child) => {
+            let on_running = match result.as_str() {
+                "success" => NonBlockingResult::Success,
+                "failure" => NonBlockingResult::Failure,
+                _ => {
+                    let message = format!("Expected success or failure, found {}", result);
+                    return Err(ParseError::InvalidArgument { message: message });
+                }
+            };
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            Ok(NodeFactory::new_non_blocking(on_running, Box::new(new_child)))
+        }
+        Node::StoreResult(variable, child, span) => {
+            // The result is written by the leaf named `store_result` of the FactoryProducer
+            let name = String::from("store_result");
+            let writer = try!(leaves.generate_leaf(&name, &None).map_err(|error| {
+                leaf_error(error, &name, tree, span)
+            }));
+            let new_child = try!(resolve_dependencies(*child, tree, leaves));
+            let writer = LeafFactory::new(Some(name), None, writer);
+            Ok(NodeFactory::new_store_result(variable, writer, Box::new(new_child)))
+        }
+        Node::Switch(options, children, span) => {
+            // The child is selected by the leaf named `switch` of the FactoryProducer
+            let name = String::from("switch");
+            let selector = try!(leaves.generate_leaf(&name, &options).map_err(|error| {
+                leaf_error(error, &name, tree, span)
+            }));
+            let new_children = try!(resolve_dependencies_vec(children, tree, leaves));
+            Ok(NodeFactory::new_switch(LeafFactory::new(Some(name), options, selector), new_children))
+        }
+        Node::Subtree(name) => {
+            Ok(NodeFactory::new_subtree(name))
+        }
+        Node::SubtreeCall(name, _) => panic!("Trying to resolve an unexpanded call to subtree {}", name),
+        // The comments and identifiers are added to the tree by `collect_annotations`
+        Node::Commented(_, node) | Node::Identified(_, node) => resolve_dependencies(*node, tree, leaves),
+        Node::Leaf(name, options, span) => {
+            let f = try!(leaves.generate_leaf(&name, &options).map_err(|error| {
+                leaf_error(error, &name, tree, span)
+            }));
+            Ok(NodeFactory::new_named_leaf(name, options, f))
+        }
+    }
+}
+
+// Lists the comments and identifiers of the node and its descendants, with the pre-order index of
+// the node they belong to in the resolved tree. `index` is the index of `node`.
+fn collect_annotations(node: &Node, index: &mut usize, comments: &mut Vec<(usize,String)>,
+                       ids: &mut Vec<(usize,String)>) {
+    match *node {
+        Node::Commented(ref lines, ref child) => {
+            comments.extend(lines.iter().map(|line| (*index, line.clone())));
+            // The comments do not take an index of their own
+            return collect_annotations(child, index, comments, ids);
+        }
+        Node::Identified(ref id, ref child) => {
+            ids.push((*index, id.clone()));
+            return collect_annotations(child, index, comments, ids);
         }
+        _ => *index += 1,
+    }
+    for child in node.children() {
+        collect_annotations(child, index, comments, ids);
     }
 }
 
-fn resolve_dependencies_vec<T: ?Sized>(nodes: Vec<Node>, leaves: &T)
--> Result<Vec<NodeFactory<T::Factory>>, String>
+fn resolve_dependencies_vec<T: ?Sized>(nodes: Vec<Node>, tree: &str, leaves: &T)
+-> Result<Vec<NodeFactory<T::Factory>>, ParseError>
 where T: FactoryProducer {
     let mut new_nodes = Vec::new();
     for node in nodes {
-        let new_node = try!(resolve_dependencies(node, leaves));
+        let new_node = try!(resolve_dependencies(node, tree, leaves));
         new_nodes.push(new_node);
     }
     Ok(new_nodes)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tree::{BehaviourTreeNode,VisitResult};
+    use standard::{LeavesCollection,Number,StoreKind};
+    use super::{Comparison,ParseError,ParserConfig,Span,Token,Value};
+    use super::ast::Node;
+
+    type TestContext = HashMap<String,StoreKind>;
+
+    #[test]
+    fn subtree() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree main {
+                sequence {
+                    print_text(first),
+                    subtree other,
+                }
+            }
+            tree other {
+                print_text(second)
+            }
+        "#;
+        let trees = super::parse(input, &leaves).unwrap();
+        assert_eq!(trees.len(), 2);
+        let mut context = HashMap::new();
+        for tree in trees.iter() {
+            assert_eq!(tree.optimize().visit(&mut context), VisitResult::Success);
+            assert_eq!(tree.instanciate().visit(&mut context), VisitResult::Success);
+        }
+    }
+
+    #[test]
+    fn undefined_subtree() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main { subtree missing }";
+        match super::parse(input, &leaves) {
+            Err(ParseError::UnresolvedSubtree { ref name, ref tree }) => {
+                assert_eq!(name, "missing");
+                assert_eq!(tree, "main");
+            }
+            other => panic!("Expected unresolved subtree error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recursive_subtree() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main { sequence { print_text(loop), subtree main } }";
+        match super::parse(input, &leaves) {
+            Err(ParseError::SubtreeCycle { ref path }) => assert_eq!(*path, ["main", "main"]),
+            other => panic!("Expected subtree cycle error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_position() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main {\n    sequence {\n        print_text(a)\n        print_text(b)\n    }\n}";
+        let error = super::parse(input, &leaves).unwrap_err();
+        assert!(error.to_string().contains("line 4, column 9"), "{}", error);
+        match error {
+            ParseError::UnexpectedToken { found, span, .. } => {
+                assert_eq!(found, Token::Ident(String::from("print_text")));
+                assert_eq!((span.line, span.column), (4, 9));
+            }
+            other => panic!("Expected unexpected token error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let error = super::parse_ast("tree x {").unwrap_err();
+        assert!(error.to_string().contains("unexpected end of file"), "{}", error);
+        match error {
+            ParseError::UnexpectedEof { expected } => assert!(!expected.is_empty()),
+            other => panic!("Expected unexpected end of file error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn integer_overflow() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        match super::parse("tree main { wait(99999999999999999999) }", &leaves) {
+            Err(ParseError::Lex { ref message, span }) => {
+                assert!(message.contains("99999999999999999999"), "{}", message);
+                assert_eq!((span.line, span.column), (1, 18));
+            }
+            other => panic!("Expected lexer error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_leaf() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        match super::parse("tree main { not_a_leaf }", &leaves) {
+            Err(ParseError::InTree { ref error, .. }) => match **error {
+                ParseError::UnknownLeaf { ref name } => assert_eq!(name, "not_a_leaf"),
+                ref other => panic!("Expected unknown leaf error, got {:?}", other),
+            },
+            other => panic!("Expected unknown leaf error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn leaf_error_position() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree first { wait(1) }\ntree second {\n    sequence { wait(1), not_a_leaf }\n}";
+        let error = super::parse(input, &leaves).unwrap_err();
+        match error {
+            ParseError::InTree { ref tree, span, .. } => {
+                assert_eq!(tree, "second");
+                assert_eq!((span.line, span.column), (3, 25));
+            }
+            ref other => panic!("Expected an error in tree second, got {:?}", other),
+        }
+        assert_eq!(error.to_string(),
+                   "Could not find leaf with name not_a_leaf in tree second at line 3, column 25");
+        match super::parse("tree main {\n  wait(true)\n}", &leaves) {
+            Err(ParseError::InTree { ref tree, span, ref error }) => {
+                assert_eq!((tree.as_str(), span.line, span.column), ("main", 2, 3));
+                match **error {
+                    ParseError::InvalidLeafOptions { ref name, .. } => assert_eq!(name, "wait"),
+                    ref other => panic!("Expected invalid options, got {:?}", other),
+                }
+            }
+            other => panic!("Expected an error in tree main, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_lenient() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main { sequence { print_text(\"start\"), jump_around, wait(1) } }";
+        let (trees, warnings) = super::parse_lenient(input, &leaves);
+        assert_eq!(warnings, ["Unknown leaf jump_around in tree main, replaced by always_failure"]);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].leaf_names(), ["print_text", "always_failure", "wait"]);
+        let mut tree = trees[0].optimize();
+        assert_eq!(tree.visit(&mut HashMap::new()), VisitResult::Failure);
+        // Other errors are still fatal
+        let (trees, warnings) = super::parse_lenient("tree main { wait(-1) }", &leaves);
+        assert!(trees.is_empty());
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn parse_iter() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        // The trees before the truncated one are returned before its error is found
+        let input = "tree a { wait(1) }\n tree b { sequence { wait(1), print_text(\"b\") } }\n tree c {";
+        let mut trees = super::parse_iter(input, &leaves);
+        let first = trees.next().unwrap().unwrap();
+        assert_eq!(first.get_name(), "a");
+        assert_eq!(first.optimize().visit(&mut HashMap::new()), VisitResult::Running);
+        let second = trees.next().unwrap().unwrap();
+        assert_eq!(second.leaf_names(), ["wait", "print_text"]);
+        match trees.next() {
+            Some(Err(ParseError::UnexpectedEof { .. })) => {}
+            other => panic!("Expected an unexpected end of input, got {:?}", other),
+        }
+        assert!(trees.next().is_none());
+
+        let input = "tree a { wait(1) } tree b { not_a_leaf } tree c { wait(1) }";
+        let results: Vec<_> = super::parse_iter(input, &leaves).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    const SOURCE: &'static str = r#"
+        tree main {
+            sequence {
+                print_text("Hello \"world\"\n"),
+                inverter condition({ operator: ">=", exp2: [ 3 -1 - ], exp1: [ x ] }),
+                repeat(2) { wait(1) },
+                parallel(1) {
+                    force_success { increment({ variable: x, value: -1 }) },
+                    until_fail { wait(0) },
+                    cooldown(10) { wait(2) },
+                    limit(3) { wait(1) },
+                    retry(5) { wait(1) },
+                },
+                random_selector { subtree other, priority { wait(0) } },
+                weighted_selector { 3: wait(1), 1: print_text(rare) },
+                switch(state) { wait(0), print_text(done) },
+                reactive_selector { condition({ operator: "=", exp1: [ x ], exp2: [ 0 ] }), wait(3) },
+            }
+        }
+        tree other { selector { print_text(other) } }
+    "#;
+
+    #[test]
+    fn to_source() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let trees = super::parse(SOURCE, &leaves).unwrap();
+        let expected = r#"tree other {
+    selector {
+        print_text("other"),
+    }
+}
+"#;
+        assert_eq!(trees[1].to_source(), expected);
+    }
+
+    #[test]
+    fn budget() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let factory = super::parse_one("tree main { budget(2) { wait(1) } }", &leaves).unwrap();
+        assert_eq!(factory.to_source(), "tree main {\n    budget(2) {\n        wait(1)\n    }\n}\n");
+        match super::parse("tree main { budget(-2) { wait(1) } }", &leaves) {
+            Err(ParseError::InvalidArgument { ref message }) => assert!(message.contains("-2"), "{}", message),
+            other => panic!("Expected invalid argument error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_blocking() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let source = "tree main {\n    non_blocking(failure) {\n        wait(2)\n    }\n}\n";
+        let factory = super::parse_one(source, &leaves).unwrap();
+        assert_eq!(factory.to_source(), source);
+        assert_eq!(factory.optimize().visit(&mut HashMap::new()), VisitResult::Failure);
+        match super::parse("tree main { non_blocking(running) { wait(1) } }", &leaves) {
+            Err(ParseError::InvalidArgument { ref message }) => assert!(message.contains("running"), "{}", message),
+            other => panic!("Expected invalid argument error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn store_result() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let source = "tree main {\n    store_result(\"waited\") {\n        wait(1)\n    }\n}\n";
+        let factory = super::parse_one(source, &leaves).unwrap();
+        assert_eq!(factory.to_source(), source);
+        let mut context = HashMap::new();
+        assert_eq!(factory.optimize().visit(&mut context), VisitResult::Running);
+        assert_eq!(context.get("waited"), Some(&StoreKind::Number(Number::Int(2))));
+    }
+
+    #[test]
+    fn comments_round_trip() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree main {
+                // Greets, then waits
+                sequence {
+                    // Said once
+                    print_text("hello"),
+                    // Inlined below
+                    subtree pause,
+                    // Lost, as no node follows
+                }
+            }
+
+            /* Block comments are not kept */
+            tree pause {
+                // Short pause
+                wait(1)
+            }
+        "#;
+        let trees = super::parse(input, &leaves).unwrap();
+        assert_eq!(trees[0].comments(0), [" Greets, then waits"]);
+        assert_eq!(trees[0].comments(2), [" Inlined below", " Short pause"]);
+        let expected = r#"tree main {
+    // Greets, then waits
+    sequence {
+        // Said once
+        print_text("hello"),
+        // Inlined below
+        // Short pause
+        wait(1),
+    }
+}
+"#;
+        assert_eq!(trees[0].to_source(), expected);
+        let reparsed = super::parse(expected, &leaves).unwrap();
+        assert_eq!(reparsed[0].to_source(), expected);
+    }
+
+    #[test]
+    fn node_ids() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree main {
+                selector {
+                    inverter #guard wait(1),
+                    sequence #patrol { subtree pause, wait(2) #long },
+                }
+            }
+
+            tree pause {
+                repeat(2) #pause { wait(1) }
+            }
+        "#;
+        let trees = super::parse(input, &leaves).unwrap();
+        let optimized = trees[0].optimize();
+        assert_eq!(optimized.index_of("guard"), Some(1));
+        assert_eq!(optimized.index_of("patrol"), Some(3));
+        assert_eq!(optimized.index_of("pause"), Some(4));
+        assert_eq!(optimized.index_of("long"), Some(6));
+        assert_eq!(optimized.index_of("missing"), None);
+        assert_eq!(trees[0].optimize_packed().index_of("patrol"), Some(3));
+        // Adding a node before the sequence changes its index, not its identifier
+        let input = "tree main { selector { wait(1), wait(1), sequence #patrol { wait(2) } } }";
+        let trees = super::parse(input, &leaves).unwrap();
+        assert_eq!(trees[0].optimize().index_of("patrol"), Some(3));
+        let expected = "tree main {\n    selector {\n        wait(1),\n        wait(1),\n        \
+                        sequence #patrol {\n            wait(2),\n        },\n    }\n}\n";
+        assert_eq!(trees[0].to_source(), expected);
+        assert!(super::parse("tree main { sequence # { wait(1) } }", &leaves).is_err());
+        // The same identifier twice in a tree is an error, unless it comes from inlined subtrees
+        let input = "tree main { sequence { wait(1) #step, wait(2) #step } }";
+        match super::parse(input, &leaves) {
+            Err(ParseError::DuplicateId { ref tree, ref id }) => {
+                assert_eq!(tree, "main");
+                assert_eq!(id, "step");
+            }
+            other => panic!("Expected a duplicate identifier, got {:?}", other),
+        }
+        let input = "tree main { sequence { subtree step, subtree step } } tree step { wait(1) #step }";
+        let trees = super::parse(input, &leaves).unwrap();
+        assert_eq!(trees[0].optimize().index_of("step"), Some(1));
+    }
+
+    #[test]
+    fn clone_tree() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main { sequence #patrol { wait(1), print_text(hi) } }";
+        let trees = super::parse(input, &leaves).unwrap();
+        let mut copy = trees[0].clone();
+        assert!(copy.structural_eq(&trees[0]));
+        // Annotating the copy leaves the original untouched
+        copy.set_id(1, String::from("pause"));
+        copy.add_comment(2, String::from(" only in the copy"));
+        assert_eq!(trees[0].id(1), None);
+        assert!(trees[0].comments(2).is_empty());
+        assert_eq!(copy.optimize().index_of("pause"), Some(1));
+        assert_eq!(copy.optimize().index_of("patrol"), Some(0));
+        // Each copy instanciates its own leaves
+        let mut original = trees[0].optimize();
+        let mut copied = copy.optimize();
+        let mut context = HashMap::new();
+        assert_eq!(copied.visit(&mut context), VisitResult::Running);
+        assert_eq!(copied.visit(&mut context), VisitResult::Success);
+        assert_eq!(original.visit(&mut context), VisitResult::Running);
+    }
+
+    #[test]
+    fn to_source_round_trip() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let trees = super::parse(SOURCE, &leaves).unwrap();
+        let source: String = trees.iter().map(|tree| tree.to_source()).collect();
+        let reparsed = super::parse(&source, &leaves).unwrap();
+        let source_again: String = reparsed.iter().map(|tree| tree.to_source()).collect();
+        assert_eq!(source, source_again);
+        // Subtrees are inlined when linking, so the round trip goes through the linked trees
+        let ast = super::parse_ast(&source).unwrap();
+        assert_eq!(ast, super::parse_ast(&source_again).unwrap());
+        assert_eq!(ast[0].name, "main");
+    }
+
+    #[test]
+    fn children() {
+        let input = "tree main { weighted_selector #id { 3: inverter wait(1), 1: subtree other } }";
+        let mut trees = super::parse_ast(input).unwrap();
+        let root = &mut trees[0].root;
+        {
+            let selector = root.children()[0];
+            let children = selector.children();
+            assert_eq!(children.len(), 2);
+            assert_eq!(children[0].children().len(), 1);
+            assert!(children[1].children().is_empty());
+        }
+        for child in root.children_mut() {
+            *child = Node::Subtree(String::from("replaced"));
+        }
+        assert_eq!(*root, Node::Identified(String::from("id"), Box::new(Node::Subtree(String::from("replaced")))));
+    }
+
+    #[test]
+    fn structural_eq() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let trees = super::parse(SOURCE, &leaves).unwrap();
+        let again = super::parse(SOURCE, &leaves).unwrap();
+        assert!(trees[0].structural_eq(&again[0]));
+        assert!(!trees[0].structural_eq(&trees[1]));
+        let source: String = trees.iter().map(|tree| tree.to_source()).collect();
+        let reparsed = super::parse(&source, &leaves).unwrap();
+        assert!(trees[0].structural_eq(&reparsed[0]));
+        // Different parameter of a decorator, and different options of a leaf
+        let changed = super::parse(&SOURCE.replace("repeat(2)", "repeat(3)"), &leaves).unwrap();
+        assert!(!trees[0].structural_eq(&changed[0]));
+        let changed = super::parse(&SOURCE.replace("wait(2)", "wait(3)"), &leaves).unwrap();
+        assert!(!trees[0].structural_eq(&changed[0]));
+    }
+
+    #[test]
+    fn validate() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        assert_eq!(super::validate(SOURCE, &leaves).map_err(|errors| errors.len()), Ok(()));
+        let input = r#"
+            tree main {
+                sequence {
+                    print_text(hello),
+                    increment({ value: 1 }),
+                    parallel(3) { wait(1), wait(true) },
+                    selector {},
+                    unknown,
+                }
+            }
+        "#;
+        let errors = super::validate(input, &leaves).unwrap_err();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        match *errors[0].root_cause() {
+            ParseError::InvalidLeafOptions { ref name, .. } => assert_eq!(name, "increment"),
+            ref other => panic!("Expected invalid options for increment, got {:?}", other),
+        }
+        match *errors[1].root_cause() {
+            ParseError::InvalidLeafOptions { ref name, .. } => assert_eq!(name, "wait"),
+            ref other => panic!("Expected invalid options for wait, got {:?}", other),
+        }
+        match errors[2] {
+            ParseError::EmptyComposite { ref node } => assert_eq!(node, "selector"),
+            ref other => panic!("Expected an empty selector, got {:?}", other),
+        }
+        match errors[3] {
+            ParseError::InTree { ref tree, .. } => assert_eq!(tree, "main"),
+            ref other => panic!("Expected an error located in main, got {:?}", other),
+        }
+        match *errors[3].root_cause() {
+            ParseError::UnknownLeaf { ref name } => assert_eq!(name, "unknown"),
+            ref other => panic!("Expected an unknown leaf, got {:?}", other),
+        }
+        // Structural problems are only found once all the leaves are valid
+        let input = "tree main { parallel(3) { wait(1), wait(2) } }";
+        let errors = super::validate(input, &leaves).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(),
+                   ParseError::InvalidArgument {
+                       message: String::from("Parallel(3) can never succeed with 2 children in tree main"),
+                   }.to_string());
+    }
+
+    #[test]
+    fn custom_keywords() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let config = ParserConfig::new().rename("sequence", "all").rename("selector", "any");
+        let custom = "tree main { all { any { wait(1), print_text(hi) }, wait(2) } }";
+        let default = "tree main { sequence { selector { wait(1), print_text(hi) }, wait(2) } }";
+        let trees = super::parse_with_config(custom, &leaves, &config).unwrap();
+        let expected = super::parse(default, &leaves).unwrap();
+        assert!(trees[0].structural_eq(&expected[0]));
+        // The renamed keywords are plain identifiers
+        let ast = super::parse_ast_with_config("tree main { sequence }", &config).unwrap();
+        assert_eq!(ast[0].root, Node::Leaf(String::from("sequence"), None, Span { line: 1, column: 13 }));
+        // Aliases keep the default keyword
+        let config = ParserConfig::new().alias("sequence", "all");
+        let ast = super::parse_ast_with_config("tree main { all { sequence { wait(1) } } }", &config).unwrap();
+        let wait = Node::Leaf(String::from("wait"), Some(Value::Integer(1)), Span { line: 1, column: 30 });
+        assert_eq!(ast[0].root, Node::Sequence(vec![Node::Sequence(vec![wait])]));
+    }
+
+    const TEMPLATE: &'static str = r#"
+        tree say(text, times) { repeat(2) { print_text($text) } }
+        tree main {
+            sequence {
+                subtree say(text: hello, times: 1),
+                subtree say(times: 2, text: "good bye"),
+            }
+        }
+    "#;
+
+    #[test]
+    fn parameterized_subtree() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let trees = super::parse(TEMPLATE, &leaves).unwrap();
+        // The template itself is not a tree
+        assert_eq!(trees.len(), 1);
+        let expected = r#"tree main {
+    sequence {
+        repeat(2) {
+            print_text("hello")
+        },
+        repeat(2) {
+            print_text("good bye")
+        },
+    }
+}
+"#;
+        assert_eq!(trees[0].to_source(), expected);
+    }
+
+    fn template_error(input: &str) -> String {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        match super::parse(input, &leaves) {
+            Err(ParseError::InvalidArgument { message }) => message,
+            other => panic!("Expected an invalid argument error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parameterized_subtree_arguments() {
+        let template = "tree say(text) { print_text($text) }";
+        let missing = format!("{} tree main {{ subtree say() }}", template);
+        assert_eq!(template_error(&missing), "Missing arguments text for subtree say");
+        let extra = format!("{} tree main {{ subtree say(text: a, loud: true) }}", template);
+        assert_eq!(template_error(&extra), "Unknown argument loud for subtree say, expected text");
+        let plain = format!("{} tree main {{ subtree say }}", template);
+        assert_eq!(template_error(&plain), "Missing arguments text for subtree say");
+        let unknown = "tree main { print_text($text) }";
+        assert_eq!(template_error(unknown), "Unknown parameter $text in tree main");
+        // Templates are checked even if they are never invoked
+        let twice = "tree say(text, text) { print_text($text) } tree main { wait(1) }";
+        assert_eq!(template_error(twice), "Parameter text declared twice in tree say");
+        let unused = "tree say(text) { print_text($txt) } tree main { wait(1) }";
+        assert_eq!(template_error(unused), "Unknown parameter $txt in tree say");
+    }
+
+    #[test]
+    fn parse_one() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let tree = super::parse_one("tree main { wait(1) }", &leaves).unwrap();
+        assert_eq!(tree.get_name(), "main");
+        match super::parse_one("  // nothing\n", &leaves) {
+            Err(ParseError::ExpectedOneTree { found: 0 }) => {}
+            other => panic!("Expected a missing tree error, got {:?}", other),
+        }
+        match super::parse_one("tree a { wait(1) } tree b { wait(2) }", &leaves) {
+            Err(ParseError::ExpectedOneTree { found: 2 }) => {}
+            other => panic!("Expected a tree count error, got {:?}", other),
+        }
+    }
+
+    fn resolver(path: &str) -> Result<String,String> {
+        match path {
+            "patrol.bt" => Ok(String::from(r#"
+                import "common.bt"
+                tree patrol { sequence { subtree idle, print_text(patrol) } }
+            "#)),
+            "common.bt" => Ok(String::from("tree idle { wait(1) }")),
+            "loop_a.bt" => Ok(String::from(r#"import "loop_b.bt""#)),
+            "loop_b.bt" => Ok(String::from(r#"import "loop_a.bt""#)),
+            "broken.bt" => Ok(String::from("tree broken {")),
+            _ => Err(String::from("no such file")),
+        }
+    }
+
+    #[test]
+    fn import() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            import "patrol.bt"
+            import "common.bt"
+            tree main { sequence { subtree patrol, subtree idle } }
+        "#;
+        let trees = super::parse_with_resolver(input, &leaves, resolver).unwrap();
+        let names: Vec<&str> = trees.iter().map(|tree| tree.get_name()).collect();
+        assert_eq!(names, ["idle", "patrol", "main"]);
+        let mut tree = trees[2].optimize();
+        let mut context = HashMap::new();
+        assert_eq!(tree.visit(&mut context), VisitResult::Running);
+        assert_eq!(tree.visit(&mut context), VisitResult::Running);
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+    }
+
+    #[test]
+    fn import_errors() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let parse = |input: &str| super::parse_with_resolver(input, &leaves, resolver);
+        match parse(r#"import "loop_a.bt" tree main { wait(1) }"#) {
+            Err(ParseError::ImportCycle { ref path }) => assert_eq!(*path, ["loop_a.bt", "loop_b.bt", "loop_a.bt"]),
+            other => panic!("Expected import cycle error, got {:?}", other),
+        }
+        match parse(r#"import "missing.bt""#) {
+            Err(ParseError::Import { ref path, ref message }) => {
+                assert_eq!(path, "missing.bt");
+                assert_eq!(message, "no such file");
+            }
+            other => panic!("Expected import error, got {:?}", other),
+        }
+        match parse(r#"import "broken.bt""#) {
+            Err(ParseError::Import { ref path, .. }) => assert_eq!(path, "broken.bt"),
+            other => panic!("Expected import error, got {:?}", other),
+        }
+        match super::parse(r#"import "common.bt" tree main { subtree idle }"#, &leaves) {
+            Err(ParseError::Import { ref path, .. }) => assert_eq!(path, "common.bt"),
+            other => panic!("Expected import error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_trees() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree foo { wait(1) }
+            tree bar { wait(1) }
+            tree foo { wait(2) }
+            tree baz(x) { wait(1) }
+            tree bar { wait(2) }
+            tree baz { wait(1) }
+            tree foo { wait(3) }
+        "#;
+        match super::parse(input, &leaves) {
+            Err(ParseError::DuplicateTrees { ref names }) => assert_eq!(*names, ["foo", "bar", "baz"]),
+            other => panic!("Expected duplicate trees error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_composites() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let composites = ["sequence", "sequence_all", "selector", "random_selector", "weighted_selector",
+                          "priority", "reactive_selector", "parallel(1)"];
+        for composite in composites.iter() {
+            let input = format!("tree main {{ sequence {{ wait(1), {} {{}} }} }}", composite);
+            match super::parse(&input, &leaves) {
+                Err(ParseError::EmptyComposite { ref node }) => assert!(composite.starts_with(node.as_str())),
+                other => panic!("Expected empty composite error for {}, got {:?}", composite, other),
+            }
+        }
+    }
+
+    #[test]
+    fn nested_literals() {
+        let options = leaf_options(r#"({ path: [1, 2, 3,], meta: { k: "v", }, })"#);
+        let mut meta = HashMap::new();
+        meta.insert(String::from("k"), Value::String(String::from("v")));
+        let mut expected = HashMap::new();
+        let path = vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)];
+        expected.insert(String::from("path"), Value::Array(path));
+        expected.insert(String::from("meta"), Value::Map(meta));
+        assert_eq!(options, Some(Value::Map(expected)));
+    }
+
+    #[test]
+    fn array_separators() {
+        let spaces = leaf_options("([ [1 2] [] x ])");
+        let commas = leaf_options(r#"([
+            [1, 2,],
+            [],
+            x,
+        ])"#);
+        assert_eq!(spaces, commas);
+        let expected = Value::Array(vec![
+            Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+            Value::Array(Vec::new()),
+            Value::String(String::from("x")),
+        ]);
+        assert_eq!(spaces, Some(expected));
+    }
+
+    #[test]
+    fn selector_default() {
+        let ast = super::parse_ast("tree test { selector { a, b, default c } }").unwrap();
+        let leaf = |name: &str, column| Node::Leaf(String::from(name), None, Span { line: 1, column: column });
+        assert_eq!(ast[0].root, Node::Selector(vec![leaf("a", 24), leaf("b", 27), leaf("c", 38)]));
+        let ast = super::parse_ast("tree test { selector { default c, } }").unwrap();
+        assert_eq!(ast[0].root, Node::Selector(vec![leaf("c", 32)]));
+    }
+
+    #[test]
+    fn selector_default_not_last() {
+        match super::parse_ast("tree test { selector { a, default b, c } }") {
+            Err(ParseError::UnexpectedToken { found: Token::Ident(ref name), .. }) => assert_eq!(name, "c"),
+            other => panic!("Expected an unexpected token error, got {:?}", other),
+        }
+        match super::parse_ast("tree test { sequence { a, default b } }") {
+            Err(ParseError::UnexpectedToken { found: Token::Default, .. }) => {}
+            other => panic!("Expected an unexpected token error, got {:?}", other),
+        }
+    }
+
+    fn leaf_options(options: &str) -> Option<Value> {
+        let input = format!("tree test {{ leaf{} }}", options);
+        let mut trees = super::parse_ast(&input).unwrap();
+        match trees.remove(0).root {
+            Node::Leaf(_, options, _) => options,
+            other => panic!("Expected a leaf, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escaped_keyword_leaf() {
+        let mut leaves = LeavesCollection::<TestContext>::standard();
+        leaves.register_closure_leaf("sequence", || |_: &mut TestContext| VisitResult::Success);
+        let input = "tree `tree` { sequence { `sequence`, wait(0) } } tree main { subtree `tree` }";
+        let trees = super::parse(input, &leaves).unwrap();
+        assert_eq!(trees[0].get_name(), "tree");
+        assert_eq!(trees[0].leaf_names(), ["sequence", "wait"]);
+        assert_eq!(trees[1].optimize().visit(&mut HashMap::new()), VisitResult::Success);
+        // Keywords are escaped again when written back
+        let source = trees[0].to_source();
+        assert!(source.starts_with("tree `tree` {"), "{}", source);
+        assert!(source.contains("`sequence`,"), "{}", source);
+        assert_eq!(super::parse(&source, &leaves).unwrap()[0].leaf_names(), ["sequence", "wait"]);
+    }
+
+    #[test]
+    fn positional_argument() {
+        assert_eq!(leaf_options(r#"("hi")"#), Some(Value::String(String::from("hi"))));
+        assert_eq!(leaf_options(""), None);
+    }
+
+    #[test]
+    fn named_argument() {
+        let mut expected = HashMap::new();
+        expected.insert(String::from("text"), Value::String(String::from("hi")));
+        assert_eq!(leaf_options(r#"(text: "hi")"#), Some(Value::Map(expected)));
+    }
+
+    #[test]
+    fn named_arguments() {
+        let mut expected = HashMap::new();
+        expected.insert(String::from("text"), Value::String(String::from("hi")));
+        expected.insert(String::from("color"), Value::String(String::from("red")));
+        expected.insert(String::from("count"), Value::Integer(3));
+        assert_eq!(leaf_options(r#"(text: "hi", color: red, count: 3)"#), Some(Value::Map(expected.clone())));
+        // Trailing comma, and same result as a map literal
+        assert_eq!(leaf_options(r#"(text: "hi", color: red, count: 3,)"#), Some(Value::Map(expected.clone())));
+        assert_eq!(leaf_options(r#"({ text: "hi", color: red, count: 3 })"#), Some(Value::Map(expected)));
+    }
+
+    #[test]
+    fn bool_literals() {
+        assert_eq!(leaf_options("(true)"), Some(Value::Bool(true)));
+        let mut expected = HashMap::new();
+        expected.insert(String::from("variable"), Value::String(String::from("alerted")));
+        expected.insert(String::from("value"), Value::Bool(false));
+        assert_eq!(leaf_options(r#"(variable: "alerted", value: false)"#), Some(Value::Map(expected)));
+    }
+
+    #[test]
+    fn comparison_options() {
+        assert_eq!(leaf_options("(>)"), Some(Value::Comparison(Comparison::Greater)));
+        let mut expected = HashMap::new();
+        expected.insert(String::from("operator"), Value::Comparison(Comparison::LessOrEqual));
+        assert_eq!(leaf_options("(operator: <=)"), Some(Value::Map(expected)));
+        let array = vec![Value::Comparison(Comparison::GreaterOrEqual),
+                         Value::Comparison(Comparison::NotEqual)];
+        assert_eq!(leaf_options("([>= !=])"), Some(Value::Array(array)));
+        assert_eq!(Value::Comparison(Comparison::NotEqual).to_string(), "!=");
+    }
+
+    #[test]
+    fn check_flag() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree main {
+                sequence {
+                    set_variable(variable: alerted, value: true),
+                    check_flag({ variable: "alerted" }),
+                }
+            }
+        "#;
+        let trees = super::parse(input, &leaves).unwrap();
+        let mut context = HashMap::new();
+        assert_eq!(trees[0].optimize().visit(&mut context), VisitResult::Success);
+        assert_eq!(context.get("alerted"), Some(&StoreKind::Bool(true)));
+    }
+}
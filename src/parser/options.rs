@@ -0,0 +1,314 @@
+//! Conversion of the options of a leaf into a typed struct
+//!
+//! `FromOptions` is usually derived with the `derive` feature, each field of the struct being
+//! read from the entry with the same name:
+//!
+//! ```ignore
+//! #[derive(FromOptions)]
+//! struct IncrementOptions {
+//!     variable: String,
+//!     value: i64,
+//! }
+//! ```
+//!
+//! The struct is then parsed from `increment({ variable: count, value: 2 })`. Fields of type
+//! `Option<T>` can be left out, all other fields are required, and entries that do not match a
+//! field are rejected.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self,Display,Formatter};
+
+use parser::{ParseError,Value};
+
+/// Type that can be read from the options of a leaf
+pub trait FromOptions: Sized {
+    fn from_options(options: &Option<Value>) -> Result<Self,OptionsError>;
+}
+
+/// Type of a field of a struct implementing `FromOptions`
+pub trait FromValue: Sized {
+    /// Converts the value, or returns None if it has the wrong type
+    fn from_value(value: &Value) -> Option<Self>;
+
+    /// Description of the expected values, used in the errors
+    fn expected() -> String;
+
+    /// Value of the field when it is missing from the options, if it is optional
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+/// Error returned by `FromOptions`
+#[derive(Debug,Clone,PartialEq)]
+pub enum OptionsError {
+    /// The options are not a map of named entries
+    NotAMap {
+        found: Option<Value>,
+    },
+    /// A required field has no entry
+    MissingField {
+        field: String,
+    },
+    /// The entry of a field does not have the type of the field
+    WrongType {
+        field: String,
+        expected: String,
+        found: Value,
+    },
+    /// An entry does not match any field
+    UnknownField {
+        field: String,
+    },
+}
+
+impl Display for OptionsError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            OptionsError::NotAMap { found: Some(ref found) } => {
+                write!(f, "expected named options, found {}", found)
+            }
+            OptionsError::NotAMap { found: None } => write!(f, "expected named options, found none"),
+            OptionsError::MissingField { ref field } => write!(f, "missing required field \"{}\"", field),
+            OptionsError::WrongType { ref field, ref expected, ref found } => {
+                write!(f, "expected {} for field \"{}\", found {}", expected, field, found)
+            }
+            OptionsError::UnknownField { ref field } => write!(f, "unknown field \"{}\"", field),
+        }
+    }
+}
+
+impl Error for OptionsError {
+    fn description(&self) -> &str {
+        match *self {
+            OptionsError::NotAMap { .. } => "options are not a map",
+            OptionsError::MissingField { .. } => "missing field",
+            OptionsError::WrongType { .. } => "field of the wrong type",
+            OptionsError::UnknownField { .. } => "unknown field",
+        }
+    }
+}
+
+/// Lets `FactoryProducer::generate_leaf` use `try!` on the result of `FromOptions`
+///
+/// The options do not know the name of their leaf, so it is left empty here and filled in by the
+/// parser, which also wraps the error in `ParseError::InTree`.
+impl From<OptionsError> for ParseError {
+    fn from(error: OptionsError) -> ParseError {
+        ParseError::InvalidLeafOptions {
+            name: String::new(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// The entries of the options, which must be a map
+pub fn options_map(options: &Option<Value>) -> Result<&HashMap<String,Value>,OptionsError> {
+    match *options {
+        Some(Value::Map(ref map)) => Ok(map),
+        ref other => Err(OptionsError::NotAMap { found: other.clone() }),
+    }
+}
+
+/// Checks that every entry of `map` is one of `fields`
+///
+/// The unknown entries are reported in alphabetical order, so the error does not depend on the
+/// order of the map.
+pub fn check_fields(map: &HashMap<String,Value>, fields: &[&str]) -> Result<(),OptionsError> {
+    let mut unknown: Vec<&String> = map.keys().filter(|key| !fields.contains(&key.as_str())).collect();
+    unknown.sort();
+    match unknown.first() {
+        Some(field) => Err(OptionsError::UnknownField { field: (*field).clone() }),
+        None => Ok(()),
+    }
+}
+
+/// Reads the entry `field` of `map`
+pub fn field<T: FromValue>(map: &HashMap<String,Value>, field: &str) -> Result<T,OptionsError> {
+    match map.get(field) {
+        Some(value) => T::from_value(value).ok_or_else(|| OptionsError::WrongType {
+            field: String::from(field),
+            expected: T::expected(),
+            found: value.clone(),
+        }),
+        None => T::missing().ok_or_else(|| OptionsError::MissingField { field: String::from(field) }),
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<String> {
+        match *value {
+            Value::String(ref string) => Some(string.clone()),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("a string")
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<i64> {
+        match *value {
+            Value::Integer(integer) => Some(integer),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("an integer")
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: &Value) -> Option<u64> {
+        match *value {
+            Value::Integer(integer) if integer >= 0 => Some(integer as u64),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("a non-negative integer")
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: &Value) -> Option<usize> {
+        match *value {
+            Value::Integer(integer) if integer >= 0 => Some(integer as usize),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("a non-negative integer")
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<bool> {
+        match *value {
+            Value::Bool(boolean) => Some(boolean),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("a boolean")
+    }
+}
+
+/// Any value, left for the leaf to interpret
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Option<Value> {
+        Some(value.clone())
+    }
+
+    fn expected() -> String {
+        String::from("a value")
+    }
+}
+
+impl <T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Option<Vec<T>> {
+        match *value {
+            Value::Array(ref array) => array.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        format!("an array of {}", T::expected())
+    }
+}
+
+impl <T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Option<T>> {
+        T::from_value(value).map(Some)
+    }
+
+    fn expected() -> String {
+        T::expected()
+    }
+
+    fn missing() -> Option<Option<T>> {
+        Some(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parser::{FactoryProducer,ParseError,Value};
+    use super::{FromOptions,OptionsError,check_fields,field,options_map};
+
+    // What the derive generates
+    #[derive(Debug,Clone,PartialEq)]
+    struct Move {
+        target: String,
+        speed: i64,
+        run: Option<bool>,
+    }
+
+    impl FromOptions for Move {
+        fn from_options(options: &Option<Value>) -> Result<Move,OptionsError> {
+            let map = try!(options_map(options));
+            try!(check_fields(map, &["target", "speed", "run"]));
+            Ok(Move {
+                target: try!(field(map, "target")),
+                speed: try!(field(map, "speed")),
+                run: try!(field(map, "run")),
+            })
+        }
+    }
+
+    fn options(entries: Vec<(&str,Value)>) -> Option<Value> {
+        Some(Value::Map(entries.into_iter().map(|(k, v)| (String::from(k), v)).collect()))
+    }
+
+    #[test]
+    fn from_options() {
+        let target = || ("target", Value::String(String::from("door")));
+        let parsed = Move::from_options(&options(vec![target(), ("speed", Value::Integer(2))]));
+        assert_eq!(parsed, Ok(Move { target: String::from("door"), speed: 2, run: None }));
+        let parsed = Move::from_options(&options(vec![target(), ("speed", Value::Integer(2)),
+                                                      ("run", Value::Bool(true))]));
+        assert_eq!(parsed.map(|parsed| parsed.run), Ok(Some(true)));
+
+        assert_eq!(Move::from_options(&None), Err(OptionsError::NotAMap { found: None }));
+        assert_eq!(Move::from_options(&options(vec![target()])),
+                   Err(OptionsError::MissingField { field: String::from("speed") }));
+        let error = Move::from_options(&options(vec![target(), ("speed", Value::Bool(true))])).unwrap_err();
+        assert_eq!(error.to_string(), "expected an integer for field \"speed\", found true");
+        let error = Move::from_options(&options(vec![target(), ("speed", Value::Integer(1)),
+                                                     ("sped", Value::Integer(1))]));
+        assert_eq!(error, Err(OptionsError::UnknownField { field: String::from("sped") }));
+    }
+
+    // Producer whose only leaf is `move`, reading its options with `FromOptions`
+    struct Moves;
+
+    impl FactoryProducer for Moves {
+        type Factory = Move;
+        fn generate_leaf(&self, name: &str, options: &Option<Value>) -> Result<Move,ParseError> {
+            match name {
+                "move" => Ok(try!(Move::from_options(options))),
+                _ => Err(ParseError::UnknownLeaf { name: String::from(name) }),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_error() {
+        let error = ::parse("tree main { move(target: door) }", &Moves).unwrap_err();
+        match *error.root_cause() {
+            ParseError::InvalidLeafOptions { ref name, ref message } => {
+                assert_eq!(name, "move");
+                assert_eq!(message, "missing required field \"speed\"");
+            }
+            ref other => panic!("Expected invalid options for move, got {:?}", other),
+        }
+    }
+}
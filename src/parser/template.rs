@@ -0,0 +1,165 @@
+//! Expansion of parameterized subtrees
+//!
+//! A tree declared with parameters, like `tree patrol(speed) { move(speed: $speed) }`, is a
+//! template: every `subtree patrol(speed: 5)` is replaced by a copy of its root where `$speed` is
+//! substituted by the given value. Templates are not returned as trees themselves.
+
+use std::collections::HashMap;
+use std::mem;
+
+use parser::{ParseError,Value};
+use parser::ast::{Node,Tree};
+
+/// Replaces the invocations of parameterized trees by their substituted roots, and removes these
+/// trees from the collection
+///
+/// Invocations of trees without parameters are left as `Node::Subtree`, to be linked later.
+///
+/// Every template is checked, even if it is never invoked: its parameters must have different
+/// names, and its body must only use these parameters and invoke its subtrees correctly.
+pub fn expand(trees: Vec<Tree>) -> Result<Vec<Tree>,ParseError> {
+    let mut templates = HashMap::new();
+    for tree in trees.iter().filter(|tree| !tree.params.is_empty()) {
+        templates.insert(tree.name.clone(), tree.clone());
+    }
+    for tree in trees.iter().filter(|tree| !tree.params.is_empty()) {
+        try!(check_template(tree, &templates));
+    }
+    let mut expanded = Vec::new();
+    for tree in trees.into_iter().filter(|tree| tree.params.is_empty()) {
+        let mut stack = vec![tree.name.clone()];
+        let mut root = tree.root;
+        try!(expand_node(&mut root, &HashMap::new(), &templates, &mut stack));
+        expanded.push(Tree { name: tree.name, params: Vec::new(), root: root });
+    }
+    Ok(expanded)
+}
+
+// Expands the body of the template with its parameters left in place, which reports the same
+// errors as an invocation would
+fn check_template(template: &Tree, templates: &HashMap<String,Tree>) -> Result<(),ParseError> {
+    let mut args = HashMap::new();
+    for param in template.params.iter() {
+        if args.insert(param.clone(), Value::Parameter(param.clone())).is_some() {
+            let message = format!("Parameter {} declared twice in tree {}", param, template.name);
+            return Err(ParseError::InvalidArgument { message: message });
+        }
+    }
+    let mut stack = vec![template.name.clone()];
+    expand_node(&mut template.root.clone(), &args, templates, &mut stack)
+}
+
+// Expands the node and its descendants in place
+fn expand_node(node: &mut Node, args: &HashMap<String,Value>, templates: &HashMap<String,Tree>,
+               stack: &mut Vec<String>) -> Result<(),ParseError> {
+    if let Node::SubtreeCall(..) = *node {
+        let (name, call_args) = match mem::replace(node, Node::Sequence(Vec::new())) {
+            Node::SubtreeCall(name, call_args) => (name, call_args),
+            _ => unreachable!(),
+        };
+        *node = try!(expand_call(name, call_args, args, templates, stack));
+        return Ok(());
+    }
+    match *node {
+        Node::Leaf(_, ref mut options, _) | Node::Switch(ref mut options, _, _) => {
+            if let Some(value) = options.take() {
+                *options = Some(try!(substitute(value, args, stack)));
+            }
+        }
+        Node::Subtree(ref name) => {
+            if let Some(template) = templates.get(name) {
+                let message = format!("Missing arguments {} for subtree {}",
+                                      template.params.join(", "), name);
+                return Err(ParseError::InvalidArgument { message: message });
+            }
+        }
+        _ => {}
+    }
+    for child in node.children_mut() {
+        try!(expand_node(child, args, templates, stack));
+    }
+    Ok(())
+}
+
+// Replaces an invocation of a template by its expanded root
+fn expand_call(name: String, call_args: Vec<(String,Value)>, args: &HashMap<String,Value>,
+               templates: &HashMap<String,Tree>, stack: &mut Vec<String>) -> Result<Node,ParseError> {
+    let template = match templates.get(&name) {
+        Some(template) => template,
+        None if call_args.is_empty() => return Ok(Node::Subtree(name)),
+        None => {
+            let message = format!("Subtree {} does not take any argument", name);
+            return Err(ParseError::InvalidArgument { message: message });
+        }
+    };
+    if stack.iter().any(|n| *n == name) {
+        let mut path = stack.clone();
+        path.push(name);
+        return Err(ParseError::SubtreeCycle { path: path });
+    }
+    let new_args = try!(bind_arguments(template, call_args, args, stack));
+    let mut root = template.root.clone();
+    stack.push(name);
+    try!(expand_node(&mut root, &new_args, templates, stack));
+    stack.pop();
+    Ok(root)
+}
+
+// Matches the arguments of an invocation with the parameters of the template. The values of the
+// arguments can themselves reference the parameters of the calling tree.
+fn bind_arguments(template: &Tree, call_args: Vec<(String,Value)>, args: &HashMap<String,Value>,
+                  stack: &[String]) -> Result<HashMap<String,Value>,ParseError> {
+    let mut bound = HashMap::new();
+    for (key, value) in call_args {
+        if !template.params.contains(&key) {
+            let message = format!("Unknown argument {} for subtree {}, expected {}",
+                                  key, template.name, template.params.join(", "));
+            return Err(ParseError::InvalidArgument { message: message });
+        }
+        let value = try!(substitute(value, args, stack));
+        if bound.insert(key.clone(), value).is_some() {
+            let message = format!("Argument {} given twice for subtree {}", key, template.name);
+            return Err(ParseError::InvalidArgument { message: message });
+        }
+    }
+    let missing: Vec<&str> = template.params.iter()
+                                     .filter(|param| !bound.contains_key(*param))
+                                     .map(|param| param.as_str())
+                                     .collect();
+    if !missing.is_empty() {
+        let message = format!("Missing arguments {} for subtree {}", missing.join(", "), template.name);
+        return Err(ParseError::InvalidArgument { message: message });
+    }
+    Ok(bound)
+}
+
+fn substitute(value: Value, args: &HashMap<String,Value>, stack: &[String]) -> Result<Value,ParseError> {
+    let substituted = match value {
+        Value::Parameter(name) => {
+            match args.get(&name) {
+                Some(value) => value.clone(),
+                None => {
+                    let tree = stack.last().map(|name| name.as_str()).unwrap_or("");
+                    let message = format!("Unknown parameter ${} in tree {}", name, tree);
+                    return Err(ParseError::InvalidArgument { message: message });
+                }
+            }
+        }
+        Value::Map(map) => {
+            let mut new_map = HashMap::with_capacity(map.len());
+            for (key, value) in map {
+                new_map.insert(key, try!(substitute(value, args, stack)));
+            }
+            Value::Map(new_map)
+        }
+        Value::Array(array) => {
+            let mut new_array = Vec::with_capacity(array.len());
+            for value in array {
+                new_array.push(try!(substitute(value, args, stack)));
+            }
+            Value::Array(new_array)
+        }
+        other => other,
+    };
+    Ok(substituted)
+}
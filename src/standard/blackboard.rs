@@ -0,0 +1,206 @@
+use tree::{BehaviourTreeNode,NodeError,VisitResult};
+use standard::{Context,Gettable,StoreKind};
+
+/// A context where variables can be namespaced in nested scopes
+pub trait Scoped {
+    fn push_scope(&mut self, scope: &str);
+    fn pop_scope(&mut self);
+}
+
+/// Context layered over another one, storing the variables of each scope under a prefixed key
+///
+/// A variable of the scope `patrol` nested in `guard` is stored as `guard/patrol/name` in the
+/// inner context. Reading a variable looks for it from the innermost scope outwards, down to the
+/// unscoped variables, while new variables are always created in the innermost scope. Two sibling
+/// scopes can therefore use the same variable names without clashing.
+#[derive(Debug,Clone)]
+pub struct Blackboard<C> {
+    inner: C,
+    scopes: Vec<String>,
+}
+
+impl <C> Blackboard<C> {
+    pub fn new(inner: C) -> Blackboard<C> {
+        Blackboard {
+            inner: inner,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    // Key of `name` in the scope made of the `depth` outermost scopes
+    fn scoped_key(&self, depth: usize, name: &str) -> String {
+        let mut key = String::new();
+        for scope in &self.scopes[..depth] {
+            key.push_str(scope);
+            key.push('/');
+        }
+        key.push_str(name);
+        key
+    }
+}
+
+impl <C> Scoped for Blackboard<C> {
+    fn push_scope(&mut self, scope: &str) {
+        self.scopes.push(String::from(scope));
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop().expect("Popped a scope that was not pushed");
+    }
+}
+
+impl <C> Gettable<str,StoreKind> for Blackboard<C>
+where C: Gettable<str,StoreKind> {
+    fn get(&self, name: &str) -> Option<&StoreKind> {
+        for depth in (0..self.scopes.len() + 1).rev() {
+            if let Some(value) = self.inner.get(&self.scoped_key(depth, name)) {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+impl <C: Context> Context for Blackboard<C> {
+    fn insert_value(&mut self, name: String, value: StoreKind) {
+        let key = self.scoped_key(self.scopes.len(), &name);
+        self.inner.insert_value(key, value);
+    }
+
+    fn set_value(&mut self, name: &str, value: StoreKind) -> Result<(),()> {
+        for depth in (0..self.scopes.len() + 1).rev() {
+            let key = self.scoped_key(depth, name);
+            if self.inner.get(&key).is_some() {
+                return self.inner.set_value(&key, value);
+            }
+        }
+        Err(())
+    }
+}
+
+/// Visits its child in its own scope of the context
+///
+/// The scope is pushed before every visit of the child and popped right after, so the child can
+/// be a whole subtree, for instance an `OptimizedTree` used as a leaf.
+#[derive(Debug,Clone)]
+pub struct BlackboardNode<T> {
+    pub scope: String,
+    pub child: T,
+}
+
+impl <T> BlackboardNode<T> {
+    pub fn new(scope: &str, child: T) -> BlackboardNode<T> {
+        BlackboardNode {
+            scope: String::from(scope),
+            child: child,
+        }
+    }
+}
+
+impl <C,T> BehaviourTreeNode<C> for BlackboardNode<T>
+where C: Scoped,
+      T: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        context.push_scope(&self.scope);
+        let result = self.child.visit(context);
+        context.pop_scope();
+        result
+    }
+
+    /// The scope is left even if `child` returns an error
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        context.push_scope(&self.scope);
+        let result = self.child.try_visit(context);
+        context.pop_scope();
+        result
+    }
+
+    fn select_child(&mut self, context: &mut C) -> Option<usize> {
+        context.push_scope(&self.scope);
+        let index = self.child.select_child(context);
+        context.pop_scope();
+        index
+    }
+
+    /// The result is stored in the scope of the node
+    fn store_result(&mut self, context: &mut C, variable: &str, result: VisitResult) {
+        context.push_scope(&self.scope);
+        self.child.store_result(context, variable, result);
+        context.pop_scope();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tree::{BehaviourTreeNode,VisitResult};
+    use standard::{Context,Gettable,Number,SetVariable,StoreKind,StoreResult,SwitchIndex,Variable};
+    use super::{Blackboard,BlackboardNode,Scoped};
+
+    fn number(value: i64) -> StoreKind {
+        StoreKind::Number(Number::Int(value))
+    }
+
+    fn set(name: &str, value: i64) -> SetVariable {
+        SetVariable { variable: String::from(name), value: number(value) }
+    }
+
+    #[test]
+    fn sibling_scopes() {
+        let mut board: Blackboard<HashMap<String,StoreKind>> = Blackboard::new(HashMap::new());
+        board.insert_value(String::from("target"), number(0));
+        let mut first = BlackboardNode::new("first", set("target", 1));
+        let mut second = BlackboardNode::new("second", set("target", 2));
+        assert_eq!(first.visit(&mut board), VisitResult::Success);
+        assert_eq!(second.visit(&mut board), VisitResult::Success);
+        // Each scope got its own variable, and the unscoped one is untouched
+        assert_eq!(Gettable::get(&board, "target"), Some(&number(0)));
+        board.push_scope("first");
+        assert_eq!(Gettable::get(&board, "target"), Some(&number(1)));
+        board.pop_scope();
+        board.push_scope("second");
+        assert_eq!(Gettable::get(&board, "target"), Some(&number(2)));
+        board.pop_scope();
+        let inner = board.into_inner();
+        assert_eq!(inner.get("first/target"), Some(&number(1)));
+        assert_eq!(inner.get("second/target"), Some(&number(2)));
+    }
+
+    #[test]
+    fn forwarded_hooks() {
+        let mut board: Blackboard<HashMap<String,StoreKind>> = Blackboard::new(HashMap::new());
+        let mut writer = BlackboardNode::new("guard", StoreResult);
+        writer.store_result(&mut board, "done", VisitResult::Success);
+        let mut selector = BlackboardNode::new("guard", SwitchIndex { variable: Variable::new("done") });
+        assert_eq!(selector.select_child(&mut board), Some(1));
+        assert_eq!(selector.try_visit(&mut board), Ok(VisitResult::Success));
+        let inner = board.into_inner();
+        assert_eq!(inner.get("guard/done"), Some(&number(1)));
+        assert_eq!(inner.len(), 1);
+    }
+
+    #[test]
+    fn outer_variables() {
+        let mut board: Blackboard<HashMap<String,StoreKind>> = Blackboard::new(HashMap::new());
+        board.insert_value(String::from("alert"), StoreKind::Bool(false));
+        board.push_scope("guard");
+        board.push_scope("patrol");
+        // Variables of the outer scopes are visible, and modified in place
+        assert_eq!(Gettable::get(&board, "alert"), Some(&StoreKind::Bool(false)));
+        assert_eq!(board.set_value("alert", StoreKind::Bool(true)), Ok(()));
+        assert_eq!(board.set_value("missing", StoreKind::Bool(true)), Err(()));
+        board.pop_scope();
+        board.pop_scope();
+        assert_eq!(Gettable::get(&board, "alert"), Some(&StoreKind::Bool(true)));
+        assert_eq!(board.inner().len(), 1);
+    }
+}
@@ -0,0 +1,125 @@
+use tree::FrameCounter;
+use standard::{Context,Gettable,KeyedContext,StoreKind};
+
+/// Context layered over another one, adding a frame counter
+///
+/// The counter starts at 0 and is advanced by `OptimizedTree::tick`. Several trees sharing the
+/// same context must not be ticked, or the clock would advance once per tree: advance it once per
+/// frame with `next_tick`, and then visit the trees.
+#[derive(Debug,Clone,Default)]
+pub struct Clocked<C> {
+    inner: C,
+    tick: u64,
+}
+
+impl <C> Clocked<C> {
+    pub fn new(inner: C) -> Clocked<C> {
+        Clocked {
+            inner: inner,
+            tick: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl <C> FrameCounter for Clocked<C> {
+    fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    fn next_tick(&mut self) {
+        self.tick += 1;
+    }
+}
+
+impl <C> Gettable<str,StoreKind> for Clocked<C>
+where C: Gettable<str,StoreKind> {
+    fn get(&self, name: &str) -> Option<&StoreKind> {
+        self.inner.get(name)
+    }
+}
+
+impl <C: Context> Context for Clocked<C> {
+    fn insert_value(&mut self, name: String, value: StoreKind) {
+        self.inner.insert_value(name, value)
+    }
+
+    fn set_value(&mut self, name: &str, value: StoreKind) -> Result<(),()> {
+        self.inner.set_value(name, value)
+    }
+
+    fn as_keyed(&self) -> Option<&KeyedContext> {
+        self.inner.as_keyed()
+    }
+
+    fn as_keyed_mut(&mut self) -> Option<&mut KeyedContext> {
+        self.inner.as_keyed_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tree::{FrameCounter,VisitResult};
+    use standard::{Context,Gettable,LeavesCollection,Number,StoreKind};
+    use super::Clocked;
+
+    type TestContext = Clocked<HashMap<String,StoreKind>>;
+
+    #[test]
+    fn tick() {
+        let mut leaves = LeavesCollection::<TestContext>::new();
+        leaves.register_closure_leaf("record_tick", || {
+            |context: &mut TestContext| {
+                let tick = Number::Int(context.current_tick() as i64);
+                context.insert_value(String::from("tick"), StoreKind::Number(tick));
+                VisitResult::Success
+            }
+        });
+        let trees = ::parse("tree main { record_tick }", &leaves).unwrap();
+        let mut tree = trees[0].optimize();
+        let mut context = Clocked::new(HashMap::new());
+        for expected in 1..4 {
+            assert_eq!(tree.tick(&mut context), VisitResult::Success);
+            let tick = StoreKind::Number(Number::Int(expected));
+            assert_eq!(Gettable::get(&context, "tick"), Some(&tick));
+        }
+        assert_eq!(context.current_tick(), 3);
+    }
+
+    #[test]
+    fn shared_clock() {
+        let mut leaves = LeavesCollection::<TestContext>::new();
+        leaves.register_closure_leaf("count_ticks", || {
+            |context: &mut TestContext| {
+                let tick = Number::Int(context.current_tick() as i64);
+                if let Err(()) = context.set_value("last", StoreKind::Number(tick)) {
+                    context.insert_value(String::from("last"), StoreKind::Number(tick));
+                }
+                VisitResult::Success
+            }
+        });
+        let trees = ::parse("tree first { count_ticks } tree second { count_ticks }", &leaves).unwrap();
+        let mut trees: Vec<_> = trees.iter().map(|tree| tree.optimize()).collect();
+        let mut context = Clocked::new(HashMap::new());
+        for _ in 0..2 {
+            context.next_tick();
+            for tree in trees.iter_mut() {
+                assert_eq!(tree.visit(&mut context), VisitResult::Success);
+            }
+        }
+        assert_eq!(Gettable::get(&context, "last"), Some(&StoreKind::Number(Number::Int(2))));
+    }
+}
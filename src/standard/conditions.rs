@@ -1,49 +1,137 @@
-use tree::{LeafNode,Context,VisitResult,BehaviourTreeNode};
-use standard::{Value,LeafNodeFactory};
-use standard::expressions::{self,PostfixedExpression};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use tree::{VisitResult,BehaviourTreeNode,NodeError};
+use parser::{Comparison,Value};
+use standard::{Context,Number,StandardFactory};
+use standard::expressions::{self,EvalError,KeyedExpression};
 
 #[derive(Debug,Clone,Copy)]
 enum CondOp {
     SuperiorStrict,
     InferiorStrict,
     Equal,
+    NotEqual,
     Superior,
     Inferior,
 }
 
-struct ConditionChecker {
-    exp1: PostfixedExpression,
-    exp2: PostfixedExpression,
+#[derive(Debug,Clone)]
+pub struct ConditionChecker {
+    exp1: KeyedExpression,
+    exp2: KeyedExpression,
     operator: CondOp,
 }
 
-impl BehaviourTreeNode for ConditionChecker {
-    fn visit(&mut self, context: &mut Context) -> VisitResult {
-        let result_1 = expressions::evaluate_expression_int(context, &self.exp1);
-        let result_2 = expressions::evaluate_expression_int(context, &self.exp2);
-        if check_condition(result_1, result_2, self.operator) {
-            VisitResult::Success
-        } else {
-            VisitResult::Failure
+impl ConditionChecker {
+    fn evaluate<C: ?Sized + Context>(&mut self, context: &mut C) -> Result<bool,EvalError> {
+        let result_1 = try!(self.exp1.evaluate(context));
+        let result_2 = try!(self.exp2.evaluate(context));
+        Ok(check_condition(result_1, result_2, self.operator))
+    }
+}
+
+/// Evaluation errors are returned as errors by `try_visit`, and are failures for `visit`.
+impl <C: Context> BehaviourTreeNode<C> for ConditionChecker {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        match self.evaluate(context) {
+            Ok(true) => Ok(VisitResult::Success),
+            Ok(false) => Ok(VisitResult::Failure),
+            Err(e) => Err(NodeError::new(format!("Could not evaluate condition: {}", e))),
+        }
+    }
+}
+
+/// Combination of comparisons with and, or and not
+///
+/// Operands are evaluated from left to right, and only until the result is known: an `and` stops
+/// at the first false operand, an `or` at the first true one.
+#[derive(Debug,Clone)]
+enum BoolExpression {
+    Comparison(ConditionChecker),
+    And(Vec<BoolExpression>),
+    Or(Vec<BoolExpression>),
+    Not(Box<BoolExpression>),
+}
+
+impl BoolExpression {
+    fn evaluate<C: ?Sized + Context>(&mut self, context: &mut C) -> Result<bool,EvalError> {
+        match *self {
+            BoolExpression::Comparison(ref mut comparison) => comparison.evaluate(context),
+            BoolExpression::And(ref mut operands) => {
+                for operand in operands.iter_mut() {
+                    if !try!(operand.evaluate(context)) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            BoolExpression::Or(ref mut operands) => {
+                for operand in operands.iter_mut() {
+                    if try!(operand.evaluate(context)) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            BoolExpression::Not(ref mut operand) => operand.evaluate(context).map(|result| !result),
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct BoolConditionChecker {
+    expression: BoolExpression,
+}
+
+/// Same as `ConditionChecker`, evaluation errors are returned as errors by `try_visit`
+impl <C: Context> BehaviourTreeNode<C> for BoolConditionChecker {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        match self.expression.evaluate(context) {
+            Ok(true) => Ok(VisitResult::Success),
+            Ok(false) => Ok(VisitResult::Failure),
+            Err(e) => Err(NodeError::new(format!("Could not evaluate boolean condition: {}", e))),
         }
     }
 }
 
-fn check_condition(exp1: i64, exp2: i64, operator: CondOp) -> bool {
+// Integers and floats can be compared, but any comparison involving NaN is false
+fn check_condition(exp1: Number, exp2: Number, operator: CondOp) -> bool {
+    let ordering = match exp1.compare(exp2) {
+        Some(ordering) => ordering,
+        None => return false,
+    };
     match operator {
-        CondOp::SuperiorStrict => exp1 > exp2,
-        CondOp::InferiorStrict => exp1 < exp2,
-        CondOp::Equal => exp1 == exp2,
-        CondOp::Superior => exp1 >= exp2,
-        CondOp::Inferior => exp1 <= exp2,
+        CondOp::SuperiorStrict => ordering == Ordering::Greater,
+        CondOp::InferiorStrict => ordering == Ordering::Less,
+        CondOp::Equal => ordering == Ordering::Equal,
+        CondOp::NotEqual => ordering != Ordering::Equal,
+        CondOp::Superior => ordering != Ordering::Less,
+        CondOp::Inferior => ordering != Ordering::Greater,
     }
 }
 
-pub fn check_condition_node(options: &Option<Value>) -> Result<LeafNodeFactory, String> {
+pub fn check_condition_node<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_check_condition(options).map(super::prototype)
+}
+
+pub fn parse_check_condition(options: &Option<Value>) -> Result<ConditionChecker, String> {
     let options_map = match options {
         &Some(Value::Map(ref map)) => map,
         other => return Err(format!("Expected hashmap, found {:?}", other)),
     };
+    parse_comparison(options_map)
+}
+
+fn parse_comparison(options_map: &HashMap<String,Value>) -> Result<ConditionChecker,String> {
     let exp1 = match options_map.get("exp1") {
         None => return Err("Expected value for key exp1".to_string()),
         Some(&Value::Array(ref array)) => try!(expressions::generate_postfixed_expression(array)),
@@ -56,12 +144,14 @@ pub fn check_condition_node(options: &Option<Value>) -> Result<LeafNodeFactory,
     };
     let operator = match options_map.get("operator") {
         None => return Err("Expected value for key operator".to_string()),
-        Some(&Value::Unknown(op)) => {
-            match op {
-                '>' => CondOp::SuperiorStrict,
-                '<' => CondOp::InferiorStrict,
-                '=' => CondOp::Equal,
-                other => return Err(format!("Expected operator, found {}", other)),
+        Some(&Value::Comparison(comparison)) => {
+            match comparison {
+                Comparison::Greater => CondOp::SuperiorStrict,
+                Comparison::Less => CondOp::InferiorStrict,
+                Comparison::Equal => CondOp::Equal,
+                Comparison::NotEqual => CondOp::NotEqual,
+                Comparison::GreaterOrEqual => CondOp::Superior,
+                Comparison::LessOrEqual => CondOp::Inferior,
             }
         }
         Some(&Value::String(ref op)) => {
@@ -69,6 +159,7 @@ pub fn check_condition_node(options: &Option<Value>) -> Result<LeafNodeFactory,
                 ">" => CondOp::SuperiorStrict,
                 "<" => CondOp::InferiorStrict,
                 "=" => CondOp::Equal,
+                "!=" | "≠" => CondOp::NotEqual,
                 ">=" => CondOp::Superior,
                 "<=" => CondOp::Inferior,
                 other => return Err(format!("Expected operator, found {}", other)),
@@ -76,9 +167,265 @@ pub fn check_condition_node(options: &Option<Value>) -> Result<LeafNodeFactory,
         }
         Some(other) => return Err(format!("Expected operator, found {:?}", other)),
     };
-    Ok(Box::new(move || LeafNode::new(Box::new(ConditionChecker {
-        exp1: exp1.clone(),
-        exp2: exp2.clone(),
-        operator: operator.clone(),
-    }))))
+    Ok(ConditionChecker {
+        exp1: KeyedExpression::new(exp1),
+        exp2: KeyedExpression::new(exp2),
+        operator: operator,
+    })
+}
+
+/// Creates a leaf evaluating a boolean expression, written as nested maps:
+///
+/// - `{ and: [ e1 e2 ... ] }` and `{ or: [ e1 e2 ... ] }` combine one or more expressions
+/// - `{ not: e }` negates an expression
+/// - any other map is a comparison, with the same keys as the options of `condition`
+///
+/// For instance `bool_condition(and: [ { exp1: [a], operator: ">", exp2: [b] }
+/// { not: { exp1: [c], operator: ">=", exp2: [d] } } ])` checks `a > b and c < d`.
+pub fn bool_condition_node<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_bool_condition(options).map(super::prototype)
+}
+
+pub fn parse_bool_condition(options: &Option<Value>) -> Result<BoolConditionChecker, String> {
+    let expression = match *options {
+        Some(ref value) => try!(parse_bool_expression(value)),
+        None => return Err(String::from("Expected boolean expression, found nothing")),
+    };
+    Ok(BoolConditionChecker { expression: expression })
+}
+
+fn parse_bool_expression(value: &Value) -> Result<BoolExpression,String> {
+    let map = match *value {
+        Value::Map(ref map) => map,
+        ref other => return Err(format!("Expected hashmap, found {:?}", other)),
+    };
+    let operator = ["and", "or", "not"].iter().cloned().find(|key| map.contains_key(*key));
+    let operator = match operator {
+        Some(operator) => operator,
+        None => return parse_comparison(map).map(BoolExpression::Comparison),
+    };
+    if map.len() != 1 {
+        return Err(format!("Expected only the key {}, found {} keys", operator, map.len()));
+    }
+    let operands = &map[operator];
+    if operator == "not" {
+        return parse_bool_expression(operands).map(|operand| BoolExpression::Not(Box::new(operand)));
+    }
+    let operands = match *operands {
+        Value::Array(ref array) if !array.is_empty() => {
+            try!(array.iter().map(parse_bool_expression).collect::<Result<Vec<_>,_>>())
+        }
+        ref other => return Err(format!("Expected non empty array of operands for {}, found {:?}", operator, other)),
+    };
+    if operator == "and" {
+        Ok(BoolExpression::And(operands))
+    } else {
+        Ok(BoolExpression::Or(operands))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tree::{BehaviourTreeNode,LeafNodeFactory,NodeError,VisitResult};
+    use parser::{Comparison,Value};
+    use standard::{Number,StoreKind};
+    use super::CondOp;
+
+    type TestContext = HashMap<String,StoreKind>;
+
+    fn check(exp1: i64, exp2: i64, operator: CondOp) -> bool {
+        super::check_condition(Number::Int(exp1), Number::Int(exp2), operator)
+    }
+
+    #[test]
+    fn check_condition() {
+        assert!(check(2, 1, CondOp::SuperiorStrict));
+        assert!(!check(1, 1, CondOp::SuperiorStrict));
+        assert!(check(1, 2, CondOp::InferiorStrict));
+        assert!(!check(1, 1, CondOp::InferiorStrict));
+        assert!(check(1, 1, CondOp::Equal));
+        assert!(!check(1, 2, CondOp::Equal));
+        assert!(check(1, 2, CondOp::NotEqual));
+        assert!(!check(1, 1, CondOp::NotEqual));
+        assert!(check(1, 1, CondOp::Superior));
+        assert!(!check(1, 2, CondOp::Superior));
+        assert!(check(1, 1, CondOp::Inferior));
+        assert!(!check(2, 1, CondOp::Inferior));
+    }
+
+    #[test]
+    fn check_condition_float() {
+        assert!(super::check_condition(Number::Float(1.5), Number::Int(1), CondOp::SuperiorStrict));
+        assert!(super::check_condition(Number::Int(2), Number::Float(2.0), CondOp::Equal));
+        let nan = Number::Float(::std::f64::NAN);
+        assert!(!super::check_condition(nan, nan, CondOp::Equal));
+        assert!(!super::check_condition(nan, Number::Int(1), CondOp::Inferior));
+    }
+
+    // Evaluates `x operator 3` with x = 3
+    fn evaluate(operator: Value) -> Result<VisitResult,String> {
+        let mut options = HashMap::new();
+        options.insert(String::from("exp1"), Value::Array(vec![Value::String(String::from("x"))]));
+        options.insert(String::from("exp2"), Value::Array(vec![Value::Integer(3)]));
+        options.insert(String::from("operator"), operator);
+        let factory = try!(super::check_condition_node::<TestContext>(&Some(Value::Map(options))));
+        let mut context = HashMap::new();
+        context.insert(String::from("x"), StoreKind::Number(Number::Int(3)));
+        Ok(factory.instanciate().visit(&mut context))
+    }
+
+    fn string(operator: &str) -> Value {
+        Value::String(String::from(operator))
+    }
+
+    #[test]
+    fn string_operators() {
+        assert_eq!(evaluate(string(">")), Ok(VisitResult::Failure));
+        assert_eq!(evaluate(string("<")), Ok(VisitResult::Failure));
+        assert_eq!(evaluate(string("=")), Ok(VisitResult::Success));
+        assert_eq!(evaluate(string(">=")), Ok(VisitResult::Success));
+        assert_eq!(evaluate(string("<=")), Ok(VisitResult::Success));
+        assert_eq!(evaluate(string("!=")), Ok(VisitResult::Failure));
+        assert_eq!(evaluate(string("≠")), Ok(VisitResult::Failure));
+        assert!(evaluate(string("=>")).is_err());
+    }
+
+    #[test]
+    fn evaluation_error() {
+        let mut options = HashMap::new();
+        options.insert(String::from("exp1"), Value::Array(vec![Value::String(String::from("missing"))]));
+        options.insert(String::from("exp2"), Value::Array(vec![Value::Integer(3)]));
+        options.insert(String::from("operator"), string("<"));
+        let factory = super::check_condition_node::<TestContext>(&Some(Value::Map(options))).unwrap();
+        let mut context = HashMap::new();
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+    }
+
+    #[test]
+    fn error_past_selector() {
+        use parser;
+        use standard::{Gettable,LeavesCollection};
+
+        let source = r#"
+            tree main {
+                selector {
+                    condition(exp1: [missing], operator: "<", exp2: [3]),
+                    set_variable(variable: fallback, value: true),
+                }
+            }
+        "#;
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let factory = parser::parse_one(source, &leaves).unwrap();
+        let mut context = HashMap::new();
+        let message = "Could not evaluate condition: undefined variable missing";
+        assert_eq!(factory.optimize().try_visit(&mut context), Err(NodeError::new(message)));
+        assert_eq!(Gettable::get(&context, "fallback"), None);
+        // A plain visit turns the error into a failure of the whole tree
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        assert_eq!(Gettable::get(&context, "fallback"), None);
+        // Boolean conditions report their errors the same way
+        let mut options = HashMap::new();
+        options.insert(String::from("not"), comparison("a", "=", "b"));
+        let factory = super::bool_condition_node::<TestContext>(&Some(Value::Map(options))).unwrap();
+        let message = "Could not evaluate boolean condition: undefined variable a";
+        assert_eq!(factory.instanciate().try_visit(&mut context), Err(NodeError::new(message)));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let comparison = |comparison| evaluate(Value::Comparison(comparison));
+        assert_eq!(comparison(Comparison::Greater), Ok(VisitResult::Failure));
+        assert_eq!(comparison(Comparison::Less), Ok(VisitResult::Failure));
+        assert_eq!(comparison(Comparison::Equal), Ok(VisitResult::Success));
+        assert_eq!(comparison(Comparison::NotEqual), Ok(VisitResult::Failure));
+        assert_eq!(comparison(Comparison::GreaterOrEqual), Ok(VisitResult::Success));
+        assert_eq!(comparison(Comparison::LessOrEqual), Ok(VisitResult::Success));
+        assert!(evaluate(Value::Integer(1)).is_err());
+    }
+
+    fn variable(name: &str) -> Value {
+        Value::Array(vec![Value::String(String::from(name))])
+    }
+
+    fn comparison(exp1: &str, operator: &str, exp2: &str) -> Value {
+        let mut options = HashMap::new();
+        options.insert(String::from("exp1"), variable(exp1));
+        options.insert(String::from("operator"), string(operator));
+        options.insert(String::from("exp2"), variable(exp2));
+        Value::Map(options)
+    }
+
+    fn operation(operator: &str, operand: Value) -> Value {
+        let mut options = HashMap::new();
+        options.insert(String::from(operator), operand);
+        Value::Map(options)
+    }
+
+    // Evaluates `expression` with a = 1, b = 2
+    fn evaluate_bool(expression: Value) -> VisitResult {
+        let factory = super::bool_condition_node::<TestContext>(&Some(expression)).unwrap();
+        let mut context = HashMap::new();
+        context.insert(String::from("a"), StoreKind::Number(Number::Int(1)));
+        context.insert(String::from("b"), StoreKind::Number(Number::Int(2)));
+        factory.instanciate().visit(&mut context)
+    }
+
+    #[test]
+    fn bool_condition_and() {
+        let both = Value::Array(vec![comparison("b", ">", "a"), comparison("a", "<", "b")]);
+        assert_eq!(evaluate_bool(operation("and", both)), VisitResult::Success);
+        let one = Value::Array(vec![comparison("b", ">", "a"), comparison("a", ">", "b")]);
+        assert_eq!(evaluate_bool(operation("and", one.clone())), VisitResult::Failure);
+        assert_eq!(evaluate_bool(operation("or", one)), VisitResult::Success);
+    }
+
+    #[test]
+    fn bool_condition_short_circuit() {
+        // The second operand would fail because of the undefined variable, but is not evaluated
+        let and = Value::Array(vec![comparison("a", ">", "b"), comparison("missing", "=", "a")]);
+        assert_eq!(evaluate_bool(operation("not", operation("and", and))), VisitResult::Success);
+        let or = Value::Array(vec![comparison("a", "<", "b"), comparison("missing", "=", "a")]);
+        assert_eq!(evaluate_bool(operation("or", or)), VisitResult::Success);
+        // Evaluation errors make the node fail
+        let or = Value::Array(vec![comparison("a", ">", "b"), comparison("missing", "=", "a")]);
+        assert_eq!(evaluate_bool(operation("not", operation("or", or))), VisitResult::Failure);
+    }
+
+    #[test]
+    fn bool_condition_not() {
+        assert_eq!(evaluate_bool(operation("not", comparison("a", "=", "b"))), VisitResult::Success);
+        assert_eq!(evaluate_bool(operation("not", comparison("a", "<", "b"))), VisitResult::Failure);
+        let double = operation("not", operation("not", comparison("a", "<", "b")));
+        assert_eq!(evaluate_bool(double), VisitResult::Success);
+    }
+
+    #[test]
+    fn parsed_not_equal() {
+        use parser;
+        use standard::LeavesCollection;
+
+        let source = "tree t { condition({ exp1: [a], operator: !=, exp2: [b] }) }";
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let mut tree = parser::parse(source, &leaves).unwrap()[0].optimize();
+        let mut context = HashMap::new();
+        context.insert(String::from("a"), StoreKind::Number(Number::Int(1)));
+        context.insert(String::from("b"), StoreKind::Number(Number::Int(2)));
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+        context.insert(String::from("b"), StoreKind::Number(Number::Int(1)));
+        assert_eq!(tree.visit(&mut context), VisitResult::Failure);
+    }
+
+    #[test]
+    fn bool_condition_invalid() {
+        let empty = operation("and", Value::Array(Vec::new()));
+        assert!(super::bool_condition_node::<TestContext>(&Some(empty)).is_err());
+        let mut mixed = HashMap::new();
+        mixed.insert(String::from("not"), comparison("a", "=", "b"));
+        mixed.insert(String::from("exp1"), variable("a"));
+        assert!(super::bool_condition_node::<TestContext>(&Some(Value::Map(mixed))).is_err());
+        assert!(super::bool_condition_node::<TestContext>(&None).is_err());
+    }
 }
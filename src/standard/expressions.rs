@@ -1,5 +1,9 @@
-use tree::{LeafNode,Context,VisitResult,StoreKind,BehaviourTreeNode};
-use standard::{Value,LeafNodeFactory,Operator};
+use std::error::Error;
+use std::fmt::{self,Display,Formatter};
+
+use tree::{VisitResult,BehaviourTreeNode,NodeError};
+use parser::{Value,Operator};
+use standard::{self,Context,Gettable,Number,StoreKind,StandardFactory};
 use self::PostfixedExpressionMember::*;
 
 // Postfixed expression notation
@@ -9,34 +13,83 @@ use self::PostfixedExpressionMember::*;
 // Few examples:
 // 1 3 + 3 4 + *    => (1 + 3) * (3 + 4)
 // 1 2 3 4 5 6 + * + * + => 1 + (2 * (3 + (4 * (5 + 6))))
-#[derive(Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum PostfixedExpressionMember {
     Op(Operator),
-    Constant(i64),
+    Constant(Number),
     Variable(String),
 }
 
-struct ExpressionEvaluator {
-    expression: Vec<PostfixedExpressionMember>,
-    variable: String,
+/// Leaf evaluating an expression, and storing its result in `variable`
+#[derive(Debug,Clone)]
+pub struct ExpressionEvaluator {
+    expression: KeyedExpression,
+    variable: standard::Variable,
 }
 
-impl BehaviourTreeNode for ExpressionEvaluator {
-    fn visit(&mut self, context: &mut Context) -> VisitResult {
-        let result = evaluate_expression_int(context, &self.expression);
-        let old = context.map.insert(self.variable.clone(),StoreKind::I64(result));
-        if let Some(other) = old {
-            println!("WARNING: replaced variable {}, which contained {:?} by {}", self.variable, other, result);
-        } else {
-            println!("Set variable {} to {}", self.variable, result);
+/// Evaluation errors, like a division by zero, are returned as errors by `try_visit`, and are
+/// failures for `visit`.
+impl <C: Context> BehaviourTreeNode<C> for ExpressionEvaluator {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        let result = match self.expression.evaluate(context) {
+            Ok(result) => result,
+            Err(e) => {
+                let message = format!("Could not evaluate expression for variable {}: {}",
+                                      self.variable.name(), e);
+                return Err(NodeError::new(message));
+            }
+        };
+        match self.variable.get(context) {
+            Some(other) => {
+                println!("WARNING: replaced variable {}, which contained {:?} by {}",
+                         self.variable.name(), other, result);
+            }
+            None => println!("Set variable {} to {}", self.variable.name(), result),
         }
-        VisitResult::Success
+        self.variable.insert(context, StoreKind::Number(result));
+        Ok(VisitResult::Success)
     }
 }
 
 pub type PostfixedExpression = Vec<PostfixedExpressionMember>;
 
-pub fn evaluate_int_node(options: &Option<Value>) -> Result<LeafNodeFactory, String> {
+/// Postfixed expression remembering the keys of its variables, so that they are read without
+/// looking up their names on keyed contexts
+#[derive(Debug,Clone)]
+pub struct KeyedExpression {
+    members: PostfixedExpression,
+    // One entry per `Variable` member, in order
+    variables: Vec<standard::Variable>,
+}
+
+impl KeyedExpression {
+    pub fn new(members: PostfixedExpression) -> KeyedExpression {
+        let variables = members.iter().filter_map(|member| match *member {
+            Variable(ref name) => Some(standard::Variable::new(name)),
+            _ => None,
+        }).collect();
+        KeyedExpression {
+            members: members,
+            variables: variables,
+        }
+    }
+
+    /// Same as `evaluate_expression`
+    pub fn evaluate<C: ?Sized + Context>(&mut self, context: &mut C) -> Result<Number,EvalError> {
+        let KeyedExpression { ref members, ref mut variables } = *self;
+        evaluate_with(members, |index, name| number(name, variables[index].get(context)))
+    }
+}
+
+pub fn evaluate_int_node<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_evaluate_int(options).map(super::prototype)
+}
+
+pub fn parse_evaluate_int(options: &Option<Value>) -> Result<ExpressionEvaluator, String> {
     let options_map = match options {
         &Some(Value::Map(ref map)) => map,
         other => return Err(format!("Expected hashmap, found {:?}", other)),
@@ -52,10 +105,10 @@ pub fn evaluate_int_node(options: &Option<Value>) -> Result<LeafNodeFactory, Str
         Some(&Value::String(ref key)) => key.clone(),
         other => return Err(format!("Expected variable name, found {:?}", other)),
     };
-    Ok(Box::new(move || LeafNode::new(Box::new(ExpressionEvaluator {
-        variable: variable.clone(),
-        expression: expression.clone(),
-    }))))
+    Ok(ExpressionEvaluator {
+        variable: standard::Variable::new(&variable),
+        expression: KeyedExpression::new(expression),
+    })
 }
 
 pub fn generate_postfixed_expression(array: &[Value]) -> Result<Vec<PostfixedExpressionMember>,String> {
@@ -65,7 +118,7 @@ pub fn generate_postfixed_expression(array: &[Value]) -> Result<Vec<PostfixedExp
             Value::String(ref op) => {
                 res.push(Variable(op.clone()))
             }
-            Value::Integer(value) => res.push(Constant(value)),
+            Value::Integer(value) => res.push(Constant(Number::Int(value))),
             Value::Operator(op) => {
                 res.push(Op(op));
             }
@@ -75,84 +128,312 @@ pub fn generate_postfixed_expression(array: &[Value]) -> Result<Vec<PostfixedExp
     Ok(res)
 }
 
-pub fn evaluate_expression_int(context: &Context, expression: &[PostfixedExpressionMember]) -> i64 {
+/// Error that can happen while evaluating a postfixed expression
+#[derive(Debug,Clone,PartialEq)]
+pub enum EvalError {
+    /// An operator did not find its two operands
+    StackUnderflow,
+    /// The expression left several values instead of a single result
+    LeftoverOperands {
+        count: usize,
+    },
+    MissingVariable {
+        name: String,
+    },
+    /// A variable does not contain a number
+    TypeMismatch {
+        name: String,
+        found: StoreKind,
+    },
+    DivisionByZero,
+    /// An integer raised to a negative power, which does not give an integer
+    NegativeExponent,
+    Overflow,
+    /// The result is a float, while an integer was expected
+    NotAnInteger {
+        value: f64,
+    },
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            EvalError::StackUnderflow => f.write_str("missing operand in expression"),
+            EvalError::LeftoverOperands { count } => {
+                write!(f, "expression leaves {} values instead of one", count)
+            }
+            EvalError::MissingVariable { ref name } => write!(f, "undefined variable {}", name),
+            EvalError::TypeMismatch { ref name, ref found } => {
+                write!(f, "expected number in variable {}, found {:?}", name, found)
+            }
+            EvalError::DivisionByZero => f.write_str("division by zero"),
+            EvalError::NegativeExponent => f.write_str("negative exponent for an integer"),
+            EvalError::Overflow => f.write_str("integer overflow"),
+            EvalError::NotAnInteger { value } => write!(f, "expected integer result, found {}", value),
+        }
+    }
+}
+
+impl Error for EvalError {
+    fn description(&self) -> &str {
+        match *self {
+            EvalError::StackUnderflow => "missing operand in expression",
+            EvalError::LeftoverOperands { .. } => "too many operands in expression",
+            EvalError::MissingVariable { .. } => "undefined variable",
+            EvalError::TypeMismatch { .. } => "variable does not contain a number",
+            EvalError::DivisionByZero => "division by zero",
+            EvalError::NegativeExponent => "negative exponent for an integer",
+            EvalError::Overflow => "integer overflow",
+            EvalError::NotAnInteger { .. } => "expected integer result",
+        }
+    }
+}
+
+/// Same as `evaluate_expression`, but fails if the result is not an integer
+pub fn evaluate_expression_int<C: ?Sized>(context: &C, expression: &[PostfixedExpressionMember])
+-> Result<i64,EvalError>
+where C: Gettable<str,StoreKind> {
+    match try!(evaluate_expression(context, expression)) {
+        Number::Int(value) => Ok(value),
+        Number::Float(value) => Err(EvalError::NotAnInteger { value: value }),
+    }
+}
+
+/// Evaluates a postfixed expression, reading the variables from `context`
+///
+/// The result is an integer if all the operands are integers, and a float otherwise.
+pub fn evaluate_expression<C: ?Sized>(context: &C, expression: &[PostfixedExpressionMember])
+-> Result<Number,EvalError>
+where C: Gettable<str,StoreKind> {
+    evaluate_with(expression, |_, name| number(name, context.get(name)))
+}
+
+fn number(name: &str, value: Option<&StoreKind>) -> Result<Number,EvalError> {
+    match value {
+        Some(&StoreKind::Number(value)) => Ok(value),
+        Some(other) => Err(EvalError::TypeMismatch { name: name.to_string(), found: other.clone() }),
+        None => Err(EvalError::MissingVariable { name: name.to_string() }),
+    }
+}
+
+// `variable` reads the value of the variables, given their position among the variables of the
+// expression and their name
+fn evaluate_with<F>(expression: &[PostfixedExpressionMember], mut variable: F) -> Result<Number,EvalError>
+where F: FnMut(usize, &str) -> Result<Number,EvalError> {
     let mut stack = Vec::new();
+    let mut variables = 0;
     for member in expression.iter() {
         match *member {
             Constant(value) => stack.push(value),
             Variable(ref name) => {
-                let value = match context.map.get::<str>(name.as_ref()) {
-                    Some(&StoreKind::I64(value)) => value,
-                    other => panic!("Expected I64 as variable value, found {:?}", other),
-                };
-                stack.push(value);
+                stack.push(try!(variable(variables, name)));
+                variables += 1;
             },
             Op(operator) => {
                 // First member will be the second one in the stack
-                let member2 = stack.pop().expect("Expected first expression member");
-                let member1 = stack.pop().expect("Expected second expression member");
+                let member2 = try!(stack.pop().ok_or(EvalError::StackUnderflow));
+                let member1 = try!(stack.pop().ok_or(EvalError::StackUnderflow));
                 let result = match operator {
-                    Operator::Plus => member1 + member2,
-                    Operator::Minus => member1 - member2,
-                    Operator::Multiply => member1 * member2,
-                    Operator::Divide => member1 / member2,
+                    Operator::Plus => member1.checked_add(member2),
+                    Operator::Minus => member1.checked_sub(member2),
+                    Operator::Multiply => member1.checked_mul(member2),
+                    Operator::Divide => {
+                        if member2.is_zero() {
+                            return Err(EvalError::DivisionByZero);
+                        }
+                        member1.checked_div(member2)
+                    }
+                    Operator::Power => {
+                        if let (Number::Int(_), Number::Int(exponent)) = (member1, member2) {
+                            if exponent < 0 {
+                                return Err(EvalError::NegativeExponent);
+                            }
+                        }
+                        member1.checked_pow(member2)
+                    }
                 };
-                stack.push(result);
+                stack.push(try!(result.ok_or(EvalError::Overflow)));
             }
         }
     }
-    let result = stack.pop().expect("Unexpected absence of result!");
-    assert!(stack.is_empty());
-    result
+    match stack.len() {
+        0 => Err(EvalError::StackUnderflow),
+        1 => Ok(stack[0]),
+        count => Err(EvalError::LeftoverOperands { count: count }),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
 
-    use tree::{Context,StoreKind};
-    use standard::Operator;
+    use tree::{BehaviourTreeNode,NodeError,VisitResult};
+    use parser::{self,Operator,Value};
+    use parser::ast::Node;
+    use standard::{Gettable,LeavesCollection,Number,StoreKind};
     use super::PostfixedExpressionMember::*;
+    use super::EvalError;
+
+    type TestContext = HashMap<String,StoreKind>;
+
     #[test]
     fn evaluate_int() {
-        let context = Context::new(HashMap::new());
+        let context: TestContext = HashMap::new();
         let expression = vec! [
-            Constant(1),
-            Constant(2),
+            Constant(Number::Int(1)),
+            Constant(Number::Int(2)),
             Op(Operator::Plus),
             ];
-        assert!(super::evaluate_expression_int(&context,&expression) == 3);
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Ok(3));
     }
 
     #[test]
-    #[should_panic]
     fn incorrect_expression() {
-        let context = Context::new(HashMap::new());
+        let context: TestContext = HashMap::new();
         let expression = vec! [
-            Constant(1),
-            Constant(2),
+            Constant(Number::Int(1)),
+            Constant(Number::Int(2)),
             Op(Operator::Plus),
             Op(Operator::Multiply),
             ];
-        super::evaluate_expression_int(&context,&expression);
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Err(EvalError::StackUnderflow));
+    }
+
+    #[test]
+    fn leftover_operands() {
+        let context: TestContext = HashMap::new();
+        let expression = vec! [Constant(Number::Int(1)), Constant(Number::Int(2))];
+        assert_eq!(super::evaluate_expression_int(&context,&expression),
+                   Err(EvalError::LeftoverOperands { count: 2 }));
+        assert_eq!(super::evaluate_expression_int(&context,&[]), Err(EvalError::StackUnderflow));
+    }
+
+    #[test]
+    fn invalid_variables() {
+        let mut context: TestContext = HashMap::new();
+        context.insert("name".to_string(), StoreKind::String("bob".to_string()));
+        let expression = vec! [Variable("missing".to_string())];
+        assert_eq!(super::evaluate_expression_int(&context,&expression),
+                   Err(EvalError::MissingVariable { name: "missing".to_string() }));
+        let expression = vec! [Variable("name".to_string())];
+        assert_eq!(super::evaluate_expression_int(&context,&expression),
+                   Err(EvalError::TypeMismatch {
+                       name: "name".to_string(),
+                       found: StoreKind::String("bob".to_string()),
+                   }));
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        let context: TestContext = HashMap::new();
+        let expression = vec! [Constant(Number::Int(1)), Constant(Number::Int(0)), Op(Operator::Divide)];
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Err(EvalError::DivisionByZero));
+        let expression = vec! [Constant(Number::Int(i64::max_value())), Constant(Number::Int(1)), Op(Operator::Plus)];
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Err(EvalError::Overflow));
     }
 
     #[test]
     fn evaluate_int_variable() {
-        let mut hashmap = HashMap::new();
-        hashmap.insert("forty_two".to_string(), StoreKind::I64(42));
-        hashmap.insert("two".to_string(), StoreKind::I64(2));
-        let context = Context::new(hashmap);
+        let mut context: TestContext = HashMap::new();
+        context.insert("forty_two".to_string(), StoreKind::Number(Number::Int(42)));
+        context.insert("two".to_string(), StoreKind::Number(Number::Int(2)));
         // Calculates 2 * (forty_two / two) - 3
         let expression = vec! [
-            Constant(2),
+            Constant(Number::Int(2)),
             Variable("forty_two".to_string()),
             Variable("two".to_string()),
             Op(Operator::Divide),
             Op(Operator::Multiply),
-            Constant(3),
+            Constant(Number::Int(3)),
             Op(Operator::Minus),
             ];
-        assert!(super::evaluate_expression_int(&context,&expression) == 39);
+        assert_eq!(super::evaluate_expression(&context,&expression), Ok(Number::Int(39)));
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Ok(39));
+    }
+
+    #[test]
+    fn int_stays_int() {
+        let context: TestContext = HashMap::new();
+        let expression = vec! [Constant(Number::Int(7)), Constant(Number::Int(2)), Op(Operator::Divide)];
+        assert_eq!(super::evaluate_expression(&context,&expression), Ok(Number::Int(3)));
+    }
+
+    #[test]
+    fn float_promotion() {
+        let mut context: TestContext = HashMap::new();
+        context.insert("speed".to_string(), StoreKind::Number(Number::Float(1.5)));
+        // Calculates speed * 2 + 1
+        let expression = vec! [
+            Variable("speed".to_string()),
+            Constant(Number::Int(2)),
+            Op(Operator::Multiply),
+            Constant(Number::Int(1)),
+            Op(Operator::Plus),
+            ];
+        assert_eq!(super::evaluate_expression(&context,&expression), Ok(Number::Float(4.0)));
+        assert_eq!(super::evaluate_expression_int(&context,&expression), Err(EvalError::NotAnInteger { value: 4.0 }));
+        let expression = vec! [Variable("speed".to_string()), Constant(Number::Float(0.0)), Op(Operator::Divide)];
+        assert_eq!(super::evaluate_expression(&context,&expression), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn error_past_selector() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"
+            tree main {
+                selector {
+                    evaluate_int(result: ratio, expression: [1 0 /]),
+                    set_variable(variable: fallback, value: true),
+                }
+            }
+        "#;
+        let factory = ::parse_one(input, &leaves).unwrap();
+        let mut tree = factory.optimize();
+        let mut context: TestContext = HashMap::new();
+        let message = "Could not evaluate expression for variable ratio: division by zero";
+        assert_eq!(tree.try_visit(&mut context), Err(NodeError::new(message)));
+        assert_eq!(Gettable::get(&context, "fallback"), None);
+        // A plain visit turns the error into a failure of the whole tree
+        let mut instance = factory.instanciate();
+        assert_eq!(instance.visit(&mut context), VisitResult::Failure);
+        assert_eq!(Gettable::get(&context, "fallback"), None);
+    }
+
+    fn expression(source: &str) -> Vec<super::PostfixedExpressionMember> {
+        let input = format!("tree test {{ leaf({}) }}", source);
+        let trees = parser::parse_ast(&input).unwrap();
+        match trees[0].root {
+            Node::Leaf(_, Some(Value::Array(ref array)), _) => super::generate_postfixed_expression(array).unwrap(),
+            ref other => panic!("Expected a leaf with an array, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_operands() {
+        let context: TestContext = HashMap::new();
+        let negative = expression("[5 -2 -]");
+        assert_eq!(negative, [Constant(Number::Int(5)), Constant(Number::Int(-2)), Op(Operator::Minus)]);
+        assert_eq!(super::evaluate_expression_int(&context, &negative), Ok(7));
+        let positive = expression("[5 2 -]");
+        assert_eq!(positive, [Constant(Number::Int(5)), Constant(Number::Int(2)), Op(Operator::Minus)]);
+        assert_eq!(super::evaluate_expression_int(&context, &positive), Ok(3));
+        let separated = expression("[5, -2, -, -3, -]");
+        assert_eq!(super::evaluate_expression_int(&context, &separated), Ok(10));
+    }
+
+    #[test]
+    fn power() {
+        let context: TestContext = HashMap::new();
+        let cube = expression("[2 3 ^]");
+        assert_eq!(cube, [Constant(Number::Int(2)), Constant(Number::Int(3)), Op(Operator::Power)]);
+        assert_eq!(super::evaluate_expression_int(&context, &cube), Ok(8));
+        assert_eq!(super::evaluate_expression_int(&context, &expression("[2 63 ^]")),
+                   Err(EvalError::Overflow));
+        assert_eq!(super::evaluate_expression_int(&context, &expression("[2 -1 ^]")),
+                   Err(EvalError::NegativeExponent));
+        // Large exponents only overflow for other bases
+        assert_eq!(super::evaluate_expression_int(&context, &expression("[-1 5000000001 ^]")), Ok(-1));
+        let half = vec![Constant(Number::Float(4.0)), Constant(Number::Float(0.5)), Op(Operator::Power)];
+        assert_eq!(super::evaluate_expression(&context, &half), Ok(Number::Float(2.0)));
     }
 }
@@ -0,0 +1,116 @@
+use standard::{Context,Gettable,StoreKind};
+
+/// Context delegating the storage of the variables to a host type through plain functions
+///
+/// This lets the standard leaves run against a game state that is not a `HashMap`: the functions
+/// given to `new` tell where the variables live in the host. They can be closures, as long as they
+/// do not capture anything, any state they need being part of the host itself.
+pub struct HostContext<H> {
+    host: H,
+    get: for<'h> fn(&'h H, &str) -> Option<&'h StoreKind>,
+    insert: fn(&mut H, String, StoreKind),
+    set: fn(&mut H, &str, StoreKind) -> Result<(),()>,
+}
+
+impl <H> HostContext<H> {
+    /// `get`, `insert` and `set` implement the methods of the same name of `Gettable` and `Context`
+    pub fn new(host: H,
+               get: for<'h> fn(&'h H, &str) -> Option<&'h StoreKind>,
+               insert: fn(&mut H, String, StoreKind),
+               set: fn(&mut H, &str, StoreKind) -> Result<(),()>) -> HostContext<H> {
+        HostContext {
+            host: host,
+            get: get,
+            insert: insert,
+            set: set,
+        }
+    }
+
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut H {
+        &mut self.host
+    }
+
+    pub fn into_host(self) -> H {
+        self.host
+    }
+}
+
+impl <H> Gettable<str,StoreKind> for HostContext<H> {
+    fn get(&self, name: &str) -> Option<&StoreKind> {
+        (self.get)(&self.host, name)
+    }
+}
+
+impl <H> Context for HostContext<H> {
+    fn insert_value(&mut self, name: String, value: StoreKind) {
+        (self.insert)(&mut self.host, name, value)
+    }
+
+    fn set_value(&mut self, name: &str, value: StoreKind) -> Result<(),()> {
+        (self.set)(&mut self.host, name, value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use parser;
+    use tree::{BehaviourTreeNode,VisitResult};
+    use standard::{LeavesCollection,Number,StoreKind};
+    use super::HostContext;
+
+    struct Game {
+        variables: HashMap<String,StoreKind>,
+        created: Vec<String>,
+    }
+
+    fn game(variables: HashMap<String,StoreKind>) -> HostContext<Game> {
+        HostContext::new(
+            Game { variables: variables, created: Vec::new() },
+            |game, name| game.variables.get(name),
+            |game, name, value| {
+                game.created.push(name.clone());
+                game.variables.insert(name, value);
+            },
+            |game, name, value| {
+                match game.variables.get_mut(name) {
+                    Some(current) => {
+                        *current = value;
+                        Ok(())
+                    }
+                    None => Err(()),
+                }
+            })
+    }
+
+    const TREE: &'static str = r#"
+        tree main {
+            sequence {
+                print_text("Hello from the host"),
+                set_variable({ variable: alert, value: true }),
+                increment({ variable: score, value: 2 }),
+            }
+        }
+    "#;
+
+    #[test]
+    fn standard_leaves() {
+        let leaves = LeavesCollection::<HostContext<Game>>::standard();
+        let trees = parser::parse(TREE, &leaves).unwrap();
+        let mut tree = trees[0].optimize();
+        let mut variables = HashMap::new();
+        variables.insert(String::from("score"), StoreKind::Number(Number::Int(1)));
+        let mut context = game(variables);
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+        let game = context.into_host();
+        // The existing variable was updated in place, only the new one was created
+        assert_eq!(game.created, ["alert"]);
+        assert_eq!(game.variables.get("alert"), Some(&StoreKind::Bool(true)));
+        assert_eq!(game.variables.get("score"), Some(&StoreKind::Number(Number::Int(3))));
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+use parser::Value;
+use parser::options::FromValue;
+use standard::{Context,Gettable,StoreKind};
+
+// Number of stores created, giving each one its own identifier
+static STORES: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle on a variable of a `KeyedContext`, obtained once with `KeyedContext::intern`
+///
+/// A key is only meaningful for the context that produced it, see `KeyedContext::owns`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct ContextKey {
+    store: u32,
+    index: u32,
+}
+
+impl ContextKey {
+    /// Key of the variable at `index` in the context identified by `store`
+    pub fn new(store: u32, index: u32) -> ContextKey {
+        ContextKey {
+            store: store,
+            index: index,
+        }
+    }
+
+    pub fn store(self) -> u32 {
+        self.store
+    }
+
+    pub fn index(self) -> u32 {
+        self.index
+    }
+}
+
+/// A context whose variables can also be accessed through interned keys
+///
+/// Leaves visited in tight loops can intern their variable names once, and then access the
+/// variables without hashing the names on every tick.
+pub trait KeyedContext: Context {
+    /// Returns the key of the variable `name`, which does not need to exist yet
+    fn intern(&mut self, name: &str) -> ContextKey;
+    /// Whether `key` was returned by `intern` on this context
+    fn owns(&self, key: ContextKey) -> bool;
+    fn get_by_key(&self, key: ContextKey) -> Option<&StoreKind>;
+    /// Same as `Context::set_value`, failing if the variable does not exist
+    fn set_by_key(&mut self, key: ContextKey, value: StoreKind) -> Result<(),()>;
+}
+
+/// A context storing its variables in a vector indexed by their keys
+///
+/// Every store, including a clone, has its own identifier, so the keys of a store are never used
+/// to read another one.
+#[derive(Debug)]
+pub struct KeyedStore {
+    id: u32,
+    names: HashMap<String,ContextKey>,
+    values: Vec<Option<StoreKind>>,
+}
+
+impl Default for KeyedStore {
+    fn default() -> KeyedStore {
+        KeyedStore {
+            id: STORES.fetch_add(1, Ordering::Relaxed) as u32,
+            names: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+/// The clone gets a new identifier, as both stores can then intern different names
+impl Clone for KeyedStore {
+    fn clone(&self) -> KeyedStore {
+        let mut store = KeyedStore::new();
+        store.values = self.values.clone();
+        store.names = self.names.iter()
+                                .map(|(name, key)| (name.clone(), ContextKey::new(store.id, key.index)))
+                                .collect();
+        store
+    }
+}
+
+impl KeyedStore {
+    pub fn new() -> KeyedStore {
+        KeyedStore::default()
+    }
+
+    fn lookup(&self, name: &str) -> Option<ContextKey> {
+        self.names.get(name).cloned()
+    }
+}
+
+impl Gettable<str,StoreKind> for KeyedStore {
+    fn get(&self, name: &str) -> Option<&StoreKind> {
+        self.lookup(name).and_then(|key| self.get_by_key(key))
+    }
+}
+
+impl Context for KeyedStore {
+    fn insert_value(&mut self, name: String, value: StoreKind) {
+        let key = self.intern(&name);
+        self.values[key.index as usize] = Some(value);
+    }
+
+    fn set_value(&mut self, name: &str, value: StoreKind) -> Result<(),()> {
+        match self.lookup(name) {
+            Some(key) => self.set_by_key(key, value),
+            None => Err(()),
+        }
+    }
+
+    fn as_keyed(&self) -> Option<&KeyedContext> {
+        Some(self)
+    }
+
+    fn as_keyed_mut(&mut self) -> Option<&mut KeyedContext> {
+        Some(self)
+    }
+}
+
+impl KeyedContext for KeyedStore {
+    fn intern(&mut self, name: &str) -> ContextKey {
+        if let Some(key) = self.lookup(name) {
+            return key;
+        }
+        let key = ContextKey::new(self.id, self.values.len() as u32);
+        self.names.insert(String::from(name), key);
+        self.values.push(None);
+        key
+    }
+
+    fn owns(&self, key: ContextKey) -> bool {
+        key.store == self.id
+    }
+
+    fn get_by_key(&self, key: ContextKey) -> Option<&StoreKind> {
+        if !self.owns(key) {
+            return None;
+        }
+        self.values.get(key.index as usize).and_then(|value| value.as_ref())
+    }
+
+    fn set_by_key(&mut self, key: ContextKey, value: StoreKind) -> Result<(),()> {
+        if !self.owns(key) {
+            return Err(());
+        }
+        match self.values.get_mut(key.index as usize) {
+            Some(&mut Some(ref mut current)) => {
+                *current = value;
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Name of a variable used by a leaf, with its key in the last `KeyedContext` it was used with
+///
+/// The name is interned the first time the variable is used with a keyed context, and the key is
+/// then reused as long as the leaf is visited with the same context. On other contexts, the
+/// variable is looked up by name.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Variable {
+    name: String,
+    key: Option<ContextKey>,
+}
+
+impl Variable {
+    pub fn new(name: &str) -> Variable {
+        Variable {
+            name: String::from(name),
+            key: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Key of the variable in `context`, if it is keyed
+    fn key<C: ?Sized + Context>(&mut self, context: &mut C) -> Option<ContextKey> {
+        let keyed = match context.as_keyed_mut() {
+            Some(keyed) => keyed,
+            None => return None,
+        };
+        match self.key {
+            Some(key) if keyed.owns(key) => Some(key),
+            _ => {
+                let key = keyed.intern(&self.name);
+                self.key = Some(key);
+                Some(key)
+            }
+        }
+    }
+
+    pub fn get<'a, C: ?Sized + Context>(&mut self, context: &'a mut C) -> Option<&'a StoreKind> {
+        match self.key(context) {
+            Some(key) => context.as_keyed().and_then(|keyed| keyed.get_by_key(key)),
+            None => context.get(self.name.as_str()),
+        }
+    }
+
+    /// Same as `Context::set_value`, failing if the variable does not exist
+    pub fn set<C: ?Sized + Context>(&mut self, context: &mut C, value: StoreKind) -> Result<(),()> {
+        match self.key(context) {
+            Some(key) => context.as_keyed_mut().map_or(Err(()), |keyed| keyed.set_by_key(key, value)),
+            None => context.set_value(&self.name, value),
+        }
+    }
+
+    /// Sets the variable, creating it if it does not exist
+    pub fn insert<C: ?Sized + Context>(&mut self, context: &mut C, value: StoreKind) {
+        if let Err(()) = self.set(context, value.clone()) {
+            context.insert_value(self.name.clone(), value);
+        }
+    }
+}
+
+/// Variables are given by name in the options of the leaves
+impl FromValue for Variable {
+    fn from_value(value: &Value) -> Option<Variable> {
+        match *value {
+            Value::String(ref name) => Some(Variable::new(name)),
+            _ => None,
+        }
+    }
+
+    fn expected() -> String {
+        String::from("a variable name")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tree::VisitResult;
+    use standard::{Context,Gettable,LeavesCollection,Number,StoreKind};
+    use super::{KeyedContext,KeyedStore,Variable};
+
+    #[test]
+    fn intern() {
+        let mut store = KeyedStore::new();
+        let a = store.intern("a");
+        assert_eq!(store.intern("a"), a);
+        assert!(store.intern("b") != a);
+        // Interning does not create the variable
+        assert_eq!(store.get_by_key(a), None);
+        assert_eq!(store.set_by_key(a, StoreKind::Bool(true)), Err(()));
+        store.insert_value(String::from("a"), StoreKind::Bool(false));
+        assert_eq!(store.set_by_key(a, StoreKind::Bool(true)), Ok(()));
+        assert_eq!(Gettable::get(&store, "a"), Some(&StoreKind::Bool(true)));
+    }
+
+    #[test]
+    fn key_and_string_access() {
+        let mut store = KeyedStore::new();
+        let mut map = HashMap::new();
+        let names = ["x", "y", "z"];
+        let keys: Vec<_> = names.iter().map(|name| store.intern(name)).collect();
+        for name in names.iter() {
+            store.insert_value(String::from(*name), StoreKind::Number(Number::Int(0)));
+            map.insert_value(String::from(*name), StoreKind::Number(Number::Int(0)));
+        }
+        for i in 0..10000 {
+            let index = i % names.len();
+            let value = match store.get_by_key(keys[index]) {
+                Some(&StoreKind::Number(value)) => value + Number::Int(i as i64),
+                other => panic!("Expected a number, found {:?}", other),
+            };
+            store.set_by_key(keys[index], StoreKind::Number(value)).unwrap();
+            let value = match Gettable::get(&map, names[index]) {
+                Some(&StoreKind::Number(value)) => value + Number::Int(i as i64),
+                other => panic!("Expected a number, found {:?}", other),
+            };
+            map.set_value(names[index], StoreKind::Number(value)).unwrap();
+        }
+        for name in names.iter() {
+            assert_eq!(Gettable::get(&store, *name), Gettable::get(&map, *name));
+        }
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let mut store = KeyedStore::new();
+        let a = store.intern("a");
+        store.insert_value(String::from("a"), StoreKind::Bool(true));
+        let mut clone = store.clone();
+        assert!(store.owns(a));
+        assert!(!clone.owns(a));
+        assert_eq!(clone.get_by_key(a), None);
+        assert_eq!(clone.set_by_key(a, StoreKind::Bool(false)), Err(()));
+        // The clone has its own keys for the same variables
+        let cloned_a = clone.intern("a");
+        assert_eq!(clone.get_by_key(cloned_a), Some(&StoreKind::Bool(true)));
+        assert_eq!(store.get_by_key(cloned_a), None);
+    }
+
+    #[test]
+    fn variable() {
+        let mut variable = Variable::new("a");
+        let mut store = KeyedStore::new();
+        assert_eq!(variable.get(&mut store), None);
+        variable.insert(&mut store, StoreKind::Bool(true));
+        assert_eq!(Gettable::get(&store, "a"), Some(&StoreKind::Bool(true)));
+        // The variable is interned again when used with another store
+        let mut clone = store.clone();
+        assert_eq!(variable.set(&mut clone, StoreKind::Bool(false)), Ok(()));
+        assert_eq!(variable.get(&mut store), Some(&StoreKind::Bool(true)));
+        assert_eq!(variable.get(&mut clone), Some(&StoreKind::Bool(false)));
+        // Contexts without keys are accessed by name
+        let mut map: HashMap<String,StoreKind> = HashMap::new();
+        assert_eq!(variable.set(&mut map, StoreKind::Bool(false)), Err(()));
+        variable.insert(&mut map, StoreKind::Bool(false));
+        assert_eq!(variable.get(&mut map), Some(&StoreKind::Bool(false)));
+    }
+
+    #[test]
+    fn standard_leaves() {
+        let leaves = LeavesCollection::<KeyedStore>::standard();
+        let input = r#"
+            tree main {
+                sequence {
+                    increment(variable: count, value: 2),
+                    evaluate_int(result: double, expression: [count 2 *]),
+                    condition(exp1: [double], operator: "=", exp2: [4]),
+                    set_variable(variable: done, value: true),
+                    check_flag(variable: done),
+                }
+            }
+        "#;
+        let mut tree = ::parse_one(input, &leaves).unwrap().optimize();
+        let mut store = KeyedStore::new();
+        assert_eq!(tree.visit(&mut store), VisitResult::Success);
+        assert_eq!(Gettable::get(&store, "double"), Some(&StoreKind::Number(Number::Int(4))));
+        // The keys cached by the leaves are not used with another store
+        let mut clone = store.clone();
+        assert_eq!(tree.visit(&mut clone), VisitResult::Failure);
+        assert_eq!(Gettable::get(&clone, "count"), Some(&StoreKind::Number(Number::Int(4))));
+        assert_eq!(Gettable::get(&store, "count"), Some(&StoreKind::Number(Number::Int(2))));
+    }
+}
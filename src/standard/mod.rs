@@ -1,16 +1,56 @@
 use std::collections::HashMap;
 use std::hash::{Hash,BuildHasher};
 use std::borrow::Borrow;
+use std::rc::Rc;
+use std::str::FromStr;
 
-use tree::{VisitResult,BehaviourTreeNode,Prototype};
+use std::marker::PhantomData;
+
+use tree::{VisitResult,BehaviourTreeNode,Closure,Condition,NodeError,Prototype,SendPrototype};
 use tree::{LeafNodeFactory};
-use parser::{Value,FactoryProducer};
+use tree::random::SeededContext;
+use parser::{self,Value,FactoryProducer,FromOptions,OptionsError,ParseError};
+use parser::options;
+use parser::collection::TreeCollection;
+
+pub mod expressions;
+mod blackboard;
+mod clock;
+mod conditions;
+mod host;
+mod keyed;
+mod nested;
+mod number;
+
+pub use self::blackboard::{Blackboard,BlackboardNode,Scoped};
+pub use self::clock::Clocked;
+pub use self::host::HostContext;
+pub use self::keyed::{ContextKey,KeyedContext,KeyedStore,Variable};
+pub use self::nested::NestedContext;
+pub use self::number::Number;
+
+// Reference counted so that factories can be shared when a subtree is inlined in several trees
+pub type StandardFactory<C> = Rc<LeafNodeFactory<Output=Box<BehaviourTreeNode<C>>>>;
+
+/// Factory of leaves that can be sent to another thread
+///
+/// Trees built from a `SendLeavesCollection` are `Send`, so they can be ticked on a worker thread,
+/// but every leaf registered in the collection must then be `Send` as well. Use
+/// `LeavesCollection` and `StandardFactory` for leaves that hold `Rc` or other thread local state.
+pub type SendFactory<C> = Rc<LeafNodeFactory<Output=Box<BehaviourTreeNode<C> + Send>>>;
 
-//mod fake_nodes;
-//pub mod expressions;
-//mod conditions;
+pub type SendLeavesCollection<C> = LeavesCollection<C,SendFactory<C>>;
+
+fn prototype<C,T>(node: T) -> StandardFactory<C>
+where T: Clone + BehaviourTreeNode<C> + 'static {
+    Rc::new(Prototype::new(node))
+}
+
+fn send_prototype<C,T>(node: T) -> SendFactory<C>
+where T: Clone + BehaviourTreeNode<C> + Send + 'static {
+    Rc::new(SendPrototype::new(node))
+}
 
-pub type StandardFactory<C> = Box<LeafNodeFactory<Output=Box<BehaviourTreeNode<C>>>>;
 pub trait LeafNodeFactoryFactory {
     type Output;
     fn create_factory(&self, options: &Option<Value>) -> Result<Self::Output,String>;
@@ -24,14 +64,18 @@ where T: Fn(&Option<Value>) -> Result<U,String> {
     }
 }
 
-impl <C: 'static> FactoryProducer for LeavesCollection<C> {
-    type Factory = StandardFactory<C>;
-    fn generate_leaf(&self, name: &str, option: &Option<Value>) -> Result<Self::Factory,String> {
+impl <C,F> FactoryProducer for LeavesCollection<C,F> {
+    type Factory = F;
+    fn generate_leaf(&self, name: &str, option: &Option<Value>) -> Result<Self::Factory,ParseError> {
         match self.inner.get(name) {
-            None => Err(format!("Could not find leaf with name {}", name)),
+            None => Err(ParseError::UnknownLeaf { name: String::from(name) }),
             Some(fact_fact) => {
-                let fact = try!(fact_fact.create_factory(option));
-                Ok(fact) 
+                fact_fact.create_factory(option).map_err(|message| {
+                    ParseError::InvalidLeafOptions {
+                        name: String::from(name),
+                        message: message,
+                    }
+                })
             }
         }
     }
@@ -76,6 +120,17 @@ where T: Gettable<Q,V> {
 pub trait Context: Gettable<str,StoreKind> {
     fn insert_value(&mut self, key: String, value: StoreKind);
     fn set_value(&mut self, key: &str, value: StoreKind) -> Result<(),()>;
+
+    /// The context itself if it is a `KeyedContext`, letting the standard leaves read their
+    /// variables by key instead of by name
+    fn as_keyed(&self) -> Option<&KeyedContext> {
+        None
+    }
+
+    /// Same as `as_keyed`
+    fn as_keyed_mut(&mut self) -> Option<&mut KeyedContext> {
+        None
+    }
 }
 
 impl <S: BuildHasher> Context for HashMap<String,StoreKind,S> {
@@ -94,6 +149,32 @@ impl <S: BuildHasher> Context for HashMap<String,StoreKind,S> {
     }
 }
 
+// The standard leaves can be visited with `visit_with_rng`
+impl <C> Gettable<str,StoreKind> for SeededContext<C>
+where C: Gettable<str,StoreKind> {
+    fn get(&self, name: &str) -> Option<&StoreKind> {
+        self.inner.get(name)
+    }
+}
+
+impl <C: Context> Context for SeededContext<C> {
+    fn insert_value(&mut self, name: String, value: StoreKind) {
+        self.inner.insert_value(name, value)
+    }
+
+    fn set_value(&mut self, name: &str, value: StoreKind) -> Result<(),()> {
+        self.inner.set_value(name, value)
+    }
+
+    fn as_keyed(&self) -> Option<&KeyedContext> {
+        self.inner.as_keyed()
+    }
+
+    fn as_keyed_mut(&mut self) -> Option<&mut KeyedContext> {
+        self.inner.as_keyed_mut()
+    }
+}
+
 #[derive(Debug,Clone)]
 pub struct PrintText {
     pub text: String,
@@ -107,6 +188,10 @@ impl <C> BehaviourTreeNode<C> for PrintText {
 }
 
 pub fn print_text<C: 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_print_text(options).map(prototype)
+}
+
+fn parse_print_text(options: &Option<Value>) -> Result<PrintText, String> {
     let message_orig = match options {
         &Some(Value::String(ref message)) => message,
         other => return Err(format!("Expected message, found {:?}", other)),
@@ -114,91 +199,469 @@ pub fn print_text<C: 'static>(options: &Option<Value>) -> Result<StandardFactory
 
     let message = message_orig.replace("_"," ");
 
-    Ok(Box::new(Prototype::new(PrintText { text: message })))
+    Ok(PrintText { text: message })
 }
 
-/*
-TODO: Finish this
-
+/// Adds `value` to the numeric variable `variable`, creating it if it does not exist yet
+///
+/// Fails if the variable exists but does not contain a number.
 #[derive(Debug,Clone)]
 pub struct Increment {
-    pub variable: String,
+    pub variable: Variable,
     pub value: i64,
 }
 
 impl <C: Context> BehaviourTreeNode<C> for Increment {
     fn visit(&mut self, context: &mut C) -> VisitResult {
-        let current_value = match context.get(&self.variable) {
-            None => {
-                None
-            },
-            Some(&StoreKind::I64(variable)) => {
-                Some(variable)
-            },
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        let current_value = match self.variable.get(context) {
+            None => None,
+            Some(&StoreKind::Number(value)) => Some(value),
             Some(other) => {
-                println!("Expected integer variable for key {}, found {:?}", variable_name, other);
-                return VisitResult::Failure
+                let message = format!("Increment: expected number for variable {}, found {:?}",
+                                      self.variable.name(), other);
+                return Err(NodeError::new(message));
             }
         };
         match current_value {
-            Some(v) => {
-                match context.set_value(&self.variable, v + self.value) {
-                    Ok(_) => VisitResult::Success,
-                    Err(_) => {
-                        warning!("Context::set_value failed for variable {} after a successfull get", self.variable);
-                        VisitResult::Success,
+            Some(value) => {
+                let new_value = match value.checked_add(Number::Int(self.value)) {
+                    Some(new_value) => new_value,
+                    None => {
+                        let message = format!("Increment: overflow when adding {} to variable {}",
+                                              self.value, self.variable.name());
+                        return Err(NodeError::new(message));
+                    }
+                };
+                match self.variable.set(context, StoreKind::Number(new_value)) {
+                    Ok(()) => Ok(VisitResult::Success),
+                    Err(()) => {
+                        let message = format!("Increment: could not store variable {} after a successful get",
+                                              self.variable.name());
+                        Err(NodeError::new(message))
                     }
                 }
             }
-            TODO
-            None => context.insert_value(self.variable.clone(), self.value),
+            None => {
+                self.variable.insert(context, StoreKind::Number(Number::Int(self.value)));
+                Ok(VisitResult::Success)
+            }
         }
+    }
+}
+
+pub fn increment<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_increment(options).map(prototype)
+}
+
+fn parse_increment(options: &Option<Value>) -> Result<Increment, String> {
+    Increment::from_options(options).map_err(|error| format!("Increment: {}", error))
+}
+
+impl FromOptions for Increment {
+    fn from_options(options: &Option<Value>) -> Result<Increment,OptionsError> {
+        let map = try!(options::options_map(options));
+        try!(options::check_fields(map, &["variable", "value"]));
+        Ok(Increment {
+            variable: try!(options::field(map, "variable")),
+            value: try!(options::field(map, "value")),
+        })
+    }
+}
+
+/// Writes a constant value into the variable `variable`, always succeeding
+#[derive(Debug,Clone)]
+pub struct SetVariable {
+    pub variable: String,
+    pub value: StoreKind,
+}
+
+impl <C: Context> BehaviourTreeNode<C> for SetVariable {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        context.insert_value(self.variable.clone(), self.value.clone());
         VisitResult::Success
     }
 }
 
-pub fn increment<C: Gettable<str,Value>>(options: &Option<Value>) -> Result<Box<LeafNodeFactory<C>>, String> {
+pub fn set_variable<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_set_variable(options).map(prototype)
+}
+
+fn parse_set_variable(options: &Option<Value>) -> Result<SetVariable, String> {
     let options_map = match options {
         &Some(Value::Map(ref map)) => map,
         other => return Err(format!("Expected hashmap, found {:?}", other)),
     };
     let variable = match options_map.get("variable") {
-        None => return Err(format!("Increment: missing required \"variable\" field")),
-        Some(Value::String(ref name)) => name.clone(),
-        Some(other) => return Err(format!("Increment: expected string for field \"variable\", got {:?}", other)),
+        None => return Err(format!("SetVariable: missing required \"variable\" field")),
+        Some(&Value::String(ref name)) => name.clone(),
+        Some(other) => return Err(format!("SetVariable: expected string for field \"variable\", got {:?}", other)),
     };
     let value = match options_map.get("value") {
-        None => return Err(format!("Increment: missing required \"value\" field")),
-        Some(Value::Integer(value)) => value,
-        Some(other) => return Err(format!("Increment: expected integer for field \"value\", got {:?}", other)),
+        None => return Err(format!("SetVariable: missing required \"value\" field")),
+        Some(&Value::Integer(value)) => StoreKind::Number(Number::Int(value)),
+        Some(&Value::String(ref value)) => StoreKind::String(value.clone()),
+        Some(&Value::Bool(value)) => StoreKind::Bool(value),
+        Some(other) => return Err(format!("SetVariable: expected integer, string or boolean for field \"value\", got {:?}", other)),
     };
-    let increment = Increment {
+    let set_variable = SetVariable {
         variable: variable,
         value: value,
     };
-    Ok(Box::new(Prototype(increment)))
+    Ok(set_variable)
+}
+
+/// Writes the results of `store_result(variable) { ... }` nodes in the context
+///
+/// The result is stored as a number: 0 for Failure, 1 for Success and 2 for Running, so that
+/// other leaves can branch on the outcome of the child later on. Visited as a plain leaf, it does
+/// nothing and succeeds.
+#[derive(Debug,Clone,Copy)]
+pub struct StoreResult;
+
+impl <C: Context> BehaviourTreeNode<C> for StoreResult {
+    fn visit(&mut self, _context: &mut C) -> VisitResult {
+        VisitResult::Success
+    }
+
+    fn store_result(&mut self, context: &mut C, variable: &str, result: VisitResult) {
+        let value = StoreKind::Number(Number::Int(match result {
+            VisitResult::Failure => 0,
+            VisitResult::Success => 1,
+            VisitResult::Running => 2,
+        }));
+        if let Err(()) = context.set_value(variable, value.clone()) {
+            context.insert_value(String::from(variable), value);
+        }
+    }
+}
+
+/// Creates the writer of `store_result(variable) { ... }` nodes
+pub fn store_result<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_store_result(options).map(prototype)
+}
+
+fn parse_store_result(options: &Option<Value>) -> Result<StoreResult, String> {
+    match *options {
+        None => Ok(StoreResult),
+        Some(ref other) => Err(format!("StoreResult: expected no options, found {:?}", other)),
+    }
+}
+
+/// Returns Running for `ticks` visits, and then Success
+///
+/// The countdown starts again after the success, so the node can be re-entered.
+#[derive(Debug,Clone)]
+pub struct Wait {
+    pub ticks: u64,
+    pub elapsed: u64,
+}
+
+impl <C> BehaviourTreeNode<C> for Wait {
+    fn visit(&mut self, _context: &mut C) -> VisitResult {
+        if self.elapsed < self.ticks {
+            self.elapsed += 1;
+            VisitResult::Running
+        } else {
+            self.elapsed = 0;
+            VisitResult::Success
+        }
+    }
+}
+
+pub fn wait<C: 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_wait(options).map(prototype)
+}
+
+fn parse_wait(options: &Option<Value>) -> Result<Wait, String> {
+    let ticks = match options {
+        &Some(Value::Integer(ticks)) if ticks >= 0 => ticks as u64,
+        other => return Err(format!("Expected positive number of ticks, found {:?}", other)),
+    };
+    Ok(Wait { ticks: ticks, elapsed: 0 })
+}
+
+/// Succeeds if the boolean variable `variable` is true, and fails if it is false
+///
+/// Also fails if the variable does not exist. A variable that does not contain a boolean is an
+/// error for `try_visit`, and a failure for `visit`.
+#[derive(Debug,Clone)]
+pub struct CheckFlag {
+    pub variable: Variable,
+}
+
+impl <C: Context> BehaviourTreeNode<C> for CheckFlag {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        match self.variable.get(context) {
+            Some(&StoreKind::Bool(true)) => Ok(VisitResult::Success),
+            Some(&StoreKind::Bool(false)) | None => Ok(VisitResult::Failure),
+            Some(other) => {
+                let message = format!("CheckFlag: expected boolean for variable {}, found {:?}",
+                                      self.variable.name(), other);
+                Err(NodeError::new(message))
+            }
+        }
+    }
+}
+
+pub fn check_flag<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_check_flag(options).map(prototype)
+}
+
+fn parse_check_flag(options: &Option<Value>) -> Result<CheckFlag, String> {
+    let options_map = match options {
+        &Some(Value::Map(ref map)) => map,
+        other => return Err(format!("Expected hashmap, found {:?}", other)),
+    };
+    let variable = match options_map.get("variable") {
+        None => return Err(format!("CheckFlag: missing required \"variable\" field")),
+        Some(&Value::String(ref name)) => Variable::new(name),
+        Some(other) => return Err(format!("CheckFlag: expected string for field \"variable\", got {:?}", other)),
+    };
+    Ok(CheckFlag { variable: variable })
+}
+
+/// Compares the string variable `variable` with `value`
+///
+/// Succeeds if they are equal, or different when `equal` is false. Fails if the variable does
+/// not exist. A variable that does not contain a string is an error for `try_visit`, and a
+/// failure for `visit`.
+#[derive(Debug,Clone)]
+pub struct CheckString {
+    pub variable: Variable,
+    pub value: String,
+    pub equal: bool,
+}
+
+impl <C: Context> BehaviourTreeNode<C> for CheckString {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        match self.variable.get(context) {
+            Some(&StoreKind::String(ref value)) if (*value == self.value) == self.equal => {
+                Ok(VisitResult::Success)
+            }
+            Some(&StoreKind::String(_)) | None => Ok(VisitResult::Failure),
+            Some(other) => {
+                let message = format!("CheckString: expected string for variable {}, found {:?}",
+                                      self.variable.name(), other);
+                Err(NodeError::new(message))
+            }
+        }
+    }
+}
+
+/// Creates a leaf comparing a string variable, with the options
+/// `{ variable: state, equals: alert }` or `{ variable: state, not_equals: alert }`
+pub fn check_string<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_check_string(options).map(prototype)
+}
+
+fn parse_check_string(options: &Option<Value>) -> Result<CheckString, String> {
+    let options_map = match options {
+        &Some(Value::Map(ref map)) => map,
+        other => return Err(format!("Expected hashmap, found {:?}", other)),
+    };
+    let variable = match options_map.get("variable") {
+        None => return Err(format!("CheckString: missing required \"variable\" field")),
+        Some(&Value::String(ref name)) => Variable::new(name),
+        Some(other) => return Err(format!("CheckString: expected string for field \"variable\", got {:?}", other)),
+    };
+    let (value, equal) = match (options_map.get("equals"), options_map.get("not_equals")) {
+        (Some(value), None) => (value, true),
+        (None, Some(value)) => (value, false),
+        _ => return Err(format!("CheckString: expected exactly one of the fields \"equals\" and \"not_equals\"")),
+    };
+    let value = match *value {
+        Value::String(ref value) => value.clone(),
+        ref other => return Err(format!("CheckString: expected string to compare with, got {:?}", other)),
+    };
+    Ok(CheckString { variable: variable, value: value, equal: equal })
+}
+
+/// Selector of the `switch` nodes, giving the index stored in the integer variable `variable`
+///
+/// No child is selected if the variable does not exist, is not an integer, or is negative. When
+/// visited as a plain leaf, succeeds if a child would be selected.
+#[derive(Debug,Clone)]
+pub struct SwitchIndex {
+    pub variable: Variable,
+}
+
+impl <C: Context> BehaviourTreeNode<C> for SwitchIndex {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        match self.select_child(context) {
+            Some(_) => VisitResult::Success,
+            None => VisitResult::Failure,
+        }
+    }
+
+    fn select_child(&mut self, context: &mut C) -> Option<usize> {
+        match self.variable.get(context) {
+            Some(&StoreKind::Number(Number::Int(index))) if index >= 0 => Some(index as usize),
+            _ => None,
+        }
+    }
+}
+
+/// Creates the selector of `switch(variable) { ... }` nodes
+pub fn switch_index<C: Context + 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_switch_index(options).map(prototype)
+}
+
+fn parse_switch_index(options: &Option<Value>) -> Result<SwitchIndex, String> {
+    match *options {
+        Some(Value::String(ref variable)) => Ok(SwitchIndex { variable: Variable::new(variable) }),
+        ref other => Err(format!("Switch: expected the name of an integer variable, found {:?}", other)),
+    }
+}
+
+/// Always returns the same result, whatever the context
+///
+/// Useful as a placeholder for leaves that are not written yet.
+#[derive(Debug,Clone)]
+pub struct Always {
+    pub result: VisitResult,
 }
-*/
 
+impl <C> BehaviourTreeNode<C> for Always {
+    fn visit(&mut self, _context: &mut C) -> VisitResult {
+        self.result
+    }
+}
+
+pub fn always_running<C: 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_always(options, VisitResult::Running).map(prototype)
+}
 
-#[derive(Default)]
-pub struct LeavesCollection<C> {
-    inner: HashMap<String,Box<LeafNodeFactoryFactory<Output=StandardFactory<C>>>>,
+pub fn always_success<C: 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_always(options, VisitResult::Success).map(prototype)
 }
 
-impl <C> LeavesCollection<C> {
-    pub fn new() -> LeavesCollection<C> {
+pub fn always_failure<C: 'static>(options: &Option<Value>) -> Result<StandardFactory<C>, String> {
+    parse_always(options, VisitResult::Failure).map(prototype)
+}
+
+fn parse_always(options: &Option<Value>, result: VisitResult) -> Result<Always, String> {
+    match *options {
+        None => Ok(Always { result: result }),
+        Some(ref other) => Err(format!("Expected no options, found {:?}", other)),
+    }
+}
+
+pub struct LeavesCollection<C,F = StandardFactory<C>> {
+    inner: HashMap<String,Box<LeafNodeFactoryFactory<Output=F>>>,
+    _marker: PhantomData<C>,
+}
+
+impl <C,F> Default for LeavesCollection<C,F> {
+    fn default() -> LeavesCollection<C,F> {
+        LeavesCollection::new()
+    }
+}
+
+impl <C,F> LeavesCollection<C,F> {
+    pub fn new() -> LeavesCollection<C,F> {
         LeavesCollection {
             inner: HashMap::new(),
+            _marker: PhantomData,
         }
     }
 
+    /// Registers `f` as the factory of the leaves called `key`
+    ///
+    /// Returns true if it replaced a leaf with the same name.
     pub fn register_function(
         &mut self,
         key: String,
-        f: Box<LeafNodeFactoryFactory<Output=StandardFactory<C>>>,
-        ) {
-        self.inner.insert(key,f);
+        f: Box<LeafNodeFactoryFactory<Output=F>>,
+        ) -> bool {
+        self.inner.insert(key,f).is_some()
+    }
+
+    /// Removes the leaf called `name`, returning true if there was one
+    ///
+    /// Trees already resolved keep using the removed leaf.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.inner.remove(name).is_some()
+    }
+
+    /// Whether a leaf is registered under `name`
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.contains_key(name)
+    }
+
+    /// Registers `f` as the factory of the leaves called `name`, replacing any previous leaf with
+    /// the same name
+    pub fn register_leaf<G>(&mut self, name: &str, f: G)
+    where G: Fn(&Option<Value>) -> Result<F,String> + 'static {
+        self.inner.insert(String::from(name), Box::new(f));
+    }
+
+    /// Adds all the leaves of `other` to this collection.
+    ///
+    /// When both collections contain a leaf with the same name, the one from `other` wins, so
+    /// that the standard leaves can be overridden by merging application specific ones.
+    pub fn merge(&mut self, other: LeavesCollection<C,F>) {
+        self.inner.extend(other.inner);
+    }
+}
+
+impl <C: 'static> LeavesCollection<C> {
+    /// Registers a leaf backed by a closure, ignoring the options given in the DSL
+    ///
+    /// `f` is called once per instance of the leaf, and returns the closure visiting it, so that
+    /// every instance gets its own state:
+    ///
+    /// ```ignore
+    /// leaves.register_closure_leaf("twice", || {
+    ///     let mut visits = 0;
+    ///     move |_: &mut C| {
+    ///         visits += 1;
+    ///         if visits < 2 { VisitResult::Running } else { VisitResult::Success }
+    ///     }
+    /// });
+    /// ```
+    pub fn register_closure_leaf<F,G>(&mut self, name: &str, f: F)
+    where F: Fn() -> G + 'static,
+          G: FnMut(&mut C) -> VisitResult + 'static {
+        let f = Rc::new(f);
+        self.register_leaf(name, move |_options: &Option<Value>| {
+            let f = f.clone();
+            let factory: StandardFactory<C> = Rc::new(Closure::new(move || {
+                Box::new(Closure::new(f())) as Box<BehaviourTreeNode<C>>
+            }));
+            Ok(factory)
+        });
+    }
+
+    /// Registers a condition backed by a closure, ignoring the options given in the DSL
+    ///
+    /// The leaf succeeds when `f` returns true, and fails otherwise:
+    ///
+    /// ```ignore
+    /// leaves.register_condition_leaf("is_night", |world: &World| world.hour >= 20);
+    /// ```
+    pub fn register_condition_leaf<F>(&mut self, name: &str, f: F)
+    where F: Fn(&C) -> bool + 'static {
+        let f = Rc::new(f);
+        self.register_leaf(name, move |_options: &Option<Value>| {
+            let f = f.clone();
+            let factory: StandardFactory<C> = Rc::new(Closure::new(move || {
+                let f = f.clone();
+                Box::new(Condition::new(move |context: &C| f(context))) as Box<BehaviourTreeNode<C>>
+            }));
+            Ok(factory)
+        });
     }
 }
 
@@ -219,20 +682,547 @@ macro_rules! insert_all {
         insert_all!($($name => $fun),+)
         );
 }
+
+// Leaves shared by `standard` and `standard_send`, wrapping their nodes with `$prototype`
+macro_rules! standard_leaves {
+    ($prototype:ident) => (
+        insert_all!(
+            "print_text" => |options: &Option<Value>| {
+                parse_print_text(options).map($prototype::<C,_>)
+            },
+            "increment" => |options: &Option<Value>| {
+                parse_increment(options).map($prototype::<C,_>)
+            },
+            "set_variable" => |options: &Option<Value>| {
+                parse_set_variable(options).map($prototype::<C,_>)
+            },
+            "wait" => |options: &Option<Value>| parse_wait(options).map($prototype::<C,_>),
+            "check_flag" => |options: &Option<Value>| {
+                parse_check_flag(options).map($prototype::<C,_>)
+            },
+            "check_string" => |options: &Option<Value>| {
+                parse_check_string(options).map($prototype::<C,_>)
+            },
+            "switch" => |options: &Option<Value>| {
+                parse_switch_index(options).map($prototype::<C,_>)
+            },
+            "store_result" => |options: &Option<Value>| {
+                parse_store_result(options).map($prototype::<C,_>)
+            },
+            "evaluate_int" => |options: &Option<Value>| {
+                expressions::parse_evaluate_int(options).map($prototype::<C,_>)
+            },
+            "condition" => |options: &Option<Value>| {
+                conditions::parse_check_condition(options).map($prototype::<C,_>)
+            },
+            "bool_condition" => |options: &Option<Value>| {
+                conditions::parse_bool_condition(options).map($prototype::<C,_>)
+            },
+            "always_running" => |options: &Option<Value>| {
+                parse_always(options, VisitResult::Running).map($prototype::<C,_>)
+            },
+            "always_success" => |options: &Option<Value>| {
+                parse_always(options, VisitResult::Success).map($prototype::<C,_>)
+            },
+            "always_failure" => |options: &Option<Value>| {
+                parse_always(options, VisitResult::Failure).map($prototype::<C,_>)
+            },
+            )
+        );
+}
+
 impl <C: Context + 'static> LeavesCollection<C> {
     pub fn standard() -> LeavesCollection<C> {
-        let collection = insert_all!(
-            "print_text" => print_text,
-            //"increment" => increment,
-
-            );
+        standard_leaves!(prototype)
+    }
 
+    /// Same as `standard`, with the leaves of `extra` added
+    ///
+    /// A leaf of `extra` replaces the standard leaf with the same name, as with `merge`.
+    pub fn standard_with<I>(extra: I) -> LeavesCollection<C>
+    where I: IntoIterator<Item=(String,Box<LeafNodeFactoryFactory<Output=StandardFactory<C>>>)> {
+        let mut collection = LeavesCollection::standard();
+        collection.inner.extend(extra);
         collection
     }
 }
 
-#[derive(Debug)]
+impl <C: Context + 'static> LeavesCollection<C,SendFactory<C>> {
+    /// Same leaves as `standard`, producing trees that can be sent to another thread
+    pub fn standard_send() -> SendLeavesCollection<C> {
+        standard_leaves!(send_prototype)
+    }
+}
+
+/// Parses trees using the standard leaves
+impl <C: Context + 'static> FromStr for TreeCollection<StandardFactory<C>> {
+    type Err = ParseError;
+    fn from_str(input: &str) -> Result<TreeCollection<StandardFactory<C>>,ParseError> {
+        let trees = try!(parser::parse(input, &LeavesCollection::standard()));
+        TreeCollection::new(trees)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum StoreKind {
     String(String),
-    I64(i64),
+    Number(Number),
+    Bool(bool),
+    Map(HashMap<String,StoreKind>),
+}
+
+/// Resolves a dotted path such as `position.x` through nested maps
+///
+/// Every segment of the path is looked up in the map reached by the previous ones, so the lookup
+/// fails as soon as a segment is missing or names something that is not a map.
+impl Gettable<str,StoreKind> for StoreKind {
+    fn get(&self, path: &str) -> Option<&StoreKind> {
+        let mut value = self;
+        for segment in path.split('.') {
+            value = match *value {
+                StoreKind::Map(ref map) => match map.get(segment) {
+                    Some(inner) => inner,
+                    None => return None,
+                },
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use std::rc::Rc;
+
+    use tree::{BehaviourTreeNode,LeafNodeFactory,NodeError,Prototype,VisitResult};
+    use tree::random::SeededContext;
+    use parser::{FactoryProducer,ParseError,Value};
+    use parser::collection::TreeCollection;
+    use super::{LeavesCollection,Number,StandardFactory,StoreKind,Gettable,Wait};
+
+    type TestContext = HashMap<String,StoreKind>;
+
+    fn options(entries: Vec<(&str,Value)>) -> Option<Value> {
+        let map = entries.into_iter().map(|(k, v)| (String::from(k), v)).collect();
+        Some(Value::Map(map))
+    }
+
+    fn increment_options(variable: &str, value: i64) -> Option<Value> {
+        options(vec![
+            ("variable", Value::String(String::from(variable))),
+            ("value", Value::Integer(value)),
+        ])
+    }
+
+    #[test]
+    fn increment_creates_variable() {
+        let factory = super::increment::<TestContext>(&increment_options("hp", 5)).unwrap();
+        let mut context = HashMap::new();
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Success);
+        assert_eq!(context.get("hp"), Some(&StoreKind::Number(Number::Int(5))));
+    }
+
+    #[test]
+    fn increment_existing_variable() {
+        let factory = super::increment::<TestContext>(&increment_options("hp", -3)).unwrap();
+        let mut context = HashMap::new();
+        context.insert(String::from("hp"), StoreKind::Number(Number::Int(10)));
+        let mut node = factory.instanciate();
+        assert_eq!(node.visit(&mut context), VisitResult::Success);
+        assert_eq!(node.visit(&mut context), VisitResult::Success);
+        assert_eq!(context.get("hp"), Some(&StoreKind::Number(Number::Int(4))));
+    }
+
+    #[test]
+    fn increment_wrong_type() {
+        let factory = super::increment::<TestContext>(&increment_options("name", 1)).unwrap();
+        let mut context = HashMap::new();
+        context.insert(String::from("name"), StoreKind::String(String::from("bob")));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        assert_eq!(context.get("name"), Some(&StoreKind::String(String::from("bob"))));
+
+        context.insert(String::from("name"), StoreKind::Bool(true));
+        let message = "Increment: expected number for variable name, found Bool(true)";
+        assert_eq!(factory.instanciate().try_visit(&mut context), Err(NodeError::new(message)));
+        assert_eq!(context.get("name"), Some(&StoreKind::Bool(true)));
+    }
+
+    #[test]
+    fn increment_overflow() {
+        let factory = super::increment::<TestContext>(&increment_options("hp", 1)).unwrap();
+        let mut context = HashMap::new();
+        let max = StoreKind::Number(Number::Int(i64::max_value()));
+        context.insert(String::from("hp"), max.clone());
+        let message = "Increment: overflow when adding 1 to variable hp";
+        assert_eq!(factory.instanciate().try_visit(&mut context), Err(NodeError::new(message)));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        assert_eq!(context.get("hp"), Some(&max));
+    }
+
+    #[test]
+    fn increment_invalid_options() {
+        assert!(super::increment::<TestContext>(&None).is_err());
+        let missing_value = options(vec![("variable", Value::String(String::from("hp")))]);
+        assert!(super::increment::<TestContext>(&missing_value).is_err());
+    }
+
+    fn set_variable_options(variable: &str, value: Value) -> Option<Value> {
+        options(vec![
+            ("variable", Value::String(String::from(variable))),
+            ("value", value),
+        ])
+    }
+
+    #[test]
+    fn set_variable() {
+        let mut context: TestContext = HashMap::new();
+        let integer = super::set_variable(&set_variable_options("state", Value::Integer(3))).unwrap();
+        assert_eq!(integer.instanciate().visit(&mut context), VisitResult::Success);
+        assert_eq!(Gettable::get(&context, "state"), Some(&StoreKind::Number(Number::Int(3))));
+
+        let string = Value::String(String::from("alert"));
+        let string = super::set_variable(&set_variable_options("state", string)).unwrap();
+        assert_eq!(string.instanciate().visit(&mut context), VisitResult::Success);
+        assert_eq!(Gettable::get(&context, "state"), Some(&StoreKind::String(String::from("alert"))));
+    }
+
+    #[test]
+    fn set_variable_unsupported_value() {
+        let map = Value::Map(HashMap::new());
+        assert!(super::set_variable::<TestContext>(&set_variable_options("state", map)).is_err());
+    }
+
+    #[test]
+    fn wait() {
+        let factory = super::wait::<TestContext>(&Some(Value::Integer(5))).unwrap();
+        let mut first = factory.instanciate();
+        let mut second = factory.instanciate();
+        let mut context = HashMap::new();
+        for _ in 0..2 {
+            for _ in 0..5 {
+                assert_eq!(first.visit(&mut context), VisitResult::Running);
+            }
+            assert_eq!(first.visit(&mut context), VisitResult::Success);
+        }
+        // The other instance has its own countdown
+        assert_eq!(second.visit(&mut context), VisitResult::Running);
+    }
+
+    #[test]
+    fn check_flag() {
+        let options = options(vec![("variable", Value::String(String::from("alerted")))]);
+        let factory = super::check_flag::<TestContext>(&options).unwrap();
+        let mut context = HashMap::new();
+        // A missing variable is not set
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        context.insert(String::from("alerted"), StoreKind::Bool(true));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Success);
+        context.insert(String::from("alerted"), StoreKind::Bool(false));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        context.insert(String::from("alerted"), StoreKind::Number(Number::Int(1)));
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Failure);
+        let message = "CheckFlag: expected boolean for variable alerted, found Number(Int(1))";
+        assert_eq!(factory.instanciate().try_visit(&mut context), Err(NodeError::new(message)));
+    }
+
+    #[test]
+    fn set_variable_bool() {
+        let mut context: TestContext = HashMap::new();
+        let factory = super::set_variable(&set_variable_options("alerted", Value::Bool(true))).unwrap();
+        assert_eq!(factory.instanciate().visit(&mut context), VisitResult::Success);
+        assert_eq!(Gettable::get(&context, "alerted"), Some(&StoreKind::Bool(true)));
+    }
+
+    fn wait_one_tick(_options: &Option<Value>) -> Result<StandardFactory<TestContext>,String> {
+        Ok(Rc::new(Prototype::new(Wait { ticks: 1, elapsed: 0 })))
+    }
+
+    #[test]
+    fn register_leaf() {
+        let mut leaves = LeavesCollection::<TestContext>::new();
+        leaves.register_leaf("slow", wait_one_tick);
+        leaves.register_leaf("fast", |_options: &Option<Value>| super::wait(&Some(Value::Integer(0))));
+        let mut context = HashMap::new();
+        let slow = leaves.generate_leaf("slow", &None).unwrap();
+        assert_eq!(slow.instanciate().visit(&mut context), VisitResult::Running);
+        let fast = leaves.generate_leaf("fast", &None).unwrap();
+        assert_eq!(fast.instanciate().visit(&mut context), VisitResult::Success);
+        match leaves.generate_leaf("print_text", &None) {
+            Err(ParseError::UnknownLeaf { name }) => assert_eq!(name, "print_text"),
+            _ => panic!("Expected an unknown leaf"),
+        }
+    }
+
+    #[test]
+    fn standard_with() {
+        use parser;
+        use super::LeafNodeFactoryFactory;
+
+        let extra: Vec<(String,Box<LeafNodeFactoryFactory<Output=StandardFactory<TestContext>>>)> =
+            vec![(String::from("slow"), Box::new(wait_one_tick))];
+        let leaves = LeavesCollection::standard_with(extra);
+        let input = r#"tree main { sequence { print_text("start"), slow } }"#;
+        let trees = parser::parse(input, &leaves).unwrap();
+        assert_eq!(trees[0].leaf_names(), ["print_text", "slow"]);
+        let mut tree = trees[0].optimize();
+        let mut context = HashMap::new();
+        assert_eq!(tree.visit(&mut context), VisitResult::Running);
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+    }
+
+    #[test]
+    fn register_closure_leaf() {
+        let mut leaves = LeavesCollection::<TestContext>::new();
+        leaves.register_closure_leaf("twice", || {
+            let mut visits = 0;
+            move |_: &mut TestContext| {
+                visits += 1;
+                if visits < 2 { VisitResult::Running } else { VisitResult::Success }
+            }
+        });
+        let mut context = HashMap::new();
+        let factory = leaves.generate_leaf("twice", &Some(Value::Integer(3))).unwrap();
+        let mut first = factory.instanciate();
+        let mut second = factory.instanciate();
+        assert_eq!(first.visit(&mut context), VisitResult::Running);
+        assert_eq!(first.visit(&mut context), VisitResult::Success);
+        // The second instance has its own counter
+        assert_eq!(second.visit(&mut context), VisitResult::Running);
+        assert_eq!(second.visit(&mut context), VisitResult::Success);
+    }
+
+    #[test]
+    fn store_result() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let tree = ::parse_one("tree main { store_result(waited) { wait(1) } }", &leaves).unwrap();
+        let mut context: TestContext = HashMap::new();
+        let mut optimized = tree.optimize();
+        assert_eq!(optimized.visit(&mut context), VisitResult::Running);
+        assert_eq!(context.get("waited"), Some(&StoreKind::Number(Number::Int(2))));
+        assert_eq!(optimized.visit(&mut context), VisitResult::Success);
+        assert_eq!(context.get("waited"), Some(&StoreKind::Number(Number::Int(1))));
+        let input = "tree main { store_result(waited) { check_flag(variable: missing) } }";
+        let tree = ::parse_one(input, &leaves).unwrap();
+        assert_eq!(tree.instanciate().visit(&mut context), VisitResult::Failure);
+        assert_eq!(context.get("waited"), Some(&StoreKind::Number(Number::Int(0))));
+    }
+
+    #[test]
+    fn store_result_in_budget() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = r#"tree main {
+            budget(3) { store_result(done) { sequence { always_success, always_success } } }
+        }"#;
+        let tree = ::parse_one(input, &leaves).unwrap();
+        let mut optimized = tree.optimize();
+        let mut instance = tree.instanciate();
+        let mut optimized_context: TestContext = HashMap::new();
+        let mut instance_context: TestContext = HashMap::new();
+        // The sequence cut short by the budget has no result to store yet
+        assert_eq!(optimized.visit(&mut optimized_context), VisitResult::Running);
+        assert_eq!(instance.visit(&mut instance_context), VisitResult::Running);
+        assert_eq!(optimized_context.get("done"), None);
+        assert_eq!(instance_context.get("done"), None);
+        assert_eq!(optimized.visit(&mut optimized_context), VisitResult::Success);
+        assert_eq!(instance.visit(&mut instance_context), VisitResult::Success);
+        assert_eq!(optimized_context.get("done"), Some(&StoreKind::Number(Number::Int(1))));
+        assert_eq!(instance_context.get("done"), Some(&StoreKind::Number(Number::Int(1))));
+    }
+
+    #[test]
+    fn seeded_context() {
+        let leaves = LeavesCollection::<SeededContext<TestContext>>::standard();
+        let input = r#"tree main {
+            sequence { random_selector { wait(0), wait(0) }, increment(variable: hp, value: 2) }
+        }"#;
+        let tree = ::parse_one(input, &leaves).unwrap();
+        let mut optimized = tree.optimize();
+        let mut context = SeededContext::new(HashMap::new(), 7);
+        assert_eq!(optimized.visit_with_rng(&mut context), VisitResult::Success);
+        assert_eq!(context.inner.get("hp"), Some(&StoreKind::Number(Number::Int(2))));
+    }
+
+    #[test]
+    fn register_condition_leaf() {
+        let mut leaves = LeavesCollection::<TestContext>::standard();
+        leaves.register_condition_leaf("ready", |context: &TestContext| context.contains_key("ready"));
+        let tree = ::parse_one("tree main { sequence { ready, print_text(go) } }", &leaves).unwrap();
+        let mut tree = tree.optimize();
+        let mut context = HashMap::new();
+        assert_eq!(tree.visit(&mut context), VisitResult::Failure);
+        context.insert(String::from("ready"), StoreKind::Bool(true));
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+    }
+
+    #[test]
+    fn always() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let expected = [
+            ("always_running", VisitResult::Running),
+            ("always_success", VisitResult::Success),
+            ("always_failure", VisitResult::Failure),
+        ];
+        let mut context = HashMap::new();
+        context.insert(String::from("a"), StoreKind::Bool(false));
+        for &(name, result) in expected.iter() {
+            let mut leaf = leaves.generate_leaf(name, &None).unwrap().instanciate();
+            assert_eq!(leaf.visit(&mut context), result);
+            assert_eq!(leaf.visit(&mut HashMap::new()), result);
+        }
+        assert!(leaves.generate_leaf("always_success", &Some(Value::Integer(1))).is_err());
+    }
+
+    fn check_string_options(variable: &str, key: &str, value: &str) -> Option<Value> {
+        options(vec![
+            ("variable", Value::String(String::from(variable))),
+            (key, Value::String(String::from(value))),
+        ])
+    }
+
+    #[test]
+    fn check_string() {
+        let check = |key: &str, value: &str| {
+            let factory = super::check_string::<TestContext>(&check_string_options("state", key, value));
+            factory.unwrap().instanciate()
+        };
+        let mut context = HashMap::new();
+        // A missing variable never matches
+        assert_eq!(check("equals", "alert").visit(&mut context), VisitResult::Failure);
+        assert_eq!(check("not_equals", "alert").visit(&mut context), VisitResult::Failure);
+        context.insert(String::from("state"), StoreKind::String(String::from("alert")));
+        assert_eq!(check("equals", "alert").visit(&mut context), VisitResult::Success);
+        assert_eq!(check("equals", "idle").visit(&mut context), VisitResult::Failure);
+        assert_eq!(check("not_equals", "idle").visit(&mut context), VisitResult::Success);
+        assert_eq!(check("not_equals", "alert").visit(&mut context), VisitResult::Failure);
+        context.insert(String::from("state"), StoreKind::Bool(true));
+        assert_eq!(check("equals", "alert").visit(&mut context), VisitResult::Failure);
+        assert_eq!(check("not_equals", "alert").visit(&mut context), VisitResult::Failure);
+        let message = "CheckString: expected string for variable state, found Bool(true)";
+        assert_eq!(check("equals", "alert").try_visit(&mut context), Err(NodeError::new(message)));
+    }
+
+    #[test]
+    fn check_string_invalid_options() {
+        let missing_value = options(vec![("variable", Value::String(String::from("state")))]);
+        assert!(super::check_string::<TestContext>(&missing_value).is_err());
+        let mut both = check_string_options("state", "equals", "alert");
+        if let Some(Value::Map(ref mut map)) = both {
+            map.insert(String::from("not_equals"), Value::String(String::from("idle")));
+        }
+        assert!(super::check_string::<TestContext>(&both).is_err());
+        let number = options(vec![
+            ("variable", Value::String(String::from("state"))),
+            ("equals", Value::Integer(1)),
+        ]);
+        assert!(super::check_string::<TestContext>(&number).is_err());
+    }
+
+    #[test]
+    fn switch() {
+        let leaves = LeavesCollection::<TestContext>::standard();
+        let input = "tree main { switch(state) { print_text(idle), wait(1), always_failure } }";
+        let factory = ::parse_one(input, &leaves).unwrap();
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut context = HashMap::new();
+        let set_state = |context: &mut TestContext, state: StoreKind| {
+            context.insert(String::from("state"), state);
+        };
+        // Missing, wrong type or out of range variables select nothing
+        for state in [None, Some(StoreKind::Bool(true)), Some(StoreKind::Number(Number::Int(3))),
+                      Some(StoreKind::Number(Number::Int(-1)))].iter() {
+            if let Some(ref state) = *state {
+                set_state(&mut context, state.clone());
+            }
+            assert_eq!(optimized.visit(&mut context), VisitResult::Failure);
+            assert_eq!(instance.visit(&mut context), VisitResult::Failure);
+        }
+        set_state(&mut context, StoreKind::Number(Number::Int(0)));
+        assert_eq!(optimized.visit(&mut context), VisitResult::Success);
+        assert_eq!(instance.visit(&mut context), VisitResult::Success);
+        set_state(&mut context, StoreKind::Number(Number::Int(2)));
+        assert_eq!(optimized.visit(&mut context), VisitResult::Failure);
+        assert_eq!(instance.visit(&mut context), VisitResult::Failure);
+        // The running child keeps control when the state changes
+        set_state(&mut context, StoreKind::Number(Number::Int(1)));
+        assert_eq!(optimized.visit(&mut context), VisitResult::Running);
+        assert_eq!(instance.visit(&mut context), VisitResult::Running);
+        set_state(&mut context, StoreKind::Number(Number::Int(2)));
+        assert_eq!(optimized.visit(&mut context), VisitResult::Success);
+        assert_eq!(instance.visit(&mut context), VisitResult::Success);
+        match ::parse_one("tree main { switch { wait(1) } }", &leaves) {
+            Err(ParseError::InTree { ref tree, span, ref error }) => {
+                assert_eq!((tree.as_str(), span.line, span.column), ("main", 1, 13));
+                match **error {
+                    ParseError::InvalidLeafOptions { ref name, .. } => assert_eq!(name, "switch"),
+                    ref other => panic!("Expected invalid options for switch, got {:?}", other),
+                }
+            }
+            other => panic!("Expected an error located in main, got {:?}", other),
+        }
+        // The selector is a leaf of the collection, which may not provide it
+        let error = ::parse_one(input, &LeavesCollection::<TestContext>::new()).unwrap_err();
+        match *error.root_cause() {
+            ParseError::UnknownLeaf { ref name } => assert_eq!(name, "switch"),
+            ref other => panic!("Expected an unknown switch leaf, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_str() {
+        let trees: TreeCollection<StandardFactory<TestContext>> = "tree x { print_text(hi) } tree y { wait(1) }".parse().unwrap();
+        assert_eq!(trees.names(), ["x", "y"]);
+        let mut tree = trees.get("y").unwrap().optimize();
+        let mut context = HashMap::new();
+        assert_eq!(tree.visit(&mut context), VisitResult::Running);
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+        match "tree x { not_a_leaf }".parse::<TreeCollection<StandardFactory<TestContext>>>() {
+            Err(ParseError::InTree { error, .. }) => match *error {
+                ParseError::UnknownLeaf { name } => assert_eq!(name, "not_a_leaf"),
+                other => panic!("Expected an unknown leaf error, got {:?}", other),
+            },
+            other => panic!("Expected an unknown leaf error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut leaves = LeavesCollection::<TestContext>::standard();
+        let mut overrides = LeavesCollection::new();
+        overrides.register_leaf("wait", wait_one_tick);
+        overrides.register_leaf("slow", wait_one_tick);
+        leaves.merge(overrides);
+        let mut context = HashMap::new();
+        // The merged collection wins on name clashes
+        let wait = leaves.generate_leaf("wait", &None).unwrap();
+        assert_eq!(wait.instanciate().visit(&mut context), VisitResult::Running);
+        assert!(leaves.generate_leaf("slow", &None).is_ok());
+        match leaves.generate_leaf("increment", &None) {
+            Err(ParseError::InvalidLeafOptions { .. }) => {}
+            _ => panic!("Expected the standard increment leaf to reject empty options"),
+        }
+        assert!(leaves.generate_leaf("print_text", &Some(Value::String(String::from("hi")))).is_ok());
+    }
+
+    #[test]
+    fn unregister() {
+        let mut leaves = LeavesCollection::<TestContext>::standard();
+        assert!(leaves.contains("wait"));
+        assert!(!leaves.contains("slow"));
+        assert!(!leaves.register_function(String::from("slow"), Box::new(wait_one_tick)));
+        assert!(leaves.register_function(String::from("wait"), Box::new(wait_one_tick)));
+        let wait = leaves.generate_leaf("wait", &None).unwrap();
+        assert_eq!(wait.instanciate().visit(&mut HashMap::new()), VisitResult::Running);
+        assert!(leaves.unregister("wait"));
+        assert!(!leaves.unregister("wait"));
+        assert!(!leaves.contains("wait"));
+        assert!(leaves.contains("slow"));
+        match leaves.generate_leaf("wait", &None) {
+            Err(ParseError::UnknownLeaf { name }) => assert_eq!(name, "wait"),
+            _ => panic!("Expected an unknown leaf"),
+        }
+    }
 }
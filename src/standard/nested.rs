@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use standard::{Context,Gettable,StoreKind};
+
+/// Context whose variables can be nested maps, read and written with dotted paths
+///
+/// `player.position.x` is the entry `x` of the map stored in the entry `position` of the variable
+/// `player`. Dotted names are quoted in the trees, as in `[ "player.hp" 2 * ]`, and work with every
+/// standard leaf. Inserting a value creates the missing intermediate maps, replacing any value that
+/// is in the way, while setting a value fails if the whole path does not exist yet.
+#[derive(Debug,Clone,Default)]
+pub struct NestedContext {
+    values: HashMap<String,StoreKind>,
+}
+
+impl NestedContext {
+    pub fn new(values: HashMap<String,StoreKind>) -> NestedContext {
+        NestedContext {
+            values: values,
+        }
+    }
+
+    pub fn values(&self) -> &HashMap<String,StoreKind> {
+        &self.values
+    }
+
+    pub fn into_values(self) -> HashMap<String,StoreKind> {
+        self.values
+    }
+}
+
+impl Gettable<str,StoreKind> for NestedContext {
+    fn get(&self, path: &str) -> Option<&StoreKind> {
+        let mut segments = path.splitn(2, '.');
+        // splitn always yields at least one segment
+        let root = self.values.get(segments.next().unwrap());
+        match segments.next() {
+            Some(rest) => root.and_then(|root| root.get(rest)),
+            None => root,
+        }
+    }
+}
+
+impl Context for NestedContext {
+    fn insert_value(&mut self, path: String, value: StoreKind) {
+        let mut segments: Vec<&str> = path.split('.').collect();
+        let name = segments.pop().unwrap();
+        let mut map = &mut self.values;
+        for segment in segments {
+            let entry = map.entry(String::from(segment)).or_insert_with(|| StoreKind::Map(HashMap::new()));
+            let is_map = match *entry {
+                StoreKind::Map(_) => true,
+                _ => false,
+            };
+            if !is_map {
+                *entry = StoreKind::Map(HashMap::new());
+            }
+            map = match *entry {
+                StoreKind::Map(ref mut inner) => inner,
+                _ => unreachable!(),
+            };
+        }
+        map.insert(String::from(name), value);
+    }
+
+    fn set_value(&mut self, path: &str, value: StoreKind) -> Result<(),()> {
+        let mut segments = path.split('.');
+        let mut current = match self.values.get_mut(segments.next().unwrap()) {
+            Some(current) => current,
+            None => return Err(()),
+        };
+        for segment in segments {
+            current = match *current {
+                StoreKind::Map(ref mut map) => match map.get_mut(segment) {
+                    Some(inner) => inner,
+                    None => return Err(()),
+                },
+                _ => return Err(()),
+            };
+        }
+        *current = value;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use tree::{BehaviourTreeNode,VisitResult};
+    use parser::collection::TreeCollection;
+    use standard::{Context,Gettable,Number,StandardFactory,StoreKind};
+    use super::NestedContext;
+
+    fn number(value: i64) -> StoreKind {
+        StoreKind::Number(Number::Int(value))
+    }
+
+    fn player() -> NestedContext {
+        let mut position = HashMap::new();
+        position.insert(String::from("x"), number(3));
+        let mut player = HashMap::new();
+        player.insert(String::from("position"), StoreKind::Map(position));
+        player.insert(String::from("hp"), number(10));
+        let mut values = HashMap::new();
+        values.insert(String::from("player"), StoreKind::Map(player));
+        NestedContext::new(values)
+    }
+
+    #[test]
+    fn two_level_path() {
+        let context = player();
+        assert_eq!(Gettable::get(&context, "player.hp"), Some(&number(10)));
+        assert_eq!(Gettable::get(&context, "player.position.x"), Some(&number(3)));
+        match Gettable::get(&context, "player") {
+            Some(&StoreKind::Map(ref map)) => assert_eq!(map.len(), 2),
+            other => panic!("expected a map, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_intermediate_segment() {
+        let mut context = player();
+        assert_eq!(Gettable::get(&context, "player.velocity.x"), None);
+        assert_eq!(Gettable::get(&context, "enemy.hp"), None);
+        // hp is not a map, so it has no entries
+        assert_eq!(Gettable::get(&context, "player.hp.max"), None);
+        assert_eq!(context.set_value("player.velocity.x", number(1)), Err(()));
+        assert_eq!(context.set_value("player.position.x", number(4)), Ok(()));
+        assert_eq!(Gettable::get(&context, "player.position.x"), Some(&number(4)));
+        // Inserting creates the missing maps
+        context.insert_value(String::from("player.velocity.x"), number(1));
+        assert_eq!(Gettable::get(&context, "player.velocity.x"), Some(&number(1)));
+        assert_eq!(Gettable::get(&context, "player.hp"), Some(&number(10)));
+    }
+
+    #[test]
+    fn standard_leaves() {
+        let source = r#"
+            tree main {
+                sequence {
+                    condition({ exp1: [ "player.hp" ], exp2: [ 5 ], operator: > }),
+                    evaluate_int({ result: "player.position.y", expression: [ "player.position.x" 2 * ] }),
+                }
+            }
+        "#;
+        let collection: TreeCollection<StandardFactory<NestedContext>> = FromStr::from_str(source).unwrap();
+        let mut tree = collection.get("main").unwrap().optimize();
+        let mut context = player();
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+        assert_eq!(Gettable::get(&context, "player.position.y"), Some(&number(6)));
+    }
+}
@@ -0,0 +1,157 @@
+use std::cmp::Ordering;
+use std::fmt::{self,Display,Formatter};
+use std::ops::{Add,Sub,Mul,Div};
+
+/// Numeric value stored in a context
+///
+/// Operations between two integers give an integer, while mixing an integer with a float promotes
+/// the integer, and gives a float.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(value) => value as f64,
+            Number::Float(value) => value,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        match self {
+            Number::Int(value) => value == 0,
+            Number::Float(value) => value == 0.0,
+        }
+    }
+
+    /// Compares the values, promoting integers to floats when mixed
+    ///
+    /// Returns None if one of the values is NaN.
+    pub fn compare(self, other: Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (a, b) => a.as_f64().partial_cmp(&b.as_f64()),
+        }
+    }
+
+    /// Addition returning None on integer overflow
+    pub fn checked_add(self, other: Number) -> Option<Number> {
+        self.checked_op(other, i64::checked_add, |a, b| a + b)
+    }
+
+    /// Subtraction returning None on integer overflow
+    pub fn checked_sub(self, other: Number) -> Option<Number> {
+        self.checked_op(other, i64::checked_sub, |a, b| a - b)
+    }
+
+    /// Multiplication returning None on integer overflow
+    pub fn checked_mul(self, other: Number) -> Option<Number> {
+        self.checked_op(other, i64::checked_mul, |a, b| a * b)
+    }
+
+    /// Division returning None on integer overflow or integer division by zero
+    pub fn checked_div(self, other: Number) -> Option<Number> {
+        self.checked_op(other, i64::checked_div, |a, b| a / b)
+    }
+
+    /// Exponentiation returning None on integer overflow or negative integer exponent
+    pub fn checked_pow(self, other: Number) -> Option<Number> {
+        self.checked_op(other, checked_pow, f64::powf)
+    }
+
+    fn checked_op<I,F>(self, other: Number, int_op: I, float_op: F) -> Option<Number>
+    where I: Fn(i64, i64) -> Option<i64>,
+          F: Fn(f64, f64) -> f64 {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => int_op(a, b).map(Number::Int),
+            (a, b) => Some(Number::Float(float_op(a.as_f64(), b.as_f64()))),
+        }
+    }
+}
+
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+    if exponent < 0 {
+        return None;
+    }
+    match base {
+        // The only bases that can be raised to exponents too large for i64::checked_pow
+        0 | 1 => Some(if exponent == 0 { 1 } else { base }),
+        -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+        _ if exponent > u32::max_value() as i64 => None,
+        _ => base.checked_pow(exponent as u32),
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            Number::Int(value) => write!(f, "{}", value),
+            Number::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(value: i64) -> Number {
+        Number::Int(value)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(value: f64) -> Number {
+        Number::Float(value)
+    }
+}
+
+// The operators behave like the ones of i64 and f64, panicking on integer overflow in debug builds
+macro_rules! impl_operator {
+    ($trait_name:ident, $method:ident, $op:tt) => (
+        impl $trait_name for Number {
+            type Output = Number;
+            fn $method(self, other: Number) -> Number {
+                match (self, other) {
+                    (Number::Int(a), Number::Int(b)) => Number::Int(a $op b),
+                    (a, b) => Number::Float(a.as_f64() $op b.as_f64()),
+                }
+            }
+        }
+        );
+}
+
+impl_operator!(Add, add, +);
+impl_operator!(Sub, sub, -);
+impl_operator!(Mul, mul, *);
+impl_operator!(Div, div, /);
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use super::Number;
+
+    #[test]
+    fn int_operations() {
+        assert_eq!(Number::Int(2) + Number::Int(3), Number::Int(5));
+        assert_eq!(Number::Int(7) / Number::Int(2), Number::Int(3));
+        assert_eq!(Number::Int(i64::max_value()).checked_add(Number::Int(1)), None);
+        assert_eq!(Number::Int(1).checked_div(Number::Int(0)), None);
+    }
+
+    #[test]
+    fn mixed_operations() {
+        assert_eq!(Number::Int(2) + Number::Float(0.5), Number::Float(2.5));
+        assert_eq!(Number::Float(7.0) / Number::Int(2), Number::Float(3.5));
+        assert_eq!(Number::Int(3).checked_mul(Number::Float(1.5)), Some(Number::Float(4.5)));
+    }
+
+    #[test]
+    fn compare() {
+        assert_eq!(Number::Int(2).compare(Number::Int(3)), Some(Ordering::Less));
+        assert_eq!(Number::Int(2).compare(Number::Float(2.0)), Some(Ordering::Equal));
+        assert_eq!(Number::Float(2.5).compare(Number::Int(2)), Some(Ordering::Greater));
+        assert_eq!(Number::Float(::std::f64::NAN).compare(Number::Int(2)), None);
+    }
+}
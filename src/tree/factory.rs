@@ -1,29 +1,112 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self,Write};
+
 use flat_tree::FlatTree;
 use flat_tree::HasChildren;
+use flat_tree::buffer::ChildrenMut;
 
 use tree::non_optimized::*;
-use super::OptimizedNode;
+#[cfg(feature = "std")]
+use parser::{ParseError,Value,is_keyword};
+use super::{FlatNode,OptimizedNode};
 use super::OptimizedTree;
-use super::LeafNode;
+use super::{LeafNode,NonBlockingResult};
+use super::random::XorShift;
 use super::{LeafNodeFactory};
+use super::packed::{PackedLeaf,PackedTree};
 
-#[derive(Debug,Clone)]
+#[derive(Clone,PartialEq)]
 pub struct TreeFactory<F> {
     name: String,
     root: NodeFactory<F>,
+    // Lines of the comments written before a node, by pre-order index of the node
+    comments: BTreeMap<usize,Vec<String>>,
+    // Identifiers given to nodes, by pre-order index of the node
+    ids: BTreeMap<usize,String>,
+}
+
+// Written by hand so that results of `parse` can be printed, leaf factories rarely being Debug
+impl <F> fmt::Debug for TreeFactory<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(),fmt::Error> {
+        let mut debug = f.debug_struct("TreeFactory");
+        debug.field("name", &self.name);
+        #[cfg(feature = "std")]
+        debug.field("source", &self.to_source());
+        debug.finish()
+    }
 }
 
 fn optimize_inner<F: LeafNodeFactory>(node: &NodeFactory<F>)
--> Option<OptimizedNode<<F as LeafNodeFactory>::Output>> {
+-> Option<FlatNode<<F as LeafNodeFactory>::Output>> {
+    Some(optimize_node(node, |factory| factory.instanciate()))
+}
+
+// Leaves are instanciated separately by a PackVisitor, in the same order
+fn pack_inner<F>(node: &NodeFactory<F>) -> Option<FlatNode<PackedLeaf>> {
+    Some(optimize_node(node, |_| PackedLeaf::unnumbered()))
+}
+
+fn optimize_node<F,A,L>(node: &NodeFactory<F>, leaf: L) -> FlatNode<A>
+where L: Fn(&F) -> A {
     let optimized = match *node {
-        NodeFactory::Leaf(ref leaf) => OptimizedNode::Leaf(LeafNode::new(leaf.instanciate())),
+        NodeFactory::Leaf(ref leaf_factory) => OptimizedNode::Leaf(LeafNode::new(leaf(&leaf_factory.factory))),
         NodeFactory::Sequence(_) => OptimizedNode::sequence(None),
+        NodeFactory::SequenceAll(_) => OptimizedNode::sequence_all(),
         NodeFactory::Selector(_) => OptimizedNode::selector(None),
+        NodeFactory::RandomSelector(ref node) => OptimizedNode::random_selector(node.children.len()),
+        NodeFactory::WeightedSelector(ref node) => OptimizedNode::weighted_selector(node.weights.clone()),
         NodeFactory::Inverter(_) => OptimizedNode::Inverter,
-        NodeFactory::Priority(_) => OptimizedNode::Priority,
-        NodeFactory::Subtree(_) => panic!("Subtrees are currently unsupported"),
+        NodeFactory::Repeater(ref node) => OptimizedNode::repeater(node.count),
+        NodeFactory::UntilFail(_) => OptimizedNode::UntilFail,
+        NodeFactory::Succeeder(_) => OptimizedNode::Succeeder,
+        NodeFactory::Cooldown(ref node) => OptimizedNode::cooldown(node.ticks),
+        NodeFactory::Limiter(ref node) => OptimizedNode::limiter(node.max),
+        NodeFactory::Retry(ref node) => OptimizedNode::retry(node.attempts),
+        NodeFactory::Switch(ref node) => {
+            OptimizedNode::switch(LeafNode::new(leaf(&node.selector.factory)))
+        }
+        NodeFactory::Priority(_) => OptimizedNode::priority(),
+        NodeFactory::ReactiveSelector(_) => OptimizedNode::reactive_selector(),
+        NodeFactory::Parallel(ref node) => OptimizedNode::parallel(node.threshold, node.children.len()),
+        NodeFactory::Budget(ref node) => OptimizedNode::budget(node.max),
+        NodeFactory::NonBlocking(ref node) => OptimizedNode::non_blocking(node.on_running),
+        NodeFactory::StoreResult(ref node) => {
+            OptimizedNode::store_result(node.variable.clone(), LeafNode::new(leaf(&node.writer.factory)))
+        }
+        NodeFactory::Subtree(ref name) => panic!("Trying to optimize an unlinked subtree {}", name),
     };
-    Some(optimized)
+    FlatNode::new(optimized)
+}
+
+// Whether the nodes below `node` have the same number of children as the nodes of `factory`
+fn same_shape<F,A>(factory: &NodeFactory<F>, mut children: ChildrenMut<FlatNode<A>>) -> bool {
+    let mut children = children.children_mut();
+    let mut factory_children = factory.get_children().iter();
+    // Both lists are walked in lockstep, without collecting the children of the tree
+    loop {
+        match (children.next(), factory_children.next()) {
+            (Some((_, grandchildren)), Some(factory)) => {
+                if !same_shape(factory, grandchildren) {
+                    return false;
+                }
+            }
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Replaces `node` and its descendants by the optimized nodes of `factory`, which has the same shape
+fn rebuild_node<F>(factory: &NodeFactory<F>, node: &mut FlatNode<F::Output>,
+                   mut children: ChildrenMut<FlatNode<F::Output>>)
+where F: LeafNodeFactory {
+    *node = optimize_node(factory, |leaf_factory| leaf_factory.instanciate());
+    for ((child, grandchildren), factory) in children.children_mut().zip(factory.get_children()) {
+        rebuild_node(factory, child, grandchildren);
+    }
 }
 
 impl <F> TreeFactory<F> {
@@ -31,6 +114,41 @@ impl <F> TreeFactory<F> {
         TreeFactory {
             name: name,
             root: root,
+            comments: BTreeMap::new(),
+            ids: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a line of comment before the node at `index`, in pre-order
+    ///
+    /// `comment` is written after `//` by `to_source`, and must not contain a line break. The
+    /// parser adds the comments found before each node.
+    pub fn add_comment(&mut self, index: usize, comment: String) {
+        self.comments.entry(index).or_insert_with(Vec::new).push(comment);
+    }
+
+    /// The lines of comment before the node at `index`, in pre-order
+    pub fn comments(&self, index: usize) -> &[String] {
+        self.comments.get(&index).map(|lines| &lines[..]).unwrap_or(&[])
+    }
+
+    /// Gives the identifier `id` to the node at `index`, in pre-order
+    ///
+    /// The index of the node can then be found with `OptimizedTree::index_of`. The parser sets
+    /// the identifiers written `#id` after the keyword of a node.
+    pub fn set_id(&mut self, index: usize, id: String) {
+        self.ids.insert(index, id);
+    }
+
+    /// The identifier of the node at `index`, in pre-order
+    pub fn id(&self, index: usize) -> Option<&str> {
+        self.ids.get(&index).map(|id| id.as_str())
+    }
+
+    // Gives the identifiers to the optimized tree, whose nodes are numbered in the same order
+    fn copy_ids<A>(&self, tree: &mut OptimizedTree<A>) {
+        for (index, id) in self.ids.iter() {
+            tree.ids.entry(id.clone()).or_insert(*index);
         }
     }
 
@@ -39,18 +157,177 @@ impl <F> TreeFactory<F> {
         Tree::new(self.root.instanciate())
     }
 
+    /// Same as `instanciate`, with the random nodes seeded as in `optimize_with_seed`
+    pub fn instanciate_with_seed(&self, seed: u64) -> Tree<F::Output>
+    where F: LeafNodeFactory {
+        let mut tree = self.instanciate();
+        tree.seed(seed);
+        tree
+    }
+
     pub fn optimize(&self) -> OptimizedTree<F::Output>
+    where F: LeafNodeFactory {
+        self.optimize_with_seed(XorShift::from_entropy().next_u64())
+    }
+
+    /// Same as `optimize`, but the random nodes are seeded from `seed`, making their choices
+    /// reproducible.
+    pub fn optimize_with_seed(&self, seed: u64) -> OptimizedTree<F::Output>
     where F: LeafNodeFactory {
         let tree = FlatTree::new(
             &self.root,
             0,
             optimize_inner);
-        OptimizedTree{inner: tree}
+        let mut optimized = OptimizedTree::new(tree);
+        optimized.number_nodes();
+        optimized.seed(seed);
+        self.copy_ids(&mut optimized);
+        optimized
+    }
+
+    /// Same as `optimize`, but rebuilds `tree` instead of allocating a new one
+    ///
+    /// When `tree` has the same shape as this factory, for instance because it was optimized from
+    /// it before, its nodes are replaced in place and the structure is not allocated again, which
+    /// saves allocations when agents are spawned often. Otherwise `tree` is replaced by a new
+    /// optimized tree. Either way, `tree` then starts a new activation.
+    pub fn optimize_into(&self, tree: &mut OptimizedTree<F::Output>)
+    where F: LeafNodeFactory {
+        self.optimize_into_with_seed(tree, XorShift::from_entropy().next_u64())
+    }
+
+    /// Same as `optimize_into`, with the random nodes seeded as in `optimize_with_seed`
+    pub fn optimize_into_with_seed(&self, tree: &mut OptimizedTree<F::Output>, seed: u64)
+    where F: LeafNodeFactory {
+        let same_shape = {
+            let (_, children) = tree.inner.tree_iter_mut()
+                                .nth(0).expect("Tried to optimize into a tree without node");
+            same_shape(&self.root, children)
+        };
+        if !same_shape {
+            *tree = self.optimize_with_seed(seed);
+            return;
+        }
+        {
+            let (root, children) = tree.inner.tree_iter_mut().nth(0).unwrap();
+            rebuild_node(&self.root, root, children);
+        }
+        tree.number_nodes();
+        tree.seed(seed);
+        tree.running_ticks = 0;
+        tree.finished = false;
+        tree.ids.clear();
+        self.copy_ids(tree);
+    }
+
+    /// Same as `optimize`, but the leaves are stored in a single vector instead of inside the
+    /// nodes
+    ///
+    /// This only pays off when the leaves are not boxed, for instance with a factory producing
+    /// the variants of an enum.
+    pub fn optimize_packed(&self) -> PackedTree<F::Output>
+    where F: LeafNodeFactory {
+        self.optimize_packed_with_seed(XorShift::from_entropy().next_u64())
+    }
+
+    /// Same as `optimize_packed`, with the random nodes seeded as in `optimize_with_seed`
+    pub fn optimize_packed_with_seed(&self, seed: u64) -> PackedTree<F::Output>
+    where F: LeafNodeFactory {
+        let tree = FlatTree::new(
+            &self.root,
+            0,
+            pack_inner);
+        let mut structure = OptimizedTree::new(tree);
+        structure.number_nodes();
+        structure.seed(seed);
+        self.copy_ids(&mut structure);
+        let mut visitor = PackVisitor { leaves: Vec::new() };
+        self.root.walk(&mut visitor);
+        PackedTree::new(structure, visitor.leaves)
+    }
+
+    /// Same as `NodeFactory::structural_eq`, the names of the trees being compared too
+    pub fn structural_eq(&self, other: &TreeFactory<F>) -> bool {
+        self.name == other.name && self.root.structural_eq(&other.root)
     }
 
     pub fn get_name(&self) -> &str {
         &self.name
     }
+
+    pub fn root(&self) -> &NodeFactory<F> {
+        &self.root
+    }
+
+    /// Number of nodes on the longest path from the root to a leaf, the root included
+    pub fn depth(&self) -> usize {
+        let mut visitor = DepthVisitor { current: 0, max: 0 };
+        self.root.walk(&mut visitor);
+        visitor.max
+    }
+
+    /// Number of nodes of the tree, unlinked subtree references counting as one node
+    pub fn node_count(&self) -> usize {
+        let mut visitor = CountVisitor { count: 0 };
+        self.root.walk(&mut visitor);
+        visitor.count
+    }
+
+    /// Checks the structure of the tree, returning every problem found
+    ///
+    /// The leaves were validated when their factories were created, this looks for composites
+    /// without children, parallel nodes that can never succeed, weighted selectors that can never
+    /// pick a child, and subtrees that were not linked.
+    pub fn validate(&self) -> Result<(),Vec<String>> {
+        let mut visitor = ValidateVisitor { errors: Vec::new() };
+        self.root.walk(&mut visitor);
+        if visitor.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(visitor.errors)
+        }
+    }
+
+    /// Names of the leaves, in depth-first order
+    ///
+    /// A name appears once per leaf using it, and unnamed leaves are skipped.
+    pub fn leaf_names(&self) -> Vec<&str> {
+        let mut visitor = LeafNamesVisitor { names: Vec::new() };
+        self.root.walk(&mut visitor);
+        visitor.names
+    }
+
+    /// Describes the tree as a Graphviz digraph, each node being labeled by its kind, or by its
+    /// name for leaves
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph {} {{", dot_escape(&self.name)).unwrap();
+        self.root.write_dot(&mut out, &mut 0);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Writes the tree back in the tree description language
+    ///
+    /// Leaves created without a name (with `NodeFactory::new_leaf`) are written as
+    /// `unnamed_leaf`, so the output can only be parsed back if all leaves are named. The
+    /// comments of the nodes are written before them.
+    #[cfg(feature = "std")]
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        out.push_str("tree ");
+        write_ident(&mut out, &self.name);
+        out.push_str(" {\n    ");
+        let mut comments = SourceComments { comments: &self.comments, ids: &self.ids, id: None, next: 0 };
+        self.root.write_source(&mut out, 1, &mut comments);
+        out.push_str("\n}\n");
+        out
+    }
+}
+
+// Quotes a string so that it can be used as a dot identifier
+fn dot_escape(s: &str) -> String {
+    format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\""))
 }
 
 /// Visits all its children in order. If one fails, then return immediatly a failure. If all
@@ -66,7 +343,7 @@ impl <F> TreeFactory<F> {
 /// 2. Walk to door
 /// 3. Open door
 /// 4. Walk through door
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub struct SequenceNodeFactory<F> {
     children: Vec<NodeFactory<F>>,
 }
@@ -89,12 +366,36 @@ impl <F> SequenceNodeFactory<F> {
     }
 }
 
+/// Same as Sequence, but a failing child does not stop the sequence: all the children are visited
+/// in order, and the node fails once they all finished if any of them failed.
+///
+/// This is useful when every child has side effects that must happen, like cleaning up after
+/// a task, even if some of them fail.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SequenceAllNodeFactory<F> {
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> SequenceAllNodeFactory<F> {
+    pub fn new(children: Vec<NodeFactory<F>>) -> SequenceAllNodeFactory<F> {
+        SequenceAllNodeFactory {
+            children: children,
+        }
+    }
+
+    pub fn instanciate(&self) -> SequenceAllNode<F::Output>
+    where F: LeafNodeFactory {
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        SequenceAllNode::new(children)
+    }
+}
+
 /// Counterpart of Sequence: returns Success on the first child returning Success, and return
 /// Failure if all children fail.
 ///
 /// This is typically used when a set of actions have the same objective, but those actions are
 /// classified by preference.
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub struct SelectorNodeFactory<F> {
     children: Vec<NodeFactory<F>>,
 }
@@ -117,8 +418,71 @@ impl <F> SelectorNodeFactory<F> {
     }
 }
 
+/// Same as Selector, but the children are tried in a random order, picked every time the node is
+/// entered.
+///
+/// This is typically used to give some variety to the behaviour of agents, when several actions
+/// are equally good.
+#[derive(Debug,Clone,PartialEq)]
+pub struct RandomSelectorNodeFactory<F> {
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> RandomSelectorNodeFactory<F> {
+    pub fn new(children: Vec<NodeFactory<F>>) -> RandomSelectorNodeFactory<F> {
+        RandomSelectorNodeFactory {
+            children: children,
+        }
+    }
+
+    pub fn push(&mut self, node: NodeFactory<F>) {
+        self.children.push(node);
+    }
+
+    pub fn instanciate(&self) -> RandomSelectorNode<F::Output>
+    where F: LeafNodeFactory {
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        RandomSelectorNode::new(children, XorShift::from_entropy())
+    }
+}
+
+/// Visits a single child, picked randomly with a probability proportional to its weight every
+/// time the node is entered, and returns its result.
+///
+/// This is typically used for loot tables, or to pick an idle animation.
+#[derive(Debug,Clone,PartialEq)]
+pub struct WeightedSelectorNodeFactory<F> {
+    weights: Vec<u32>,
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> WeightedSelectorNodeFactory<F> {
+    pub fn new(children: Vec<(u32,NodeFactory<F>)>) -> WeightedSelectorNodeFactory<F> {
+        let (weights, children) = children.into_iter().unzip();
+        WeightedSelectorNodeFactory {
+            weights: weights,
+            children: children,
+        }
+    }
+
+    pub fn push(&mut self, weight: u32, node: NodeFactory<F>) {
+        self.weights.push(weight);
+        self.children.push(node);
+    }
+
+    pub fn instanciate(&self) -> WeightedSelectorNode<F::Output>
+    where F: LeafNodeFactory {
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        WeightedSelectorNode::new(self.weights.clone(), children, XorShift::from_entropy())
+    }
+}
+
 /// Same as Sequence, but do not remember the last running child and revisit all children
-#[derive(Debug,Clone)]
+///
+/// Sequence and Selector are not reactive: a running child is resumed directly on the next tick,
+/// without checking its previous siblings again. Priority is the reactive counterpart of
+/// Sequence, as ReactiveSelector is the one of Selector.
+#[derive(Debug,Clone,PartialEq)]
 pub struct PriorityNodeFactory<F> {
     children: Vec<NodeFactory<F>>,
 }
@@ -139,8 +503,68 @@ impl <F> PriorityNodeFactory<F> {
     }
 }
 
+/// Same as Selector, but all children are evaluated again from the first one on every tick
+///
+/// A Selector remembers its running child and resumes it directly. A ReactiveSelector instead
+/// checks the children before the running one again, and if one of them now succeeds or runs,
+/// the running child is abandoned and its state reset. This lets a higher priority behaviour,
+/// like fleeing, interrupt a long lower priority one, like patrolling.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ReactiveSelectorNodeFactory<F> {
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> ReactiveSelectorNodeFactory<F> {
+    pub fn new(children: Vec<NodeFactory<F>>) -> ReactiveSelectorNodeFactory<F> {
+        ReactiveSelectorNodeFactory{children: children}
+    }
+
+    pub fn push(&mut self, node: NodeFactory<F>) {
+        self.children.push(node);
+    }
+
+    pub fn instanciate(&self) -> ReactiveSelectorNode<F::Output>
+    where F: LeafNodeFactory {
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        ReactiveSelectorNode::new(children)
+    }
+}
+
+/// Visits all its children on every tick. Returns Success once at least `threshold` children
+/// succeeded, Failure once more than `children.len() - threshold` children failed, and Running
+/// otherwise.
+///
+/// Children that already finished are not visited again until the parallel node itself finishes.
+///
+/// This is typically used to perform an action while monitoring a condition, for example walking
+/// to a target while checking that it is still visible.
+#[derive(Debug,Clone,PartialEq)]
+pub struct ParallelNodeFactory<F> {
+    threshold: usize,
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> ParallelNodeFactory<F> {
+    pub fn new(threshold: usize, children: Vec<NodeFactory<F>>) -> ParallelNodeFactory<F> {
+        ParallelNodeFactory {
+            threshold: threshold,
+            children: children,
+        }
+    }
+
+    pub fn push(&mut self, node: NodeFactory<F>) {
+        self.children.push(node);
+    }
+
+    pub fn instanciate(&self) -> ParallelNode<F::Output>
+    where F: LeafNodeFactory {
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        ParallelNode::new(self.threshold, children)
+    }
+}
+
 /// Inverts the output of the child
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub struct InverterNodeFactory<F> {
     child: Box<NodeFactory<F>>,
 }
@@ -157,13 +581,458 @@ impl <F> InverterNodeFactory<F> {
     }
 }
 
-#[derive(Debug,Clone)]
+/// Visits its child `count` times, returning Running until the last successful visit
+#[derive(Debug,Clone,PartialEq)]
+pub struct RepeaterNodeFactory<F> {
+    count: usize,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> RepeaterNodeFactory<F> {
+    pub fn new(count: usize, child: Box<NodeFactory<F>>) -> RepeaterNodeFactory<F> {
+        RepeaterNodeFactory {
+            count: count,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> RepeaterNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        RepeaterNode::new(self.count, child)
+    }
+}
+
+/// Visits its child until it fails. Returns Running while the child succeeds or runs, and Success
+/// once the child failed.
+///
+/// The child is visited only once per tick, so a child that always succeeds keeps this node
+/// running forever instead of looping inside a single visit.
+#[derive(Debug,Clone,PartialEq)]
+pub struct UntilFailNodeFactory<F> {
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> UntilFailNodeFactory<F> {
+    pub fn new(child: Box<NodeFactory<F>>) -> UntilFailNodeFactory<F> {
+        UntilFailNodeFactory{child: child}
+    }
+
+    pub fn instanciate(&self) -> UntilFailNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        UntilFailNode::new(child)
+    }
+}
+
+/// Returns Success when its child finishes, whether it succeeded or failed. Running is propagated.
+///
+/// This is typically used for optional actions inside a sequence, whose failure should not abort
+/// the whole sequence.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SucceederNodeFactory<F> {
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> SucceederNodeFactory<F> {
+    pub fn new(child: Box<NodeFactory<F>>) -> SucceederNodeFactory<F> {
+        SucceederNodeFactory{child: child}
+    }
+
+    pub fn instanciate(&self) -> SucceederNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        SucceederNode::new(child)
+    }
+}
+
+/// Returns Failure without visiting its child during the `ticks` visits following a success of
+/// the child.
+///
+/// Ticks are counted by the node itself, one per visit.
+#[derive(Debug,Clone,PartialEq)]
+pub struct CooldownNodeFactory<F> {
+    ticks: usize,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> CooldownNodeFactory<F> {
+    pub fn new(ticks: usize, child: Box<NodeFactory<F>>) -> CooldownNodeFactory<F> {
+        CooldownNodeFactory {
+            ticks: ticks,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> CooldownNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        CooldownNode::new(self.ticks, child)
+    }
+}
+
+/// Visits its child until it succeeded `max` times, and then fails
+///
+/// The count survives errors and preemptions, and is only cleared by `OptimizedTree::reset`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LimiterNodeFactory<F> {
+    max: usize,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> LimiterNodeFactory<F> {
+    pub fn new(max: usize, child: Box<NodeFactory<F>>) -> LimiterNodeFactory<F> {
+        LimiterNodeFactory {
+            max: max,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> LimiterNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        LimiterNode::new(self.max, child)
+    }
+}
+
+/// Visits its failing child again on the next ticks, and fails after `attempts` failures in a row
+#[derive(Debug,Clone,PartialEq)]
+pub struct RetryNodeFactory<F> {
+    attempts: usize,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> RetryNodeFactory<F> {
+    pub fn new(attempts: usize, child: Box<NodeFactory<F>>) -> RetryNodeFactory<F> {
+        RetryNodeFactory {
+            attempts: attempts,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> RetryNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        RetryNode::new(self.attempts, child)
+    }
+}
+
+/// Visits at most `max` nodes of its child subtree during a tick, the rest being left for the next
+/// ticks
+///
+/// Spreads the work of large trees over several frames. See `BudgetNode`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct BudgetNodeFactory<F> {
+    max: usize,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> BudgetNodeFactory<F> {
+    pub fn new(max: usize, child: Box<NodeFactory<F>>) -> BudgetNodeFactory<F> {
+        BudgetNodeFactory {
+            max: max,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> BudgetNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        BudgetNode::new(self.max, child)
+    }
+}
+
+/// Returns `on_running` instead of `Running` when its child is still running. See `NonBlockingNode`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct NonBlockingNodeFactory<F> {
+    on_running: NonBlockingResult,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> NonBlockingNodeFactory<F> {
+    pub fn new(on_running: NonBlockingResult, child: Box<NodeFactory<F>>) -> NonBlockingNodeFactory<F> {
+        NonBlockingNodeFactory {
+            on_running: on_running,
+            child: child,
+        }
+    }
+
+    pub fn instanciate(&self) -> NonBlockingNode<F::Output>
+    where F: LeafNodeFactory {
+        let child = Box::new(self.child.instanciate());
+        NonBlockingNode::new(self.on_running, child)
+    }
+}
+
+/// Writes the result of its child to the variable `variable` through the `writer` leaf, see
+/// `BehaviourTreeNode::store_result`
+///
+/// Later nodes can then branch on the outcome of the child. See `StoreResultNode`.
+#[derive(Debug,Clone,PartialEq)]
+pub struct StoreResultNodeFactory<F> {
+    variable: String,
+    writer: LeafFactory<F>,
+    child: Box<NodeFactory<F>>,
+}
+
+impl <F> StoreResultNodeFactory<F> {
+    pub fn new(variable: String, writer: LeafFactory<F>, child: Box<NodeFactory<F>>)
+    -> StoreResultNodeFactory<F> {
+        StoreResultNodeFactory {
+            variable: variable,
+            writer: writer,
+            child: child,
+        }
+    }
+
+    pub fn variable(&self) -> &str {
+        &self.variable
+    }
+
+    pub fn writer(&self) -> &LeafFactory<F> {
+        &self.writer
+    }
+
+    pub fn instanciate(&self) -> StoreResultNode<F::Output>
+    where F: LeafNodeFactory {
+        let writer = LeafNode::new(self.writer.factory.instanciate());
+        let child = Box::new(self.child.instanciate());
+        StoreResultNode::new(self.variable.clone(), writer, child)
+    }
+}
+
+/// Visits the child whose index is given by the `selector` leaf, see
+/// `BehaviourTreeNode::select_child`
+///
+/// This is typically used for state machines, the index of the child being the current state.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SwitchNodeFactory<F> {
+    selector: LeafFactory<F>,
+    children: Vec<NodeFactory<F>>,
+}
+
+impl <F> SwitchNodeFactory<F> {
+    pub fn new(selector: LeafFactory<F>, children: Vec<NodeFactory<F>>) -> SwitchNodeFactory<F> {
+        SwitchNodeFactory {
+            selector: selector,
+            children: children,
+        }
+    }
+
+    pub fn push(&mut self, node: NodeFactory<F>) {
+        self.children.push(node);
+    }
+
+    pub fn selector(&self) -> &LeafFactory<F> {
+        &self.selector
+    }
+
+    pub fn instanciate(&self) -> SwitchNode<F::Output>
+    where F: LeafNodeFactory {
+        let selector = LeafNode::new(self.selector.factory.instanciate());
+        let children = self.children.iter().map(|child| child.instanciate()).collect();
+        SwitchNode::new(selector, children)
+    }
+}
+
+/// A leaf factory, along with the name and options it was generated from
+///
+/// Options are only produced by the parser, so they are only available with the `std` feature.
+#[derive(Debug,Clone,PartialEq)]
+pub struct LeafFactory<F> {
+    name: Option<String>,
+    #[cfg(feature = "std")]
+    options: Option<Value>,
+    factory: F,
+}
+
+impl <F> LeafFactory<F> {
+    #[cfg(feature = "std")]
+    pub fn new(name: Option<String>, options: Option<Value>, factory: F) -> LeafFactory<F> {
+        LeafFactory {
+            name: name,
+            options: options,
+            factory: factory,
+        }
+    }
+
+    // Leaf built in code rather than parsed
+    fn without_options(name: Option<String>, factory: F) -> LeafFactory<F> {
+        LeafFactory {
+            name: name,
+            #[cfg(feature = "std")]
+            options: None,
+            factory: factory,
+        }
+    }
+
+    /// Name of the leaf, if it was created by name (for example by the parser)
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.as_ref())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn options(&self) -> &Option<Value> {
+        &self.options
+    }
+
+    pub fn factory(&self) -> &F {
+        &self.factory
+    }
+
+    // Same name and options, whatever the factories
+    fn same_source(&self, other: &LeafFactory<F>) -> bool {
+        #[cfg(feature = "std")]
+        {
+            if self.options != other.options {
+                return false;
+            }
+        }
+        self.name == other.name
+    }
+}
+
+/// Callbacks invoked by `NodeFactory::walk` during a depth-first traversal
+///
+/// Composites and decorators are entered before their children and left after them. All the
+/// methods do nothing by default.
+pub trait FactoryVisitor<'a, F: 'a> {
+    fn enter_leaf(&mut self, _leaf: &'a LeafFactory<F>) {}
+    fn enter_composite(&mut self, _node: &'a NodeFactory<F>) {}
+    fn leave_composite(&mut self, _node: &'a NodeFactory<F>) {}
+    /// Called for references to subtrees that are not linked yet
+    fn enter_subtree(&mut self, _name: &'a str) {}
+}
+
+struct PackVisitor<A> {
+    leaves: Vec<A>,
+}
+
+impl <'a, F: LeafNodeFactory + 'a> FactoryVisitor<'a, F> for PackVisitor<F::Output> {
+    fn enter_leaf(&mut self, leaf: &'a LeafFactory<F>) {
+        self.leaves.push(leaf.factory.instanciate());
+    }
+
+    fn enter_composite(&mut self, node: &'a NodeFactory<F>) {
+        match *node {
+            NodeFactory::Switch(ref switch) => self.leaves.push(switch.selector.factory.instanciate()),
+            NodeFactory::StoreResult(ref store) => self.leaves.push(store.writer.factory.instanciate()),
+            _ => {}
+        }
+    }
+
+    fn enter_subtree(&mut self, name: &'a str) {
+        panic!("Trying to optimize an unlinked subtree {}", name);
+    }
+}
+
+struct ValidateVisitor {
+    errors: Vec<String>,
+}
+
+impl <'a, F: 'a> FactoryVisitor<'a, F> for ValidateVisitor {
+    fn enter_composite(&mut self, node: &'a NodeFactory<F>) {
+        let children = node.get_children().len();
+        if children == 0 {
+            self.errors.push(format!("{} has no children", node.label()));
+        }
+        match *node {
+            NodeFactory::Parallel(ref parallel) if parallel.threshold > children => {
+                self.errors.push(format!("{} can never succeed with {} children", node.label(), children));
+            }
+            NodeFactory::WeightedSelector(ref selector) if children > 0 => {
+                if selector.weights.iter().all(|weight| *weight == 0) {
+                    self.errors.push(format!("{} has only zero weights", node.label()));
+                }
+            }
+            NodeFactory::Budget(ref budget) if budget.max == 0 => {
+                self.errors.push(format!("{} never visits its child", node.label()));
+            }
+            _ => {}
+        }
+    }
+
+    fn enter_subtree(&mut self, name: &'a str) {
+        self.errors.push(format!("Subtree {} is not linked", name));
+    }
+}
+
+struct DepthVisitor {
+    current: usize,
+    max: usize,
+}
+
+impl <'a, F: 'a> FactoryVisitor<'a, F> for DepthVisitor {
+    fn enter_leaf(&mut self, _leaf: &'a LeafFactory<F>) {
+        self.max = ::core::cmp::max(self.max, self.current + 1);
+    }
+
+    fn enter_composite(&mut self, _node: &'a NodeFactory<F>) {
+        self.current += 1;
+        self.max = ::core::cmp::max(self.max, self.current);
+    }
+
+    fn leave_composite(&mut self, _node: &'a NodeFactory<F>) {
+        self.current -= 1;
+    }
+
+    fn enter_subtree(&mut self, _name: &'a str) {
+        self.max = ::core::cmp::max(self.max, self.current + 1);
+    }
+}
+
+struct CountVisitor {
+    count: usize,
+}
+
+impl <'a, F: 'a> FactoryVisitor<'a, F> for CountVisitor {
+    fn enter_leaf(&mut self, _leaf: &'a LeafFactory<F>) {
+        self.count += 1;
+    }
+
+    fn enter_composite(&mut self, _node: &'a NodeFactory<F>) {
+        self.count += 1;
+    }
+
+    fn enter_subtree(&mut self, _name: &'a str) {
+        self.count += 1;
+    }
+}
+
+struct LeafNamesVisitor<'a> {
+    names: Vec<&'a str>,
+}
+
+impl <'a, F: 'a> FactoryVisitor<'a, F> for LeafNamesVisitor<'a> {
+    fn enter_leaf(&mut self, leaf: &'a LeafFactory<F>) {
+        if let Some(name) = leaf.name() {
+            self.names.push(name);
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum NodeFactory<F> {
-    Leaf(F),
+    Leaf(LeafFactory<F>),
     Sequence(SequenceNodeFactory<F>),
+    SequenceAll(SequenceAllNodeFactory<F>),
     Priority(PriorityNodeFactory<F>),
+    ReactiveSelector(ReactiveSelectorNodeFactory<F>),
     Selector(SelectorNodeFactory<F>),
+    RandomSelector(RandomSelectorNodeFactory<F>),
+    WeightedSelector(WeightedSelectorNodeFactory<F>),
+    Parallel(ParallelNodeFactory<F>),
     Inverter(InverterNodeFactory<F>),
+    Repeater(RepeaterNodeFactory<F>),
+    UntilFail(UntilFailNodeFactory<F>),
+    Succeeder(SucceederNodeFactory<F>),
+    Cooldown(CooldownNodeFactory<F>),
+    Limiter(LimiterNodeFactory<F>),
+    Retry(RetryNodeFactory<F>),
+    Switch(SwitchNodeFactory<F>),
+    Budget(BudgetNodeFactory<F>),
+    NonBlocking(NonBlockingNodeFactory<F>),
+    StoreResult(StoreResultNodeFactory<F>),
     Subtree(String),
 }
 
@@ -171,49 +1040,928 @@ impl <F> NodeFactory<F> {
     pub fn instanciate(&self) -> Node<F::Output>
     where F: LeafNodeFactory {
         match *self {
-            NodeFactory::Leaf(ref node_factory) => Node::Leaf(LeafNode::new(node_factory.instanciate())),
+            NodeFactory::Leaf(ref leaf) => Node::Leaf(LeafNode::new(leaf.factory.instanciate())),
             NodeFactory::Sequence(ref node) => Node::Sequence(node.instanciate()),
+            NodeFactory::SequenceAll(ref node) => Node::SequenceAll(node.instanciate()),
             NodeFactory::Priority(ref node) => Node::Priority(node.instanciate()),
+            NodeFactory::ReactiveSelector(ref node) => Node::ReactiveSelector(node.instanciate()),
             NodeFactory::Selector(ref node) => Node::Selector(node.instanciate()),
+            NodeFactory::RandomSelector(ref node) => Node::RandomSelector(node.instanciate()),
+            NodeFactory::WeightedSelector(ref node) => Node::WeightedSelector(node.instanciate()),
+            NodeFactory::Parallel(ref node) => Node::Parallel(node.instanciate()),
             NodeFactory::Inverter(ref node) => Node::Inverter(node.instanciate()),
+            NodeFactory::Repeater(ref node) => Node::Repeater(node.instanciate()),
+            NodeFactory::UntilFail(ref node) => Node::UntilFail(node.instanciate()),
+            NodeFactory::Succeeder(ref node) => Node::Succeeder(node.instanciate()),
+            NodeFactory::Cooldown(ref node) => Node::Cooldown(node.instanciate()),
+            NodeFactory::Limiter(ref node) => Node::Limiter(node.instanciate()),
+            NodeFactory::Retry(ref node) => Node::Retry(node.instanciate()),
+            NodeFactory::Switch(ref node) => Node::Switch(node.instanciate()),
+            NodeFactory::Budget(ref node) => Node::Budget(node.instanciate()),
+            NodeFactory::NonBlocking(ref node) => Node::NonBlocking(node.instanciate()),
+            NodeFactory::StoreResult(ref node) => Node::StoreResult(node.instanciate()),
             NodeFactory::Subtree(ref name) => panic!("Trying to instanciate an unlinked subtree {}", name),
         }
     }
 
+    /// Depth-first traversal of the node and its descendants, without running anything
+    pub fn walk<'a, V: FactoryVisitor<'a, F>>(&'a self, visitor: &mut V) {
+        match *self {
+            NodeFactory::Leaf(ref leaf) => visitor.enter_leaf(leaf),
+            NodeFactory::Subtree(ref name) => visitor.enter_subtree(name),
+            _ => {
+                visitor.enter_composite(self);
+                for child in self.get_children() {
+                    child.walk(visitor);
+                }
+                visitor.leave_composite(self);
+            }
+        }
+    }
+
+    /// Compares the shape of the two trees, without comparing the leaf factories themselves
+    ///
+    /// Leaves are equal if they have the same name and options, so two trees parsed from the same
+    /// source are equal, even if their leaf factories cannot be compared. Unnamed leaves only
+    /// differ by their options.
+    pub fn structural_eq(&self, other: &NodeFactory<F>) -> bool {
+        let same_node = match (self, other) {
+            (&NodeFactory::Leaf(ref a), &NodeFactory::Leaf(ref b)) => {
+                return a.same_source(b);
+            }
+            (&NodeFactory::Subtree(ref a), &NodeFactory::Subtree(ref b)) => return a == b,
+            (&NodeFactory::Leaf(_), _) | (_, &NodeFactory::Leaf(_))
+                | (&NodeFactory::Subtree(_), _) | (_, &NodeFactory::Subtree(_)) => return false,
+            (&NodeFactory::WeightedSelector(ref a), &NodeFactory::WeightedSelector(ref b)) => {
+                a.weights == b.weights
+            }
+            (&NodeFactory::Switch(ref a), &NodeFactory::Switch(ref b)) => {
+                a.selector.same_source(&b.selector)
+            }
+            (&NodeFactory::StoreResult(ref a), &NodeFactory::StoreResult(ref b)) => {
+                a.variable == b.variable && a.writer.same_source(&b.writer)
+            }
+            // The labels of composites and decorators contain their kind and parameters
+            _ => self.label() == other.label(),
+        };
+        let (children, other_children) = (self.get_children(), other.get_children());
+        same_node
+            && children.len() == other_children.len()
+            && children.iter().zip(other_children).all(|(a, b)| a.structural_eq(b))
+    }
+
+    /// Short description of the node, without its children
+    pub fn label(&self) -> String {
+        match *self {
+            NodeFactory::Leaf(ref leaf) => String::from(leaf.name().unwrap_or("Leaf")),
+            NodeFactory::Sequence(_) => String::from("Sequence"),
+            NodeFactory::SequenceAll(_) => String::from("SequenceAll"),
+            NodeFactory::Priority(_) => String::from("Priority"),
+            NodeFactory::ReactiveSelector(_) => String::from("ReactiveSelector"),
+            NodeFactory::Selector(_) => String::from("Selector"),
+            NodeFactory::RandomSelector(_) => String::from("RandomSelector"),
+            NodeFactory::WeightedSelector(_) => String::from("WeightedSelector"),
+            NodeFactory::Parallel(ref node) => format!("Parallel({})", node.threshold),
+            NodeFactory::Inverter(_) => String::from("Inverter"),
+            NodeFactory::Repeater(ref node) => format!("Repeater({})", node.count),
+            NodeFactory::UntilFail(_) => String::from("UntilFail"),
+            NodeFactory::Succeeder(_) => String::from("Succeeder"),
+            NodeFactory::Cooldown(ref node) => format!("Cooldown({})", node.ticks),
+            NodeFactory::Limiter(ref node) => format!("Limiter({})", node.max),
+            NodeFactory::Retry(ref node) => format!("Retry({})", node.attempts),
+            NodeFactory::Switch(_) => String::from("Switch"),
+            NodeFactory::Budget(ref node) => format!("Budget({})", node.max),
+            NodeFactory::NonBlocking(ref node) => format!("NonBlocking({:?})", node.on_running),
+            NodeFactory::StoreResult(ref node) => format!("StoreResult({})", node.variable),
+            NodeFactory::Subtree(ref name) => format!("Subtree({})", name),
+        }
+    }
+
+    /// Writes the dot description of this node and its descendants, numbered from `next_id`,
+    /// and returns the identifier of this node
+    fn write_dot(&self, out: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        writeln!(out, "    {} [label={}];", id, dot_escape(&self.label())).unwrap();
+        if let NodeFactory::Subtree(_) = *self {
+            return id;
+        }
+        for child in self.get_children() {
+            let child_id = child.write_dot(out, next_id);
+            writeln!(out, "    {} -> {};", id, child_id).unwrap();
+        }
+        id
+    }
+
+    // Writes the node at the current position of out, its children being indented one level
+    // deeper than `indent`
+    #[cfg(feature = "std")]
+    fn write_source(&self, out: &mut String, indent: usize, comments: &mut SourceComments) {
+        if let Some(lines) = comments.comments.get(&comments.next) {
+            for line in lines {
+                write!(out, "//{}\n", line).unwrap();
+                write_indent(out, indent);
+            }
+        }
+        let ids = comments.ids;
+        comments.id = ids.get(&comments.next).map(|id| id.as_str());
+        comments.next += 1;
+        match *self {
+            NodeFactory::Leaf(ref leaf) => {
+                write_ident(out, leaf.name().unwrap_or("unnamed_leaf"));
+                if let Some(ref options) = leaf.options {
+                    write!(out, "({})", options).unwrap();
+                }
+                write_id(out, comments);
+            }
+            NodeFactory::Sequence(ref node) => {
+                write_list(out, "sequence", &node.children, indent, comments)
+            }
+            NodeFactory::SequenceAll(ref node) => {
+                write_list(out, "sequence_all", &node.children, indent, comments)
+            }
+            NodeFactory::Priority(ref node) => {
+                write_list(out, "priority", &node.children, indent, comments)
+            }
+            NodeFactory::ReactiveSelector(ref node) => {
+                write_list(out, "reactive_selector", &node.children, indent, comments)
+            }
+            NodeFactory::Selector(ref node) => {
+                write_list(out, "selector", &node.children, indent, comments)
+            }
+            NodeFactory::RandomSelector(ref node) => {
+                write_list(out, "random_selector", &node.children, indent, comments)
+            }
+            NodeFactory::WeightedSelector(ref node) => {
+                out.push_str("weighted_selector");
+                write_id(out, comments);
+                if node.children.is_empty() {
+                    out.push_str(" {}");
+                    return;
+                }
+                out.push_str(" {\n");
+                for (weight, child) in node.weights.iter().zip(node.children.iter()) {
+                    write_indent(out, indent + 1);
+                    write!(out, "{}: ", weight).unwrap();
+                    child.write_source(out, indent + 1, comments);
+                    out.push_str(",\n");
+                }
+                write_indent(out, indent);
+                out.push_str("}");
+            }
+            NodeFactory::Parallel(ref node) => {
+                let keyword = format!("parallel({})", node.threshold);
+                write_list(out, &keyword, &node.children, indent, comments)
+            }
+            NodeFactory::Inverter(ref node) => {
+                out.push_str("inverter");
+                write_id(out, comments);
+                out.push_str(" ");
+                node.child.write_source(out, indent, comments);
+            }
+            NodeFactory::Repeater(ref node) => {
+                let keyword = format!("repeat({})", node.count);
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::UntilFail(ref node) => {
+                write_braced(out, "until_fail", &node.child, indent, comments)
+            }
+            NodeFactory::Succeeder(ref node) => {
+                write_braced(out, "force_success", &node.child, indent, comments)
+            }
+            NodeFactory::Cooldown(ref node) => {
+                let keyword = format!("cooldown({})", node.ticks);
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::Limiter(ref node) => {
+                let keyword = format!("limit({})", node.max);
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::Retry(ref node) => {
+                let keyword = format!("retry({})", node.attempts);
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::Budget(ref node) => {
+                let keyword = format!("budget({})", node.max);
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::NonBlocking(ref node) => {
+                let keyword = match node.on_running {
+                    NonBlockingResult::Success => "non_blocking(success)",
+                    NonBlockingResult::Failure => "non_blocking(failure)",
+                };
+                write_braced(out, keyword, &node.child, indent, comments)
+            }
+            NodeFactory::StoreResult(ref node) => {
+                let keyword = format!("store_result({})", Value::String(node.variable.clone()));
+                write_braced(out, &keyword, &node.child, indent, comments)
+            }
+            NodeFactory::Switch(ref node) => {
+                let keyword = match node.selector.options {
+                    Some(ref options) => format!("switch({})", options),
+                    None => String::from("switch"),
+                };
+                write_list(out, &keyword, &node.children, indent, comments)
+            }
+            NodeFactory::Subtree(ref name) => {
+                out.push_str("subtree ");
+                write_ident(out, name);
+            }
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph {\n");
+        self.write_dot(&mut out, &mut 0);
+        out.push_str("}\n");
+        out
+    }
+
     pub fn new_leaf(factory: F) -> NodeFactory<F> {
-        NodeFactory::Leaf(factory)
+        NodeFactory::Leaf(LeafFactory::without_options(None, factory))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new_named_leaf(name: String, options: Option<Value>, factory: F) -> NodeFactory<F> {
+        NodeFactory::Leaf(LeafFactory::new(Some(name), options, factory))
     }
 
     pub fn new_sequence(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
         NodeFactory::Sequence(SequenceNodeFactory::new(children))
     }
 
+    pub fn new_sequence_all(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::SequenceAll(SequenceAllNodeFactory::new(children))
+    }
+
     pub fn new_selector(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
         NodeFactory::Selector(SelectorNodeFactory::new(children))
     }
 
+    pub fn new_random_selector(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::RandomSelector(RandomSelectorNodeFactory::new(children))
+    }
+
+    pub fn new_weighted_selector(children: Vec<(u32,NodeFactory<F>)>) -> NodeFactory<F> {
+        NodeFactory::WeightedSelector(WeightedSelectorNodeFactory::new(children))
+    }
+
     pub fn new_priority(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
         NodeFactory::Priority(PriorityNodeFactory::new(children))
     }
 
+    pub fn new_reactive_selector(children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::ReactiveSelector(ReactiveSelectorNodeFactory::new(children))
+    }
+
+    pub fn new_parallel(threshold: usize, children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Parallel(ParallelNodeFactory::new(threshold, children))
+    }
+
     pub fn new_inverter(child: Box<NodeFactory<F>>) -> NodeFactory<F> {
         NodeFactory::Inverter(InverterNodeFactory::new(child))
     }
 
+    pub fn new_repeater(count: usize, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Repeater(RepeaterNodeFactory::new(count, child))
+    }
+
+    pub fn new_until_fail(child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::UntilFail(UntilFailNodeFactory::new(child))
+    }
+
+    pub fn new_succeeder(child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Succeeder(SucceederNodeFactory::new(child))
+    }
+
+    pub fn new_cooldown(ticks: usize, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Cooldown(CooldownNodeFactory::new(ticks, child))
+    }
+
+    pub fn new_limiter(max: usize, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Limiter(LimiterNodeFactory::new(max, child))
+    }
+
+    pub fn new_retry(attempts: usize, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Retry(RetryNodeFactory::new(attempts, child))
+    }
+
+    pub fn new_budget(max: usize, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Budget(BudgetNodeFactory::new(max, child))
+    }
+
+    pub fn new_non_blocking(on_running: NonBlockingResult, child: Box<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::NonBlocking(NonBlockingNodeFactory::new(on_running, child))
+    }
+
+    pub fn new_switch(selector: LeafFactory<F>, children: Vec<NodeFactory<F>>) -> NodeFactory<F> {
+        NodeFactory::Switch(SwitchNodeFactory::new(selector, children))
+    }
+
+    pub fn new_store_result(variable: String, writer: LeafFactory<F>, child: Box<NodeFactory<F>>)
+    -> NodeFactory<F> {
+        NodeFactory::StoreResult(StoreResultNodeFactory::new(variable, writer, child))
+    }
+
     pub fn new_subtree(name: String) -> NodeFactory<F> {
         NodeFactory::Subtree(name)
     }
 }
 
+/// Builds trees from Rust code, without going through the parser
+///
+/// Each composite or decorator method takes a closure receiving a new builder, in which the
+/// children of the node are added:
+///
+/// ```ignore
+/// let tree = TreeBuilder::new()
+///     .sequence(|b| b
+///         .leaf(locate_door)
+///         .selector(|b| b
+///             .leaf(open_door)
+///             .leaf(break_door))
+///         .leaf(walk_through_door))
+///     .build("enter_room");
+/// ```
+pub struct TreeBuilder<F> {
+    nodes: Vec<NodeFactory<F>>,
+}
+
+impl <F> TreeBuilder<F> {
+    pub fn new() -> TreeBuilder<F> {
+        TreeBuilder {
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Adds an already built node
+    pub fn node(mut self, node: NodeFactory<F>) -> TreeBuilder<F> {
+        self.nodes.push(node);
+        self
+    }
+
+    pub fn leaf(self, factory: F) -> TreeBuilder<F> {
+        self.node(NodeFactory::new_leaf(factory))
+    }
+
+    pub fn sequence<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_sequence(children))
+    }
+
+    pub fn sequence_all<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_sequence_all(children))
+    }
+
+    pub fn selector<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_selector(children))
+    }
+
+    pub fn random_selector<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_random_selector(children))
+    }
+
+    /// The children added by the closure get the weights of `weights`, in the same order
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure does not add as many nodes as there are weights.
+    pub fn weighted_selector<B>(self, weights: Vec<u32>, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        if children.len() != weights.len() {
+            panic!("WeightedSelector with {} weights but {} children", weights.len(), children.len());
+        }
+        self.node(NodeFactory::new_weighted_selector(weights.into_iter().zip(children).collect()))
+    }
+
+    pub fn priority<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_priority(children))
+    }
+
+    pub fn reactive_selector<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_reactive_selector(children))
+    }
+
+    pub fn parallel<B>(self, threshold: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_parallel(threshold, children))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn inverter<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Inverter");
+        self.node(NodeFactory::new_inverter(Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn repeater<B>(self, count: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Repeater");
+        self.node(NodeFactory::new_repeater(count, Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn until_fail<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("UntilFail");
+        self.node(NodeFactory::new_until_fail(Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn succeeder<B>(self, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Succeeder");
+        self.node(NodeFactory::new_succeeder(Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn cooldown<B>(self, ticks: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Cooldown");
+        self.node(NodeFactory::new_cooldown(ticks, Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn limit<B>(self, max: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Limiter");
+        self.node(NodeFactory::new_limiter(max, Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn retry<B>(self, attempts: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Retry");
+        self.node(NodeFactory::new_retry(attempts, Box::new(child)))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn budget<B>(self, max: usize, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("Budget");
+        self.node(NodeFactory::new_budget(max, Box::new(child)))
+    }
+
+    /// The node returns `on_running` while its child is running
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn non_blocking<B>(self, on_running: NonBlockingResult, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("NonBlocking");
+        self.node(NodeFactory::new_non_blocking(on_running, Box::new(child)))
+    }
+
+    /// The child visited is chosen by the `selector` leaf, see `BehaviourTreeNode::select_child`
+    pub fn switch<B>(self, selector: F, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let children = build(TreeBuilder::new()).nodes;
+        self.node(NodeFactory::new_switch(LeafFactory::without_options(None, selector), children))
+    }
+
+    /// The result of the child is written to `variable` by the `writer` leaf, see
+    /// `BehaviourTreeNode::store_result`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the closure does not add exactly one node.
+    pub fn store_result<B>(self, variable: &str, writer: F, build: B) -> TreeBuilder<F>
+    where B: FnOnce(TreeBuilder<F>) -> TreeBuilder<F> {
+        let child = build(TreeBuilder::new()).single("StoreResult");
+        let writer = LeafFactory::without_options(None, writer);
+        self.node(NodeFactory::new_store_result(String::from(variable), writer, Box::new(child)))
+    }
+
+    /// Creates the tree, whose root is the only node added to this builder
+    ///
+    /// # Panics
+    ///
+    /// Panics if the builder does not contain exactly one node.
+    pub fn build(self, name: &str) -> TreeFactory<F> {
+        let root = self.single("Tree");
+        TreeFactory::new(root, String::from(name))
+    }
+
+    fn single(mut self, kind: &str) -> NodeFactory<F> {
+        if self.nodes.len() != 1 {
+            panic!("{} expects exactly one child, found {}", kind, self.nodes.len());
+        }
+        self.nodes.pop().unwrap()
+    }
+}
+
+// Writes a name, escaped with backticks if it is a keyword
+#[cfg(feature = "std")]
+fn write_ident(out: &mut String, name: &str) {
+    if is_keyword(name) {
+        write!(out, "`{}`", name).unwrap();
+    } else {
+        out.push_str(name);
+    }
+}
+
+#[cfg(feature = "std")]
+fn write_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str("    ");
+    }
+}
+
+// Comments and identifiers written by `TreeFactory::to_source`, and pre-order index of the next
+// node written
+#[cfg(feature = "std")]
+struct SourceComments<'a> {
+    comments: &'a BTreeMap<usize,Vec<String>>,
+    ids: &'a BTreeMap<usize,String>,
+    // Identifier of the node being written, until it is written
+    id: Option<&'a str>,
+    next: usize,
+}
+
+// Writes ` #id` if the node being written has an identifier
+#[cfg(feature = "std")]
+fn write_id(out: &mut String, comments: &mut SourceComments) {
+    if let Some(id) = comments.id.take() {
+        write!(out, " #{}", id).unwrap();
+    }
+}
+
+// Writes `keyword { child }`
+#[cfg(feature = "std")]
+fn write_braced<F>(out: &mut String, keyword: &str, child: &NodeFactory<F>, indent: usize,
+                   comments: &mut SourceComments) {
+    out.push_str(keyword);
+    write_id(out, comments);
+    out.push_str(" {\n");
+    write_indent(out, indent + 1);
+    child.write_source(out, indent + 1, comments);
+    out.push_str("\n");
+    write_indent(out, indent);
+    out.push_str("}");
+}
+
+// Writes `keyword { child1, child2 }`
+#[cfg(feature = "std")]
+fn write_list<F>(out: &mut String, keyword: &str, children: &[NodeFactory<F>], indent: usize,
+                 comments: &mut SourceComments) {
+    out.push_str(keyword);
+    write_id(out, comments);
+    if children.is_empty() {
+        out.push_str(" {}");
+        return;
+    }
+    out.push_str(" {\n");
+    for child in children {
+        write_indent(out, indent + 1);
+        child.write_source(out, indent + 1, comments);
+        out.push_str(",\n");
+    }
+    write_indent(out, indent);
+    out.push_str("}");
+}
+
+#[cfg(feature = "std")]
+impl <F: Clone> NodeFactory<F> {
+    /// Returns a copy of this node where every subtree reference has been replaced by the root of
+    /// the tree it names.
+    ///
+    /// The comments of the node are copied to the tree being linked, those of a subtree reference
+    /// going to the root it is replaced by.
+    fn link(&self, linker: &mut Linker<F>) -> Result<NodeFactory<F>,ParseError> {
+        let subtree = if let NodeFactory::Subtree(_) = *self { true } else { false };
+        linker.copy_annotations(subtree);
+        let linked = match *self {
+            NodeFactory::Leaf(ref leaf) => NodeFactory::Leaf(leaf.clone()),
+            NodeFactory::Sequence(ref node) => {
+                NodeFactory::new_sequence(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::SequenceAll(ref node) => {
+                NodeFactory::new_sequence_all(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::Priority(ref node) => {
+                NodeFactory::new_priority(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::ReactiveSelector(ref node) => {
+                NodeFactory::new_reactive_selector(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::Selector(ref node) => {
+                NodeFactory::new_selector(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::RandomSelector(ref node) => {
+                NodeFactory::new_random_selector(try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::WeightedSelector(ref node) => {
+                let children = try!(link_vec(&node.children, linker));
+                NodeFactory::new_weighted_selector(node.weights.iter().cloned().zip(children).collect())
+            }
+            NodeFactory::Parallel(ref node) => {
+                NodeFactory::new_parallel(node.threshold, try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::Inverter(ref node) => {
+                NodeFactory::new_inverter(Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Repeater(ref node) => {
+                NodeFactory::new_repeater(node.count, Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::UntilFail(ref node) => {
+                NodeFactory::new_until_fail(Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Succeeder(ref node) => {
+                NodeFactory::new_succeeder(Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Cooldown(ref node) => {
+                NodeFactory::new_cooldown(node.ticks, Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Limiter(ref node) => {
+                NodeFactory::new_limiter(node.max, Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Retry(ref node) => {
+                NodeFactory::new_retry(node.attempts, Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::Budget(ref node) => {
+                NodeFactory::new_budget(node.max, Box::new(try!(node.child.link(linker))))
+            }
+            NodeFactory::NonBlocking(ref node) => {
+                let child = try!(node.child.link(linker));
+                NodeFactory::new_non_blocking(node.on_running, Box::new(child))
+            }
+            NodeFactory::Switch(ref node) => {
+                NodeFactory::new_switch(node.selector.clone(), try!(link_vec(&node.children, linker)))
+            }
+            NodeFactory::StoreResult(ref node) => {
+                let child = try!(node.child.link(linker));
+                NodeFactory::new_store_result(node.variable.clone(), node.writer.clone(), Box::new(child))
+            }
+            NodeFactory::Subtree(ref name) => {
+                if linker.stack.iter().any(|n| n == name) {
+                    let mut path = linker.stack.clone();
+                    path.push(name.clone());
+                    return Err(ParseError::SubtreeCycle { path: path });
+                }
+                let trees = linker.trees;
+                let tree = match trees.get::<str>(name) {
+                    Some(tree) => *tree,
+                    None => return Err(ParseError::UnresolvedSubtree {
+                        name: name.clone(),
+                        tree: linker.stack[0].clone(),
+                    }),
+                };
+                linker.stack.push(name.clone());
+                linker.sources.push((tree, 0));
+                let linked = try!(tree.root.link(linker));
+                linker.sources.pop();
+                linker.stack.pop();
+                linked
+            }
+        };
+        Ok(linked)
+    }
+}
+
+#[cfg(feature = "std")]
+fn link_vec<F: Clone>(nodes: &[NodeFactory<F>], linker: &mut Linker<F>)
+-> Result<Vec<NodeFactory<F>>,ParseError> {
+    let mut linked = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        linked.push(try!(node.link(linker)));
+    }
+    Ok(linked)
+}
+
+/// Replaces every `NodeFactory::Subtree` in the trees by a copy of the root of the tree it
+/// references, so that the resulting trees can be instanciated or optimized.
+///
+/// Returns an error if a subtree references an unknown tree, or if trees reference each other in
+/// a cycle.
+#[cfg(feature = "std")]
+pub fn link_subtrees<F: Clone>(trees: Vec<TreeFactory<F>>) -> Result<Vec<TreeFactory<F>>,ParseError> {
+    let mut by_name = BTreeMap::new();
+    for tree in trees.iter() {
+        by_name.insert(tree.get_name(), tree);
+    }
+    let mut linked = Vec::with_capacity(trees.len());
+    for tree in trees.iter() {
+        let mut linker = Linker {
+            trees: &by_name,
+            stack: vec![tree.name.clone()],
+            sources: vec![(tree, 0)],
+            comments: BTreeMap::new(),
+            ids: BTreeMap::new(),
+            next: 0,
+        };
+        let root = try!(tree.root.link(&mut linker));
+        linked.push(TreeFactory {
+            name: tree.name.clone(),
+            root: root,
+            comments: linker.comments,
+            ids: linker.ids,
+        });
+    }
+    Ok(linked)
+}
+
+// State of the linking of a tree
+#[cfg(feature = "std")]
+struct Linker<'a, F: 'a> {
+    trees: &'a BTreeMap<&'a str,&'a TreeFactory<F>>,
+    // Names of the trees currently being inlined, used to detect cycles
+    stack: Vec<String>,
+    // Trees currently being inlined, with the index of their next node
+    sources: Vec<(&'a TreeFactory<F>,usize)>,
+    // Comments and identifiers of the linked tree, and index of its next node
+    comments: BTreeMap<usize,Vec<String>>,
+    ids: BTreeMap<usize,String>,
+    next: usize,
+}
+
+#[cfg(feature = "std")]
+impl <'a, F> Linker<'a, F> {
+    // Copies the comments and the identifier of the next node of the innermost tree. The root of
+    // the subtree replacing a subtree reference takes its index in the linked tree.
+    fn copy_annotations(&mut self, subtree: bool) {
+        let &mut (tree, ref mut index) = self.sources.last_mut().expect("Linking without tree");
+        if let Some(lines) = tree.comments.get(&*index) {
+            self.comments.entry(self.next).or_insert_with(Vec::new).extend(lines.iter().cloned());
+        }
+        if let Some(id) = tree.ids.get(&*index) {
+            self.ids.entry(self.next).or_insert_with(|| id.clone());
+        }
+        *index += 1;
+        if !subtree {
+            self.next += 1;
+        }
+    }
+}
+
 impl <F> HasChildren for NodeFactory<F> {
     fn get_children(&self) -> &[NodeFactory<F>] {
         match *self {
             NodeFactory::Leaf(_) => &[],
             NodeFactory::Sequence(ref node) => &node.children,
+            NodeFactory::SequenceAll(ref node) => &node.children,
             NodeFactory::Priority(ref node) => &node.children,
+            NodeFactory::ReactiveSelector(ref node) => &node.children,
             NodeFactory::Selector(ref node) => &node.children,
+            NodeFactory::RandomSelector(ref node) => &node.children,
+            NodeFactory::WeightedSelector(ref node) => &node.children,
+            NodeFactory::Parallel(ref node) => &node.children,
             NodeFactory::Inverter(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Repeater(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::UntilFail(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Succeeder(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Cooldown(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Limiter(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Retry(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::Switch(ref node) => &node.children,
+            NodeFactory::Budget(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::NonBlocking(ref node) => ::ref_slice::ref_slice(&node.child),
+            NodeFactory::StoreResult(ref node) => ::ref_slice::ref_slice(&node.child),
             NodeFactory::Subtree(ref name) => panic!("Trying to instanciate an unlinked subtree {}", name),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use tree::{BehaviourTreeNode,NodeKind,OptimizedTree};
+    use tree::test_helpers::*;
+    use super::NodeFactory;
+
+    // Address of the storage of the nodes
+    fn nodes_address(tree: &OptimizedTree<Script>) -> *const () {
+        tree.inner.tree_iter().next().unwrap().0 as *const _ as *const ()
+    }
+
+    #[test]
+    fn to_dot() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_inverter(Box::new(leaf("b", &[Success]))),
+        ]));
+        let dot = factory.to_dot();
+        assert!(dot.starts_with("digraph \"test\" {\n"));
+        for line in &[
+            "    0 [label=\"Sequence\"];",
+            "    1 [label=\"a\"];",
+            "    0 -> 1;",
+            "    2 [label=\"Inverter\"];",
+            "    3 [label=\"b\"];",
+            "    2 -> 3;",
+            "    0 -> 2;",
+        ] {
+            assert!(dot.lines().any(|l| l == *line), "Missing {} in {}", line, dot);
+        }
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn depth_and_count() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_selector(vec![
+                NodeFactory::new_inverter(Box::new(leaf("b", &[Success]))),
+                leaf("c", &[Success]),
+            ]),
+            NodeFactory::new_subtree(String::from("other")),
+        ]));
+        assert_eq!(factory.depth(), 4);
+        assert_eq!(factory.node_count(), 7);
+        assert_eq!(tree(leaf("a", &[Success])).depth(), 1);
+    }
+
+    #[test]
+    fn leaf_names() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_parallel(1, vec![
+                NodeFactory::new_repeater(2, Box::new(leaf("b", &[Success]))),
+                NodeFactory::new_leaf(script("unnamed", &[Success])),
+                leaf("a", &[Success]),
+            ]),
+        ]));
+        assert_eq!(factory.leaf_names(), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn validate() {
+        let valid = tree(NodeFactory::new_parallel(2, vec![leaf("a", &[Success]), leaf("b", &[Success])]));
+        assert_eq!(valid.validate(), Ok(()));
+        let invalid = tree(NodeFactory::new_sequence(vec![
+            NodeFactory::new_selector(vec![]),
+            NodeFactory::new_parallel(3, vec![leaf("a", &[Success]), leaf("b", &[Success])]),
+            NodeFactory::new_weighted_selector(vec![(0, leaf("c", &[Success]))]),
+            NodeFactory::new_subtree(String::from("other")),
+        ]));
+        assert_eq!(invalid.validate(), Err(vec![
+            String::from("Selector has no children"),
+            String::from("Parallel(3) can never succeed with 2 children"),
+            String::from("WeightedSelector has only zero weights"),
+            String::from("Subtree other is not linked"),
+        ]));
+    }
+
+    #[test]
+    fn optimize_into() {
+        let first = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_selector(vec![leaf("b", &[Failure]), leaf("c", &[Running])]),
+        ]));
+        let second = tree(NodeFactory::new_selector(vec![
+            leaf("d", &[Failure]),
+            NodeFactory::new_sequence(vec![leaf("e", &[Success]), leaf("f", &[Success])]),
+        ]));
+        let mut optimized = first.optimize_with_seed(0);
+        let mut log: Log = Vec::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "c"]);
+        let address = nodes_address(&optimized);
+
+        // Same shape: the nodes are rebuilt in place, and the running state is gone
+        second.optimize_into_with_seed(&mut optimized, 0);
+        assert_eq!(nodes_address(&optimized), address);
+        let kinds: Vec<NodeKind> = optimized.nodes().map(|node| node.kind).collect();
+        assert_eq!(kinds, [NodeKind::Selector, NodeKind::Leaf, NodeKind::Sequence, NodeKind::Leaf, NodeKind::Leaf]);
+        assert!(optimized.nodes().all(|node| node.running.is_none()));
+        log.clear();
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(log, ["d", "e", "f"]);
+
+        // Different shape: the tree is replaced
+        tree(leaf("g", &[Success])).optimize_into(&mut optimized);
+        assert_eq!(optimized.nodes().count(), 1);
+        log.clear();
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(log, ["g"]);
+    }
+}
@@ -1,9 +1,32 @@
 mod non_optimized;
 pub mod factory;
+mod packed;
+pub mod random;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(test)]
+mod test_helpers;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self,Display,Formatter};
+use core::mem;
+#[cfg(feature = "std")]
+use std::error::Error;
 
 use flat_tree::FlatTree;
 use flat_tree::buffer::ChildrenMut;
 
+use self::random::{RngContext,XorShift};
+
+pub use self::packed::PackedTree;
+#[cfg(feature = "stats")]
+pub use self::stats::ExecutionStats;
+
 
 #[derive(Debug,Copy,Eq,PartialEq,Clone)]
 pub enum VisitResult {
@@ -12,13 +35,83 @@ pub enum VisitResult {
     Running,
 }
 
+/// Result returned by a `non_blocking` node while its child is running
+///
+/// Running is left out, the node would then block like its child.
+#[derive(Debug,Copy,Eq,PartialEq,Clone)]
+pub enum NonBlockingResult {
+    Success,
+    Failure,
+}
+
+impl From<NonBlockingResult> for VisitResult {
+    fn from(result: NonBlockingResult) -> VisitResult {
+        match result {
+            NonBlockingResult::Success => VisitResult::Success,
+            NonBlockingResult::Failure => VisitResult::Failure,
+        }
+    }
+}
+
 
 pub trait BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult;
+
+    /// Same as `visit`, but can report an error that is not a simple failure
+    ///
+    /// Composite nodes do not handle errors like failures: they stop visiting their children and
+    /// return the error. A selector will not try its next child after an error, for example.
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        Ok(self.visit(context))
+    }
+
+    /// Index of the child to visit, for leaves used as the selector of a switch node
+    ///
+    /// Other leaves return None, which makes the switch fail.
+    fn select_child(&mut self, _context: &mut C) -> Option<usize> {
+        None
+    }
+
+    /// Writes `result` to the variable `variable` of the context, for leaves used as the writer of
+    /// a store_result node
+    ///
+    /// Other leaves do nothing, so the result is not stored anywhere.
+    fn store_result(&mut self, _context: &mut C, _variable: &str, _result: VisitResult) {}
+}
+
+/// Unrecoverable error reported by `BehaviourTreeNode::try_visit`
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct NodeError {
+    pub message: String,
+}
+
+impl NodeError {
+    pub fn new<S: Into<String>>(message: S) -> NodeError {
+        NodeError { message: message.into() }
+    }
+}
+
+impl Display for NodeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for NodeError {
+    fn description(&self) -> &str {
+        "error while visiting a behaviour tree node"
+    }
 }
 
 pub struct Closure<T>(T);
 
+impl <T> Closure<T> {
+    pub fn new(inner: T) -> Closure<T> {
+        Closure(inner)
+    }
+}
+
 impl <T,C> BehaviourTreeNode<C> for Closure<T>
 where T: FnMut(&mut C) -> VisitResult {
     fn visit(&mut self, context: &mut C) -> VisitResult {
@@ -26,11 +119,80 @@ where T: FnMut(&mut C) -> VisitResult {
     }
 }
 
+/// Leaf backed by a closure returning a boolean, which succeeds when it returns true and fails
+/// otherwise
+pub struct Condition<F>(F);
+
+impl <F> Condition<F> {
+    pub fn new(inner: F) -> Condition<F> {
+        Condition(inner)
+    }
+}
+
+impl <F,C> BehaviourTreeNode<C> for Condition<F>
+where F: FnMut(&C) -> bool {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.visit_ref(context)
+    }
+}
+
+impl <F,C> ReadOnlyBehaviourTreeNode<C> for Condition<F>
+where F: FnMut(&C) -> bool {
+    fn visit_ref(&mut self, context: &C) -> VisitResult {
+        if self.0(context) { VisitResult::Success } else { VisitResult::Failure }
+    }
+}
+
 impl <T: ?Sized, C> BehaviourTreeNode<C> for Box<T>
 where T: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
         (**self).visit(context)
     }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        (**self).try_visit(context)
+    }
+
+    fn select_child(&mut self, context: &mut C) -> Option<usize> {
+        (**self).select_child(context)
+    }
+
+    fn store_result(&mut self, context: &mut C, variable: &str, result: VisitResult) {
+        (**self).store_result(context, variable, result)
+    }
+}
+
+/// Node that only reads its context, like a condition
+///
+/// Trees whose leaves are all read-only can be visited with `&C` instead of `&mut C`, for
+/// example to evaluate the same checks against a context shared between threads.
+pub trait ReadOnlyBehaviourTreeNode<C> {
+    fn visit_ref(&mut self, context: &C) -> VisitResult;
+
+    /// Same as `BehaviourTreeNode::try_visit`, with an immutable context
+    fn try_visit_ref(&mut self, context: &C) -> Result<VisitResult,NodeError> {
+        Ok(self.visit_ref(context))
+    }
+
+    /// Same as `BehaviourTreeNode::select_child`, with an immutable context
+    fn select_child_ref(&mut self, _context: &C) -> Option<usize> {
+        None
+    }
+}
+
+impl <T: ?Sized, C> ReadOnlyBehaviourTreeNode<C> for Box<T>
+where T: ReadOnlyBehaviourTreeNode<C> {
+    fn visit_ref(&mut self, context: &C) -> VisitResult {
+        (**self).visit_ref(context)
+    }
+
+    fn try_visit_ref(&mut self, context: &C) -> Result<VisitResult,NodeError> {
+        (**self).try_visit_ref(context)
+    }
+
+    fn select_child_ref(&mut self, context: &C) -> Option<usize> {
+        (**self).select_child_ref(context)
+    }
 }
 
 pub trait LeafNodeFactory {
@@ -40,14 +202,14 @@ pub trait LeafNodeFactory {
 
 pub struct Prototype<T: Clone + BehaviourTreeNode<C>,C> {
     pub inner: T,
-    _marker: ::std::marker::PhantomData<C>,
+    _marker: ::core::marker::PhantomData<C>,
 }
 
 impl <T: Clone + BehaviourTreeNode<C>, C> Prototype<T,C> {
     pub fn new(inner: T) -> Prototype<T,C> {
         Prototype {
             inner: inner,
-            _marker: ::std::marker::PhantomData,
+            _marker: ::core::marker::PhantomData,
         }
     }
 }
@@ -70,6 +232,107 @@ where T: Clone,
     }
 }
 
+/// Builds a new leaf by calling a function on each instanciation
+///
+/// Unlike `Prototype`, the leaves do not need to be `Clone`, and each one gets fresh state, such
+/// as a random generator with its own seed.
+pub struct FnFactory<F,C> {
+    pub constructor: F,
+    _marker: ::core::marker::PhantomData<C>,
+}
+
+impl <F,T,C> FnFactory<F,C>
+where F: Fn() -> T,
+      T: BehaviourTreeNode<C> {
+    pub fn new(constructor: F) -> FnFactory<F,C> {
+        FnFactory {
+            constructor: constructor,
+            _marker: ::core::marker::PhantomData,
+        }
+    }
+}
+
+impl <F, T, C> LeafNodeFactory for FnFactory<F,C>
+where F: Fn() -> T,
+      T: BehaviourTreeNode<C>,
+      T: 'static {
+    type Output = Box<BehaviourTreeNode<C>>;
+    fn instanciate(&self) -> Self::Output {
+        Box::new((self.constructor)())
+    }
+}
+
+/// Gives the same leaf to every instanciated tree
+///
+/// The leaves produced are handles to a single node, not copies of it: a visit from any tree
+/// changes the state seen by all the others, and resetting a tree does not reset the node. This is
+/// meant for state that really is global, like a cooldown shared by all the agents. Visiting the
+/// node from within its own visit panics.
+pub struct SharedLeaf<C> {
+    pub inner: Rc<RefCell<BehaviourTreeNode<C>>>,
+}
+
+impl <C> SharedLeaf<C> {
+    pub fn new<T>(node: T) -> SharedLeaf<C>
+    where T: BehaviourTreeNode<C> + 'static {
+        SharedLeaf { inner: Rc::new(RefCell::new(node)) }
+    }
+}
+
+impl <C: 'static> LeafNodeFactory for SharedLeaf<C> {
+    type Output = Box<BehaviourTreeNode<C>>;
+    fn instanciate(&self) -> Self::Output {
+        Box::new(self.inner.clone())
+    }
+}
+
+impl <T: ?Sized, C> BehaviourTreeNode<C> for Rc<RefCell<T>>
+where T: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.borrow_mut().visit(context)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.borrow_mut().try_visit(context)
+    }
+
+    fn select_child(&mut self, context: &mut C) -> Option<usize> {
+        self.borrow_mut().select_child(context)
+    }
+
+    fn store_result(&mut self, context: &mut C, variable: &str, result: VisitResult) {
+        self.borrow_mut().store_result(context, variable, result)
+    }
+}
+
+/// Same as `Prototype`, but produces leaves that can be sent to another thread
+///
+/// Trees built from these leaves are `Send`, at the cost of requiring every leaf to be `Send`.
+pub struct SendPrototype<T: Clone + BehaviourTreeNode<C> + Send,C> {
+    pub inner: T,
+    _marker: ::core::marker::PhantomData<C>,
+}
+
+impl <T: Clone + BehaviourTreeNode<C> + Send, C> SendPrototype<T,C> {
+    pub fn new(inner: T) -> SendPrototype<T,C> {
+        SendPrototype {
+            inner: inner,
+            _marker: ::core::marker::PhantomData,
+        }
+    }
+}
+
+impl <T, C> LeafNodeFactory for SendPrototype<T,C>
+where T: Clone,
+      T: BehaviourTreeNode<C>,
+      T: Send,
+      T: 'static {
+    type Output = Box<BehaviourTreeNode<C> + Send>;
+    fn instanciate(&self) -> Self::Output {
+        Box::new(self.inner.clone())
+    }
+}
+
 impl <T: ?Sized> LeafNodeFactory for Box<T>
 where T: LeafNodeFactory {
     type Output = T::Output;
@@ -78,6 +341,14 @@ where T: LeafNodeFactory {
     }
 }
 
+impl <T: ?Sized> LeafNodeFactory for Rc<T>
+where T: LeafNodeFactory {
+    type Output = T::Output;
+    fn instanciate(&self) -> Self::Output {
+        (**self).instanciate()
+    }
+}
+
 /// Carries an action of checks a condition
 ///
 /// Leaf nodes are the only nodes that actually do something in the game
@@ -97,141 +368,2484 @@ where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
         self.inner.visit(context)
     }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.inner.try_visit(context)
+    }
+
+    fn select_child(&mut self, context: &mut C) -> Option<usize> {
+        self.inner.select_child(context)
+    }
+
+    fn store_result(&mut self, context: &mut C, variable: &str, result: VisitResult) {
+        self.inner.store_result(context, variable, result)
+    }
 }
 
 #[derive(Debug)]
 pub struct OptimizedTree<A> {
-    inner: FlatTree<OptimizedNode<A>>,
+    inner: FlatTree<FlatNode<A>>,
+    // Visits of the current activation that returned Running
+    running_ticks: usize,
+    // The last visit finished the activation, the next one starts a new one
+    finished: bool,
+    // Index of the nodes given an identifier, see `index_of`
+    ids: BTreeMap<String,usize>,
+}
+
+/// The clone starts a new activation from the root on its first visit, whatever the state of the
+/// original tree was.
+///
+/// This is cheaper than optimizing the factory again when many agents share the same behaviour.
+/// Note that random nodes keep the generator of the original tree, use
+/// `TreeFactory::optimize` to get a different random sequence. With the `stats` feature, the
+/// visits of the clone are counted from 0.
+impl <A: Clone> Clone for OptimizedTree<A> {
+    fn clone(&self) -> OptimizedTree<A> {
+        let mut tree = OptimizedTree::new(self.inner.clone());
+        tree.ids = self.ids.clone();
+        tree.reset();
+        #[cfg(feature = "stats")]
+        tree.reset_stats();
+        tree
+    }
 }
 
+/// Errors of the leaves are visited with `try_visit`. With a plain `visit`, an error makes the
+/// whole tree fail.
 impl <C,A> BehaviourTreeNode<C> for OptimizedTree<A>
 where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
-        let (root, children) = self.inner.tree_iter_mut()
-                               .nth(0).expect("Tried to visit a tree without node");
-        root.visit(context, children)
+        self.visit_observed(context, &mut NoObserver)
     }
-}
 
-#[derive(Debug)]
-enum OptimizedNode<A> {
-    Leaf(OptimizedLeafNode<A>),
-    Sequence(OptimizedSequenceNode),
-    Inverter,
-    Priority,
-    Selector(OptimizedSelectorNode),
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.try_visit_observed(context, &mut NoObserver)
+    }
 }
 
-type OptimizedLeafNode<A> = LeafNode<A>;
+/// Trees of read-only leaves can be visited against a shared context
+impl <C,A> ReadOnlyBehaviourTreeNode<C> for OptimizedTree<A>
+where A: ReadOnlyBehaviourTreeNode<C> {
+    fn visit_ref(&mut self, context: &C) -> VisitResult {
+        self.try_visit_ref(context).unwrap_or(VisitResult::Failure)
+    }
 
-#[derive(Debug)]
-struct OptimizedSequenceNode {
-    running: Option<usize>,
+    fn try_visit_ref(&mut self, context: &C) -> Result<VisitResult,NodeError> {
+        self.visit_leaves(&mut Shared(context), &mut NoObserver)
+    }
 }
 
-impl OptimizedSequenceNode {
-    fn visit<A,C>(&mut self, context: &mut C, mut children: ChildrenMut<OptimizedNode<A>>) -> VisitResult
-    where A: BehaviourTreeNode<C> {
-        let mut index = self.running.take().unwrap_or(0);
-        let mut children = children.children_mut();
+impl <A> OptimizedTree<A> {
+    fn new(inner: FlatTree<FlatNode<A>>) -> OptimizedTree<A> {
+        OptimizedTree {
+            inner: inner,
+            running_ticks: 0,
+            finished: false,
+            ids: BTreeMap::new(),
+        }
+    }
 
-        // Go the the last previous running node
-        for _ in 0..index {
-            children.next();
+    /// Index of the node given the identifier `id`, written `#id` in the tree description
+    ///
+    /// Nodes are numbered as in `nodes`. Unlike the index, the identifier does not change when
+    /// other nodes are added to the tree. If several nodes have the same identifier, for instance
+    /// in a subtree used twice, the first one in pre-order is returned.
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.ids.get(id).cloned()
+    }
+
+    /// Number of visits of the current activation that returned Running
+    ///
+    /// Once the tree succeeds or fails, this still counts the visits of the activation that just
+    /// finished. The count starts again from 0 with the next visit, or after a `reset`.
+    pub fn ticks_in_current_activation(&self) -> usize {
+        self.running_ticks
+    }
+
+    /// Clears the running state of every node, so that the next visit starts a new activation
+    /// from the root instead of resuming the running nodes.
+    pub fn reset(&mut self) {
+        for (node, _) in self.inner.tree_iter_mut() {
+            node.node.reset();
+            node.node.clear_limit();
         }
-        for (child, grandchildren) in children {
-            match child.visit(context, grandchildren) {
-                VisitResult::Running => {
-                    self.running = Some(index);
-                    return VisitResult::Running;
-                }
-                VisitResult::Failure => {
-                    return VisitResult::Failure;
-                }
-                VisitResult::Success => {}
+        self.running_ticks = 0;
+        self.finished = false;
+    }
+
+    /// Same as `reset`, but only for the node `node_index` and its descendants
+    ///
+    /// Nodes are numbered as in `nodes`. The rest of the tree keeps its running state, so a
+    /// parent resuming the reset node will start it over.
+    pub fn reset_subtree(&mut self, node_index: usize) -> Result<(),NodeIndexOutOfRange> {
+        fn reset_from<A>(node: &mut FlatNode<A>, mut children: ChildrenMut<FlatNode<A>>,
+                         target: usize, inside: bool) -> bool {
+            let inside = inside || node.index == target;
+            if inside {
+                node.node.reset();
+                node.node.clear_limit();
             }
-            index = index + 1;
+            let mut found = inside;
+            for (child, grandchildren) in children.children_mut() {
+                found |= reset_from(child, grandchildren, target, inside);
+            }
+            found
+        }
+        let found = {
+            let (root, children) = self.inner.tree_iter_mut()
+                                   .nth(0).expect("Tried to reset a tree without node");
+            reset_from(root, children, node_index, false)
+        };
+        if found {
+            Ok(())
+        } else {
+            let node_count = self.inner.tree_iter_mut().count();
+            Err(NodeIndexOutOfRange { index: node_index, node_count: node_count })
         }
-        VisitResult::Success
     }
-}
 
-#[derive(Debug)]
-struct OptimizedSelectorNode {
-    running: Option<usize>,
-}
+    /// Same as `visit`, but `observer` is notified of every node visited during this tick
+    ///
+    /// Nodes are identified by their index in a depth-first, pre-order traversal of the tree,
+    /// the root being 0. These are also the identifiers used by `TreeFactory::to_dot`.
+    pub fn visit_observed<C,O>(&mut self, context: &mut C, observer: &mut O) -> VisitResult
+    where A: BehaviourTreeNode<C>, O: ?Sized + TreeObserver {
+        self.try_visit_observed(context, observer).unwrap_or(VisitResult::Failure)
+    }
 
-impl OptimizedSelectorNode {
-    fn visit<A,C>(&mut self, context: &mut C, mut children: ChildrenMut<OptimizedNode<A>>) -> VisitResult
-    where A: BehaviourTreeNode<C> {
-        let mut index = self.running.take().unwrap_or(0);
-        let mut children = children.children_mut();
+    /// Same as `visit_observed`, but returns the errors of the leaves
+    ///
+    /// The nodes interrupted by an error are not notified of a result. Each of them is reset, from
+    /// the leaf that failed up to the root, so the next visit starts a new activation from the
+    /// root. The other nodes keep their state, like the uses of a `limiter` outside of that path.
+    pub fn try_visit_observed<C,O>(&mut self, context: &mut C, observer: &mut O)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C>, O: ?Sized + TreeObserver {
+        self.visit_leaves(&mut Mutable(context), observer)
+    }
 
-        // Go the the last previous running node
-        for _ in 0..index {
-            children.next();
+    /// Same as `visit`, but the random nodes draw their choices from the context instead of their
+    /// own generator
+    ///
+    /// Several trees can then share a single generator, and a seeded one like `SeededContext`
+    /// makes their choices reproducible, whatever the seed the trees were optimized with.
+    pub fn visit_with_rng<C>(&mut self, context: &mut C) -> VisitResult
+    where A: BehaviourTreeNode<C>, C: RngContext {
+        self.try_visit_with_rng(context).unwrap_or(VisitResult::Failure)
+    }
+
+    /// Same as `visit_with_rng`, but returns the errors of the leaves
+    pub fn try_visit_with_rng<C>(&mut self, context: &mut C) -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C>, C: RngContext {
+        self.visit_leaves(&mut WithRng(context), &mut NoObserver)
+    }
+
+    fn visit_leaves<L,O>(&mut self, leaves: &mut L, observer: &mut O)
+    -> Result<VisitResult,NodeError>
+    where L: LeafContext<A>, O: ?Sized + TreeObserver {
+        if self.finished {
+            self.running_ticks = 0;
         }
-        for (child, grandchildren) in children {
-            match child.visit(context, grandchildren) {
-                VisitResult::Running => {
-                    self.running = Some(index);
-                    return VisitResult::Running;
-                }
-                VisitResult::Success => {
-                    return VisitResult::Success;
-                }
-                VisitResult::Failure => {}
+        let result = {
+            let (root, children) = self.inner.tree_iter_mut()
+                                   .nth(0).expect("Tried to visit a tree without node");
+            root.visit(leaves, children, observer, &mut Budget::new())
+        };
+        match result {
+            Ok(VisitResult::Running) => {
+                self.running_ticks += 1;
+                self.finished = false;
             }
-            index = index + 1;
+            // The nodes interrupted by an error were reset on the way up
+            Ok(_) | Err(_) => self.finished = true,
         }
-        VisitResult::Failure
+        result
     }
-}
 
-impl <A> OptimizedNode<A> {
-    fn visit<C>(&mut self, context: &mut C, children: ChildrenMut<OptimizedNode<A>>) -> VisitResult
+    /// Same as `visit`, but also returns the path to the first node that returned Running, usually
+    /// the running leaf
+    ///
+    /// Results are reported from the leaves up, so this is the deepest node of the first running
+    /// branch: below a parallel node, the other running children are not reported. The path is
+    /// None if the tree did not return Running. The path is built in a vector allocated on every
+    /// call, use `visit_observed` with an observer of your own to avoid it.
+    pub fn visit_tracked<C>(&mut self, context: &mut C) -> (VisitResult, Option<NodePath>)
     where A: BehaviourTreeNode<C> {
-        match *self {
-            OptimizedNode::Sequence(ref mut node) => node.visit(context, children),
-            OptimizedNode::Inverter => inverter_visit(context, children),
-            OptimizedNode::Leaf(ref mut node) => node.visit(context),
-            OptimizedNode::Priority => priority_visit(context, children),
-            OptimizedNode::Selector(ref mut node) => node.visit(context, children),
+        let mut tracker = PathTracker { stack: Vec::new(), running: None };
+        let result = self.visit_observed(context, &mut tracker);
+        match result {
+            VisitResult::Running => (result, tracker.running),
+            _ => (result, None),
         }
     }
 
-    fn sequence(running: Option<usize>) -> OptimizedNode<A> {
-        OptimizedNode::Sequence(OptimizedSequenceNode{ running: running })
+    /// Same as `visit`, but also returns the result of every node visited during this tick
+    ///
+    /// Entries are in the order the results were known, so a node comes after its descendants.
+    /// Children skipped by a sequence or selector resuming a running child are also listed.
+    pub fn visit_with_trace<C>(&mut self, context: &mut C)
+    -> (VisitResult, Vec<(usize, TraceResult)>)
+    where A: BehaviourTreeNode<C> {
+        let mut recorder = TraceRecorder { trace: Vec::new() };
+        let result = self.visit_observed(context, &mut recorder);
+        (result, recorder.trace)
     }
 
-    fn selector(running: Option<usize>) -> OptimizedNode<A> {
-        OptimizedNode::Selector(OptimizedSelectorNode{ running: running })
+    /// Iterates over the nodes of the tree in evaluation order, reporting their current state
+    ///
+    /// Nodes come in the same depth-first, pre-order as the indices given by `visit_observed`.
+    pub fn nodes(&self) -> Nodes {
+        let mut views: Vec<NodeView> = self.inner.tree_iter()
+                                           .map(|(node, _)| node.view())
+                                           .collect();
+        views.sort_by_key(|view| view.index);
+        Nodes { inner: views.into_iter() }
     }
-}
 
-fn inverter_visit<A,C>(context: &mut C, mut children: ChildrenMut<OptimizedNode<A>>) -> VisitResult
-where A: BehaviourTreeNode<C> {
-    let (child, grandchildren) = children.get_mut(0).expect("Inverter without children");
-    match child.visit(context, grandchildren) {
-        VisitResult::Success => VisitResult::Failure,
-        VisitResult::Failure => VisitResult::Success,
-        VisitResult::Running => VisitResult::Running,
+    // Numbers the nodes in pre-order, which does not depend on the layout of the FlatTree
+    fn number_nodes(&mut self) {
+        fn number<A>(node: &mut FlatNode<A>, mut children: ChildrenMut<FlatNode<A>>, next: &mut usize) {
+            node.index = *next;
+            *next += 1;
+            for (child, grandchildren) in children.children_mut() {
+                number(child, grandchildren, next);
+            }
+        }
+        let (root, children) = self.inner.tree_iter_mut()
+                               .nth(0).expect("Tried to number a tree without node");
+        number(root, children, &mut 0);
     }
-}
 
-fn priority_visit<A,C>(context: &mut C, mut children: ChildrenMut<OptimizedNode<A>>) -> VisitResult
-where A: BehaviourTreeNode<C> {
-    let children = children.children_mut();
-    for (child, grandchildren) in children {
-        match child.visit(context, grandchildren) {
-            VisitResult::Running => {
-                return VisitResult::Running;
+    /// Advances the frame counter of the context, and then visits the tree
+    ///
+    /// Leaves can then read the number of the current tick from the context, instead of counting
+    /// their own visits. Only one tree may be ticked with a given context, as every call advances
+    /// the counter: when several trees share a context, call `FrameCounter::next_tick` once per
+    /// frame and then `visit` each of them.
+    pub fn tick<C>(&mut self, context: &mut C) -> VisitResult
+    where A: BehaviourTreeNode<C>, C: FrameCounter {
+        context.next_tick();
+        self.visit(context)
+    }
+
+    /// Visits the tree until it stops running, for at most `max_ticks` visits.
+    ///
+    /// Returns the final result and the number of visits it took. If the tree is still running
+    /// after `max_ticks` visits, it is left in its running state and an error is returned.
+    pub fn run_to_completion<C>(&mut self, context: &mut C, max_ticks: usize)
+    -> Result<(VisitResult, usize), TickLimitExceeded>
+    where A: BehaviourTreeNode<C> {
+        for tick in 1..=max_ticks {
+            match self.visit(context) {
+                VisitResult::Running => {}
+                result => return Ok((result, tick)),
             }
-            VisitResult::Failure => {
-                return VisitResult::Failure;
+        }
+        Err(TickLimitExceeded { max_ticks: max_ticks })
+    }
+
+    /// Visits the tree until it stops running, as long as `should_continue` returns true
+    ///
+    /// `should_continue` is called after every visit that returns `Running`, so the caller can
+    /// stop at a deadline using its own clock. The tree is then left in its running state and
+    /// `Running` is returned.
+    pub fn run_with_deadline<C,F>(&mut self, context: &mut C, mut should_continue: F) -> VisitResult
+    where A: BehaviourTreeNode<C>, F: FnMut() -> bool {
+        loop {
+            match self.visit(context) {
+                VisitResult::Running => {
+                    if !should_continue() {
+                        return VisitResult::Running;
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Gives every random node its own generator, derived from `seed`
+    fn seed(&mut self, seed: u64) {
+        let mut rng = XorShift::new(seed);
+        for (node, _) in self.inner.tree_iter_mut() {
+            match node.node {
+                OptimizedNode::RandomSelector(ref mut node) => node.rng = XorShift::new(rng.next_u64()),
+                OptimizedNode::WeightedSelector(ref mut node) => node.rng = XorShift::new(rng.next_u64()),
+                _ => {}
             }
-            VisitResult::Success => {}
         }
     }
-    VisitResult::Success
+}
+
+/// Kind of a node of an `OptimizedTree`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum NodeKind {
+    Leaf,
+    Sequence,
+    SequenceAll,
+    Priority,
+    ReactiveSelector,
+    Selector,
+    RandomSelector,
+    WeightedSelector,
+    Parallel,
+    Inverter,
+    Repeater,
+    UntilFail,
+    Succeeder,
+    Cooldown,
+    Limiter,
+    Retry,
+    Switch,
+    Budget,
+    NonBlocking,
+    StoreResult,
+}
+
+/// Snapshot of the state of a node, as returned by `OptimizedTree::nodes`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct NodeView {
+    /// Position of the node in a depth-first, pre-order traversal of the tree
+    pub index: usize,
+    pub kind: NodeKind,
+    /// For sequences, selectors and switches, the index of the child that was running at the end of the
+    /// last tick, if any. For priority nodes, the child cut short by a `budget` that the next tick
+    /// resumes.
+    pub running: Option<usize>,
+}
+
+/// Iterator over the nodes of an `OptimizedTree`, see `OptimizedTree::nodes`
+pub struct Nodes {
+    inner: ::alloc::vec::IntoIter<NodeView>,
+}
+
+impl Iterator for Nodes {
+    type Item = NodeView;
+    fn next(&mut self) -> Option<NodeView> {
+        self.inner.next()
+    }
+}
+
+/// A context counting frames, advanced by `OptimizedTree::tick` or once per frame by the caller
+pub trait FrameCounter {
+    /// Number of the current tick, the first one being 1
+    fn current_tick(&self) -> u64;
+    fn next_tick(&mut self);
+}
+
+/// Receives notifications while an `OptimizedTree` is visited with `visit_observed`
+///
+/// This can be used to trace the execution of a tree, to build a debugger or to profile nodes.
+pub trait TreeObserver {
+    /// Called before the node `index` is visited
+    fn on_node_enter(&mut self, index: usize);
+    /// Called after the node `index` was visited, with its result
+    fn on_node_result(&mut self, index: usize, result: VisitResult);
+    /// Called for the node `index` when its parent sequence or selector does not visit it,
+    /// because the parent resumes a running child coming after it
+    fn on_node_skipped(&mut self, _index: usize) {}
+}
+
+/// Indices of the nodes from the root to a node, as used by `visit_observed`
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct NodePath(pub Vec<usize>);
+
+impl NodePath {
+    /// Index of the node the path leads to
+    pub fn last(&self) -> Option<usize> {
+        self.0.last().cloned()
+    }
+}
+
+// Records the path to the first node returning Running, which is the deepest of its branch as
+// results are reported from the leaves up
+struct PathTracker {
+    stack: Vec<usize>,
+    running: Option<NodePath>,
+}
+
+impl TreeObserver for PathTracker {
+    fn on_node_enter(&mut self, index: usize) {
+        self.stack.push(index);
+    }
+
+    fn on_node_result(&mut self, _index: usize, result: VisitResult) {
+        if result == VisitResult::Running && self.running.is_none() {
+            self.running = Some(NodePath(self.stack.clone()));
+        }
+        self.stack.pop();
+    }
+}
+
+/// What happened to a node during a tick, as reported by `OptimizedTree::visit_with_trace`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TraceResult {
+    Visited(VisitResult),
+    /// The parent resumed a running child coming after this node
+    Skipped,
+}
+
+struct TraceRecorder {
+    trace: Vec<(usize, TraceResult)>,
+}
+
+impl TreeObserver for TraceRecorder {
+    fn on_node_enter(&mut self, _index: usize) {}
+
+    fn on_node_result(&mut self, index: usize, result: VisitResult) {
+        self.trace.push((index, TraceResult::Visited(result)));
+    }
+
+    fn on_node_skipped(&mut self, index: usize) {
+        self.trace.push((index, TraceResult::Skipped));
+    }
+}
+
+// Gives the leaves of type A access to the context during the internal visits, mutably or not
+trait LeafContext<A> {
+    fn visit_leaf(&mut self, leaf: &mut A) -> Result<VisitResult,NodeError>;
+    fn select_child(&mut self, leaf: &mut A) -> Option<usize>;
+    fn store_result(&mut self, leaf: &mut A, variable: &str, result: VisitResult);
+
+    // Seed replacing the generator of a random node before it draws, if the context provides the
+    // randomness
+    fn next_seed(&mut self) -> Option<u64> {
+        None
+    }
+}
+
+struct Mutable<'a, C: 'a>(&'a mut C);
+
+impl <'a, A, C> LeafContext<A> for Mutable<'a, C>
+where A: BehaviourTreeNode<C> {
+    fn visit_leaf(&mut self, leaf: &mut A) -> Result<VisitResult,NodeError> {
+        leaf.try_visit(self.0)
+    }
+
+    fn select_child(&mut self, leaf: &mut A) -> Option<usize> {
+        leaf.select_child(self.0)
+    }
+
+    fn store_result(&mut self, leaf: &mut A, variable: &str, result: VisitResult) {
+        leaf.store_result(self.0, variable, result)
+    }
+}
+
+struct WithRng<'a, C: 'a>(&'a mut C);
+
+impl <'a, A, C> LeafContext<A> for WithRng<'a, C>
+where A: BehaviourTreeNode<C>, C: RngContext {
+    fn visit_leaf(&mut self, leaf: &mut A) -> Result<VisitResult,NodeError> {
+        leaf.try_visit(self.0)
+    }
+
+    fn select_child(&mut self, leaf: &mut A) -> Option<usize> {
+        leaf.select_child(self.0)
+    }
+
+    fn store_result(&mut self, leaf: &mut A, variable: &str, result: VisitResult) {
+        leaf.store_result(self.0, variable, result)
+    }
+
+    fn next_seed(&mut self) -> Option<u64> {
+        let high = self.0.next_u32() as u64;
+        Some((high << 32) | self.0.next_u32() as u64)
+    }
+}
+
+struct Shared<'a, C: 'a>(&'a C);
+
+impl <'a, A, C> LeafContext<A> for Shared<'a, C>
+where A: ReadOnlyBehaviourTreeNode<C> {
+    fn visit_leaf(&mut self, leaf: &mut A) -> Result<VisitResult,NodeError> {
+        leaf.try_visit_ref(self.0)
+    }
+
+    fn select_child(&mut self, leaf: &mut A) -> Option<usize> {
+        leaf.select_child_ref(self.0)
+    }
+
+    // A shared context cannot be written, the results are not stored
+    fn store_result(&mut self, _leaf: &mut A, _variable: &str, _result: VisitResult) {}
+}
+
+// Number of node visits left during the current tick to each budget node being visited, the
+// innermost last
+struct Budget {
+    remaining: Vec<usize>,
+    // Nodes left for a later tick so far, so that a composite can tell whether the Running of a
+    // child comes from a spent budget
+    deferred: usize,
+}
+
+impl Budget {
+    fn new() -> Budget {
+        Budget { remaining: Vec::new(), deferred: 0 }
+    }
+
+    // Counts a visit against every budget, unless one of them is already spent
+    fn spend(&mut self) -> bool {
+        if self.remaining.iter().any(|&left| left == 0) {
+            self.deferred += 1;
+            return false;
+        }
+        for left in self.remaining.iter_mut() {
+            *left -= 1;
+        }
+        true
+    }
+}
+
+// Used by plain visits, compiled away
+struct NoObserver;
+
+impl TreeObserver for NoObserver {
+    fn on_node_enter(&mut self, _index: usize) {}
+    fn on_node_result(&mut self, _index: usize, _result: VisitResult) {}
+}
+
+/// Node stored in the FlatTree, along with its position in the tree
+#[derive(Debug,Clone)]
+struct FlatNode<A> {
+    index: usize,
+    node: OptimizedNode<A>,
+    // Visits since the last `OptimizedTree::take_stats`
+    #[cfg(feature = "stats")]
+    visits: u64,
+}
+
+impl <A> FlatNode<A> {
+    fn view(&self) -> NodeView {
+        let (kind, running) = match self.node {
+            OptimizedNode::Leaf(_) => (NodeKind::Leaf, None),
+            OptimizedNode::Sequence(ref node) => (NodeKind::Sequence, node.running),
+            OptimizedNode::SequenceAll(ref node) => (NodeKind::SequenceAll, node.running),
+            OptimizedNode::Inverter => (NodeKind::Inverter, None),
+            OptimizedNode::Repeater { .. } => (NodeKind::Repeater, None),
+            OptimizedNode::UntilFail => (NodeKind::UntilFail, None),
+            OptimizedNode::Succeeder => (NodeKind::Succeeder, None),
+            OptimizedNode::Cooldown { .. } => (NodeKind::Cooldown, None),
+            OptimizedNode::Limiter { .. } => (NodeKind::Limiter, None),
+            OptimizedNode::Retry { .. } => (NodeKind::Retry, None),
+            OptimizedNode::Switch { running, .. } => (NodeKind::Switch, running),
+            OptimizedNode::Priority { deferred } => (NodeKind::Priority, deferred),
+            OptimizedNode::ReactiveSelector(ref node) => (NodeKind::ReactiveSelector, node.running),
+            OptimizedNode::Selector(ref node) => (NodeKind::Selector, node.running),
+            OptimizedNode::Parallel(_) => (NodeKind::Parallel, None),
+            OptimizedNode::RandomSelector(ref node) => {
+                (NodeKind::RandomSelector, node.running.map(|position| node.order[position]))
+            }
+            OptimizedNode::WeightedSelector(ref node) => (NodeKind::WeightedSelector, node.running),
+            OptimizedNode::Budget { .. } => (NodeKind::Budget, None),
+            OptimizedNode::NonBlocking { .. } => (NodeKind::NonBlocking, None),
+            OptimizedNode::StoreResult { .. } => (NodeKind::StoreResult, None),
+        };
+        NodeView {
+            index: self.index,
+            kind: kind,
+            running: running,
+        }
+    }
+
+    fn new(node: OptimizedNode<A>) -> FlatNode<A> {
+        // The index is set once the whole tree is built
+        FlatNode {
+            index: 0,
+            node: node,
+            #[cfg(feature = "stats")]
+            visits: 0,
+        }
+    }
+
+    fn visit<C,O>(&mut self, context: &mut C, children: ChildrenMut<FlatNode<A>>,
+                  observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        if !budget.spend() {
+            // Left for a later tick, parents resume it as if it was running. The ones that act on
+            // a Running result (priority nodes, reactive selectors, non_blocking and store_result)
+            // tell this one apart from a real Running through `budget.deferred`
+            return Ok(VisitResult::Running);
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.visits += 1;
+        }
+        observer.on_node_enter(self.index);
+        let result = match self.node.visit(context, children, observer, budget) {
+            Ok(result) => result,
+            Err(error) => {
+                // Interrupted, the next visit starts a new activation of this node
+                self.node.reset();
+                return Err(error);
+            }
+        };
+        observer.on_node_result(self.index, result);
+        Ok(result)
+    }
+}
+
+/// Error returned by `OptimizedTree::run_to_completion` when the tree is still running after the
+/// maximum number of ticks
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct TickLimitExceeded {
+    pub max_ticks: usize,
+}
+
+impl Display for TickLimitExceeded {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        write!(f, "tree still running after {} ticks", self.max_ticks)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for TickLimitExceeded {
+    fn description(&self) -> &str {
+        "tree still running after the maximum number of ticks"
+    }
+}
+
+/// Error returned by `OptimizedTree::reset_subtree` when the tree has no node with this index
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct NodeIndexOutOfRange {
+    pub index: usize,
+    pub node_count: usize,
+}
+
+impl Display for NodeIndexOutOfRange {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        write!(f, "no node with index {} in a tree of {} nodes", self.index, self.node_count)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for NodeIndexOutOfRange {
+    fn description(&self) -> &str {
+        "node index out of range"
+    }
+}
+
+#[derive(Debug,Clone)]
+enum OptimizedNode<A> {
+    Leaf(OptimizedLeafNode<A>),
+    Sequence(OptimizedSequenceNode),
+    SequenceAll(OptimizedSequenceAllNode),
+    Inverter,
+    Repeater {
+        remaining: usize,
+        total: usize,
+    },
+    UntilFail,
+    Succeeder,
+    Cooldown {
+        remaining: usize,
+        ticks: usize,
+    },
+    Limiter {
+        used: usize,
+        max: usize,
+    },
+    Retry {
+        attempts_left: usize,
+        attempts: usize,
+    },
+    Switch {
+        selector: LeafNode<A>,
+        running: Option<usize>,
+    },
+    Priority {
+        // Child cut short by a spent budget, resumed directly on the next visit
+        deferred: Option<usize>,
+    },
+    ReactiveSelector(OptimizedReactiveSelectorNode),
+    Selector(OptimizedSelectorNode),
+    Parallel(OptimizedParallelNode),
+    RandomSelector(OptimizedRandomSelectorNode),
+    WeightedSelector(OptimizedWeightedSelectorNode),
+    Budget {
+        max: usize,
+    },
+    NonBlocking {
+        on_running: NonBlockingResult,
+    },
+    StoreResult {
+        variable: String,
+        writer: LeafNode<A>,
+    },
+}
+
+type OptimizedLeafNode<A> = LeafNode<A>;
+
+#[derive(Debug,Clone)]
+struct OptimizedSequenceNode {
+    // Child that returned Running on the last visit. Taken on every visit, so it is None once the
+    // sequence finished and the next visit starts from the first child.
+    running: Option<usize>,
+}
+
+impl OptimizedSequenceNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let mut index = self.running.take().unwrap_or(0);
+        let mut children = children.children_mut();
+
+        // Go the the last previous running node
+        for _ in 0..index {
+            if let Some((child, _)) = children.next() {
+                observer.on_node_skipped(child.index);
+            }
+        }
+        for (child, grandchildren) in children {
+            match try!(child.visit(context, grandchildren, observer, budget)) {
+                VisitResult::Running => {
+                    self.running = Some(index);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Failure => {
+                    return Ok(VisitResult::Failure);
+                }
+                VisitResult::Success => {}
+            }
+            index = index + 1;
+        }
+        Ok(VisitResult::Success)
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedSequenceAllNode {
+    // Same as in `OptimizedSequenceNode`
+    running: Option<usize>,
+    // A child already failed during this activation
+    failed: bool,
+}
+
+impl OptimizedSequenceAllNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let mut index = self.running.take().unwrap_or(0);
+        let mut children = children.children_mut();
+
+        for _ in 0..index {
+            if let Some((child, _)) = children.next() {
+                observer.on_node_skipped(child.index);
+            }
+        }
+        for (child, grandchildren) in children {
+            let result = child.visit(context, grandchildren, observer, budget);
+            // An error ends the activation, the failures seen so far must not leak into the next one
+            if result.is_err() {
+                self.failed = false;
+            }
+            match try!(result) {
+                VisitResult::Running => {
+                    self.running = Some(index);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Failure => self.failed = true,
+                VisitResult::Success => {}
+            }
+            index = index + 1;
+        }
+        Ok(sequence_all_result(&mut self.failed))
+    }
+}
+
+/// Result of a sequence_all node whose children all finished, which starts a new activation
+fn sequence_all_result(failed: &mut bool) -> VisitResult {
+    if mem::replace(failed, false) {
+        VisitResult::Failure
+    } else {
+        VisitResult::Success
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedSelectorNode {
+    // Same as in `OptimizedSequenceNode`
+    running: Option<usize>,
+}
+
+impl OptimizedSelectorNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let mut index = self.running.take().unwrap_or(0);
+        let mut children = children.children_mut();
+
+        // Go the the last previous running node
+        for _ in 0..index {
+            if let Some((child, _)) = children.next() {
+                observer.on_node_skipped(child.index);
+            }
+        }
+        for (child, grandchildren) in children {
+            match try!(child.visit(context, grandchildren, observer, budget)) {
+                VisitResult::Running => {
+                    self.running = Some(index);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Success => {
+                    return Ok(VisitResult::Success);
+                }
+                VisitResult::Failure => {}
+            }
+            index = index + 1;
+        }
+        Ok(VisitResult::Failure)
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedReactiveSelectorNode {
+    running: Option<usize>,
+    // Child cut short by a spent budget, the next visit starts from it instead of the first child
+    deferred: Option<usize>,
+}
+
+impl OptimizedReactiveSelectorNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let mut index = self.deferred.take().unwrap_or(0);
+        for skipped in 0..index {
+            if let Some((child, _)) = children.get_mut(skipped) {
+                observer.on_node_skipped(child.index);
+            }
+        }
+        loop {
+            let deferred_before = budget.deferred;
+            let result = match children.get_mut(index) {
+                Some((child, grandchildren)) => {
+                    try!(child.visit(context, grandchildren, observer, budget))
+                }
+                None => break,
+            };
+            if result == VisitResult::Running && budget.deferred != deferred_before {
+                // Not a real Running: the running child keeps running until this child is
+                // resumed and actually takes over
+                self.deferred = Some(index);
+                if self.running.map_or(true, |running| running < index) {
+                    self.running = Some(index);
+                }
+                return Ok(result);
+            }
+            if result != VisitResult::Failure {
+                // A running child after this one was preempted
+                if let Some(running) = self.running.take() {
+                    if running > index {
+                        let (child, grandchildren) = children.get_mut(running)
+                                                             .expect("Running child out of range");
+                        reset_all(child, grandchildren);
+                    }
+                }
+                if result == VisitResult::Running {
+                    self.running = Some(index);
+                }
+                return Ok(result);
+            }
+            index += 1;
+        }
+        self.running = None;
+        Ok(VisitResult::Failure)
+    }
+}
+
+// Resets a node and all its descendants
+fn reset_all<A>(node: &mut FlatNode<A>, mut children: ChildrenMut<FlatNode<A>>) {
+    node.node.reset();
+    for (child, grandchildren) in children.children_mut() {
+        reset_all(child, grandchildren);
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedRandomSelectorNode {
+    // Order in which the children are visited during the current activation
+    order: Vec<usize>,
+    // Position in `order` of the running child
+    running: Option<usize>,
+    rng: XorShift,
+}
+
+impl OptimizedRandomSelectorNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let start = match self.running.take() {
+            Some(position) => position,
+            None => {
+                // New activation, pick a new order
+                if let Some(seed) = context.next_seed() {
+                    self.rng = XorShift::new(seed);
+                }
+                self.rng.shuffle(&mut self.order);
+                0
+            }
+        };
+        for position in start..self.order.len() {
+            let (child, grandchildren) = children.get_mut(self.order[position])
+                                                 .expect("Random selector child out of range");
+            match try!(child.visit(context, grandchildren, observer, budget)) {
+                VisitResult::Running => {
+                    self.running = Some(position);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Success => {
+                    return Ok(VisitResult::Success);
+                }
+                VisitResult::Failure => {}
+            }
+        }
+        Ok(VisitResult::Failure)
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedWeightedSelectorNode {
+    weights: Vec<u32>,
+    // Index of the running child
+    running: Option<usize>,
+    rng: XorShift,
+}
+
+impl OptimizedWeightedSelectorNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        let running = self.running.take();
+        if running.is_none() {
+            if let Some(seed) = context.next_seed() {
+                self.rng = XorShift::new(seed);
+            }
+        }
+        let index = match running.or_else(|| self.rng.weighted(&self.weights)) {
+            Some(index) => index,
+            None => return Ok(VisitResult::Failure),
+        };
+        let (child, grandchildren) = children.get_mut(index)
+                                             .expect("Weighted selector child out of range");
+        let result = try!(child.visit(context, grandchildren, observer, budget));
+        if result == VisitResult::Running {
+            self.running = Some(index);
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Debug,Clone)]
+struct OptimizedParallelNode {
+    threshold: usize,
+    // Results of the children that already finished during the current activation
+    results: Vec<Option<VisitResult>>,
+}
+
+impl OptimizedParallelNode {
+    fn visit<A,C,O>(&mut self, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                    observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        for ((child, grandchildren), result) in children.children_mut().zip(self.results.iter_mut()) {
+            if result.is_some() {
+                // Already finished during this activation
+                continue;
+            }
+            match try!(child.visit(context, grandchildren, observer, budget)) {
+                VisitResult::Running => {}
+                other => *result = Some(other),
+            }
+        }
+        let successes = self.results.iter().filter(|r| **r == Some(VisitResult::Success)).count();
+        let failures = self.results.iter().filter(|r| **r == Some(VisitResult::Failure)).count();
+        let result = parallel_result(self.threshold, self.results.len(), successes, failures);
+        if result != VisitResult::Running {
+            for ((child, grandchildren), r) in children.children_mut().zip(self.results.iter_mut()) {
+                // The children still running are abandoned, the next activation starts them over
+                if r.is_none() {
+                    reset_all(child, grandchildren);
+                }
+                *r = None;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Decides the result of a parallel node from the number of children that finished.
+///
+/// Succeeds once at least `threshold` children succeeded, and fails once more than
+/// `total - threshold` children failed, as success is then out of reach.
+///
+/// Both conditions can never hold during the same tick, since successes and failures never add up
+/// to more than `total`. The success threshold is nevertheless checked first, so a threshold of 0
+/// always succeeds and a threshold greater than `total` always fails.
+fn parallel_result(threshold: usize, total: usize, successes: usize, failures: usize) -> VisitResult {
+    if successes >= threshold {
+        VisitResult::Success
+    } else if failures + threshold > total {
+        VisitResult::Failure
+    } else {
+        VisitResult::Running
+    }
+}
+
+impl <A> OptimizedNode<A> {
+    fn visit<C,O>(&mut self, context: &mut C, children: ChildrenMut<FlatNode<A>>,
+                  observer: &mut O, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where C: LeafContext<A>, O: ?Sized + TreeObserver {
+        match *self {
+            OptimizedNode::Sequence(ref mut node) => node.visit(context, children, observer, budget),
+            OptimizedNode::SequenceAll(ref mut node) => node.visit(context, children, observer, budget),
+            OptimizedNode::Inverter => inverter_visit(context, children, observer, budget),
+            OptimizedNode::Repeater { ref mut remaining, total } => {
+                repeater_visit(remaining, total, context, children, observer, budget)
+            }
+            OptimizedNode::UntilFail => until_fail_visit(context, children, observer, budget),
+            OptimizedNode::Succeeder => succeeder_visit(context, children, observer, budget),
+            OptimizedNode::Cooldown { ref mut remaining, ticks } => {
+                cooldown_visit(remaining, ticks, context, children, observer, budget)
+            }
+            OptimizedNode::Limiter { ref mut used, max } => {
+                limiter_visit(used, max, context, children, observer, budget)
+            }
+            OptimizedNode::Retry { ref mut attempts_left, attempts } => {
+                retry_visit(attempts_left, attempts, context, children, observer, budget)
+            }
+            OptimizedNode::Switch { ref mut selector, ref mut running } => {
+                switch_visit(selector, running, context, children, observer, budget)
+            }
+            OptimizedNode::Leaf(ref mut node) => context.visit_leaf(&mut node.inner),
+            OptimizedNode::Priority { ref mut deferred } => {
+                priority_visit(deferred, context, children, observer, budget)
+            }
+            OptimizedNode::ReactiveSelector(ref mut node) => {
+                node.visit(context, children, observer, budget)
+            }
+            OptimizedNode::Selector(ref mut node) => node.visit(context, children, observer, budget),
+            OptimizedNode::Parallel(ref mut node) => node.visit(context, children, observer, budget),
+            OptimizedNode::RandomSelector(ref mut node) => node.visit(context, children, observer, budget),
+            OptimizedNode::WeightedSelector(ref mut node) => {
+                node.visit(context, children, observer, budget)
+            }
+            OptimizedNode::Budget { max } => budget_visit(max, context, children, observer, budget),
+            OptimizedNode::NonBlocking { on_running } => {
+                non_blocking_visit(on_running, context, children, observer, budget)
+            }
+            OptimizedNode::StoreResult { ref variable, ref mut writer } => {
+                store_result_visit(variable, writer, context, children, observer, budget)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        match *self {
+            OptimizedNode::Sequence(ref mut node) => node.running = None,
+            OptimizedNode::SequenceAll(ref mut node) => {
+                node.running = None;
+                node.failed = false;
+            }
+            OptimizedNode::Selector(ref mut node) => node.running = None,
+            OptimizedNode::ReactiveSelector(ref mut node) => {
+                node.running = None;
+                node.deferred = None;
+            }
+            OptimizedNode::Priority { ref mut deferred } => *deferred = None,
+            OptimizedNode::RandomSelector(ref mut node) => node.running = None,
+            OptimizedNode::WeightedSelector(ref mut node) => node.running = None,
+            OptimizedNode::Parallel(ref mut node) => {
+                for result in node.results.iter_mut() {
+                    *result = None;
+                }
+            }
+            OptimizedNode::Repeater { ref mut remaining, total } => *remaining = total,
+            // The cooldown is not part of an activation, it keeps counting after a reset
+            OptimizedNode::Cooldown { .. } => {}
+            // Neither is the count of the limiter, only `clear_limit` clears it
+            OptimizedNode::Limiter { .. } => {}
+            OptimizedNode::Retry { ref mut attempts_left, attempts } => *attempts_left = attempts,
+            OptimizedNode::Switch { ref mut running, .. } => *running = None,
+            OptimizedNode::Leaf(_) | OptimizedNode::Inverter | OptimizedNode::UntilFail
+                | OptimizedNode::Succeeder
+                | OptimizedNode::Budget { .. } | OptimizedNode::NonBlocking { .. }
+                | OptimizedNode::StoreResult { .. } => {}
+        }
+    }
+
+    // Clears the count of a limiter, when the tree is reset from outside. Errors and preemptions
+    // only reset the activation, and keep the count.
+    fn clear_limit(&mut self) {
+        if let OptimizedNode::Limiter { ref mut used, .. } = *self {
+            *used = 0;
+        }
+    }
+
+    fn sequence(running: Option<usize>) -> OptimizedNode<A> {
+        OptimizedNode::Sequence(OptimizedSequenceNode{ running: running })
+    }
+
+    fn sequence_all() -> OptimizedNode<A> {
+        OptimizedNode::SequenceAll(OptimizedSequenceAllNode{ running: None, failed: false })
+    }
+
+    fn selector(running: Option<usize>) -> OptimizedNode<A> {
+        OptimizedNode::Selector(OptimizedSelectorNode{ running: running })
+    }
+
+    fn reactive_selector() -> OptimizedNode<A> {
+        OptimizedNode::ReactiveSelector(OptimizedReactiveSelectorNode {
+            running: None,
+            deferred: None,
+        })
+    }
+
+    fn priority() -> OptimizedNode<A> {
+        OptimizedNode::Priority { deferred: None }
+    }
+
+    fn random_selector(nb_children: usize) -> OptimizedNode<A> {
+        OptimizedNode::RandomSelector(OptimizedRandomSelectorNode {
+            order: (0..nb_children).collect(),
+            running: None,
+            // Seeded when the whole tree is built
+            rng: XorShift::new(0),
+        })
+    }
+
+    fn weighted_selector(weights: Vec<u32>) -> OptimizedNode<A> {
+        OptimizedNode::WeightedSelector(OptimizedWeightedSelectorNode {
+            weights: weights,
+            running: None,
+            // Seeded when the whole tree is built
+            rng: XorShift::new(0),
+        })
+    }
+
+    fn parallel(threshold: usize, nb_children: usize) -> OptimizedNode<A> {
+        OptimizedNode::Parallel(OptimizedParallelNode {
+            threshold: threshold,
+            results: vec![None; nb_children],
+        })
+    }
+
+    fn repeater(total: usize) -> OptimizedNode<A> {
+        OptimizedNode::Repeater { remaining: total, total: total }
+    }
+
+    fn cooldown(ticks: usize) -> OptimizedNode<A> {
+        OptimizedNode::Cooldown { remaining: 0, ticks: ticks }
+    }
+
+    fn limiter(max: usize) -> OptimizedNode<A> {
+        OptimizedNode::Limiter { used: 0, max: max }
+    }
+
+    fn budget(max: usize) -> OptimizedNode<A> {
+        OptimizedNode::Budget { max: max }
+    }
+
+    fn retry(attempts: usize) -> OptimizedNode<A> {
+        OptimizedNode::Retry { attempts_left: attempts, attempts: attempts }
+    }
+
+    fn switch(selector: LeafNode<A>) -> OptimizedNode<A> {
+        OptimizedNode::Switch { selector: selector, running: None }
+    }
+
+    fn non_blocking(on_running: NonBlockingResult) -> OptimizedNode<A> {
+        OptimizedNode::NonBlocking { on_running: on_running }
+    }
+
+    fn store_result(variable: String, writer: LeafNode<A>) -> OptimizedNode<A> {
+        OptimizedNode::StoreResult { variable: variable, writer: writer }
+    }
+}
+
+fn inverter_visit<A,C,O>(context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                         observer: &mut O, budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("Inverter without children");
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        VisitResult::Success => VisitResult::Failure,
+        VisitResult::Failure => VisitResult::Success,
+        VisitResult::Running => VisitResult::Running,
+    };
+    Ok(result)
+}
+
+fn succeeder_visit<A,C,O>(context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                          observer: &mut O, budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("Succeeder without children");
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        VisitResult::Running => VisitResult::Running,
+        VisitResult::Success | VisitResult::Failure => VisitResult::Success,
+    };
+    Ok(result)
+}
+
+fn until_fail_visit<A,C,O>(context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                           observer: &mut O, budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("UntilFail without children");
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        VisitResult::Failure => VisitResult::Success,
+        VisitResult::Success | VisitResult::Running => VisitResult::Running,
+    };
+    Ok(result)
+}
+
+fn repeater_visit<A,C,O>(remaining: &mut usize, total: usize, context: &mut C,
+                         mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                         budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    if total == 0 {
+        return Ok(VisitResult::Success);
+    }
+    let (child, grandchildren) = children.get_mut(0).expect("Repeater without children");
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        VisitResult::Failure => {
+            *remaining = total;
+            VisitResult::Failure
+        }
+        VisitResult::Running => VisitResult::Running,
+        VisitResult::Success => {
+            *remaining -= 1;
+            if *remaining == 0 {
+                *remaining = total;
+                VisitResult::Success
+            } else {
+                VisitResult::Running
+            }
+        }
+    };
+    Ok(result)
+}
+
+fn cooldown_visit<A,C,O>(remaining: &mut usize, ticks: usize, context: &mut C,
+                         mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                         budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    if *remaining > 0 {
+        *remaining -= 1;
+        return Ok(VisitResult::Failure);
+    }
+    let (child, grandchildren) = children.get_mut(0).expect("Cooldown without children");
+    let result = try!(child.visit(context, grandchildren, observer, budget));
+    if result == VisitResult::Success {
+        *remaining = ticks;
+    }
+    Ok(result)
+}
+
+fn limiter_visit<A,C,O>(used: &mut usize, max: usize, context: &mut C,
+                        mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                        budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    if *used >= max {
+        return Ok(VisitResult::Failure);
+    }
+    let (child, grandchildren) = children.get_mut(0).expect("Limiter without children");
+    let result = try!(child.visit(context, grandchildren, observer, budget));
+    if result == VisitResult::Success {
+        *used += 1;
+    }
+    Ok(result)
+}
+
+fn retry_visit<A,C,O>(attempts_left: &mut usize, attempts: usize, context: &mut C,
+                      mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                      budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("Retry without children");
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        VisitResult::Running => VisitResult::Running,
+        VisitResult::Success => {
+            *attempts_left = attempts;
+            VisitResult::Success
+        }
+        VisitResult::Failure => {
+            *attempts_left = attempts_left.saturating_sub(1);
+            if *attempts_left == 0 {
+                *attempts_left = attempts;
+                VisitResult::Failure
+            } else {
+                VisitResult::Running
+            }
+        }
+    };
+    Ok(result)
+}
+
+fn switch_visit<A,C,O>(selector: &mut LeafNode<A>, running: &mut Option<usize>, context: &mut C,
+                       mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                       budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let index = match running.take().or_else(|| context.select_child(&mut selector.inner)) {
+        Some(index) => index,
+        None => return Ok(VisitResult::Failure),
+    };
+    let (child, grandchildren) = match children.get_mut(index) {
+        Some(child) => child,
+        None => return Ok(VisitResult::Failure),
+    };
+    let result = try!(child.visit(context, grandchildren, observer, budget));
+    if result == VisitResult::Running {
+        *running = Some(index);
+    }
+    Ok(result)
+}
+
+fn priority_visit<A,C,O>(deferred: &mut Option<usize>, context: &mut C,
+                         mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                         budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    // Revisiting the first children would spend the budget again before reaching the child that
+    // was cut short, so it is resumed directly
+    let mut index = deferred.take().unwrap_or(0);
+    let mut children = children.children_mut();
+    for _ in 0..index {
+        if let Some((child, _)) = children.next() {
+            observer.on_node_skipped(child.index);
+        }
+    }
+    for (child, grandchildren) in children {
+        let deferred_before = budget.deferred;
+        match try!(child.visit(context, grandchildren, observer, budget)) {
+            VisitResult::Running => {
+                if budget.deferred != deferred_before {
+                    *deferred = Some(index);
+                }
+                return Ok(VisitResult::Running);
+            }
+            VisitResult::Failure => {
+                return Ok(VisitResult::Failure);
+            }
+            VisitResult::Success => {}
+        }
+        index += 1;
+    }
+    Ok(VisitResult::Success)
+}
+
+fn budget_visit<A,C,O>(max: usize, context: &mut C, mut children: ChildrenMut<FlatNode<A>>,
+                       observer: &mut O, budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("Budget without children");
+    budget.remaining.push(max);
+    let result = child.visit(context, grandchildren, observer, budget);
+    budget.remaining.pop();
+    result
+}
+
+fn non_blocking_visit<A,C,O>(on_running: NonBlockingResult, context: &mut C,
+                             mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                             budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("NonBlocking without children");
+    let deferred_before = budget.deferred;
+    let result = match try!(child.visit(context, grandchildren, observer, budget)) {
+        // Cut short by a spent budget, the parent must resume the child on the next tick
+        VisitResult::Running if budget.deferred != deferred_before => VisitResult::Running,
+        VisitResult::Running => VisitResult::from(on_running),
+        result => result,
+    };
+    Ok(result)
+}
+
+fn store_result_visit<A,C,O>(variable: &str, writer: &mut LeafNode<A>, context: &mut C,
+                            mut children: ChildrenMut<FlatNode<A>>, observer: &mut O,
+                            budget: &mut Budget)
+-> Result<VisitResult,NodeError>
+where C: LeafContext<A>, O: ?Sized + TreeObserver {
+    let (child, grandchildren) = children.get_mut(0).expect("StoreResult without children");
+    let deferred_before = budget.deferred;
+    let result = try!(child.visit(context, grandchildren, observer, budget));
+    // A child cut short by a spent budget has no result yet
+    if !(result == VisitResult::Running && budget.deferred != deferred_before) {
+        context.store_result(&mut writer.inner, variable, result);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BehaviourTreeNode,NodeError,NodeIndexOutOfRange,NodeKind,NodePath,TickLimitExceeded};
+    use super::{FnFactory,LeafNodeFactory,ReadOnlyBehaviourTreeNode,SharedLeaf,TraceResult};
+    use super::TreeObserver;
+    use super::{NonBlockingResult,VisitResult};
+    use super::factory::{LeafFactory,NodeFactory,TreeFactory};
+    use super::non_optimized::SequenceAllNode;
+    use super::random::SeededContext;
+    use super::test_helpers::*;
+
+    #[test]
+    fn repeater() {
+        let factory = tree(NodeFactory::new_repeater(3, Box::new(leaf("a", &[Success]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        for _ in 0..2 {
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Success);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Success);
+        }
+        assert_eq!(log.len(), 12);
+    }
+
+    #[test]
+    fn repeater_failure() {
+        let child = leaf("a", &[Success, Failure, Success, Success, Success]);
+        let mut optimized = tree(NodeFactory::new_repeater(3, Box::new(child))).optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Failure);
+        // The count starts again after a failure
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Success);
+    }
+
+    #[test]
+    fn sequence_restarts() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Running, Success]),
+            leaf("b", &[Success]),
+            leaf("c", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        for _ in 0..2 {
+            let mut log = Log::new();
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Success);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Success);
+            // Every run starts from the first child, none is skipped
+            assert_eq!(log, vec!["a", "a", "b", "c", "c", "a", "a", "b", "c", "c"]);
+        }
+    }
+
+    #[test]
+    fn selector_restarts() {
+        let factory = tree(NodeFactory::new_selector(vec![
+            leaf("a", &[Running, Failure]),
+            leaf("b", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        for _ in 0..2 {
+            let mut log = Log::new();
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.visit(&mut log), Success);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Success);
+            assert_eq!(log, vec!["a", "a", "b", "b", "a", "a", "b", "b"]);
+        }
+    }
+
+    #[test]
+    fn non_blocking() {
+        let cases = [
+            (NonBlockingResult::Success, leaf("a", &[Running, Failure]), [Success, Failure]),
+            (NonBlockingResult::Failure, leaf("a", &[Running, Success]), [Failure, Success]),
+        ];
+        for &(on_running, ref child, expected) in cases.iter() {
+            let factory = tree(NodeFactory::new_non_blocking(on_running, Box::new(child.clone())));
+            let mut optimized = factory.optimize();
+            let mut instance = factory.instanciate();
+            let mut log = Log::new();
+            for &result in expected.iter() {
+                assert_eq!(optimized.visit(&mut log), result);
+                assert_eq!(instance.visit(&mut log), result);
+            }
+            assert_eq!(log, ["a", "a", "a", "a"]);
+        }
+    }
+
+    #[test]
+    fn sequence_all() {
+        let factory = tree(NodeFactory::new_sequence_all(vec![
+            leaf("a", &[Success]),
+            leaf("b", &[Failure, Success]),
+            leaf("c", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+        // b fails, c still runs and is resumed directly
+        for &(expected, ref visited) in [(Running, vec!["a", "b", "c"]), (Failure, vec!["c"]),
+                                         (Running, vec!["a", "b", "c"]), (Success, vec!["c"])].iter() {
+            assert_eq!(optimized.visit(&mut optimized_log), expected);
+            assert_eq!(instance.visit(&mut instance_log), expected);
+            assert_eq!(optimized_log, *visited);
+            assert_eq!(instance_log, *visited);
+            optimized_log.clear();
+            instance_log.clear();
+        }
+    }
+
+    #[test]
+    fn parallel_matches_instanciated() {
+        for threshold in 0..5 {
+            let factory = tree(NodeFactory::new_parallel(threshold, vec![
+                leaf("a", &[Running, Success]),
+                NodeFactory::new_sequence(vec![leaf("b", &[Success, Running]), leaf("c", &[Running, Failure])]),
+                leaf("d", &[Failure, Running, Running]),
+                leaf("e", &[Running, Running, Success, Failure]),
+            ]));
+            let mut optimized = factory.optimize();
+            let mut instance = factory.instanciate();
+            let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+            for tick in 0..12 {
+                let expected = instance.visit(&mut instance_log);
+                assert_eq!(optimized.visit(&mut optimized_log), expected,
+                           "threshold {}, tick {}", threshold, tick);
+                assert_eq!(optimized_log, instance_log, "threshold {}, tick {}", threshold, tick);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_visits_finished_children_once() {
+        let factory = tree(NodeFactory::new_parallel(3, vec![
+            leaf("once", &[Success]),
+            leaf("slow", &[Running, Running, Running, Success]),
+            leaf("fast", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+        for _ in 0..3 {
+            assert_eq!(optimized.visit(&mut optimized_log), Running);
+            assert_eq!(instance.visit(&mut instance_log), Running);
+        }
+        assert_eq!(optimized.visit(&mut optimized_log), Success);
+        assert_eq!(instance.visit(&mut instance_log), Success);
+        for log in [optimized_log, instance_log].iter() {
+            assert_eq!(log.iter().filter(|name| **name == "once").count(), 1);
+            assert_eq!(log.iter().filter(|name| **name == "fast").count(), 2);
+            assert_eq!(log.iter().filter(|name| **name == "slow").count(), 4);
+        }
+    }
+
+    #[test]
+    fn parallel() {
+        let factory = tree(NodeFactory::new_parallel(2, vec![
+            leaf("a", &[Running, Success]),
+            leaf("b", &[Success]),
+            leaf("c", &[Failure]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Success);
+        // Finished children are not visited again during the same activation
+        assert_eq!(log, ["a", "b", "c", "a"]);
+
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        assert_eq!(instance.visit(&mut log), Running);
+        assert_eq!(instance.visit(&mut log), Success);
+        assert_eq!(log, ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn parallel_restarts_running_children() {
+        let factory = tree(NodeFactory::new_parallel(1, vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_sequence(vec![leaf("b", &[Success]), leaf("c", &[Running])]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+        for _ in 0..2 {
+            assert_eq!(optimized.visit(&mut optimized_log), Success);
+            assert_eq!(instance.visit(&mut instance_log), Success);
+        }
+        // The sequence was still running when the parallel succeeded, it starts over
+        assert_eq!(optimized_log, ["a", "b", "c", "a", "b", "c"]);
+        assert_eq!(instance_log, optimized_log);
+    }
+
+    #[test]
+    fn parallel_failure() {
+        let factory = tree(NodeFactory::new_parallel(2, vec![
+            leaf("a", &[Failure]),
+            leaf("b", &[Running]),
+            leaf("c", &[Failure]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Failure);
+        // A new activation visits all the children again
+        assert_eq!(optimized.visit(&mut log), Failure);
+        assert_eq!(log, ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn reset() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            leaf("b", &[Running]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "b"]);
+        optimized.reset();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn clone() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            leaf("b", &[Running]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b"]);
+        let mut cloned = optimized.clone();
+        // The clone starts from the root, the original keeps running
+        assert_eq!(cloned.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "a", "b"]);
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "a", "b", "b"]);
+    }
+
+    #[test]
+    fn run_to_completion() {
+        let mut optimized = tree(leaf("a", &[Running, Running, Success])).optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.run_to_completion(&mut log, 10), Ok((Success, 3)));
+        assert_eq!(log.len(), 3);
+        // The largest limit does not overflow
+        assert_eq!(optimized.run_to_completion(&mut log, usize::max_value()), Ok((Success, 3)));
+    }
+
+    #[test]
+    fn run_with_deadline() {
+        let mut optimized = tree(leaf("a", &[Running])).optimize();
+        let mut log = Log::new();
+        let mut calls = 0;
+        let result = optimized.run_with_deadline(&mut log, || { calls += 1; calls < 2 });
+        assert_eq!(result, Running);
+        assert_eq!(log, ["a", "a"]);
+        let mut optimized = tree(leaf("a", &[Running, Running, Success])).optimize();
+        assert_eq!(optimized.run_with_deadline(&mut log, || true), Success);
+    }
+
+    #[test]
+    fn run_to_completion_limit() {
+        let mut optimized = tree(leaf("a", &[Running])).optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.run_to_completion(&mut log, 5), Err(TickLimitExceeded { max_ticks: 5 }));
+        assert_eq!(log.len(), 5);
+    }
+
+    #[test]
+    fn random_selector() {
+        let factory = tree(NodeFactory::new_random_selector(vec![
+            leaf("a", &[Failure]),
+            leaf("b", &[Running, Success]),
+            leaf("c", &[Failure]),
+        ]));
+        let mut optimized = factory.optimize_with_seed(42);
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(*log.last().unwrap(), "b");
+        // The running child keeps control without reshuffling
+        let visited = log.len();
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(log[visited..], ["b"]);
+
+        // The same seed gives the same order
+        let mut other = factory.optimize_with_seed(42);
+        let mut other_log = Log::new();
+        other.visit(&mut other_log);
+        other.visit(&mut other_log);
+        assert_eq!(log, other_log);
+    }
+
+    #[test]
+    fn random_selector_order() {
+        let factory = tree(NodeFactory::new_random_selector(vec![
+            leaf("a", &[Failure]),
+            leaf("b", &[Failure]),
+            leaf("c", &[Failure]),
+        ]));
+        let mut optimized = factory.optimize_with_seed(7);
+        let mut first_visited = Vec::new();
+        for _ in 0..50 {
+            let mut log = Log::new();
+            assert_eq!(optimized.visit(&mut log), Failure);
+            let mut sorted = log.clone();
+            sorted.sort();
+            assert_eq!(sorted, ["a", "b", "c"]);
+            if !first_visited.contains(&log[0]) {
+                first_visited.push(log[0]);
+            }
+        }
+        assert!(first_visited.len() > 1);
+    }
+
+    #[test]
+    fn seeded_context() {
+        let factory = tree(NodeFactory::new_selector(vec![
+            NodeFactory::new_random_selector(vec![
+                leaf("a", &[Failure]),
+                leaf("b", &[Failure]),
+                leaf("c", &[Failure]),
+            ]),
+            NodeFactory::new_weighted_selector(vec![(1, leaf("d", &[Success])), (1, leaf("e", &[Success]))]),
+        ]));
+        // The seeds of the trees are ignored, only the seed of the context matters
+        let run = |tree_seed: u64| {
+            let mut optimized = factory.optimize_with_seed(tree_seed);
+            let mut context = SeededContext::new(Log::new(), 5);
+            for _ in 0..20 {
+                assert_eq!(optimized.visit_with_rng(&mut context), Success);
+            }
+            context.inner
+        };
+        let log = run(1);
+        assert_eq!(log.len(), 80);
+        assert_eq!(log, run(2));
+        assert_eq!(log, run(3));
+    }
+
+    #[test]
+    fn instanciate_with_seed() {
+        let factory = tree(NodeFactory::new_selector(vec![
+            NodeFactory::new_random_selector(vec![
+                leaf("a", &[Failure]),
+                leaf("b", &[Failure]),
+                leaf("c", &[Failure]),
+            ]),
+            NodeFactory::new_weighted_selector(vec![(1, leaf("d", &[Success])), (1, leaf("e", &[Success]))]),
+        ]));
+        let run = |tree: &mut BehaviourTreeNode<Log>| {
+            let mut log = Log::new();
+            for _ in 0..20 {
+                assert_eq!(tree.visit(&mut log), Success);
+            }
+            log
+        };
+        let log = run(&mut factory.instanciate_with_seed(5));
+        assert_eq!(log, run(&mut factory.instanciate_with_seed(5)));
+        // The choices are the same as in a tree optimized with the same seed
+        assert_eq!(log, run(&mut factory.optimize_with_seed(5)));
+    }
+
+    #[test]
+    fn until_fail() {
+        let child = leaf("a", &[Success, Running, Success, Failure]);
+        let factory = tree(NodeFactory::new_until_fail(Box::new(child)));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        for _ in 0..3 {
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(instance.visit(&mut log), Running);
+        }
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(instance.visit(&mut log), Success);
+        // The child is visited exactly once per tick
+        assert_eq!(log.len(), 8);
+    }
+
+    #[test]
+    fn succeeder() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            NodeFactory::new_succeeder(Box::new(leaf("a", &[Failure, Running]))),
+            leaf("b", &[Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        // The failure does not abort the sequence
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(instance.visit(&mut log), Success);
+        assert_eq!(log, ["a", "b", "a", "b"]);
+        // Running is propagated
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(instance.visit(&mut log), Running);
+    }
+
+    #[test]
+    fn cooldown() {
+        let factory = tree(NodeFactory::new_cooldown(2, Box::new(leaf("a", &[Success]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        let expected = [Success, Failure, Failure, Success, Failure, Failure, Success];
+        for &result in expected.iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        // The child is only visited outside of the cooldown window
+        assert_eq!(log.len(), 6);
+    }
+
+    #[test]
+    fn cooldown_running() {
+        let factory = tree(NodeFactory::new_cooldown(1, Box::new(leaf("a", &[Running, Failure, Success]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        let expected = [Running, Failure, Success, Failure, Running];
+        for &result in expected.iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        assert_eq!(log.len(), 8);
+    }
+
+    #[test]
+    fn limiter() {
+        let factory = tree(NodeFactory::new_limiter(3, Box::new(leaf("a", &[Success, Running, Failure]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        // Only successes are counted
+        let expected = [Success, Running, Failure, Success, Running, Failure, Success, Failure, Failure];
+        for &result in expected.iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        assert_eq!(log.len(), 14);
+        // A reset allows the child to run again
+        optimized.reset();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log.len(), 15);
+    }
+
+    #[test]
+    fn limiter_survives_errors_and_preemptions() {
+        let error = NodeError::new("broken is broken");
+        let factory = tree(NodeFactory::new_limiter(2, Box::new(NodeFactory::new_selector(vec![
+            leaf("a", &[Success, Failure]),
+            broken_leaf("broken"),
+        ]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+        // The error interrupts the activation, but the first success is still counted
+        let expected = [Ok(Success), Err(error.clone()), Ok(Success), Ok(Failure)];
+        for result in expected.iter() {
+            assert_eq!(optimized.try_visit(&mut optimized_log), *result);
+            assert_eq!(instance.try_visit(&mut instance_log), *result);
+        }
+        assert_eq!(optimized_log, ["a", "a", "broken", "a"]);
+        assert_eq!(instance_log, optimized_log);
+
+        let factory = tree(NodeFactory::new_reactive_selector(vec![
+            leaf("guard", &[Failure, Failure, Success, Failure, Failure]),
+            NodeFactory::new_limiter(2, Box::new(leaf("b", &[Success, Running]))),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        // The guard preempts the running limiter on the third tick, which keeps its count
+        for &result in [Success, Running, Success, Success, Failure].iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        // Only a reset of the whole tree clears it
+        optimized.reset();
+        instance.reset();
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(instance.visit(&mut log), Success);
+    }
+
+    #[test]
+    fn budget() {
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let leaves = names.iter().map(|&name| leaf(name, &[Success])).collect();
+        let factory = tree(NodeFactory::new_budget(4, Box::new(NodeFactory::new_sequence(leaves))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        // The sequence takes one visit of the budget on every tick
+        let expected: [(VisitResult, &[&str]); 4] = [
+            (Running, &["a", "b", "c"]),
+            (Running, &["d", "e", "f"]),
+            (Running, &["g", "h", "i"]),
+            (Success, &["j"]),
+        ];
+        for &(result, visited) in expected.iter() {
+            let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+            assert_eq!(optimized.visit(&mut optimized_log), result);
+            assert_eq!(optimized_log, visited);
+            assert_eq!(instance.visit(&mut instance_log), result);
+            assert_eq!(instance_log, visited);
+        }
+    }
+
+    // Visits both trees, checking that they visit the expected leaves on every tick
+    fn check_ticks(factory: &TreeFactory<Script>, expected: &[(VisitResult, &[&str])]) {
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        for (tick, &(result, visited)) in expected.iter().enumerate() {
+            let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+            assert_eq!(optimized.visit(&mut optimized_log), result, "tick {}", tick);
+            assert_eq!(optimized_log, visited, "tick {}", tick);
+            assert_eq!(instance.visit(&mut instance_log), result, "tick {}", tick);
+            assert_eq!(instance_log, visited, "tick {}", tick);
+        }
+    }
+
+    #[test]
+    fn budget_resumes_priority() {
+        let factory = tree(NodeFactory::new_budget(3, Box::new(NodeFactory::new_priority(vec![
+            leaf("a", &[Success]),
+            leaf("b", &[Success]),
+            leaf("c", &[Success]),
+            leaf("d", &[Success]),
+        ]))));
+        // Starting from a again would spend the budget before reaching c on every tick
+        check_ticks(&factory, &[
+            (Running, &["a", "b"]),
+            (Success, &["c", "d"]),
+            (Running, &["a", "b"]),
+        ]);
+    }
+
+    #[test]
+    fn budget_passes_through_non_blocking() {
+        let factory = tree(NodeFactory::new_budget(3, Box::new(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_non_blocking(NonBlockingResult::Success, Box::new(leaf("b", &[Success]))),
+            leaf("c", &[Success]),
+        ]))));
+        // b cut short by the budget is not running, the sequence waits for it instead of carrying
+        // on to c
+        check_ticks(&factory, &[
+            (Running, &["a"]),
+            (Running, &["b"]),
+            (Success, &["c"]),
+        ]);
+    }
+
+    #[test]
+    fn budget_resumes_reactive_selector() {
+        let factory = tree(NodeFactory::new_budget(4, Box::new(NodeFactory::new_reactive_selector(vec![
+            NodeFactory::new_sequence(vec![
+                leaf("a", &[Success]),
+                leaf("b", &[Success]),
+                leaf("c", &[Failure]),
+            ]),
+            NodeFactory::new_sequence(vec![
+                leaf("e", &[Success]),
+                leaf("f", &[Running]),
+            ]),
+        ]))));
+        // A first child cut short by the budget does not preempt the running second child, which
+        // resumes at f instead of starting again from e
+        check_ticks(&factory, &[
+            (Running, &["a", "b"]),
+            (Running, &["c"]),
+            (Running, &["e", "f"]),
+            (Running, &["a", "b"]),
+            (Running, &["c"]),
+            (Running, &["f"]),
+        ]);
+    }
+
+    #[test]
+    fn retry() {
+        let factory = tree(NodeFactory::new_retry(5, Box::new(leaf("a", &[Failure, Running, Failure, Success]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        // Succeeds on the third try, Running does not count as a try
+        let expected = [Running, Running, Running, Success];
+        for &result in expected.iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        assert_eq!(log.len(), 8);
+    }
+
+    #[test]
+    fn retry_exhausted() {
+        let factory = tree(NodeFactory::new_retry(3, Box::new(leaf("a", &[Failure]))));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        // The attempts are restored after giving up
+        let expected = [Running, Running, Failure, Running, Running, Failure];
+        for &result in expected.iter() {
+            assert_eq!(optimized.visit(&mut log), result);
+            assert_eq!(instance.visit(&mut log), result);
+        }
+        assert_eq!(log.len(), 12);
+    }
+
+    #[test]
+    fn weighted_selector() {
+        let factory = tree(NodeFactory::new_weighted_selector(vec![
+            (3, leaf("a", &[Success])),
+            (1, leaf("b", &[Failure])),
+            (0, leaf("c", &[Success])),
+        ]));
+        let mut optimized = factory.optimize_with_seed(3);
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        let draws = 4000;
+        for _ in 0..draws {
+            optimized.visit(&mut log);
+            instance.visit(&mut log);
+        }
+        // Exactly one child is visited per activation
+        assert_eq!(log.len(), 2 * draws);
+        let a = log.iter().filter(|&&name| name == "a").count();
+        let c = log.iter().filter(|&&name| name == "c").count();
+        // Expecting 6000 a, with a standard deviation around 40
+        assert!(a > 5700 && a < 6300, "{} a out of {}", a, log.len());
+        assert_eq!(c, 0);
+    }
+
+    #[test]
+    fn weighted_selector_running() {
+        let factory = tree(NodeFactory::new_weighted_selector(vec![
+            (1, leaf("a", &[Running, Running, Success])),
+            (1, leaf("b", &[Running, Running, Success])),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Success);
+        // The running child keeps control
+        assert!(log == ["a", "a", "a"] || log == ["b", "b", "b"], "{:?}", log);
+    }
+
+    #[test]
+    fn weighted_selector_no_weight() {
+        let factory = tree(NodeFactory::new_weighted_selector(vec![(0, leaf("a", &[Success]))]));
+        let mut log = Log::new();
+        assert_eq!(factory.optimize().visit(&mut log), Failure);
+        assert_eq!(factory.instanciate().visit(&mut log), Failure);
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn non_optimized_debug() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_inverter(Box::new(leaf("b", &[Success]))),
+            NodeFactory::new_parallel(1, vec![leaf("c", &[Success])]),
+        ]));
+        let formatted = format!("{:?}", factory.instanciate());
+        assert_eq!(formatted, "Tree(Sequence([Leaf(..), Inverter(Leaf(..)), Parallel(1, [Leaf(..)])]))");
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<(usize, Option<VisitResult>)>,
+    }
+
+    impl TreeObserver for Recorder {
+        fn on_node_enter(&mut self, index: usize) {
+            self.events.push((index, None));
+        }
+
+        fn on_node_result(&mut self, index: usize, result: VisitResult) {
+            self.events.push((index, Some(result)));
+        }
+    }
+
+    #[test]
+    fn nodes() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_inverter(Box::new(leaf("b", &[Running]))),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        let kinds: Vec<_> = optimized.nodes().map(|view| view.kind).collect();
+        assert_eq!(kinds, [NodeKind::Sequence, NodeKind::Leaf, NodeKind::Inverter, NodeKind::Leaf]);
+        let running: Vec<_> = optimized.nodes().filter(|view| view.running.is_some()).collect();
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].index, 0);
+        assert_eq!(running[0].running, Some(1));
+        optimized.reset();
+        assert!(optimized.nodes().all(|view| view.running.is_none()));
+    }
+
+    #[test]
+    fn reset_subtree() {
+        let factory = tree(NodeFactory::new_parallel(2, vec![
+            NodeFactory::new_sequence(vec![leaf("a", &[Success]), leaf("b", &[Running])]),
+            NodeFactory::new_sequence(vec![leaf("c", &[Success]), leaf("d", &[Running])]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "c", "d"]);
+        // Only the first sequence starts over
+        optimized.reset_subtree(1).unwrap();
+        log.clear();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(log, ["a", "b", "d"]);
+        assert_eq!(optimized.reset_subtree(7), Err(NodeIndexOutOfRange { index: 7, node_count: 7 }));
+    }
+
+    #[test]
+    fn visit_tracked() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            NodeFactory::new_succeeder(Box::new(leaf("b", &[Running, Success]))),
+            leaf("c", &[Running]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        let (result, path) = optimized.visit_tracked(&mut log);
+        assert_eq!(result, Running);
+        assert_eq!(path, Some(NodePath(vec![0, 2, 3])));
+        let (result, path) = optimized.visit_tracked(&mut log);
+        assert_eq!(result, Running);
+        assert_eq!(path.and_then(|path| path.last()), Some(4));
+        let mut done = tree(leaf("d", &[Success])).optimize();
+        assert_eq!(done.visit_tracked(&mut log), (Success, None));
+        // Only the first running child of a parallel node is reported
+        let mut parallel = tree(NodeFactory::new_parallel(2, vec![
+            leaf("e", &[Running]),
+            leaf("f", &[Running]),
+        ])).optimize();
+        assert_eq!(parallel.visit_tracked(&mut log), (Running, Some(NodePath(vec![0, 1]))));
+    }
+
+    #[test]
+    fn visit_observed() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            NodeFactory::new_inverter(Box::new(leaf("a", &[Failure]))),
+            NodeFactory::new_selector(vec![
+                leaf("b", &[Failure]),
+                leaf("c", &[Running, Success]),
+            ]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        let mut recorder = Recorder::default();
+        assert_eq!(optimized.visit_observed(&mut log, &mut recorder), Running);
+        assert_eq!(recorder.events, [
+            (0, None),
+            (1, None), (2, None), (2, Some(Failure)), (1, Some(Success)),
+            (3, None), (4, None), (4, Some(Failure)), (5, None), (5, Some(Running)), (3, Some(Running)),
+            (0, Some(Running)),
+        ]);
+        // The next tick resumes the running child directly
+        let mut recorder = Recorder::default();
+        assert_eq!(optimized.visit_observed(&mut log, &mut recorder), Success);
+        assert_eq!(recorder.events, [
+            (0, None), (3, None), (5, None), (5, Some(Success)), (3, Some(Success)), (0, Some(Success)),
+        ]);
+    }
+
+    #[test]
+    fn try_visit_error() {
+        let factory = tree(NodeFactory::new_selector(vec![
+            NodeFactory::new_sequence(vec![
+                leaf("a", &[Running, Success]),
+                broken_leaf("broken"),
+            ]),
+            leaf("b", &[Success]),
+        ]));
+        let error = NodeError::new("broken is broken");
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut log = Log::new();
+        assert_eq!(optimized.try_visit(&mut log), Ok(Running));
+        // The selector does not try its next child after an error
+        assert_eq!(optimized.try_visit(&mut log), Err(error.clone()));
+        assert_eq!(log, ["a", "a", "broken"]);
+        // The interrupted nodes were reset, and a plain visit turns the error into a failure
+        log.clear();
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.visit(&mut log), Failure);
+        assert_eq!(log, ["a", "a", "broken"]);
+        log.clear();
+        assert_eq!(instance.try_visit(&mut log), Ok(Running));
+        assert_eq!(instance.try_visit(&mut log), Err(error));
+        assert_eq!(log, ["a", "a", "broken"]);
+    }
+
+    #[test]
+    fn try_visit_error_keeps_other_nodes() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            NodeFactory::new_limiter(2, Box::new(leaf("a", &[Success]))),
+            broken_leaf("broken"),
+        ]));
+        let error = NodeError::new("broken is broken");
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let (mut optimized_log, mut instance_log) = (Log::new(), Log::new());
+        // The limiter is not on the path of the error, so it keeps counting its uses
+        for _ in 0..2 {
+            assert_eq!(optimized.try_visit(&mut optimized_log), Err(error.clone()));
+            assert_eq!(instance.try_visit(&mut instance_log), Err(error.clone()));
+        }
+        assert_eq!(optimized.try_visit(&mut optimized_log), Ok(Failure));
+        assert_eq!(instance.try_visit(&mut instance_log), Ok(Failure));
+        assert_eq!(optimized_log, ["a", "broken", "a", "broken"]);
+        assert_eq!(instance_log, optimized_log);
+    }
+
+    #[test]
+    fn sequence_all_error_clears_failure() {
+        let children = || vec![
+            leaf("a", &[Failure, Success]),
+            NodeFactory::new_selector(vec![leaf("b", &[Failure, Success]), broken_leaf("broken")]),
+        ];
+        let error = NodeError::new("broken is broken");
+        // Visited on its own, the node is not reset by a parent after the error
+        let mut node = SequenceAllNode::new(children().iter().map(NodeFactory::instanciate).collect());
+        let mut log = Log::new();
+        assert_eq!(node.try_visit(&mut log), Err(error.clone()));
+        assert_eq!(node.try_visit(&mut log), Ok(Success));
+        assert_eq!(log, ["a", "b", "broken", "a", "b"]);
+        check_ticks(&tree(NodeFactory::new_sequence_all(children())), &[
+            (Failure, &["a", "b", "broken"]),
+            (Success, &["a", "b"]),
+        ]);
+    }
+
+    #[test]
+    fn packed_tree() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            NodeFactory::new_random_selector(vec![
+                leaf("a", &[Failure, Success]),
+                leaf("b", &[Running, Failure]),
+                leaf("c", &[Success]),
+            ]),
+            NodeFactory::new_parallel(1, vec![
+                NodeFactory::new_repeater(2, Box::new(leaf("d", &[Success, Running]))),
+                NodeFactory::new_inverter(Box::new(leaf("e", &[Success, Failure, Running]))),
+            ]),
+            NodeFactory::new_succeeder(Box::new(NodeFactory::new_switch(
+                LeafFactory::new(None, None, script("selector", &[Success])),
+                vec![leaf("f", &[Success])]))),
+            NodeFactory::new_selector(vec![
+                broken_leaf("broken"),
+                leaf("g", &[Success]),
+            ]),
+        ]));
+        let mut optimized = factory.optimize_with_seed(7);
+        let mut packed = factory.optimize_packed_with_seed(7);
+        assert_eq!(packed.leaves().len(), 9);
+        let mut optimized_log = Log::new();
+        let mut packed_log = Log::new();
+        for _ in 0..20 {
+            assert_eq!(packed.visit(&mut packed_log), optimized.visit(&mut optimized_log));
+            assert_eq!(packed.try_visit(&mut packed_log), optimized.try_visit(&mut optimized_log));
+        }
+        assert_eq!(packed_log, optimized_log);
+    }
+
+    #[test]
+    fn reactive_selector() {
+        let factory = tree(NodeFactory::new_reactive_selector(vec![
+            leaf("flee", &[Failure, Running, Failure]),
+            NodeFactory::new_sequence(vec![
+                leaf("a", &[Success]),
+                leaf("b", &[Running]),
+            ]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut instance = factory.instanciate();
+        let mut optimized_log = Log::new();
+        let mut instance_log = Log::new();
+        for _ in 0..3 {
+            assert_eq!(optimized.visit(&mut optimized_log), Running);
+            assert_eq!(instance.visit(&mut instance_log), Running);
+        }
+        // The sequence was preempted by flee on the second tick, and starts over on the third
+        assert_eq!(optimized_log, ["flee", "a", "b", "flee", "flee", "a", "b"]);
+        assert_eq!(instance_log, optimized_log);
+
+        // A plain selector resumes its running child without checking flee again
+        let factory = tree(NodeFactory::new_selector(vec![
+            leaf("flee", &[Failure, Running, Failure]),
+            NodeFactory::new_sequence(vec![
+                leaf("a", &[Success]),
+                leaf("b", &[Running]),
+            ]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        for _ in 0..3 {
+            assert_eq!(optimized.visit(&mut log), Running);
+        }
+        assert_eq!(log, ["flee", "a", "b", "b", "b"]);
+    }
+
+    #[test]
+    fn visit_with_trace() {
+        let factory = tree(NodeFactory::new_selector(vec![
+            leaf("a", &[Failure]),
+            leaf("b", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        let (result, trace) = optimized.visit_with_trace(&mut log);
+        assert_eq!(result, Running);
+        assert_eq!(trace, [(1, TraceResult::Visited(Failure)), (2, TraceResult::Visited(Running)),
+                           (0, TraceResult::Visited(Running))]);
+        // The selector resumes b, without visiting a again
+        let (result, trace) = optimized.visit_with_trace(&mut log);
+        assert_eq!(result, Success);
+        assert_eq!(trace, [(1, TraceResult::Skipped), (2, TraceResult::Visited(Success)),
+                           (0, TraceResult::Visited(Success))]);
+    }
+
+    #[test]
+    fn ticks_in_current_activation() {
+        let factory = tree(leaf("wait", &[Running, Running, Running, Running, Success]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(optimized.ticks_in_current_activation(), 0);
+        for ticks in 1..5 {
+            assert_eq!(optimized.visit(&mut log), Running);
+            assert_eq!(optimized.ticks_in_current_activation(), ticks);
+        }
+        assert_eq!(optimized.visit(&mut log), Success);
+        assert_eq!(optimized.ticks_in_current_activation(), 4);
+        // The next visit starts a new activation
+        assert_eq!(optimized.visit(&mut log), Running);
+        assert_eq!(optimized.ticks_in_current_activation(), 1);
+        optimized.reset();
+        assert_eq!(optimized.ticks_in_current_activation(), 0);
+    }
+
+    #[test]
+    fn fn_factory() {
+        use std::cell::Cell;
+        use super::random::XorShift;
+
+        // Not Clone, each leaf must be constructed
+        struct Roll(XorShift);
+        impl BehaviourTreeNode<Vec<u64>> for Roll {
+            fn visit(&mut self, rolls: &mut Vec<u64>) -> VisitResult {
+                rolls.push(self.0.next_u64());
+                Success
+            }
+        }
+
+        let seed = Cell::new(0);
+        let factory = FnFactory::new(|| {
+            seed.set(seed.get() + 1);
+            Roll(XorShift::new(seed.get()))
+        });
+        let mut first = factory.instanciate();
+        let mut second = factory.instanciate();
+        let mut rolls = Vec::new();
+        first.visit(&mut rolls);
+        second.visit(&mut rolls);
+        first.visit(&mut rolls);
+        assert_eq!(seed.get(), 2);
+        assert!(rolls[0] != rolls[1]);
+        // The first leaf kept its own generator
+        let mut expected = XorShift::new(1);
+        assert_eq!(rolls[0], expected.next_u64());
+        assert_eq!(rolls[2], expected.next_u64());
+    }
+
+    #[test]
+    fn visit_ref() {
+        use std::sync::Arc;
+        use std::thread;
+        use super::factory::TreeFactory;
+
+        // Condition on the temperature, never modifies it
+        #[derive(Clone)]
+        struct Above(i64);
+        impl ReadOnlyBehaviourTreeNode<i64> for Above {
+            fn visit_ref(&mut self, temperature: &i64) -> VisitResult {
+                if *temperature > self.0 { Success } else { Failure }
+            }
+        }
+        impl LeafNodeFactory for Above {
+            type Output = Above;
+            fn instanciate(&self) -> Above {
+                self.clone()
+            }
+        }
+
+        fn check(threshold: i64) -> NodeFactory<Above> {
+            NodeFactory::new_leaf(Above(threshold))
+        }
+
+        let temperature = Arc::new(25);
+        let threads: Vec<_> = (0..4).map(|i| {
+            let temperature = temperature.clone();
+            thread::spawn(move || {
+                let root = NodeFactory::new_selector(vec![check(20 + 5 * i), check(40)]);
+                let mut optimized = TreeFactory::new(root, String::from("test")).optimize();
+                optimized.visit_ref(&*temperature)
+            })
+        }).collect();
+        let results: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(results, vec![Success, Failure, Failure, Failure]);
+        assert_eq!(*temperature, 25);
+    }
+
+    #[test]
+    fn shared_leaf() {
+        use super::factory::TreeFactory;
+
+        // Runs until it has been visited three times, by any tree
+        struct Counter(usize);
+        impl BehaviourTreeNode<Log> for Counter {
+            fn visit(&mut self, log: &mut Log) -> VisitResult {
+                log.push("counter");
+                self.0 += 1;
+                if self.0 >= 3 { Success } else { Running }
+            }
+        }
+
+        let shared = SharedLeaf::new(Counter(0));
+        let factory = TreeFactory::new(NodeFactory::new_leaf(shared), String::from("test"));
+        let mut first = factory.optimize();
+        let mut second = factory.optimize();
+        let mut log = Log::new();
+        assert_eq!(first.visit(&mut log), Running);
+        assert_eq!(second.visit(&mut log), Running);
+        assert_eq!(first.visit(&mut log), Success);
+        // Resetting a tree leaves the shared node untouched
+        second.reset();
+        assert_eq!(second.visit(&mut log), Success);
+        assert_eq!(log.len(), 4);
+    }
 }
@@ -1,18 +1,39 @@
-use std::fmt::{self,Debug,Formatter};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self,Debug,Formatter};
 
-use super::{VisitResult,BehaviourTreeNode,LeafNode};
+use super::{VisitResult,BehaviourTreeNode,Budget,LeafNode,NodeError,NonBlockingResult};
+use super::{parallel_result,sequence_all_result};
+use super::random::XorShift;
 
-#[derive(Debug)]
 pub struct Tree<A> {
     root: Node<A>,
 }
 
+impl <A> Debug for Tree<A> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        f.debug_tuple("Tree").field(&self.root).finish()
+    }
+}
+
 impl <A> Tree<A> {
     pub fn new(root: Node<A>) -> Tree<A> {
         Tree {
             root: root,
         }
     }
+
+    /// Clears the running state of every node, as `OptimizedTree::reset`
+    pub fn reset(&mut self) {
+        self.root.reset();
+    }
+
+    /// Seeds the random nodes from `seed`, so that they make the same choices as in a tree
+    /// optimized with the same seed
+    pub fn seed(&mut self, seed: u64) {
+        self.root.seed(&mut XorShift::new(seed));
+    }
 }
 
 impl <A,C> BehaviourTreeNode<C> for Tree<A>
@@ -20,6 +41,10 @@ where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
         self.root.visit(context)
     }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.root.try_visit(context)
+    }
 }
 
 /// Visits all its children in order. If one fails, then return immediatly a failure. If all
@@ -44,24 +69,34 @@ pub struct SequenceNode<A> {
 impl <A,C> BehaviourTreeNode<C> for SequenceNode<A>
 where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> SequenceNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
         // If we were running, start again where we left
         let start = self.running.take().unwrap_or(0);
         for (pos, child) in self.children[start..].iter_mut().enumerate() {
-            let result = child.visit(context);
+            let result = try!(child.visit_budgeted(context, budget));
             match result {
-                VisitResult::Failure => return VisitResult::Failure,
+                VisitResult::Failure => return Ok(VisitResult::Failure),
                 VisitResult::Running => {
                     self.running = Some(start + pos);
-                    return VisitResult::Running;
+                    return Ok(VisitResult::Running);
                 }
                 VisitResult::Success => {}
             }
         }
-        VisitResult::Success
+        Ok(VisitResult::Success)
     }
-}
 
-impl <A> SequenceNode<A> {
     pub fn new(children: Vec<Node<A>>) -> SequenceNode<A> {
         SequenceNode {
             running: None,
@@ -76,6 +111,62 @@ impl <A> SequenceNode<A> {
     }
 }
 
+/// Same as Sequence, but a failing child does not stop the sequence: all the children are visited
+/// in order, and the node fails once they all finished if any of them failed.
+///
+/// This is useful when every child has side effects that must happen, like cleaning up after
+/// a task, even if some of them fail.
+#[derive(Debug)]
+pub struct SequenceAllNode<A> {
+    running: Option<usize>,
+    // A child already failed during this activation
+    failed: bool,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for SequenceAllNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> SequenceAllNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let start = self.running.take().unwrap_or(0);
+        for (pos, child) in self.children[start..].iter_mut().enumerate() {
+            let result = child.visit_budgeted(context, budget);
+            // An error ends the activation, the failures seen so far must not leak into the next one
+            if result.is_err() {
+                self.failed = false;
+            }
+            match try!(result) {
+                VisitResult::Failure => self.failed = true,
+                VisitResult::Running => {
+                    self.running = Some(start + pos);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Success => {}
+            }
+        }
+        Ok(sequence_all_result(&mut self.failed))
+    }
+
+    pub fn new(children: Vec<Node<A>>) -> SequenceAllNode<A> {
+        SequenceAllNode {
+            running: None,
+            failed: false,
+            children: children,
+        }
+    }
+}
+
 /// Counterpart of Sequence: returns Success on the first child returning Success, and return
 /// Failure if all children fail.
 ///
@@ -90,24 +181,34 @@ pub struct SelectorNode<A> {
 impl <A,C> BehaviourTreeNode<C> for SelectorNode<A>
 where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> SelectorNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
         // If we were running, start again where we left
         let start = self.running.take().unwrap_or(0);
         for (pos, child) in self.children[start..].iter_mut().enumerate() {
-            let result = child.visit(context);
+            let result = try!(child.visit_budgeted(context, budget));
             match result {
-                VisitResult::Success => return VisitResult::Failure,
+                VisitResult::Success => return Ok(VisitResult::Success),
                 VisitResult::Running => {
                     self.running = Some(start + pos);
-                    return VisitResult::Running;
+                    return Ok(VisitResult::Running);
                 }
                 VisitResult::Failure => {}
             }
         }
-        VisitResult::Failure
+        Ok(VisitResult::Failure)
     }
-}
 
-impl <A> SelectorNode<A> {
     pub fn new(children: Vec<Node<A>>) -> SelectorNode<A> {
         SelectorNode {
             running: None,
@@ -122,30 +223,158 @@ impl <A> SelectorNode<A> {
     }
 }
 
+/// Same as Selector, but visits its children in a random order, picked every time the node is
+/// entered. A running child keeps control until it finishes.
+#[derive(Debug)]
+pub struct RandomSelectorNode<A> {
+    order: Vec<usize>,
+    running: Option<usize>,
+    rng: XorShift,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for RandomSelectorNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> RandomSelectorNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let start = match self.running.take() {
+            Some(position) => position,
+            None => {
+                // New activation, pick a new order
+                self.rng.shuffle(&mut self.order);
+                0
+            }
+        };
+        for position in start..self.order.len() {
+            match try!(self.children[self.order[position]].visit_budgeted(context, budget)) {
+                VisitResult::Success => return Ok(VisitResult::Success),
+                VisitResult::Running => {
+                    self.running = Some(position);
+                    return Ok(VisitResult::Running);
+                }
+                VisitResult::Failure => {}
+            }
+        }
+        Ok(VisitResult::Failure)
+    }
+
+    pub fn new(children: Vec<Node<A>>, rng: XorShift) -> RandomSelectorNode<A> {
+        RandomSelectorNode {
+            order: (0..children.len()).collect(),
+            running: None,
+            rng: rng,
+            children: children,
+        }
+    }
+}
+
+/// Visits a single child, picked randomly every time the node is entered with a probability
+/// proportional to its weight, and returns its result. A running child keeps control until it
+/// finishes.
+///
+/// Fails without visiting anything if all the weights are 0.
+#[derive(Debug)]
+pub struct WeightedSelectorNode<A> {
+    weights: Vec<u32>,
+    running: Option<usize>,
+    rng: XorShift,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for WeightedSelectorNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> WeightedSelectorNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let index = match self.running.take().or_else(|| self.rng.weighted(&self.weights)) {
+            Some(index) => index,
+            None => return Ok(VisitResult::Failure),
+        };
+        let result = try!(self.children[index].visit_budgeted(context, budget));
+        if result == VisitResult::Running {
+            self.running = Some(index);
+        }
+        Ok(result)
+    }
+
+    pub fn new(weights: Vec<u32>, children: Vec<Node<A>>, rng: XorShift) -> WeightedSelectorNode<A> {
+        assert_eq!(weights.len(), children.len(), "Expected one weight per child");
+        WeightedSelectorNode {
+            weights: weights,
+            running: None,
+            rng: rng,
+            children: children,
+        }
+    }
+}
+
 /// Same as Sequence, but do not remember the last running child and revisit all children
 #[derive(Debug)]
 pub struct PriorityNode<A> {
+    // Child cut short by a spent budget, resumed directly on the next visit
+    deferred: Option<usize>,
     children: Vec<Node<A>>,
 }
 
 impl <A,C> BehaviourTreeNode<C> for PriorityNode<A>
 where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
-        for child in self.children.iter_mut() {
-            let result = child.visit(context);
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> PriorityNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let start = self.deferred.take().unwrap_or(0);
+        for (pos, child) in self.children[start..].iter_mut().enumerate() {
+            let deferred_before = budget.deferred;
+            let result = try!(child.visit_budgeted(context, budget));
             match result {
-                VisitResult::Failure => return VisitResult::Failure,
-                VisitResult::Running => return VisitResult::Running,
+                VisitResult::Failure => return Ok(VisitResult::Failure),
+                VisitResult::Running => {
+                    if budget.deferred != deferred_before {
+                        self.deferred = Some(start + pos);
+                    }
+                    return Ok(VisitResult::Running);
+                }
                 VisitResult::Success => {}
             }
         }
-        VisitResult::Success
+        Ok(VisitResult::Success)
     }
-}
 
-impl <A> PriorityNode<A> {
     pub fn new(children: Vec<Node<A>>) -> PriorityNode<A> {
-        PriorityNode{children: children}
+        PriorityNode {
+            deferred: None,
+            children: children,
+        }
     }
 
     #[allow(dead_code)]
@@ -155,6 +384,134 @@ impl <A> PriorityNode<A> {
     }
 }
 
+/// Same as Selector, but revisits the children from the first one on every tick, abandoning the
+/// running child if a child before it now succeeds or runs
+#[derive(Debug)]
+pub struct ReactiveSelectorNode<A> {
+    running: Option<usize>,
+    // Child cut short by a spent budget, the next visit starts from it instead of the first child
+    deferred: Option<usize>,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for ReactiveSelectorNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> ReactiveSelectorNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let start = self.deferred.take().unwrap_or(0);
+        for index in start..self.children.len() {
+            let deferred_before = budget.deferred;
+            let result = try!(self.children[index].visit_budgeted(context, budget));
+            if result == VisitResult::Running && budget.deferred != deferred_before {
+                // Not a real Running: the running child keeps running until this child is
+                // resumed and actually takes over
+                self.deferred = Some(index);
+                if self.running.map_or(true, |running| running < index) {
+                    self.running = Some(index);
+                }
+                return Ok(result);
+            }
+            if result != VisitResult::Failure {
+                preempt(&mut self.running, index, &mut self.children);
+                if result == VisitResult::Running {
+                    self.running = Some(index);
+                }
+                return Ok(result);
+            }
+        }
+        self.running = None;
+        Ok(VisitResult::Failure)
+    }
+
+    pub fn new(children: Vec<Node<A>>) -> ReactiveSelectorNode<A> {
+        ReactiveSelectorNode {
+            running: None,
+            deferred: None,
+            children: children,
+        }
+    }
+}
+
+// Resets the running child if it comes after the child `index` that just took over
+fn preempt<A>(running: &mut Option<usize>, index: usize, children: &mut [Node<A>]) {
+    if let Some(running) = running.take() {
+        if running > index {
+            children[running].reset_activation();
+        }
+    }
+}
+
+/// Visits all its children on every tick, and returns Success once at least `threshold` of them
+/// succeeded, or Failure once too many of them failed for the threshold to be reached.
+///
+/// Children that finished are not visited again until the parallel node itself finishes.
+#[derive(Debug)]
+pub struct ParallelNode<A> {
+    threshold: usize,
+    results: Vec<Option<VisitResult>>,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for ParallelNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> ParallelNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        for (child, result) in self.children.iter_mut().zip(self.results.iter_mut()) {
+            if result.is_some() {
+                // Already finished during this activation
+                continue;
+            }
+            match try!(child.visit_budgeted(context, budget)) {
+                VisitResult::Running => {}
+                other => *result = Some(other),
+            }
+        }
+        let successes = self.results.iter().filter(|r| **r == Some(VisitResult::Success)).count();
+        let failures = self.results.iter().filter(|r| **r == Some(VisitResult::Failure)).count();
+        let result = parallel_result(self.threshold, self.children.len(), successes, failures);
+        if result != VisitResult::Running {
+            for (child, r) in self.children.iter_mut().zip(self.results.iter_mut()) {
+                // The children still running are abandoned, the next activation starts them over
+                if r.is_none() {
+                    child.reset_activation();
+                }
+                *r = None;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn new(threshold: usize, children: Vec<Node<A>>) -> ParallelNode<A> {
+        ParallelNode {
+            threshold: threshold,
+            results: vec![None; children.len()],
+            children: children,
+        }
+    }
+}
+
 /// Inverts the output of the child
 #[derive(Debug)]
 pub struct InverterNode<A> {
@@ -164,42 +521,673 @@ pub struct InverterNode<A> {
 impl <A,C> BehaviourTreeNode<C> for InverterNode<A>
 where A: BehaviourTreeNode<C> {
     fn visit(&mut self, context: &mut C) -> VisitResult {
-        match self.child.visit(context) {
-            VisitResult::Success => return VisitResult::Failure,
-            VisitResult::Failure => return VisitResult::Success,
-            VisitResult::Running => return VisitResult::Running,
-        }
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
     }
 }
 
 impl <A> InverterNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            VisitResult::Success => VisitResult::Failure,
+            VisitResult::Failure => VisitResult::Success,
+            VisitResult::Running => VisitResult::Running,
+        };
+        Ok(result)
+    }
+
     pub fn new(child: Box<Node<A>>) -> InverterNode<A> {
         InverterNode{child: child}
     }
 }
 
+/// Returns Success whether its child succeeded or failed, and propagates Running
+#[derive(Debug)]
+pub struct SucceederNode<A> {
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for SucceederNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> SucceederNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            VisitResult::Running => VisitResult::Running,
+            VisitResult::Success | VisitResult::Failure => VisitResult::Success,
+        };
+        Ok(result)
+    }
+
+    pub fn new(child: Box<Node<A>>) -> SucceederNode<A> {
+        SucceederNode{child: child}
+    }
+}
+
+/// Returns `on_running` instead of Running, and the Success or Failure of its child unchanged
+///
+/// The parent does not wait for a running child: it carries on, and the child is resumed the
+/// next time this node is visited. A child cut short by a spent `BudgetNode` is not running yet,
+/// so Running is returned unchanged and the parent resumes it on the next tick.
+#[derive(Debug)]
+pub struct NonBlockingNode<A> {
+    on_running: NonBlockingResult,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for NonBlockingNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> NonBlockingNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let deferred_before = budget.deferred;
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            // Cut short by a spent budget, the parent must resume the child on the next tick
+            VisitResult::Running if budget.deferred != deferred_before => VisitResult::Running,
+            VisitResult::Running => VisitResult::from(self.on_running),
+            result => result,
+        };
+        Ok(result)
+    }
+
+    pub fn new(on_running: NonBlockingResult, child: Box<Node<A>>) -> NonBlockingNode<A> {
+        NonBlockingNode {
+            on_running: on_running,
+            child: child,
+        }
+    }
+}
+
+/// Visits its child until it fails, returning Running as long as it succeeds or runs, and Success
+/// once it failed
+#[derive(Debug)]
+pub struct UntilFailNode<A> {
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for UntilFailNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> UntilFailNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            VisitResult::Failure => VisitResult::Success,
+            VisitResult::Success | VisitResult::Running => VisitResult::Running,
+        };
+        Ok(result)
+    }
+
+    pub fn new(child: Box<Node<A>>) -> UntilFailNode<A> {
+        UntilFailNode{child: child}
+    }
+}
+
+/// Visits its child a fixed number of times, returning Running until the child succeeded `total`
+/// times, and then Success.
+///
+/// A failure of the child aborts the repetition and is returned immediately.
+#[derive(Debug)]
+pub struct RepeaterNode<A> {
+    remaining: usize,
+    total: usize,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for RepeaterNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> RepeaterNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        if self.total == 0 {
+            return Ok(VisitResult::Success);
+        }
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            VisitResult::Failure => {
+                self.remaining = self.total;
+                VisitResult::Failure
+            }
+            VisitResult::Running => VisitResult::Running,
+            VisitResult::Success => {
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    self.remaining = self.total;
+                    VisitResult::Success
+                } else {
+                    VisitResult::Running
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    pub fn new(total: usize, child: Box<Node<A>>) -> RepeaterNode<A> {
+        RepeaterNode {
+            remaining: total,
+            total: total,
+            child: child,
+        }
+    }
+}
+
+/// Visits its child at most once every `ticks + 1` visits: after a success of the child, the
+/// next `ticks` visits return Failure without visiting it.
+///
+/// A Running or failing child does not start the cooldown.
+#[derive(Debug)]
+pub struct CooldownNode<A> {
+    remaining: usize,
+    ticks: usize,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for CooldownNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> CooldownNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return Ok(VisitResult::Failure);
+        }
+        let result = try!(self.child.visit_budgeted(context, budget));
+        if result == VisitResult::Success {
+            self.remaining = self.ticks;
+        }
+        Ok(result)
+    }
+
+    pub fn new(ticks: usize, child: Box<Node<A>>) -> CooldownNode<A> {
+        CooldownNode {
+            remaining: 0,
+            ticks: ticks,
+            child: child,
+        }
+    }
+}
+
+/// Visits its child until it succeeded `max` times, and then fails without visiting it
+///
+/// Running or failing children do not count as a use.
+#[derive(Debug)]
+pub struct LimiterNode<A> {
+    used: usize,
+    max: usize,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for LimiterNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> LimiterNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        if self.used >= self.max {
+            return Ok(VisitResult::Failure);
+        }
+        let result = try!(self.child.visit_budgeted(context, budget));
+        if result == VisitResult::Success {
+            self.used += 1;
+        }
+        Ok(result)
+    }
+
+    pub fn new(max: usize, child: Box<Node<A>>) -> LimiterNode<A> {
+        LimiterNode {
+            used: 0,
+            max: max,
+            child: child,
+        }
+    }
+}
+
+/// Limits the number of nodes of its child subtree visited during a tick to `max`
+///
+/// When the budget is spent, the next node returns Running without being visited, and its parents
+/// resume it on the next tick. Priority nodes and reactive selectors resume the child that was cut
+/// short instead of starting again from their first child, which would spend the budget before
+/// reaching it on every tick. `NonBlockingNode` and `StoreResultNode` pass the Running of a child
+/// that was cut short through unchanged. The budget counts every node of the subtree, composites
+/// included, so it must leave room for the nodes leading to the leaves.
+#[derive(Debug)]
+pub struct BudgetNode<A> {
+    max: usize,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for BudgetNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> BudgetNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        budget.remaining.push(self.max);
+        let result = self.child.visit_budgeted(context, budget);
+        budget.remaining.pop();
+        result
+    }
+
+    pub fn new(max: usize, child: Box<Node<A>>) -> BudgetNode<A> {
+        BudgetNode {
+            max: max,
+            child: child,
+        }
+    }
+}
+
+/// Visits its child again on the next tick when it fails, up to `attempts` times
+///
+/// Returns Running while attempts remain, Failure after `attempts` failures in a row, and Success
+/// as soon as the child succeeds. A Running child does not consume an attempt.
+#[derive(Debug)]
+pub struct RetryNode<A> {
+    attempts_left: usize,
+    attempts: usize,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for RetryNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> RetryNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let result = match try!(self.child.visit_budgeted(context, budget)) {
+            VisitResult::Running => VisitResult::Running,
+            VisitResult::Success => {
+                self.attempts_left = self.attempts;
+                VisitResult::Success
+            }
+            VisitResult::Failure => {
+                self.attempts_left = self.attempts_left.saturating_sub(1);
+                if self.attempts_left == 0 {
+                    self.attempts_left = self.attempts;
+                    VisitResult::Failure
+                } else {
+                    VisitResult::Running
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    pub fn new(attempts: usize, child: Box<Node<A>>) -> RetryNode<A> {
+        RetryNode {
+            attempts_left: attempts,
+            attempts: attempts,
+            child: child,
+        }
+    }
+}
+
+/// Visits the child whose index is given by the selector leaf, and returns its result
+///
+/// Fails if the selector gives no index, or an index out of range. A running child keeps control
+/// until it finishes, whatever the selector gives in the meantime.
+#[derive(Debug)]
+pub struct SwitchNode<A> {
+    selector: LeafNode<A>,
+    running: Option<usize>,
+    children: Vec<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for SwitchNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> SwitchNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let selector = &mut self.selector;
+        let index = match self.running.take().or_else(|| selector.select_child(context)) {
+            Some(index) => index,
+            None => return Ok(VisitResult::Failure),
+        };
+        let result = match self.children.get_mut(index) {
+            Some(child) => try!(child.visit_budgeted(context, budget)),
+            None => return Ok(VisitResult::Failure),
+        };
+        if result == VisitResult::Running {
+            self.running = Some(index);
+        }
+        Ok(result)
+    }
+
+    pub fn new(selector: LeafNode<A>, children: Vec<Node<A>>) -> SwitchNode<A> {
+        SwitchNode {
+            selector: selector,
+            running: None,
+            children: children,
+        }
+    }
+}
+
+/// Visits its child and writes its result to the variable `variable` of the context, through the
+/// `writer` leaf, see `BehaviourTreeNode::store_result`
+///
+/// The result of the child is returned unchanged, and nothing is written if the child returns an
+/// error or is cut short by a spent `BudgetNode`.
+#[derive(Debug)]
+pub struct StoreResultNode<A> {
+    variable: String,
+    writer: LeafNode<A>,
+    child: Box<Node<A>>,
+}
+
+impl <A,C> BehaviourTreeNode<C> for StoreResultNode<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.try_visit(context).unwrap_or(VisitResult::Failure)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> StoreResultNode<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        let deferred_before = budget.deferred;
+        let result = try!(self.child.visit_budgeted(context, budget));
+        // A child cut short by a spent budget has no result yet
+        if !(result == VisitResult::Running && budget.deferred != deferred_before) {
+            self.writer.store_result(context, &self.variable, result);
+        }
+        Ok(result)
+    }
+
+    pub fn new(variable: String, writer: LeafNode<A>, child: Box<Node<A>>) -> StoreResultNode<A> {
+        StoreResultNode {
+            variable: variable,
+            writer: writer,
+            child: child,
+        }
+    }
+}
+
 pub enum Node<A> {
     Leaf(LeafNode<A>),
     Sequence(SequenceNode<A>),
+    SequenceAll(SequenceAllNode<A>),
     Priority(PriorityNode<A>),
+    ReactiveSelector(ReactiveSelectorNode<A>),
     Selector(SelectorNode<A>),
+    RandomSelector(RandomSelectorNode<A>),
+    WeightedSelector(WeightedSelectorNode<A>),
+    Parallel(ParallelNode<A>),
     Inverter(InverterNode<A>),
+    Repeater(RepeaterNode<A>),
+    UntilFail(UntilFailNode<A>),
+    Succeeder(SucceederNode<A>),
+    Cooldown(CooldownNode<A>),
+    Limiter(LimiterNode<A>),
+    Retry(RetryNode<A>),
+    Switch(SwitchNode<A>),
+    Budget(BudgetNode<A>),
+    NonBlocking(NonBlockingNode<A>),
+    StoreResult(StoreResultNode<A>),
 }
 
+// Written by hand so that A does not need to implement Debug: leaves are printed as `Leaf(..)`
 impl <A> Debug for Node<A> {
-    fn fmt(&self, _f: &mut Formatter) -> Result<(),fmt::Error> {
-        // TODO: Derive does not work any more because of the type parameter A
-        /*
+    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+        match *self {
+            Node::Leaf(_) => f.write_str("Leaf(..)"),
+            Node::Sequence(ref node) => f.debug_tuple("Sequence").field(&node.children).finish(),
+            Node::SequenceAll(ref node) => f.debug_tuple("SequenceAll").field(&node.children).finish(),
+            Node::Priority(ref node) => f.debug_tuple("Priority").field(&node.children).finish(),
+            Node::ReactiveSelector(ref node) => {
+                f.debug_tuple("ReactiveSelector").field(&node.children).finish()
+            }
+            Node::Selector(ref node) => f.debug_tuple("Selector").field(&node.children).finish(),
+            Node::RandomSelector(ref node) => {
+                f.debug_tuple("RandomSelector").field(&node.children).finish()
+            }
+            Node::WeightedSelector(ref node) => {
+                f.debug_tuple("WeightedSelector").field(&node.weights).field(&node.children).finish()
+            }
+            Node::Parallel(ref node) => {
+                f.debug_tuple("Parallel").field(&node.threshold).field(&node.children).finish()
+            }
+            Node::Inverter(ref node) => f.debug_tuple("Inverter").field(&node.child).finish(),
+            Node::Repeater(ref node) => {
+                f.debug_tuple("Repeater").field(&node.total).field(&node.child).finish()
+            }
+            Node::UntilFail(ref node) => f.debug_tuple("UntilFail").field(&node.child).finish(),
+            Node::Succeeder(ref node) => f.debug_tuple("Succeeder").field(&node.child).finish(),
+            Node::Cooldown(ref node) => {
+                f.debug_tuple("Cooldown").field(&node.ticks).field(&node.child).finish()
+            }
+            Node::Limiter(ref node) => {
+                f.debug_tuple("Limiter").field(&node.max).field(&node.child).finish()
+            }
+            Node::Retry(ref node) => {
+                f.debug_tuple("Retry").field(&node.attempts).field(&node.child).finish()
+            }
+            Node::Switch(ref node) => f.debug_tuple("Switch").field(&node.children).finish(),
+            Node::Budget(ref node) => {
+                f.debug_tuple("Budget").field(&node.max).field(&node.child).finish()
+            }
+            Node::NonBlocking(ref node) => {
+                f.debug_tuple("NonBlocking").field(&node.on_running).field(&node.child).finish()
+            }
+            Node::StoreResult(ref node) => {
+                f.debug_tuple("StoreResult").field(&node.variable).field(&node.child).finish()
+            }
+        }
+    }
+}
+
+impl <A> Node<A> {
+    /// Clears the running state of the node and of its descendants, as `OptimizedTree::reset`
+    pub fn reset(&mut self) {
+        self.reset_recursive(true);
+    }
+
+    // Same as `reset`, but the limiters keep their count, as for an error or a preemption
+    fn reset_activation(&mut self) {
+        self.reset_recursive(false);
+    }
+
+    fn reset_recursive(&mut self, clear_limits: bool) {
+        self.reset_state();
+        match *self {
+            Node::Leaf(_) => {}
+            Node::Sequence(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::SequenceAll(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::Priority(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::ReactiveSelector(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::Selector(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::RandomSelector(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::WeightedSelector(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::Parallel(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::Switch(ref mut node) => reset_all(&mut node.children, clear_limits),
+            Node::Inverter(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::Repeater(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::UntilFail(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::Succeeder(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::Cooldown(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::Limiter(ref mut node) => {
+                if clear_limits {
+                    node.used = 0;
+                }
+                node.child.reset_recursive(clear_limits)
+            }
+            Node::Retry(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::Budget(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::NonBlocking(ref mut node) => node.child.reset_recursive(clear_limits),
+            Node::StoreResult(ref mut node) => node.child.reset_recursive(clear_limits),
+        }
+    }
+
+    // Clears the running state of the node itself, its descendants keep theirs
+    fn reset_state(&mut self) {
+        match *self {
+            Node::Sequence(ref mut node) => node.running = None,
+            Node::SequenceAll(ref mut node) => {
+                node.running = None;
+                node.failed = false;
+            }
+            Node::Priority(ref mut node) => node.deferred = None,
+            Node::ReactiveSelector(ref mut node) => {
+                node.running = None;
+                node.deferred = None;
+            }
+            Node::Selector(ref mut node) => node.running = None,
+            Node::RandomSelector(ref mut node) => node.running = None,
+            Node::WeightedSelector(ref mut node) => node.running = None,
+            Node::Parallel(ref mut node) => {
+                for result in node.results.iter_mut() {
+                    *result = None;
+                }
+            }
+            Node::Repeater(ref mut node) => node.remaining = node.total,
+            Node::Retry(ref mut node) => node.attempts_left = node.attempts,
+            Node::Switch(ref mut node) => node.running = None,
+            // The cooldown is not part of an activation, it keeps counting after a reset. Neither
+            // is the count of the limiter, only `reset` clears it.
+            Node::Leaf(_) | Node::Inverter(_) | Node::UntilFail(_) | Node::Succeeder(_)
+                | Node::Cooldown(_) | Node::Limiter(_) | Node::Budget(_) | Node::NonBlocking(_)
+                | Node::StoreResult(_) => {}
+        }
+    }
+}
+
+fn reset_all<A>(nodes: &mut [Node<A>], clear_limits: bool) {
+    for node in nodes.iter_mut() {
+        node.reset_recursive(clear_limits);
+    }
+}
+
+impl <A> Node<A> {
+    // Gives each random node of the subtree the next seed of `rng`, in pre-order like
+    // `OptimizedTree::seed`
+    fn seed(&mut self, rng: &mut XorShift) {
         match *self {
-            Node::Leaf(ref node) => {formatter.write_str("Leaf {"); node.fmt(formatter); formatter.write_str("}")}
-            Node::Sequence(ref node) => {formatter.write_str("Sequence {"); node.fmt(formatter); formatter.write_str("}")}
-            Node::Priority(ref node) => {formatter.write_str("Priority {"); node.fmt(formatter);formatter.write_str("}")}
-            Node::Selector(ref node) => {formatter.write_str("Selector {"); node.fmt(formatter);formatter.write_str("}")}
-            Node::Blackboard(ref node) => {formatter.write_str("Blackboard {"); node.fmt(formatter);formatter.write_str("}")}
-            Node::Inverter(ref node) => {formatter.write_str("Inverter {"); node.fmt(formatter);formatter.write_str("}")}
+            Node::Leaf(_) => {}
+            Node::Sequence(ref mut node) => seed_all(&mut node.children, rng),
+            Node::SequenceAll(ref mut node) => seed_all(&mut node.children, rng),
+            Node::Priority(ref mut node) => seed_all(&mut node.children, rng),
+            Node::ReactiveSelector(ref mut node) => seed_all(&mut node.children, rng),
+            Node::Selector(ref mut node) => seed_all(&mut node.children, rng),
+            Node::RandomSelector(ref mut node) => {
+                node.rng = XorShift::new(rng.next_u64());
+                seed_all(&mut node.children, rng)
+            }
+            Node::WeightedSelector(ref mut node) => {
+                node.rng = XorShift::new(rng.next_u64());
+                seed_all(&mut node.children, rng)
+            }
+            Node::Parallel(ref mut node) => seed_all(&mut node.children, rng),
+            Node::Switch(ref mut node) => seed_all(&mut node.children, rng),
+            Node::Inverter(ref mut node) => node.child.seed(rng),
+            Node::Repeater(ref mut node) => node.child.seed(rng),
+            Node::UntilFail(ref mut node) => node.child.seed(rng),
+            Node::Succeeder(ref mut node) => node.child.seed(rng),
+            Node::Cooldown(ref mut node) => node.child.seed(rng),
+            Node::Limiter(ref mut node) => node.child.seed(rng),
+            Node::Retry(ref mut node) => node.child.seed(rng),
+            Node::Budget(ref mut node) => node.child.seed(rng),
+            Node::NonBlocking(ref mut node) => node.child.seed(rng),
+            Node::StoreResult(ref mut node) => node.child.seed(rng),
         }
-        */
-        Ok(())
+    }
+}
+
+fn seed_all<A>(nodes: &mut [Node<A>], rng: &mut XorShift) {
+    for node in nodes.iter_mut() {
+        node.seed(rng);
     }
 }
 
@@ -209,9 +1197,66 @@ where A: BehaviourTreeNode<C> {
         match *self {
             Node::Leaf(ref mut node) => node.visit(context),
             Node::Sequence(ref mut node) => node.visit(context),
+            Node::SequenceAll(ref mut node) => node.visit(context),
             Node::Priority(ref mut node) => node.visit(context),
+            Node::ReactiveSelector(ref mut node) => node.visit(context),
             Node::Selector(ref mut node) => node.visit(context),
+            Node::RandomSelector(ref mut node) => node.visit(context),
+            Node::WeightedSelector(ref mut node) => node.visit(context),
+            Node::Parallel(ref mut node) => node.visit(context),
             Node::Inverter(ref mut node) => node.visit(context),
+            Node::Repeater(ref mut node) => node.visit(context),
+            Node::UntilFail(ref mut node) => node.visit(context),
+            Node::Succeeder(ref mut node) => node.visit(context),
+            Node::Cooldown(ref mut node) => node.visit(context),
+            Node::Limiter(ref mut node) => node.visit(context),
+            Node::Retry(ref mut node) => node.visit(context),
+            Node::Switch(ref mut node) => node.visit(context),
+            Node::Budget(ref mut node) => node.visit(context),
+            Node::NonBlocking(ref mut node) => node.visit(context),
+            Node::StoreResult(ref mut node) => node.visit(context),
+        }
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.visit_budgeted(context, &mut Budget::new())
+    }
+}
+
+impl <A> Node<A> {
+    fn visit_budgeted<C>(&mut self, context: &mut C, budget: &mut Budget)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C> {
+        if !budget.spend() {
+            // Left for a later tick, parents resume it as if it was running
+            return Ok(VisitResult::Running);
+        }
+        let result = match *self {
+            Node::Leaf(ref mut node) => node.try_visit(context),
+            Node::Sequence(ref mut node) => node.visit_budgeted(context, budget),
+            Node::SequenceAll(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Priority(ref mut node) => node.visit_budgeted(context, budget),
+            Node::ReactiveSelector(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Selector(ref mut node) => node.visit_budgeted(context, budget),
+            Node::RandomSelector(ref mut node) => node.visit_budgeted(context, budget),
+            Node::WeightedSelector(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Parallel(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Inverter(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Repeater(ref mut node) => node.visit_budgeted(context, budget),
+            Node::UntilFail(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Succeeder(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Cooldown(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Limiter(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Retry(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Switch(ref mut node) => node.visit_budgeted(context, budget),
+            Node::Budget(ref mut node) => node.visit_budgeted(context, budget),
+            Node::NonBlocking(ref mut node) => node.visit_budgeted(context, budget),
+            Node::StoreResult(ref mut node) => node.visit_budgeted(context, budget),
+        };
+        if result.is_err() {
+            // Interrupted, the next visit starts a new activation of this node
+            self.reset_state();
         }
+        result
     }
 }
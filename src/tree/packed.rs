@@ -0,0 +1,162 @@
+//! Tree layout keeping the state of the leaves in a contiguous vector
+//!
+//! An `OptimizedTree` stores each leaf inside the node holding it, and the leaves produced by the
+//! standard factories are boxed trait objects. When all the leaves of a tree have the same
+//! concrete type, typically an enum of the actions of a game, a `PackedTree` stores them side by
+//! side in a single `Vec`, and the nodes only keep the index of their leaf.
+
+use alloc::vec::Vec;
+
+use tree::{BehaviourTreeNode,FlatNode,NoObserver,NodeError,OptimizedNode,OptimizedTree};
+use tree::{TreeObserver,VisitResult};
+#[cfg(feature = "stats")]
+use tree::ExecutionStats;
+
+use flat_tree::buffer::ChildrenMut;
+
+/// Leaf of the structure of a `PackedTree`, standing for the leaf at this index in its vector
+#[derive(Debug,Clone,Copy)]
+pub struct PackedLeaf(usize);
+
+impl PackedLeaf {
+    /// Placeholder used while building the structure, the leaves are numbered afterwards
+    pub fn unnumbered() -> PackedLeaf {
+        PackedLeaf(0)
+    }
+}
+
+/// Context given to the structure of a `PackedTree`, to give its leaves access to their state
+pub struct PackedContext<'a, A: 'a, C: 'a> {
+    leaves: &'a mut [A],
+    context: &'a mut C,
+}
+
+impl <'a,A,C> BehaviourTreeNode<PackedContext<'a,A,C>> for PackedLeaf
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, packed: &mut PackedContext<'a,A,C>) -> VisitResult {
+        packed.leaves[self.0].visit(packed.context)
+    }
+
+    fn try_visit(&mut self, packed: &mut PackedContext<'a,A,C>) -> Result<VisitResult,NodeError> {
+        packed.leaves[self.0].try_visit(packed.context)
+    }
+
+    fn select_child(&mut self, packed: &mut PackedContext<'a,A,C>) -> Option<usize> {
+        packed.leaves[self.0].select_child(packed.context)
+    }
+
+    fn store_result(&mut self, packed: &mut PackedContext<'a,A,C>, variable: &str, result: VisitResult) {
+        packed.leaves[self.0].store_result(packed.context, variable, result)
+    }
+}
+
+/// Same as an `OptimizedTree`, with the leaves stored in a single vector
+///
+/// Created with `TreeFactory::optimize_packed`. The nodes are visited exactly as in the
+/// `OptimizedTree` produced by `TreeFactory::optimize` for the same factory.
+#[derive(Debug)]
+pub struct PackedTree<A> {
+    structure: OptimizedTree<PackedLeaf>,
+    leaves: Vec<A>,
+}
+
+/// Same behaviour as the `Clone` implementation of `OptimizedTree`
+impl <A: Clone> Clone for PackedTree<A> {
+    fn clone(&self) -> PackedTree<A> {
+        PackedTree {
+            structure: self.structure.clone(),
+            leaves: self.leaves.clone(),
+        }
+    }
+}
+
+impl <C,A> BehaviourTreeNode<C> for PackedTree<A>
+where A: BehaviourTreeNode<C> {
+    fn visit(&mut self, context: &mut C) -> VisitResult {
+        self.visit_observed(context, &mut NoObserver)
+    }
+
+    fn try_visit(&mut self, context: &mut C) -> Result<VisitResult,NodeError> {
+        self.try_visit_observed(context, &mut NoObserver)
+    }
+}
+
+impl <A> PackedTree<A> {
+    /// `leaves` must be given in the pre-order of the structure, the selector of a switch node and
+    /// the writer of a store_result node coming before their children
+    pub fn new(mut structure: OptimizedTree<PackedLeaf>, leaves: Vec<A>) -> PackedTree<A> {
+        fn number(node: &mut FlatNode<PackedLeaf>, mut children: ChildrenMut<FlatNode<PackedLeaf>>,
+                  next: &mut usize) {
+            match node.node {
+                OptimizedNode::Leaf(ref mut leaf) => {
+                    leaf.inner.0 = *next;
+                    *next += 1;
+                }
+                OptimizedNode::Switch { selector: ref mut leaf, .. }
+                    | OptimizedNode::StoreResult { writer: ref mut leaf, .. } => {
+                    leaf.inner.0 = *next;
+                    *next += 1;
+                }
+                _ => {}
+            }
+            for (child, grandchildren) in children.children_mut() {
+                number(child, grandchildren, next);
+            }
+        }
+        let mut count = 0;
+        {
+            let (root, children) = structure.inner.tree_iter_mut()
+                                   .nth(0).expect("Tried to pack a tree without node");
+            number(root, children, &mut count);
+        }
+        assert_eq!(count, leaves.len(), "The structure and the leaves of a packed tree differ");
+        PackedTree {
+            structure: structure,
+            leaves: leaves,
+        }
+    }
+
+    /// Same as `OptimizedTree::reset`
+    pub fn reset(&mut self) {
+        self.structure.reset();
+    }
+
+    /// Same as `OptimizedTree::index_of`
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.structure.index_of(id)
+    }
+
+    /// Same as `OptimizedTree::take_stats`
+    #[cfg(feature = "stats")]
+    pub fn take_stats(&mut self) -> ExecutionStats {
+        self.structure.take_stats()
+    }
+
+    /// Same as `OptimizedTree::reset_stats`
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.structure.reset_stats()
+    }
+
+    /// The leaves of the tree, in depth-first order
+    pub fn leaves(&self) -> &[A] {
+        &self.leaves
+    }
+
+    /// Same as `OptimizedTree::visit_observed`
+    pub fn visit_observed<C,O>(&mut self, context: &mut C, observer: &mut O) -> VisitResult
+    where A: BehaviourTreeNode<C>, O: ?Sized + TreeObserver {
+        self.try_visit_observed(context, observer).unwrap_or(VisitResult::Failure)
+    }
+
+    /// Same as `OptimizedTree::try_visit_observed`
+    pub fn try_visit_observed<C,O>(&mut self, context: &mut C, observer: &mut O)
+    -> Result<VisitResult,NodeError>
+    where A: BehaviourTreeNode<C>, O: ?Sized + TreeObserver {
+        let mut packed = PackedContext {
+            leaves: &mut self.leaves,
+            context: context,
+        };
+        self.structure.try_visit_observed(&mut packed, observer)
+    }
+}
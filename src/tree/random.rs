@@ -0,0 +1,139 @@
+use core::ops::{Deref,DerefMut};
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicUsize,Ordering};
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher,Hasher};
+
+// Number of generators created by `from_entropy` without std
+#[cfg(not(feature = "std"))]
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Small xorshift* pseudo-random generator used by the random nodes
+///
+/// It is not suitable for anything requiring good statistical properties, but it is enough to
+/// vary the behaviour of agents and is cheap to store in every node.
+#[derive(Debug,Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // Xorshift generators are stuck on 0
+        let state = if seed == 0 { 0x9E3779B97F4A7C15 } else { seed };
+        XorShift { state: state }
+    }
+
+    /// Creates a generator seeded from the random keys std uses for its hashmaps
+    #[cfg(feature = "std")]
+    pub fn from_entropy() -> XorShift {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u8(0);
+        XorShift::new(hasher.finish())
+    }
+
+    /// Without std there is no source of entropy: every generator gets a different seed, but the
+    /// seeds are the same from one run to the next. Use the `_with_seed` methods of the factories
+    /// to vary them.
+    #[cfg(not(feature = "std"))]
+    pub fn from_entropy() -> XorShift {
+        let created = CREATED.fetch_add(1, Ordering::Relaxed) as u64;
+        XorShift::new(created.wrapping_mul(0x9E3779B97F4A7C15).wrapping_add(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a number in `0..bound`
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Picks an index of `weights`, each index having a probability proportional to its weight
+    ///
+    /// Returns None if all the weights are 0.
+    pub fn weighted(&mut self, weights: &[u32]) -> Option<usize> {
+        let total = weights.iter().fold(0u64, |total, &weight| total + weight as u64);
+        if total == 0 {
+            return None;
+        }
+        let mut draw = self.next_u64() % total;
+        for (index, &weight) in weights.iter().enumerate() {
+            if draw < weight as u64 {
+                return Some(index);
+            }
+            draw -= weight as u64;
+        }
+        unreachable!("The draw is always below the total weight")
+    }
+
+    /// Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
+
+/// Context providing the randomness of the random nodes, see `OptimizedTree::visit_with_rng`
+pub trait RngContext {
+    fn next_u32(&mut self) -> u32;
+}
+
+impl RngContext for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+}
+
+#[cfg(feature = "rand")]
+impl <R: ::rand::RngCore> RngContext for R {
+    fn next_u32(&mut self) -> u32 {
+        ::rand::RngCore::next_u32(self)
+    }
+}
+
+/// Context adding a seeded generator to `inner`, making the random nodes reproducible
+///
+/// The context dereferences to `inner`, so leaves can be written for `SeededContext<C>` by
+/// forwarding to their implementation for `C`.
+#[derive(Debug,Clone)]
+pub struct SeededContext<C> {
+    pub inner: C,
+    rng: XorShift,
+}
+
+impl <C> SeededContext<C> {
+    pub fn new(inner: C, seed: u64) -> SeededContext<C> {
+        SeededContext {
+            inner: inner,
+            rng: XorShift::new(seed),
+        }
+    }
+}
+
+impl <C> RngContext for SeededContext<C> {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+}
+
+impl <C> Deref for SeededContext<C> {
+    type Target = C;
+    fn deref(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl <C> DerefMut for SeededContext<C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
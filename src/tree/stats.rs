@@ -0,0 +1,84 @@
+//! Visit counts of the nodes of an `OptimizedTree`, to find the nodes that dominate a tick
+//!
+//! Only compiled with the `stats` feature: without it, the nodes do not count their visits at all.
+
+use alloc::collections::BTreeMap;
+
+use tree::OptimizedTree;
+
+/// Number of visits of the nodes of a tree, returned by `OptimizedTree::take_stats`
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct ExecutionStats {
+    /// Number of visits of every node visited at least once, the nodes being numbered as in
+    /// `OptimizedTree::nodes`
+    pub visits: BTreeMap<usize,u64>,
+}
+
+impl ExecutionStats {
+    /// Number of visits of the node `index`
+    pub fn visits_of(&self, index: usize) -> u64 {
+        self.visits.get(&index).cloned().unwrap_or(0)
+    }
+}
+
+impl <A> OptimizedTree<A> {
+    /// Returns the number of visits of every node since the last call, and starts counting again
+    ///
+    /// A node is counted every time it is entered, so a leaf returning Running for 3 ticks is
+    /// counted 3 times. Nodes skipped because of a `budget` are not counted.
+    pub fn take_stats(&mut self) -> ExecutionStats {
+        let mut stats = ExecutionStats::default();
+        for (node, _) in self.inner.tree_iter_mut() {
+            if node.visits > 0 {
+                stats.visits.insert(node.index, node.visits);
+                node.visits = 0;
+            }
+        }
+        stats
+    }
+
+    /// Starts counting the visits again, discarding the current counts
+    pub fn reset_stats(&mut self) {
+        for (node, _) in self.inner.tree_iter_mut() {
+            node.visits = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tree::BehaviourTreeNode;
+    use tree::factory::NodeFactory;
+    use tree::test_helpers::*;
+
+    #[test]
+    fn visit_counts() {
+        let factory = tree(NodeFactory::new_sequence(vec![
+            leaf("a", &[Success]),
+            leaf("b", &[Running, Success]),
+        ]));
+        let mut optimized = factory.optimize();
+        let mut log = Log::new();
+        for _ in 0..6 {
+            optimized.visit(&mut log);
+        }
+        let stats = optimized.take_stats();
+        // b is resumed directly when it was running, a is only visited when the sequence starts
+        assert_eq!(stats.visits_of(0), 6);
+        assert_eq!(stats.visits_of(1), 3);
+        assert_eq!(stats.visits_of(2), 6);
+        assert_eq!(stats.visits.len(), 3);
+
+        // Taking the stats starts counting again
+        assert_eq!(optimized.take_stats().visits.len(), 0);
+        optimized.visit(&mut log);
+        optimized.reset_stats();
+        assert_eq!(optimized.take_stats().visits_of(2), 0);
+
+        let mut packed = factory.optimize_packed();
+        for _ in 0..6 {
+            packed.visit(&mut log);
+        }
+        assert_eq!(packed.take_stats(), stats);
+    }
+}
@@ -0,0 +1,74 @@
+use tree::{BehaviourTreeNode,VisitResult,LeafNodeFactory,NodeError};
+use tree::factory::{NodeFactory,TreeFactory};
+use tree::random::SeededContext;
+
+pub use tree::VisitResult::{Success,Failure,Running};
+
+/// Context used by the tests: the name of every visited leaf is appended to it
+pub type Log = Vec<&'static str>;
+
+/// Leaf cycling through a list of results, used to drive the nodes under test
+#[derive(Debug,Clone)]
+pub struct Script {
+    name: &'static str,
+    results: Vec<VisitResult>,
+    position: usize,
+    // Returns an error from try_visit instead of its results
+    broken: bool,
+}
+
+impl BehaviourTreeNode<Log> for Script {
+    fn visit(&mut self, log: &mut Log) -> VisitResult {
+        log.push(self.name);
+        let result = self.results[self.position % self.results.len()];
+        self.position += 1;
+        result
+    }
+
+    fn try_visit(&mut self, log: &mut Log) -> Result<VisitResult,NodeError> {
+        if self.broken {
+            log.push(self.name);
+            Err(NodeError::new(format!("{} is broken", self.name)))
+        } else {
+            Ok(self.visit(log))
+        }
+    }
+}
+
+// Leaves of trees visited with `visit_with_rng`
+impl BehaviourTreeNode<SeededContext<Log>> for Script {
+    fn visit(&mut self, context: &mut SeededContext<Log>) -> VisitResult {
+        BehaviourTreeNode::<Log>::visit(self, &mut context.inner)
+    }
+}
+
+impl LeafNodeFactory for Script {
+    type Output = Script;
+    fn instanciate(&self) -> Script {
+        self.clone()
+    }
+}
+
+pub fn script(name: &'static str, results: &[VisitResult]) -> Script {
+    Script {
+        name: name,
+        results: results.to_vec(),
+        position: 0,
+        broken: false,
+    }
+}
+
+pub fn leaf(name: &'static str, results: &[VisitResult]) -> NodeFactory<Script> {
+    NodeFactory::new_named_leaf(String::from(name), None, script(name, results))
+}
+
+/// Leaf returning an error when visited with `try_visit`, and failing otherwise
+pub fn broken_leaf(name: &'static str) -> NodeFactory<Script> {
+    let mut script = script(name, &[Failure]);
+    script.broken = true;
+    NodeFactory::new_named_leaf(String::from(name), None, script)
+}
+
+pub fn tree(root: NodeFactory<Script>) -> TreeFactory<Script> {
+    TreeFactory::new(root, String::from("test"))
+}
@@ -0,0 +1,80 @@
+extern crate behaviour_tree;
+
+use std::collections::HashMap;
+
+use behaviour_tree::FactoryProducer;
+use behaviour_tree::tree::{BehaviourTreeNode,NonBlockingResult,VisitResult};
+use behaviour_tree::tree::factory::TreeBuilder;
+use behaviour_tree::parser::Value;
+use behaviour_tree::standard::{LeavesCollection,StandardFactory,StoreKind};
+
+type Context = HashMap<String,StoreKind>;
+
+const TREE: &'static str = r#"
+tree test {
+    sequence {
+        increment({ variable: a, value: 1 }),
+        selector {
+            inverter increment({ variable: b, value: 2 }),
+            increment({ variable: c, value: 3 }),
+        },
+    }
+}
+"#;
+
+fn increment(leaves: &LeavesCollection<Context>, variable: &str, value: i64) -> StandardFactory<Context> {
+    let mut options = HashMap::new();
+    options.insert(String::from("variable"), Value::String(String::from(variable)));
+    options.insert(String::from("value"), Value::Integer(value));
+    leaves.generate_leaf("increment", &Some(Value::Map(options))).unwrap()
+}
+
+#[test]
+fn builder_matches_parser() {
+    let leaves = LeavesCollection::standard();
+    let parsed = behaviour_tree::parse(TREE, &leaves).unwrap();
+    let built = TreeBuilder::new()
+        .sequence(|b| b
+            .leaf(increment(&leaves, "a", 1))
+            .selector(|b| b
+                .inverter(|b| b
+                    .leaf(increment(&leaves, "b", 2)))
+                .leaf(increment(&leaves, "c", 3))))
+        .build("test");
+    assert_eq!(built.get_name(), parsed[0].get_name());
+
+    let mut parsed_instance = parsed[0].optimize();
+    let mut built_instance = built.optimize();
+    let mut parsed_context = HashMap::new();
+    let mut built_context = HashMap::new();
+    for _ in 0..3 {
+        let parsed_result = parsed_instance.visit(&mut parsed_context);
+        let built_result = built_instance.visit(&mut built_context);
+        assert_eq!(parsed_result, VisitResult::Success);
+        assert_eq!(parsed_result, built_result);
+        assert_eq!(parsed_context, built_context);
+    }
+}
+
+#[test]
+#[should_panic]
+fn inverter_with_two_children() {
+    let leaves = LeavesCollection::standard();
+    TreeBuilder::new()
+        .inverter(|b| b
+            .leaf(increment(&leaves, "a", 1))
+            .leaf(increment(&leaves, "b", 1)))
+        .build("test");
+}
+
+#[test]
+fn non_blocking() {
+    let leaves = LeavesCollection::standard();
+    let running = leaves.generate_leaf("always_running", &None).unwrap();
+    let built = TreeBuilder::new()
+        .non_blocking(NonBlockingResult::Failure, |b| b.leaf(running))
+        .build("test");
+    let mut context: Context = HashMap::new();
+    assert_eq!(built.optimize().visit(&mut context), VisitResult::Failure);
+    assert_eq!(built.instanciate().visit(&mut context), VisitResult::Failure);
+}
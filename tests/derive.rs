@@ -0,0 +1,74 @@
+#![cfg(feature = "derive")]
+
+extern crate behaviour_tree;
+
+use std::collections::HashMap;
+
+use behaviour_tree::parser::{FromOptions,OptionsError,Value};
+
+#[derive(Debug,PartialEq,FromOptions)]
+struct IncrementOptions {
+    variable: String,
+    value: i64,
+    times: Option<u64>,
+}
+
+fn options(entries: Vec<(&str,Value)>) -> Option<Value> {
+    let map: HashMap<String,Value> = entries.into_iter().map(|(k, v)| (String::from(k), v)).collect();
+    Some(Value::Map(map))
+}
+
+fn variable() -> (&'static str, Value) {
+    ("variable", Value::String(String::from("count")))
+}
+
+#[test]
+fn derived() {
+    let parsed = IncrementOptions::from_options(&options(vec![variable(), ("value", Value::Integer(2))]));
+    let expected = IncrementOptions { variable: String::from("count"), value: 2, times: None };
+    assert_eq!(parsed, Ok(expected));
+    let parsed = IncrementOptions::from_options(&options(vec![
+        variable(),
+        ("value", Value::Integer(-1)),
+        ("times", Value::Integer(3)),
+    ]));
+    assert_eq!(parsed.unwrap().times, Some(3));
+}
+
+#[test]
+fn not_a_map() {
+    let found = Some(Value::Integer(1));
+    assert_eq!(IncrementOptions::from_options(&found), Err(OptionsError::NotAMap { found: found.clone() }));
+    assert_eq!(IncrementOptions::from_options(&None), Err(OptionsError::NotAMap { found: None }));
+}
+
+#[test]
+fn missing_field() {
+    let error = IncrementOptions::from_options(&options(vec![variable()]));
+    assert_eq!(error, Err(OptionsError::MissingField { field: String::from("value") }));
+}
+
+#[test]
+fn wrong_type() {
+    let error = IncrementOptions::from_options(&options(vec![
+        variable(),
+        ("value", Value::Integer(1)),
+        ("times", Value::Integer(-3)),
+    ]));
+    let expected = OptionsError::WrongType {
+        field: String::from("times"),
+        expected: String::from("a non-negative integer"),
+        found: Value::Integer(-3),
+    };
+    assert_eq!(error, Err(expected));
+}
+
+#[test]
+fn unknown_field() {
+    let error = IncrementOptions::from_options(&options(vec![
+        variable(),
+        ("value", Value::Integer(1)),
+        ("valeu", Value::Integer(1)),
+    ]));
+    assert_eq!(error, Err(OptionsError::UnknownField { field: String::from("valeu") }));
+}
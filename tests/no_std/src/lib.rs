@@ -0,0 +1,53 @@
+#![no_std]
+
+extern crate behaviour_tree;
+
+use behaviour_tree::tree::{BehaviourTreeNode,LeafNodeFactory,VisitResult};
+use behaviour_tree::tree::factory::TreeBuilder;
+
+/// Sensors of a robot, the context of the trees
+pub struct Sensors {
+    pub distance: u32,
+    pub stopped: bool,
+}
+
+#[derive(Clone)]
+pub enum Action {
+    ObstacleCloserThan(u32),
+    Stop,
+}
+
+impl BehaviourTreeNode<Sensors> for Action {
+    fn visit(&mut self, sensors: &mut Sensors) -> VisitResult {
+        match *self {
+            Action::ObstacleCloserThan(distance) => {
+                if sensors.distance < distance { VisitResult::Success } else { VisitResult::Failure }
+            }
+            Action::Stop => {
+                sensors.stopped = true;
+                VisitResult::Success
+            }
+        }
+    }
+}
+
+impl LeafNodeFactory for Action {
+    type Output = Action;
+    fn instanciate(&self) -> Action {
+        self.clone()
+    }
+}
+
+/// Stops the robot if an obstacle is too close
+pub fn avoid_obstacles(sensors: &mut Sensors) -> VisitResult {
+    let factory = TreeBuilder::new()
+        .sequence(|b| b
+            .leaf(Action::ObstacleCloserThan(10))
+            .leaf(Action::Stop))
+        .build("avoid_obstacles");
+    let mut optimized = factory.optimize_with_seed(1);
+    let mut packed = factory.optimize_packed_with_seed(1);
+    let result = optimized.visit(sensors);
+    assert_eq!(packed.visit(sensors), result);
+    result
+}
@@ -0,0 +1,32 @@
+extern crate behaviour_tree;
+
+use std::collections::HashMap;
+use std::thread;
+
+use behaviour_tree::tree::{BehaviourTreeNode,VisitResult};
+use behaviour_tree::standard::{Gettable,Number,SendLeavesCollection,StoreKind};
+
+const TREE: &'static str = r#"
+tree counter {
+    sequence {
+        increment(variable: "ticks", value: 1),
+        wait(1),
+        increment(variable: "ticks", value: 1),
+    }
+}
+"#;
+
+#[test]
+fn tick_on_worker_thread() {
+    let leaves = SendLeavesCollection::<HashMap<String,StoreKind>>::standard_send();
+    let parsed_trees = behaviour_tree::parse(TREE, &leaves).unwrap();
+    let mut tree = parsed_trees[0].optimize();
+    let worker = thread::spawn(move || {
+        let mut context = HashMap::new();
+        assert_eq!(tree.visit(&mut context), VisitResult::Running);
+        assert_eq!(tree.visit(&mut context), VisitResult::Success);
+        context
+    });
+    let context = worker.join().unwrap();
+    assert_eq!(Gettable::get(&context, "ticks"), Some(&StoreKind::Number(Number::Int(2))));
+}
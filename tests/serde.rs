@@ -0,0 +1,80 @@
+#![cfg(feature = "serde")]
+
+extern crate behaviour_tree;
+extern crate serde_json;
+
+use std::collections::HashMap;
+
+use behaviour_tree::parser::{self,Comparison,Value,Operator};
+use behaviour_tree::parser::ast::Tree;
+use behaviour_tree::tree::{BehaviourTreeNode,VisitResult};
+use behaviour_tree::standard::{LeavesCollection,Number,StoreKind};
+
+const TREES: &'static str = r#"
+tree main {
+    sequence {
+        set_variable({ variable: "state", value: 3 }),
+        evaluate_int({ result: double, expression: [ state 2 * ] }),
+        inverter condition({ exp1: [ double ], exp2: [ 5 ], operator: < }),
+        subtree other,
+    }
+}
+tree other {
+    parallel(1) {
+        repeat(2) { wait(1) },
+        force_success { print_text("Hello") },
+    }
+}
+"#;
+
+#[test]
+fn ast_round_trip() {
+    let trees = parser::parse_ast(TREES).unwrap();
+    let json = serde_json::to_string(&trees).unwrap();
+    let deserialized: Vec<Tree> = serde_json::from_str(&json).unwrap();
+    assert_eq!(trees, deserialized);
+}
+
+#[test]
+fn value_round_trip() {
+    let mut map = HashMap::new();
+    map.insert(String::from("integer"), Value::Integer(-4));
+    map.insert(String::from("operator"), Value::Operator(Operator::Divide));
+    map.insert(String::from("comparison"), Value::Comparison(Comparison::GreaterOrEqual));
+    let value = Value::Array(vec![
+        Value::String(String::from("text")),
+        Value::Map(map),
+    ]);
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(value, serde_json::from_str(&json).unwrap());
+}
+
+#[test]
+fn resolve_deserialized_trees() {
+    let leaves = LeavesCollection::<HashMap<String,StoreKind>>::standard();
+    let json = serde_json::to_string(&parser::parse_ast(TREES).unwrap()).unwrap();
+    let deserialized: Vec<Tree> = serde_json::from_str(&json).unwrap();
+    let from_json = parser::resolve(deserialized, &leaves).unwrap();
+    let from_dsl = parser::parse(TREES, &leaves).unwrap();
+    assert_eq!(from_json.len(), from_dsl.len());
+
+    let mut json_instance = from_json[0].optimize();
+    let mut dsl_instance = from_dsl[0].optimize();
+    let mut json_context = HashMap::new();
+    let mut dsl_context = HashMap::new();
+    for _ in 0..4 {
+        let result = json_instance.visit(&mut json_context);
+        assert_eq!(result, dsl_instance.visit(&mut dsl_context));
+        assert_eq!(json_context, dsl_context);
+    }
+    assert_eq!(json_context.get("double"), Some(&StoreKind::Number(Number::Int(6))));
+    assert_eq!(json_instance.visit(&mut json_context), VisitResult::Success);
+}
+
+#[test]
+fn trees_without_params() {
+    // Trees serialized before templates existed have no params field
+    let json = r#"[{"name":"main","root":{"Leaf":["wait",{"Integer":1},{"line":1,"column":13}]}}]"#;
+    let trees: Vec<Tree> = serde_json::from_str(json).unwrap();
+    assert_eq!(trees, parser::parse_ast("tree main { wait(1) }").unwrap());
+}